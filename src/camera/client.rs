@@ -1,52 +1,352 @@
-use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use anyhow::Context;
 use num_traits::{FromPrimitive, ToPrimitive};
 use ptp::{ObjectHandle, PtpData, StorageId};
-use tokio::{io::AsyncWriteExt, sync::mpsc, time::sleep};
-
-use crate::{util::*, Channels};
-
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{broadcast, mpsc},
+    time::sleep,
+};
+
+use crate::{
+    cli::config::{CameraConfig, ImageConvertFormat, LossOfLinkPolicy, ShutdownPolicy},
+    pixhawk::state::PixhawkEvent,
+    state::{Attitude, Coords3D, GpsTime},
+    util::*,
+    Channels,
+};
+
+use super::driver::CameraDriver;
 use super::interface::*;
 use super::*;
 
+/// How many CAMERA_FEEDBACK messages to remember for geotagging.
+const FEEDBACK_HISTORY: usize = 32;
+
+/// How often to check whether the camera has switched operating modes.
+const MODE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait before asking the camera to switch operating modes again.
+const MODE_REQUEST_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The storage ID of the camera's memory card.
+const CARD_STORAGE_ID: u32 = 0x00010001;
+
+/// The storage ID that the camera reports while the memory card isn't ready.
+const PLACEHOLDER_STORAGE_ID: u32 = 0x00010000;
+
+/// How often to look for the camera when it is optional and not connected.
+const CAMERA_RETRY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How far in advance a timed capture has to be requested, so that there is
+/// time to switch modes and focus before the shutter is released.
+const CAPTURE_AT_MIN_LEAD: Duration = Duration::from_secs(1);
+
+/// How far in advance a timed capture can be requested. The camera can't do
+/// anything else while it waits, so this is kept short.
+const CAPTURE_AT_MAX_LEAD: Duration = Duration::from_secs(20);
+
+/// The longest burst that can be requested, so that the whole burst fits
+/// within the command timeout.
+const BURST_MAX_DURATION: Duration = Duration::from_secs(10);
+
+/// How many numbered names to try when a downloaded file has the same name as
+/// a file that was saved before.
+const MAX_NAME_COLLISIONS: u32 = 999;
+
+/// How long to wait for the camera to hand over the next image of a burst
+/// before assuming that it has sent all of them.
+const BURST_FRAME_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long before the command timeout a burst stops downloading images, to
+/// leave time to put the drive mode back.
+const BURST_RESTORE_MARGIN: Duration = Duration::from_secs(2);
+
+/// How long formatting the memory card may take.
+const FORMAT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Files at least this big are downloaded in chunks, with progress events.
+const DOWNLOAD_PROGRESS_THRESHOLD: u32 = 4 * 1024 * 1024;
+
+/// How much of a large file to download at a time.
+const DOWNLOAD_CHUNK_SIZE: u32 = 1024 * 1024;
+
+/// The least time between two progress events for the same download.
+const DOWNLOAD_PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many empty commands to send when measuring the latency of the link.
+const BENCHMARK_ROUND_TRIPS: u32 = 10;
+
+/// The color temperatures, in kelvin, that the camera accepts for its white
+/// balance.
+const COLOR_TEMPERATURE_RANGE: std::ops::RangeInclusive<u16> = 2500..=9900;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum CameraClientMode {
     Idle,
     ContinuousCapture,
 }
 
+/// Running totals of the images captured during this session, used to
+/// estimate how much space future images will take up.
+#[derive(Debug, Default, Copy, Clone)]
+struct CaptureStats {
+    count: u64,
+    bytes: u64,
+}
+
+impl CaptureStats {
+    fn record(&mut self, size: u64) {
+        self.count += 1;
+        self.bytes += size;
+    }
+
+    fn average_size(&self) -> Option<u64> {
+        if self.count == 0 {
+            None
+        } else {
+            Some(self.bytes / self.count)
+        }
+    }
+}
+
+/// Where the plane was when the autopilot saw the camera take a picture.
+#[derive(Debug, Clone, Copy)]
+struct CaptureFeedback {
+    /// GPS time.
+    time: SystemTime,
+    coords: Coords3D,
+    attitude: Attitude,
+}
+
+/// Tracks what we need to catch up on once downloads are resumed.
+#[derive(Debug)]
+struct DownloadPause {
+    /// Objects that were already on the memory card when downloads were
+    /// paused. Anything else on the card after that was captured during the
+    /// pause.
+    existing: HashSet<ObjectHandle>,
+
+    /// The save mode to restore when downloads are resumed.
+    save_mode: CameraSaveMode,
+}
+
+/// Opens a new connection to the camera.
+type CameraConnector = Box<dyn FnMut() -> anyhow::Result<Box<dyn CameraDriver>> + Send>;
+
 pub struct CameraClient {
-    iface: CameraInterface,
+    iface: Box<dyn CameraDriver>,
+
+    /// Used to connect to the camera again after it is reset or unplugged.
+    connector: CameraConnector,
+
     channels: Arc<Channels>,
     cmd: mpsc::Receiver<CameraCommand>,
-    error: Option<CameraErrorMode>,
+    /// Errors that the camera is reporting which have already been
+    /// broadcast.
+    errors: Vec<CameraErrorMode>,
     mode: CameraClientMode,
+    stats: CaptureStats,
+    config: CameraConfig,
+
+    /// When this client was created; used in place of the last ground contact
+    /// if we have not heard from the ground at all yet.
+    started_at: SystemTime,
+
+    /// Whether the link to the ground is currently considered lost.
+    link_lost: bool,
+
+    /// Whether continuous capture was paused because the link was lost, and
+    /// should be resumed when it comes back.
+    paused_for_link: bool,
+
+    /// Present while downloads are paused.
+    download_pause: Option<DownloadPause>,
+
+    sequence: CaptureSequence,
+
+    /// How many commands in a row have timed out.
+    timeouts: u32,
+
+    /// Files that have been saved but not synced to disk yet.
+    unsynced: Vec<PathBuf>,
+
+    /// Pixhawk events, for geotagging and for sorting files into a directory
+    /// per flight segment.
+    pixhawk_recv: broadcast::Receiver<PixhawkEvent>,
+
+    /// The most recent CAMERA_FEEDBACK messages that haven't been matched to
+    /// an image yet, oldest first.
+    feedback: VecDeque<CaptureFeedback>,
+
+    /// The directory that downloaded files are saved in.
+    save_dir: PathBuf,
+
+    /// The drive mode from before a burst, while the burst is running; put
+    /// back if the burst times out.
+    drive_mode_to_restore: Option<PtpData>,
+
+    /// Whether the plane was armed at the last heartbeat; `None` until the
+    /// first heartbeat.
+    armed: Option<bool>,
 }
 
 impl CameraClient {
-    pub fn connect(
+    /// Connects to the camera. If the camera is optional and not plugged in
+    /// yet, waits for it to show up; commands sent in the meantime fail.
+    /// Returns `None` if the system is interrupted before the camera appears.
+    pub async fn connect_when_available(
+        channels: Arc<Channels>,
+        cmd: mpsc::Receiver<CameraCommand>,
+        config: CameraConfig,
+    ) -> anyhow::Result<Option<Self>> {
+        let connector: CameraConnector = Box::new(|| -> anyhow::Result<Box<dyn CameraDriver>> {
+            let iface = CameraInterface::new().context("failed to create camera interface")?;
+            Ok(Box::new(iface))
+        });
+
+        Self::connect_with(channels, cmd, config, connector).await
+    }
+
+    /// Like `connect_when_available`, but connects to whichever camera
+    /// `connector` opens.
+    async fn connect_with(
+        channels: Arc<Channels>,
+        mut cmd: mpsc::Receiver<CameraCommand>,
+        config: CameraConfig,
+        mut connector: CameraConnector,
+    ) -> anyhow::Result<Option<Self>> {
+        let mut interrupt_recv = channels.interrupt.subscribe();
+        let mut warned = false;
+
+        // an optional camera is polled forever anyway, so it doesn't need
+        // retries of its own
+        let attempts = if config.required {
+            config.connect_attempts.max(1)
+        } else {
+            1
+        };
+
+        let retry_delay = Duration::from_millis(config.connect_retry_delay_ms);
+
+        loop {
+            match Self::open_interface(&mut connector, attempts, retry_delay).await {
+                Ok(iface) => {
+                    if warned {
+                        info!("camera connected");
+                    }
+
+                    return Self::with_interface(channels, cmd, config, connector, iface).map(Some);
+                }
+                Err(err) if config.required => {
+                    return Err(err.context(format!(
+                        "failed to connect to camera after {} attempts",
+                        attempts
+                    )))
+                }
+                Err(err) if !warned => {
+                    warn!(
+                        "CAMERA NOT CONNECTED, continuing without it until it is plugged in: {:?}",
+                        err
+                    );
+                    warned = true;
+                }
+                Err(_) => {}
+            }
+
+            while let Ok(cmd) = cmd.try_recv() {
+                let _ = cmd.error(anyhow!("camera not connected"));
+            }
+
+            if interrupt_recv.try_recv().is_ok() {
+                return Ok(None);
+            }
+
+            sleep(CAMERA_RETRY_INTERVAL).await;
+        }
+    }
+
+    /// Opens the camera and starts a session with it. Tries up to `attempts`
+    /// times, doubling the delay between attempts each time.
+    async fn open_interface(
+        connector: &mut CameraConnector,
+        attempts: u32,
+        retry_delay: Duration,
+    ) -> anyhow::Result<Box<dyn CameraDriver>> {
+        let mut delay = retry_delay;
+        let mut attempt = 1;
+
+        loop {
+            let result = connector().and_then(|mut iface| {
+                iface
+                    .connect()
+                    .context("error while connecting to camera")?;
+                Ok(iface)
+            });
+
+            match result {
+                Ok(iface) => return Ok(iface),
+                Err(err) if attempt >= attempts => return Err(err),
+                Err(err) => {
+                    warn!(
+                        "failed to connect to camera (attempt {} of {}), retrying in {:?}: {:?}",
+                        attempt, attempts, delay, err
+                    );
+
+                    sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    fn with_interface(
         channels: Arc<Channels>,
         cmd: mpsc::Receiver<CameraCommand>,
+        config: CameraConfig,
+        connector: CameraConnector,
+        iface: Box<dyn CameraDriver>,
     ) -> anyhow::Result<Self> {
-        let iface = CameraInterface::new().context("failed to create camera interface")?;
+        let sequence = CaptureSequence::load(&config.sequence_file)?;
+
+        let pixhawk_recv = channels.pixhawk_event.subscribe();
+        let save_dir = std::env::current_dir().context("failed to get current directory")?;
 
         Ok(CameraClient {
             iface,
+            connector,
             channels,
             cmd,
-            error: None,
+            errors: Vec::new(),
             mode: CameraClientMode::Idle,
+            stats: CaptureStats::default(),
+            config,
+            started_at: SystemTime::now(),
+            link_lost: false,
+            paused_for_link: false,
+            download_pause: None,
+            sequence,
+            timeouts: 0,
+            unsynced: Vec::new(),
+            pixhawk_recv,
+            feedback: VecDeque::new(),
+            save_dir,
+            drive_mode_to_restore: None,
+            armed: None,
         })
     }
 
+    /// Sets up the camera once a session has been opened.
     pub fn init(&mut self) -> anyhow::Result<()> {
         trace!("intializing camera");
 
-        self.iface
-            .connect()
-            .context("error while connecting to camera")?;
-
         let time_str = chrono::Local::now()
             .format("%Y%m%dT%H%M%S%.3f%:z")
             .to_string();
@@ -73,14 +373,27 @@ impl CameraClient {
         let mut interrupt_recv = self.channels.interrupt.subscribe();
 
         loop {
-            self.iface
-                .update()
-                .context("failed to update camera state")?;
+            if let Err(err) = self.iface.update().context("failed to update camera state") {
+                if !self.recover(err, &mut interrupt_recv).await? {
+                    return Ok(());
+                }
+
+                continue;
+            }
 
             match self.cmd.try_recv() {
                 Ok(cmd) => {
-                    let result = self.exec(cmd.request()).await;
+                    let result = self.exec_with_timeout(cmd.request()).await;
+                    let disconnected = matches!(&result, Err(err) if is_disconnect(err));
                     let _ = cmd.respond(result);
+
+                    if disconnected {
+                        if !self.reconnect(&mut interrupt_recv).await? {
+                            return Ok(());
+                        }
+
+                        continue;
+                    }
                 }
                 _ => {}
             }
@@ -88,6 +401,12 @@ impl CameraClient {
             if let Ok(event) = self.iface.recv() {
                 trace!("received event: {:?}", event);
 
+                // the event may be a change to the caution property, so look
+                // at it again before checking for errors
+                if let Err(err) = self.iface.update() {
+                    warn!("failed to update camera state after event: {:?}", err);
+                }
+
                 // in CC mode, if we receive an image capture event we should
                 // automatically download the image
                 match self.mode {
@@ -95,30 +414,12 @@ impl CameraClient {
                         ptp::EventCode::Vendor(0xC204) => {
                             debug!("received image during continuous capture");
 
-                            let save_media = self
-                                .iface
-                                .get(CameraPropertyCode::SaveMedia)
-                                .context("unknown whether image is saved to host or device")?
-                                .current;
-
-                            match save_media {
-                                PtpData::UINT16(save_media) => {
-                                    match CameraSaveMode::from_u16(save_media) {
-                                        Some(save_media) => match save_media {
-                                            CameraSaveMode::HostDevice => {
-                                                let shot_handle = ObjectHandle::from(0xFFFFC001);
-
-                                                let image_path = self.download_image(shot_handle).await?;
-
-                                                info!("saved continuous capture image to {:?}", image_path);
-                                            }
-
-                                            CameraSaveMode::MemoryCard1 => warn!("continuous capture images are being saved to camera; this is not supported"),
-                                        },
-                                        None => bail!("invalid save media"),
-                                    }
+                            if let Err(err) = self.handle_capture_event().await {
+                                if !self.recover(err, &mut interrupt_recv).await? {
+                                    return Ok(());
                                 }
-                                _ => bail!("invalid save media"),
+
+                                continue;
                             }
                         }
                         _ => {}
@@ -127,34 +428,209 @@ impl CameraClient {
                 }
             }
 
-            if let Err(camera_error) = self.check_error() {
+            for camera_error in self.check_errors() {
                 error!("detected camera error: {:?}", camera_error);
+                let _ = self
+                    .channels
+                    .camera_event
+                    .send(CameraEvent::Error(camera_error));
+            }
+
+            if let Err(err) = self.check_link() {
+                error!("failed to apply loss-of-link policy: {:?}", err);
             }
 
+            self.check_pixhawk_events();
+
             if interrupt_recv.try_recv().is_ok() {
+                if self.config.shutdown.policy == ShutdownPolicy::Finish {
+                    let deadline = Duration::from_secs(self.config.shutdown.deadline_secs);
+
+                    if let Err(err) = self.finish_captures(deadline).await {
+                        error!("failed to finish capturing: {:?}", err);
+                    }
+                }
+
                 break;
             }
 
             tokio::time::sleep(Duration::from_secs(1)).await;
         }
 
+        self.shut_down().await
+    }
+
+    /// Turns the camera off if configured to, then disconnects from it.
+    async fn shut_down(&mut self) -> anyhow::Result<()> {
+        // disconnect even if the camera won't turn off
+        if self.config.shutdown.power_off {
+            info!("turning camera off");
+
+            let deadline = Duration::from_secs(self.config.shutdown.deadline_secs);
+
+            match tokio::time::timeout(deadline, self.power_down()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => error!("failed to turn camera off: {:?}", err),
+                Err(_) => error!("camera did not turn off within {:?}", deadline),
+            }
+        }
+
         info!("disconnecting from camera");
         self.iface.disconnect()?;
 
         Ok(())
     }
 
+    async fn power_down(&mut self) -> anyhow::Result<()> {
+        self.ensure_mode(0x02).await?;
+        self.iface
+            .execute(CameraControlCode::PowerOff, ptp::PtpData::UINT16(2))?;
+        Ok(())
+    }
+
+    /// Handles an error from the camera. If the camera was disconnected,
+    /// waits for it to come back and reconnects, returning false if the
+    /// system is interrupted first. Any other error is returned.
+    async fn recover(
+        &mut self,
+        err: anyhow::Error,
+        interrupt_recv: &mut broadcast::Receiver<()>,
+    ) -> anyhow::Result<bool> {
+        if !is_disconnect(&err) {
+            return Err(err);
+        }
+
+        warn!("camera error: {:?}", err);
+
+        self.reconnect(interrupt_recv).await
+    }
+
+    /// Waits for the camera to come back after it was disconnected, and
+    /// reconnects to it. Returns false if the system is interrupted first.
+    async fn reconnect(
+        &mut self,
+        interrupt_recv: &mut broadcast::Receiver<()>,
+    ) -> anyhow::Result<bool> {
+        error!("lost connection to camera, reconnecting");
+        let _ = self.channels.camera_event.send(CameraEvent::Disconnected);
+
+        loop {
+            // don't leave commands hanging while the camera is gone
+            while let Ok(cmd) = self.cmd.try_recv() {
+                let _ = cmd.error(anyhow!("camera disconnected, reconnecting; try again"));
+            }
+
+            if interrupt_recv.try_recv().is_ok() {
+                return Ok(false);
+            }
+
+            match Self::open_interface(&mut self.connector, 1, CAMERA_RETRY_INTERVAL).await {
+                Ok(iface) => {
+                    self.iface = iface;
+                    break;
+                }
+                Err(err) => trace!("camera is not back yet: {:?}", err),
+            }
+
+            sleep(CAMERA_RETRY_INTERVAL).await;
+        }
+
+        self.restore_session()?;
+
+        Ok(true)
+    }
+
+    /// Gets a new connection to the camera back to where the old one was.
+    fn restore_session(&mut self) -> anyhow::Result<()> {
+        self.init()?;
+
+        info!("reconnected to camera");
+
+        // the camera forgets that it was capturing when it loses power or the
+        // session is closed
+        if self.mode == CameraClientMode::ContinuousCapture {
+            if let Err(err) = self.start_continuous_capture() {
+                error!("failed to resume continuous capture: {:?}", err);
+            }
+        }
+
+        let _ = self.channels.camera_event.send(CameraEvent::Reconnected);
+
+        Ok(())
+    }
+
+    /// Runs a command, giving up if it takes longer than the configured
+    /// timeout. If several commands in a row time out, the camera is reset,
+    /// since the USB link is probably stuck.
+    async fn exec_with_timeout(&mut self, cmd: &CameraRequest) -> anyhow::Result<CameraResponse> {
+        let timeout = Duration::from_secs(self.config.command_timeout_secs);
+
+        match tokio::time::timeout(timeout, self.exec(cmd)).await {
+            Ok(result) => {
+                self.timeouts = 0;
+                result
+            }
+            Err(_) => {
+                self.timeouts += 1;
+
+                warn!(
+                    "camera command timed out after {:?} ({} in a row): {:?}",
+                    timeout, self.timeouts, cmd
+                );
+
+                self.clean_up_after_timeout();
+
+                if self.timeouts >= self.config.max_timeouts {
+                    warn!("too many camera commands timed out, resetting camera");
+                    self.timeouts = 0;
+
+                    if let Err(err) = self.exec(&CameraRequest::Reset).await {
+                        error!("failed to reset camera: {:?}", err);
+                    }
+                }
+
+                bail!("command timed out after {:?}", timeout)
+            }
+        }
+    }
+
+    /// Puts the camera back the way it was after a command was abandoned
+    /// partway through, e.g. with the shutter button held down or in the
+    /// middle of a burst.
+    fn clean_up_after_timeout(&mut self) {
+        // let go of the shutter button, in case it was pressed; doing this
+        // when it isn't pressed does nothing
+        for control in &[CameraControlCode::S2Button, CameraControlCode::S1Button] {
+            if let Err(err) = self.iface.execute(*control, PtpData::UINT16(0x0001)) {
+                warn!("failed to release {:?} after timeout: {:?}", control, err);
+            }
+        }
+
+        if let Some(drive_mode) = self.drive_mode_to_restore.take() {
+            if let Err(err) = self.iface.set(CameraPropertyCode::DriveMode, drive_mode) {
+                error!("failed to restore drive mode after timeout: {:?}", err);
+            }
+        }
+    }
+
     async fn exec(&mut self, cmd: &CameraRequest) -> anyhow::Result<CameraResponse> {
         match cmd {
             CameraRequest::Reset => {
                 let _ = self.iface.disconnect();
+                let _ = self.channels.camera_event.send(CameraEvent::Disconnected);
 
                 self.iface.reset().context("error while resetting camera")?;
 
                 tokio::time::sleep(Duration::from_secs(3)).await;
 
-                self.iface = CameraInterface::new().context("failed to create camera interface")?;
-                self.init()?;
+                self.iface = Self::open_interface(
+                    &mut self.connector,
+                    self.config.connect_attempts.max(1),
+                    Duration::from_millis(self.config.connect_retry_delay_ms),
+                )
+                .await?;
+                self.restore_session()
+                    .context("error while initializing camera")?;
                 self.ensure_mode(0x02).await?;
 
                 Ok(CameraResponse::Unit)
@@ -162,25 +638,9 @@ impl CameraClient {
 
             CameraRequest::Storage(cmd) => match cmd {
                 CameraStorageRequest::List => {
-                    self.ensure_mode(0x04).await?;
-
                     trace!("getting storage ids");
 
-                    let storage_ids = retry_delay(10, Duration::from_secs(1), || {
-                        trace!("checking for storage ID 0x00010000");
-
-                        let storage_ids = self
-                            .iface
-                            .storage_ids()
-                            .context("could not get storage ids")?;
-
-                        if storage_ids.contains(&StorageId::from(0x00010000)) {
-                            bail!("no logical storage available");
-                        } else {
-                            Ok(storage_ids)
-                        }
-                    })
-                    .await?;
+                    let storage_ids = self.wait_for_storage().await?;
 
                     trace!("got storage ids: {:?}", storage_ids);
 
@@ -193,33 +653,15 @@ impl CameraClient {
             },
 
             CameraRequest::File(cmd) => match cmd {
-                CameraFileRequest::List { parent } => {
-                    self.ensure_mode(0x04).await?;
+                CameraFileRequest::List { parent, format } => {
+                    self.wait_for_storage().await?;
 
                     trace!("getting object handles");
 
-                    // wait for storage ID 0x00010001 to exist
-
-                    retry_delay(10, Duration::from_secs(1), || {
-                        trace!("checking for storage ID 0x00010001");
-
-                        let storage_ids = self
-                            .iface
-                            .storage_ids()
-                            .context("could not get storage ids")?;
-
-                        if !storage_ids.contains(&StorageId::from(0x00010001)) {
-                            bail!("no storage available");
-                        } else {
-                            Ok(())
-                        }
-                    })
-                    .await?;
-
                     let object_handles = self
                         .iface
                         .object_handles(
-                            ptp::StorageId::from(0x00010001),
+                            ptp::StorageId::from(CARD_STORAGE_ID),
                             parent
                                 .clone()
                                 .map(|v| ObjectHandle::from(v))
@@ -229,11 +671,31 @@ impl CameraClient {
 
                     trace!("got object handles: {:?}", object_handles);
 
-                    object_handles
+                    let mut objects = object_handles
                         .iter()
                         .map(|&id| self.iface.object_info(id).map(|info| (id, info)))
-                        .collect::<Result<HashMap<_, _>, _>>()
-                        .map(|objects| CameraResponse::ObjectInfo { objects })
+                        .collect::<Result<HashMap<_, _>, _>>()?;
+
+                    if let Some(format) = format {
+                        objects.retain(|_, info| CameraFileFormat::of(info) == *format);
+                    }
+
+                    Ok(CameraResponse::ObjectInfo { objects })
+                }
+
+                CameraFileRequest::Formats => {
+                    let mut formats = HashMap::new();
+
+                    for handle in self.card_objects().await? {
+                        let info = self
+                            .iface
+                            .object_info(handle)
+                            .context("error while getting file info")?;
+
+                        *formats.entry(CameraFileFormat::of(&info)).or_insert(0) += 1;
+                    }
+
+                    Ok(CameraResponse::FileFormats { formats })
                 }
 
                 CameraFileRequest::Get { handle } => {
@@ -243,6 +705,54 @@ impl CameraClient {
 
                     Ok(CameraResponse::File { path: image_path })
                 }
+
+                CameraFileRequest::Delete { handle } => {
+                    self.ensure_mode(0x04).await?;
+
+                    let object_handle = ObjectHandle::from(*handle);
+
+                    // the camera's own error for a missing handle doesn't say
+                    // much, so check that it exists first
+                    self.iface
+                        .object_info(object_handle)
+                        .with_context(|| format!("no file with handle {:x}", handle))?;
+
+                    self.iface
+                        .delete_object(object_handle)
+                        .with_context(|| format!("failed to delete file {:x}", handle))?;
+
+                    Ok(CameraResponse::Unit)
+                }
+
+                CameraFileRequest::DeleteAll { confirm } => {
+                    if !confirm {
+                        bail!("refusing to delete every file without --confirm");
+                    }
+
+                    let mut deleted = 0;
+
+                    for handle in self.card_objects().await? {
+                        let info = self
+                            .iface
+                            .object_info(handle)
+                            .context("error while getting file info")?;
+
+                        // folders are left for the camera to manage
+                        if CameraFileFormat::of(&info) == CameraFileFormat::Folder {
+                            continue;
+                        }
+
+                        self.iface
+                            .delete_object(handle)
+                            .with_context(|| format!("failed to delete {}", info.filename))?;
+
+                        deleted += 1;
+                    }
+
+                    info!("deleted {} files from the memory card", deleted);
+
+                    Ok(CameraResponse::Unit)
+                }
             },
 
             CameraRequest::Power(cmd) => {
@@ -252,103 +762,363 @@ impl CameraClient {
                     CameraPowerRequest::Up => self
                         .iface
                         .execute(CameraControlCode::PowerOff, ptp::PtpData::UINT16(1))?,
-                    CameraPowerRequest::Down => self
-                        .iface
-                        .execute(CameraControlCode::PowerOff, ptp::PtpData::UINT16(2))?,
+                    CameraPowerRequest::Down => self.power_down().await?,
                 };
 
                 Ok(CameraResponse::Unit)
             }
 
             CameraRequest::Reconnect => {
+                // the session is usually broken when this is needed, so closing
+                // it is allowed to fail
+                if let Err(err) = self.iface.disconnect() {
+                    warn!("error while disconnecting from camera: {:?}", err);
+                }
+
+                let _ = self.channels.camera_event.send(CameraEvent::Disconnected);
+
                 self.iface
-                    .disconnect()
-                    .context("error while disconnecting from camera")?;
-                self.init().context("error while initializing camera")?;
+                    .connect()
+                    .context("error while connecting to camera")?;
+                self.restore_session()
+                    .context("error while initializing camera")?;
                 self.ensure_mode(0x02).await?;
 
                 Ok(CameraResponse::Unit)
             }
 
-            CameraRequest::Capture => {
-                self.ensure_mode(0x02).await?;
+            CameraRequest::Capture {
+                require_focus,
+                burst_duration: Some(duration),
+                burst_high_speed,
+            } => {
+                if *duration <= 0. || *duration > BURST_MAX_DURATION.as_secs_f32() {
+                    bail!(
+                        "burst duration must be more than 0 and at most {:?}",
+                        BURST_MAX_DURATION
+                    );
+                }
 
-                info!("capturing image");
+                self.burst(
+                    Duration::from_secs_f32(*duration),
+                    *burst_high_speed,
+                    *require_focus,
+                )
+                .await
+            }
 
-                // press shutter button halfway to fix the focus
-                self.iface
-                    .execute(CameraControlCode::S1Button, PtpData::UINT16(0x0002))?;
+            CameraRequest::Capture {
+                require_focus,
+                burst_duration: None,
+                ..
+            } => {
+                let mut report = CaptureReport::new(self.sequence.next(), Some(SystemTime::now()));
+                let result = self.capture(&mut report, *require_focus).await;
+                self.finish_report(report, result.as_ref().err());
+                result
+            }
 
-                sleep(Duration::from_millis(200)).await;
+            CameraRequest::CaptureAt { utc } => {
+                let target = SystemTime::from(*utc);
 
-                // shoot!
-                self.iface
-                    .execute(CameraControlCode::S2Button, PtpData::UINT16(0x0002))?;
+                let host_target = match self.gps_time() {
+                    Some(gps_time) => gps_time.to_host(target),
+                    None => {
+                        warn!("no GPS time available, scheduling capture with the host clock");
+                        target
+                    }
+                };
 
-                sleep(Duration::from_millis(200)).await;
+                let lead = host_target
+                    .duration_since(SystemTime::now())
+                    .map_err(|_| anyhow!("capture time is in the past"))?;
 
-                // release
-                self.iface
-                    .execute(CameraControlCode::S2Button, PtpData::UINT16(0x0001))?;
+                if lead < CAPTURE_AT_MIN_LEAD {
+                    bail!(
+                        "capture time must be at least {:?} in the future",
+                        CAPTURE_AT_MIN_LEAD
+                    );
+                }
 
-                sleep(Duration::from_millis(200)).await;
+                if lead > CAPTURE_AT_MAX_LEAD {
+                    bail!(
+                        "capture time must be at most {:?} in the future",
+                        CAPTURE_AT_MAX_LEAD
+                    );
+                }
 
-                // hell yeah
-                self.iface
-                    .execute(CameraControlCode::S1Button, PtpData::UINT16(0x0001))?;
+                let deadline = Instant::now() + lead;
 
-                info!("waiting for image confirmation");
+                let mut report = CaptureReport::new(self.sequence.next(), Some(SystemTime::now()));
+                let result = self.capture_at(deadline, &mut report).await;
+                self.finish_report(report, result.as_ref().err());
+                result
+            }
 
-                tokio::time::timeout(Duration::from_millis(3000), async {
-                    loop {
-                        trace!("checking for events");
+            CameraRequest::Download(req) => match req {
+                CameraDownloadRequest::Pause => {
+                    self.pause_downloads().await?;
+                    Ok(CameraResponse::DownloadStatus { paused: true })
+                }
+                CameraDownloadRequest::Resume => {
+                    let paths = self.resume_downloads().await?;
+                    Ok(CameraResponse::Files { paths })
+                }
+                CameraDownloadRequest::Status => Ok(CameraResponse::DownloadStatus {
+                    paused: self.download_pause.is_some(),
+                }),
+            },
 
-                        if let Ok(event) = self.iface.recv() {
-                            // 0xC204 = image taken
-                            match event.code {
-                                ptp::EventCode::Vendor(0xC204) => match event.params[0] {
-                                    Some(1) => break,
-                                    Some(2) => bail!("capture failure"),
-                                    _ => bail!("unknown capture status"),
-                                },
-                                evt => trace!("received event: {:?}", evt),
-                            }
-                        }
+            CameraRequest::Time => {
+                let prop = self
+                    .iface
+                    .update()
+                    .context("failed to query camera properties")?
+                    .get(&CameraPropertyCode::DateTime)
+                    .context("failed to query camera time")?;
+
+                if let PtpData::STR(time_str) = &prop.current {
+                    return Ok(CameraResponse::Time {
+                        time: parse_camera_time(time_str)?,
+                    });
+                }
 
-                        tokio::task::yield_now().await;
-                    }
+                bail!("invalid camera time");
+            }
 
-                    Ok(())
+            CameraRequest::Errors(req) => {
+                if let CameraErrorsRequest::Clear = req {
+                    // the camera drops conditions by itself once they are
+                    // resolved (e.g. the card is reinserted), so all we can
+                    // clear is our record of what we have already reported
+                    self.errors.clear();
+                }
+
+                self.iface
+                    .update()
+                    .context("failed to query camera properties")?;
+
+                Ok(CameraResponse::Errors {
+                    errors: self.current_errors()?,
                 })
-                .await
-                .context("timed out while waiting for image confirmation")??;
+            }
+
+            CameraRequest::Debug { property } => {
+                let state = self
+                    .iface
+                    .update()
+                    .context("failed to query camera properties")?;
+
+                let mut properties: Vec<_> = state
+                    .iter()
+                    .filter(|(code, _)| property.map_or(true, |property| property == **code))
+                    .map(|(code, prop)| describe_property(*code, prop))
+                    .collect();
+
+                if properties.is_empty() {
+                    if let Some(property) = property {
+                        bail!("camera does not report {:?}", property);
+                    }
+                }
+
+                properties.sort_by_key(|property| property.code);
+
+                Ok(CameraResponse::PropertyDump { properties })
+            }
+
+            CameraRequest::Flush => {
+                let files = self.flush().await?;
+                Ok(CameraResponse::Flushed { files })
+            }
+
+            CameraRequest::SelfTest => {
+                let results = self.self_test().await;
+                let passed = CameraSelfTestCheck::all_passed(&results);
 
-                info!("received image confirmation");
+                Ok(CameraResponse::SelfTest { passed, results })
+            }
 
-                let save_media = self
+            CameraRequest::VerifySettings { expected } => {
+                let state = self
                     .iface
-                    .get(CameraPropertyCode::SaveMedia)
-                    .context("unknown whether image is saved to host or device")?
-                    .current;
-
-                match save_media {
-                    PtpData::UINT16(save_media) => match CameraSaveMode::from_u16(save_media) {
-                        Some(save_media) => match save_media {
-                            // continue
-                            CameraSaveMode::HostDevice => {}
-                            // we're done here
-                            CameraSaveMode::MemoryCard1 => return Ok(CameraResponse::Unit),
-                        },
-                        None => bail!("invalid save media"),
-                    },
-                    _ => bail!("invalid save media"),
+                    .update()
+                    .context("failed to query camera properties")?;
+
+                let results = expected
+                    .iter()
+                    .map(|expectation| {
+                        let actual = state.get(&expectation.property).map(|prop| &prop.current);
+
+                        CameraSettingCheck {
+                            property: format!("{:?}", expectation.property),
+                            expected: expectation.value.clone(),
+                            actual: actual.map(|data| format!("{:?}", data)),
+                            matches: actual
+                                .map(|data| ptp_data_matches(data, &expectation.value))
+                                .unwrap_or(false),
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                let ok = results.iter().all(|check| check.matches);
+
+                if !ok {
+                    warn!("camera settings do not match: {:?}", results);
+                }
+
+                Ok(CameraResponse::VerifySettings { ok, results })
+            }
+
+            CameraRequest::Benchmark { handle } => {
+                let handle = match handle {
+                    Some(handle) => ObjectHandle::from(*handle),
+                    None => self.latest_card_file().await?,
+                };
+
+                let start = Instant::now();
+
+                for _ in 0..BENCHMARK_ROUND_TRIPS {
+                    self.iface
+                        .storage_ids()
+                        .context("could not get storage ids")?;
+                }
+
+                let latency = start.elapsed() / BENCHMARK_ROUND_TRIPS;
+
+                let start = Instant::now();
+
+                let data = self
+                    .iface
+                    .object_data(handle)
+                    .context("error while downloading file")?;
+
+                let transfer_time = start.elapsed();
+                let throughput = data.len() as f64 / transfer_time.as_secs_f64() / 1_000_000.;
+
+                info!(
+                    "downloaded {} bytes in {:?} ({:.2} MB/s), latency {:?}",
+                    data.len(),
+                    transfer_time,
+                    throughput,
+                    latency
+                );
+
+                Ok(CameraResponse::Benchmark {
+                    bytes: data.len(),
+                    transfer_time_ms: transfer_time.as_millis() as u64,
+                    throughput,
+                    latency_ms: latency.as_secs_f64() * 1000.,
+                })
+            }
+
+            CameraRequest::Movie(req) => {
+                let recording = match req {
+                    CameraMovieRequest::Start => true,
+                    CameraMovieRequest::Stop => false,
+                };
+
+                // the camera can't take stills while it is in movie mode
+                if self.mode == CameraClientMode::ContinuousCapture {
+                    bail!("cannot switch to movie mode during continuous capture");
+                }
+
+                self.ensure_mode(0x03).await?;
+
+                if self.movie_recording()? != recording {
+                    // the movie control toggles recording, like a button
+                    self.iface
+                        .execute(CameraControlCode::MovieRecording, PtpData::UINT16(0x0002))?;
+
+                    sleep(Duration::from_millis(200)).await;
+
+                    self.iface
+                        .execute(CameraControlCode::MovieRecording, PtpData::UINT16(0x0001))?;
+
+                    retry_delay(10, Duration::from_millis(500), || {
+                        self.iface
+                            .update()
+                            .context("failed to query camera properties")?;
+
+                        if self.movie_recording()? != recording {
+                            bail!(
+                                "camera did not {} recording",
+                                if recording { "start" } else { "stop" }
+                            );
+                        }
+
+                        Ok(())
+                    })
+                    .await?;
+                }
+
+                Ok(CameraResponse::Movie { recording })
+            }
+
+            CameraRequest::Format { confirm } => {
+                if !confirm {
+                    bail!("formatting erases the memory card; pass --confirm to do it anyway");
                 }
 
-                let shot_handle = ObjectHandle::from(0xFFFFC001);
+                self.format().await?;
+
+                Ok(CameraResponse::Unit)
+            }
+
+            CameraRequest::Battery => {
+                let props = self
+                    .iface
+                    .update()
+                    .context("failed to query camera properties")?;
+
+                let level_percent = match props.get(&CameraPropertyCode::BatteryRemain) {
+                    Some(prop) => match prop.current {
+                        // 0xFF means that the camera doesn't know
+                        PtpData::UINT8(percent) if percent <= 100 => percent,
+                        _ => bail!("camera reported an invalid battery level"),
+                    },
+                    None => bail!("camera does not report its battery level"),
+                };
+
+                let indicator = props
+                    .get(&CameraPropertyCode::BatteryLevel)
+                    .and_then(|prop| match prop.current {
+                        PtpData::INT8(level) => CameraBatteryLevel::from_i8(level),
+                        _ => None,
+                    });
+
+                Ok(CameraResponse::Battery {
+                    level_percent,
+                    indicator,
+                })
+            }
+
+            CameraRequest::RemainingShots => {
+                self.wait_for_storage().await?;
 
-                let image_path = self.download_image(shot_handle).await?;
+                let storage_id = StorageId::from(CARD_STORAGE_ID);
+
+                let info = self
+                    .iface
+                    .storage_info(storage_id)
+                    .context("could not get storage info")?;
+
+                let free_bytes = info.free_space_in_bytes;
+
+                // prefer our own measurements once we have taken some pictures,
+                // since they reflect the settings actually in use; otherwise
+                // fall back to what the camera reports (0xFFFFFFFF = unknown)
+                let estimate = match self.stats.average_size() {
+                    Some(average_size) if average_size > 0 => Some(free_bytes / average_size),
+                    _ if info.free_space_in_images != 0xFFFFFFFF => {
+                        Some(info.free_space_in_images as u64)
+                    }
+                    _ => None,
+                };
 
-                Ok(CameraResponse::File { path: image_path })
+                Ok(CameraResponse::RemainingShots {
+                    free_bytes,
+                    estimate,
+                })
             }
 
             CameraRequest::Zoom(req) => match req {
@@ -356,7 +1126,7 @@ impl CameraClient {
                     CameraZoomLevelRequest::Set { level } => {
                         self.ensure_setting(
                             CameraPropertyCode::ZoomAbsolutePosition,
-                            PtpData::UINT16(*level as u16),
+                            PtpData::UINT16(*level),
                         )
                         .await?;
 
@@ -371,15 +1141,53 @@ impl CameraClient {
                             .context("failed to query zoom level")?;
 
                         if let PtpData::UINT16(level) = prop.current {
-                            return Ok(CameraResponse::ZoomLevel {
-                                zoom_level: level as u8,
-                            });
+                            return Ok(CameraResponse::ZoomLevel { zoom_level: level });
                         }
 
                         bail!("invalid zoom level");
                     }
                 },
-                CameraZoomRequest::Mode(_req) => bail!("unimplemented"),
+                CameraZoomRequest::Mode(req) => {
+                    let mode = match req {
+                        CameraZoomModeRequest::Optical => CameraZoomMode::Optical,
+                        CameraZoomModeRequest::OpticalDigital => CameraZoomMode::OpticalDigital,
+                    };
+
+                    self.ensure_setting(
+                        CameraPropertyCode::Zoom,
+                        PtpData::UINT8(mode.to_u8().unwrap()),
+                    )
+                    .await?;
+
+                    let zoom_mode = self.read_zoom_mode()?;
+
+                    if zoom_mode != mode {
+                        bail!(
+                            "camera is in zoom mode {:?} after being set to {:?}",
+                            zoom_mode,
+                            mode
+                        );
+                    }
+
+                    if zoom_mode == CameraZoomMode::Optical {
+                        self.clamp_zoom_level().await?;
+                    }
+
+                    Ok(CameraResponse::ZoomMode { zoom_mode })
+                }
+                CameraZoomRequest::Preset { preset } => {
+                    let level = self.zoom_preset_level(*preset)?;
+
+                    // ensure_setting reads the zoom level back to make sure
+                    // that the camera actually got there
+                    self.ensure_setting(
+                        CameraPropertyCode::ZoomAbsolutePosition,
+                        PtpData::UINT16(level),
+                    )
+                    .await?;
+
+                    return Ok(CameraResponse::ZoomLevel { zoom_level: level });
+                }
             },
 
             CameraRequest::Exposure(req) => match req {
@@ -396,24 +1204,222 @@ impl CameraClient {
                         });
                     }
                     CameraExposureModeRequest::Get => {
+                        let exposure_mode = self.exposure_mode()?;
+                        Ok(CameraResponse::ExposureMode { exposure_mode })
+                    }
+                },
+                CameraExposureRequest::Compensation(req) => match req {
+                    CameraExposureCompensationRequest::Set { ev } => {
+                        self.ensure_setting(
+                            CameraPropertyCode::ExposureCompensation,
+                            PtpData::INT16(ev.to_millistops()),
+                        )
+                        .await?;
+
+                        Ok(CameraResponse::ExposureCompensation { compensation: *ev })
+                    }
+                    CameraExposureCompensationRequest::Get => {
+                        let prop = self
+                            .iface
+                            .update()
+                            .context("failed to query camera properties")?
+                            .get(&CameraPropertyCode::ExposureCompensation)
+                            .context("failed to query exposure compensation")?;
+
+                        if let PtpData::INT16(millistops) = prop.current {
+                            if let Some(compensation) =
+                                CameraExposureCompensation::from_millistops(millistops)
+                            {
+                                return Ok(CameraResponse::ExposureCompensation { compensation });
+                            }
+                        }
+
+                        bail!("invalid exposure compensation");
+                    }
+                },
+            },
+
+            CameraRequest::Focus(req) => match req {
+                CameraFocusRequest::Mode(req) => match req {
+                    CameraFocusModeRequest::Set { mode } => {
+                        // images captured while the focus is being changed by
+                        // hand would be blurry
+                        if *mode == CameraFocusMode::Manual
+                            && self.mode == CameraClientMode::ContinuousCapture
+                        {
+                            bail!("cannot switch to manual focus during continuous capture");
+                        }
+
+                        self.ensure_setting(
+                            CameraPropertyCode::FocusMode,
+                            PtpData::UINT16(mode.to_u16().unwrap()),
+                        )
+                        .await?;
+
+                        Ok(CameraResponse::FocusMode { focus_mode: *mode })
+                    }
+                    CameraFocusModeRequest::Get => {
                         let prop = self
                             .iface
                             .update()
                             .context("failed to query camera properties")?
-                            .get(&CameraPropertyCode::ExposureMode)
-                            .context("failed to query exposure mode")?;
+                            .get(&CameraPropertyCode::FocusMode)
+                            .context("failed to query focus mode")?;
 
                         if let PtpData::UINT16(mode) = prop.current {
-                            if let Some(exposure_mode) = CameraExposureMode::from_u16(mode) {
-                                return Ok(CameraResponse::ExposureMode { exposure_mode });
+                            if let Some(focus_mode) = CameraFocusMode::from_u16(mode) {
+                                return Ok(CameraResponse::FocusMode { focus_mode });
                             }
                         }
 
-                        bail!("invalid exposure mode");
+                        bail!("invalid focus mode");
                     }
                 },
             },
 
+            CameraRequest::Aperture(req) => match req {
+                CameraApertureRequest::Set { aperture } => {
+                    self.ensure_setting(
+                        CameraPropertyCode::FNumber,
+                        PtpData::UINT16(aperture.to_u16()),
+                    )
+                    .await?;
+
+                    Ok(CameraResponse::Aperture {
+                        aperture: *aperture,
+                    })
+                }
+                CameraApertureRequest::Get => {
+                    let prop = self
+                        .iface
+                        .update()
+                        .context("failed to query camera properties")?
+                        .get(&CameraPropertyCode::FNumber)
+                        .context("failed to query aperture")?;
+
+                    if let PtpData::UINT16(aperture) = prop.current {
+                        return Ok(CameraResponse::Aperture {
+                            aperture: CameraAperture::from_u16(aperture)?,
+                        });
+                    }
+
+                    bail!("invalid aperture");
+                }
+            },
+
+            CameraRequest::Shutter(req) => match req {
+                CameraShutterRequest::Set { speed } => {
+                    if *speed == CameraShutterSpeed::Bulb {
+                        let exposure_mode = self.exposure_mode()?;
+
+                        if exposure_mode != CameraExposureMode::ManualExposure {
+                            bail!(
+                                "bulb is only allowed in manual exposure mode, not {:?}",
+                                exposure_mode
+                            );
+                        }
+                    }
+
+                    self.ensure_setting(
+                        CameraPropertyCode::ShutterSpeed,
+                        PtpData::UINT32(speed.to_u32()),
+                    )
+                    .await?;
+
+                    Ok(CameraResponse::ShutterSpeed {
+                        shutter_speed: *speed,
+                    })
+                }
+                CameraShutterRequest::Get => {
+                    let prop = self
+                        .iface
+                        .update()
+                        .context("failed to query camera properties")?
+                        .get(&CameraPropertyCode::ShutterSpeed)
+                        .context("failed to query shutter speed")?;
+
+                    if let PtpData::UINT32(speed) = prop.current {
+                        return Ok(CameraResponse::ShutterSpeed {
+                            shutter_speed: CameraShutterSpeed::from_u32(speed),
+                        });
+                    }
+
+                    bail!("invalid shutter speed");
+                }
+            },
+
+            CameraRequest::Iso(req) => match req {
+                CameraIsoRequest::Set { iso } => {
+                    let exposure_mode = self.exposure_mode()?;
+
+                    if !exposure_mode.supports_manual_iso() {
+                        bail!(
+                            "cannot set iso in {:?} exposure mode; change the exposure mode first",
+                            exposure_mode
+                        );
+                    }
+
+                    self.ensure_setting(CameraPropertyCode::ISO, PtpData::UINT32(iso.to_u32()))
+                        .await?;
+
+                    Ok(CameraResponse::Iso { iso: *iso })
+                }
+                CameraIsoRequest::Get => {
+                    let prop = self
+                        .iface
+                        .update()
+                        .context("failed to query camera properties")?
+                        .get(&CameraPropertyCode::ISO)
+                        .context("failed to query iso")?;
+
+                    if let PtpData::UINT32(iso) = prop.current {
+                        return Ok(CameraResponse::Iso {
+                            iso: CameraIso::from_u32(iso),
+                        });
+                    }
+
+                    bail!("invalid iso");
+                }
+            },
+
+            CameraRequest::WhiteBalance(req) => match req {
+                CameraWhiteBalanceRequest::Set { mode } => {
+                    self.ensure_setting(
+                        CameraPropertyCode::WhiteBalance,
+                        PtpData::UINT16(mode.to_u16().unwrap()),
+                    )
+                    .await?;
+
+                    self.white_balance()
+                }
+                CameraWhiteBalanceRequest::SetTemperature { kelvin } => {
+                    if !COLOR_TEMPERATURE_RANGE.contains(kelvin) {
+                        bail!(
+                            "color temperature must be between {}K and {}K",
+                            COLOR_TEMPERATURE_RANGE.start(),
+                            COLOR_TEMPERATURE_RANGE.end()
+                        );
+                    }
+
+                    // the color temperature is ignored unless the white
+                    // balance is set to use it
+                    self.ensure_setting(
+                        CameraPropertyCode::WhiteBalance,
+                        PtpData::UINT16(CameraWhiteBalance::ColorTemperature.to_u16().unwrap()),
+                    )
+                    .await?;
+
+                    self.ensure_setting(
+                        CameraPropertyCode::ColorTemperature,
+                        PtpData::UINT16(*kelvin),
+                    )
+                    .await?;
+
+                    self.white_balance()
+                }
+                CameraWhiteBalanceRequest::Get => self.white_balance(),
+            },
+
             CameraRequest::SaveMode(req) => match req {
                 CameraSaveModeRequest::Set { mode } => {
                     self.ensure_setting(
@@ -422,50 +1428,88 @@ impl CameraClient {
                     )
                     .await?;
 
-                    return Ok(CameraResponse::SaveMode { save_mode: *mode });
+                    // read it back, so that we report what the camera is
+                    // actually doing rather than what we asked for
+                    let save_mode = self.read_save_mode()?;
+
+                    if save_mode != *mode {
+                        bail!(
+                            "camera is saving to {:?} after being set to {:?}",
+                            save_mode,
+                            mode
+                        );
+                    }
+
+                    if save_mode == CameraSaveMode::HostDevice && self.download_pause.is_some() {
+                        warn!("downloads are paused; resuming them will change the save mode back");
+                    }
+
+                    Ok(CameraResponse::SaveMode { save_mode })
+                }
+                CameraSaveModeRequest::Get => Ok(CameraResponse::SaveMode {
+                    save_mode: self.read_save_mode()?,
+                }),
+            },
+
+            CameraRequest::LiveViewResolution(req) => match req {
+                CameraLiveViewResolutionRequest::Set { resolution } => {
+                    self.ensure_setting(
+                        CameraPropertyCode::LiveViewResolution,
+                        PtpData::UINT8(resolution.to_u8().unwrap()),
+                    )
+                    .await?;
+
+                    Ok(CameraResponse::LiveViewResolution {
+                        resolution: *resolution,
+                    })
                 }
-                CameraSaveModeRequest::Get => {
+                CameraLiveViewResolutionRequest::Get => {
                     let prop = self
                         .iface
                         .update()
                         .context("failed to query camera properties")?
-                        .get(&CameraPropertyCode::SaveMedia)
-                        .context("failed to query save media")?;
+                        .get(&CameraPropertyCode::LiveViewResolution)
+                        .context("failed to query live view resolution")?;
 
-                    if let PtpData::UINT16(mode) = prop.current {
-                        if let Some(save_mode) = CameraSaveMode::from_u16(mode) {
-                            return Ok(CameraResponse::SaveMode { save_mode });
+                    if let PtpData::UINT8(resolution) = prop.current {
+                        if let Some(resolution) = CameraLiveViewResolution::from_u8(resolution) {
+                            return Ok(CameraResponse::LiveViewResolution { resolution });
                         }
                     }
 
-                    bail!("invalid save media");
+                    bail!("invalid live view resolution");
                 }
             },
 
             CameraRequest::ContinuousCapture(req) => match req {
+                // starting and stopping are the same button on the camera, so
+                // don't press it if we are already in the right state
                 CameraContinuousCaptureRequest::Start => {
-                    self.iface
-                        .execute(
-                            CameraControlCode::IntervalStillRecording,
-                            PtpData::UINT16(0x0002),
-                        )
-                        .context("failed to start interval recording")?;
-                    self.mode = CameraClientMode::ContinuousCapture;
+                    if self.mode == CameraClientMode::ContinuousCapture {
+                        debug!("continuous capture is already running");
+                    } else {
+                        self.start_continuous_capture()?;
+                    }
 
-                    Ok(CameraResponse::Unit)
+                    self.paused_for_link = false;
+
+                    Ok(CameraResponse::ContinuousCapture { active: true })
                 }
                 CameraContinuousCaptureRequest::Stop => {
-                    self.iface
-                        .execute(
-                            CameraControlCode::IntervalStillRecording,
-                            PtpData::UINT16(0x0001),
-                        )
-                        .context("failed to stop interval recording")?;
+                    if self.mode == CameraClientMode::ContinuousCapture {
+                        self.stop_continuous_capture()?;
+                    } else {
+                        debug!("continuous capture is not running");
+                    }
 
-                    self.mode = CameraClientMode::Idle;
+                    // an explicit stop overrides a pending resume
+                    self.paused_for_link = false;
 
-                    Ok(CameraResponse::Unit)
+                    Ok(CameraResponse::ContinuousCapture { active: false })
                 }
+                CameraContinuousCaptureRequest::Status => Ok(CameraResponse::ContinuousCapture {
+                    active: self.mode == CameraClientMode::ContinuousCapture,
+                }),
                 CameraContinuousCaptureRequest::Interval { interval } => {
                     let interval = (interval * 10.) as u16;
 
@@ -494,152 +1538,3301 @@ impl CameraClient {
         }
     }
 
-    /// Checks if the camera registers a new error. Will return a given error
-    /// only once, and then returns Ok until the error changes.
-    fn check_error(&mut self) -> Result<(), CameraErrorMode> {
-        let caution_prop = self.iface.get(CameraPropertyCode::Caution);
-
-        if let Some(caution_prop) = caution_prop {
-            if let PtpData::UINT16(caution_value) = caution_prop.current {
-                if caution_value != 0x0000 {
-                    match CameraErrorMode::from_u16(caution_value) {
-                        Some(caution_mode) => {
-                            let already_reported = if let Some(current_caution_mode) = self.error {
-                                current_caution_mode == caution_mode
-                            } else {
-                                false
-                            };
-
-                            if !already_reported {
-                                self.error = Some(caution_mode);
-                                return Err(caution_mode);
-                            }
-                        }
-                        None => {
-                            warn!(
-                                "encountered unknown camera error status: 0x{:04x}",
-                                caution_value
-                            );
-                        }
-                    }
-                }
+    fn exposure_mode(&mut self) -> anyhow::Result<CameraExposureMode> {
+        let prop = self
+            .iface
+            .update()
+            .context("failed to query camera properties")?
+            .get(&CameraPropertyCode::ExposureMode)
+            .context("failed to query exposure mode")?;
+
+        if let PtpData::UINT16(mode) = prop.current {
+            if let Some(exposure_mode) = CameraExposureMode::from_u16(mode) {
+                return Ok(exposure_mode);
             }
         }
 
-        self.error = None;
-
-        Ok(())
+        bail!("invalid exposure mode");
     }
 
-    async fn ensure_mode(&mut self, mode: u8) -> anyhow::Result<()> {
-        retry_delay(10, Duration::from_millis(1000), || {
-            trace!("checking operating mode");
-
-            let current_state = self
-                .iface
-                .update()
-                .context("could not get current camera state")?;
+    /// Works out the zoom level for a preset from the range of zoom levels
+    /// that the camera supports.
+    fn zoom_preset_level(&mut self, preset: CameraZoomPreset) -> anyhow::Result<u16> {
+        let (min, max, _) = self.zoom_range()?;
 
-            let current_op_mode = current_state.get(&CameraPropertyCode::OperatingMode);
+        let level = min as f32 + (max - min) as f32 * preset.fraction();
 
-            trace!("current op mode: {:?}", current_op_mode);
+        Ok(level.round() as u16)
+    }
 
-            if let Some(PtpData::UINT8(current_op_mode)) = current_op_mode.map(|d| &d.current) {
-                if *current_op_mode == mode {
-                    // we are in the right mode, break
-                    return Ok(());
-                }
-            }
+    /// Returns the camera's zoom range and current zoom level as (min, max,
+    /// level). The range only covers what the current zoom mode allows.
+    fn zoom_range(&mut self) -> anyhow::Result<(u16, u16, u16)> {
+        let prop = self
+            .iface
+            .update()
+            .context("failed to query camera properties")?
+            .get(&CameraPropertyCode::ZoomAbsolutePosition)
+            .context("failed to query zoom level")?;
+
+        match (&prop.form, &prop.current) {
+            (
+                ptp::PtpFormData::Range {
+                    min_value: PtpData::UINT16(min),
+                    max_value: PtpData::UINT16(max),
+                    ..
+                },
+                PtpData::UINT16(level),
+            ) => Ok((*min, *max, *level)),
+            _ => bail!("camera does not report its zoom range"),
+        }
+    }
 
-            debug!("setting operating mode to 0x{:04x}", mode);
+    /// Zooms out to the end of the optical zoom range if the lens is past it.
+    /// The camera only reports the optical range once it is in optical zoom
+    /// mode, so this has to be called after switching modes.
+    async fn clamp_zoom_level(&mut self) -> anyhow::Result<()> {
+        let (_, max, level) = self.zoom_range()?;
+
+        if level > max {
+            info!(
+                "zooming out from {} to the optical maximum of {}",
+                level, max
+            );
+
+            self.ensure_setting(
+                CameraPropertyCode::ZoomAbsolutePosition,
+                PtpData::UINT16(max),
+            )
+            .await?;
+        }
 
-            self.iface
-                .set(CameraPropertyCode::OperatingMode, PtpData::UINT8(mode))
-                .context("failed to set operating mode of camera")?;
+        Ok(())
+    }
 
-            bail!("wrong operating mode")
-        })
-        .await
+    /// Asks the camera whether it is allowed to zoom digitally.
+    fn read_zoom_mode(&mut self) -> anyhow::Result<CameraZoomMode> {
+        let prop = self
+            .iface
+            .update()
+            .context("failed to query camera properties")?
+            .get(&CameraPropertyCode::Zoom)
+            .context("failed to query zoom mode")?;
+
+        match prop.current {
+            PtpData::UINT8(mode) => CameraZoomMode::from_u8(mode).context("invalid zoom mode"),
+            _ => bail!("invalid zoom mode"),
+        }
     }
 
-    async fn ensure_setting(
+    fn movie_recording(&self) -> anyhow::Result<bool> {
+        match self
+            .iface
+            .get(CameraPropertyCode::MovieRecording)
+            .map(|prop| prop.current)
+        {
+            Some(PtpData::UINT8(state)) => Ok(state != 0),
+            _ => bail!("camera does not report whether it is recording"),
+        }
+    }
+
+    /// Formats the memory card and waits for the camera to finish.
+    async fn format(&mut self) -> anyhow::Result<()> {
+        warn!("formatting camera memory card");
+
+        // the format control works like a button, so press it and let go
+        self.iface
+            .execute(CameraControlCode::MediaFormat, PtpData::UINT16(0x0002))
+            .context("failed to start formatting")?;
+
+        self.iface
+            .execute(CameraControlCode::MediaFormat, PtpData::UINT16(0x0001))
+            .context("failed to start formatting")?;
+
+        tokio::time::timeout(FORMAT_TIMEOUT, async {
+            // give the camera a moment to start before checking whether it
+            // is done
+            sleep(Duration::from_millis(500)).await;
+
+            loop {
+                let state = self
+                    .iface
+                    .update()
+                    .context("failed to query camera properties")?
+                    .get(&CameraPropertyCode::MediaFormatState)
+                    .map(|prop| prop.current.clone())
+                    .context("camera does not report whether it is formatting")?;
+
+                trace!("media format state: {:?}", state);
+
+                match state {
+                    // formatting in progress
+                    PtpData::UINT8(0x01) => {}
+                    // formatting completed
+                    PtpData::UINT8(0x02) => break,
+                    other => bail!("camera reported an unknown format state: {:?}", other),
+                }
+
+                sleep(Duration::from_millis(500)).await;
+            }
+
+            Result::<(), anyhow::Error>::Ok(())
+        })
+        .await
+        .map_err(|_| anyhow!("formatting did not finish within {:?}", FORMAT_TIMEOUT))??;
+
+        let errors = self.current_errors()?;
+
+        if !errors.is_empty() {
+            bail!("camera reported errors while formatting: {:?}", errors);
+        }
+
+        info!("formatted camera memory card");
+
+        Ok(())
+    }
+
+    /// Reads the current white balance, and the color temperature if the
+    /// white balance is using it.
+    fn white_balance(&mut self) -> anyhow::Result<CameraResponse> {
+        let props = self
+            .iface
+            .update()
+            .context("failed to query camera properties")?;
+
+        let white_balance = match props
+            .get(&CameraPropertyCode::WhiteBalance)
+            .context("failed to query white balance")?
+            .current
+        {
+            PtpData::UINT16(mode) => CameraWhiteBalance::from_u16(mode),
+            _ => None,
+        }
+        .context("invalid white balance")?;
+
+        let color_temperature = if white_balance == CameraWhiteBalance::ColorTemperature {
+            match props
+                .get(&CameraPropertyCode::ColorTemperature)
+                .context("failed to query color temperature")?
+                .current
+            {
+                PtpData::UINT16(kelvin) => Some(kelvin),
+                _ => bail!("invalid color temperature"),
+            }
+        } else {
+            None
+        };
+
+        Ok(CameraResponse::WhiteBalance {
+            white_balance,
+            color_temperature,
+        })
+    }
+
+    /// Takes a picture, and downloads it if the camera is saving images to
+    /// the host.
+    async fn capture(
         &mut self,
-        setting: CameraPropertyCode,
-        value: PtpData,
-    ) -> anyhow::Result<()> {
-        let current_setting = self.iface.get(setting);
+        report: &mut CaptureReport,
+        require_focus: bool,
+    ) -> anyhow::Result<CameraResponse> {
+        self.half_press().await?;
+
+        if require_focus {
+            if let Err(err) = self.wait_for_focus().await {
+                // let go of the shutter button so that the camera isn't stuck
+                // half-pressed
+                self.iface
+                    .execute(CameraControlCode::S1Button, PtpData::UINT16(0x0001))?;
+                return Err(err);
+            }
+        }
 
-        trace!("current {:?}: {:?}", setting, current_setting);
+        self.shoot(report).await
+    }
 
-        if let Some(current_setting) = current_setting {
-            if current_setting.current == value {
-                // we are in the right mode, break
-                return Ok(());
+    /// Captures images for `duration` with the camera in a continuous drive
+    /// mode. The previous drive mode is put back afterwards, even if the
+    /// burst fails partway through.
+    async fn burst(
+        &mut self,
+        duration: Duration,
+        high_speed: bool,
+        require_focus: bool,
+    ) -> anyhow::Result<CameraResponse> {
+        let previous_drive_mode = self
+            .iface
+            .get(CameraPropertyCode::DriveMode)
+            .context("camera does not report its drive mode")?
+            .current;
+
+        let drive_mode = if high_speed {
+            CameraDriveMode::SpeedPriorityContinuousShot
+        } else {
+            CameraDriveMode::ContinuousShot
+        };
+
+        // stop downloading images early rather than time out, so that there
+        // is time to put the drive mode back
+        let deadline = Instant::now()
+            + Duration::from_secs(self.config.command_timeout_secs)
+                .checked_sub(BURST_RESTORE_MARGIN)
+                .unwrap_or_default();
+
+        self.drive_mode_to_restore = Some(previous_drive_mode.clone());
+
+        self.ensure_setting(
+            CameraPropertyCode::DriveMode,
+            PtpData::UINT16(drive_mode.to_u16().unwrap()),
+        )
+        .await
+        .context("failed to switch to continuous drive mode")?;
+
+        let result = self.burst_shoot(duration, require_focus, deadline).await;
+
+        self.drive_mode_to_restore = None;
+
+        if let Err(err) = self
+            .ensure_setting(CameraPropertyCode::DriveMode, previous_drive_mode)
+            .await
+        {
+            // don't hide the images that were captured, or the reason that
+            // the burst failed
+            error!("failed to restore drive mode: {:?}", err);
+        }
+
+        result
+    }
+
+    /// Holds the shutter down for `duration`, then downloads every image
+    /// that the camera took if it is saving images to the host. Images that
+    /// the camera hasn't handed over by `deadline` are not downloaded.
+    async fn burst_shoot(
+        &mut self,
+        duration: Duration,
+        require_focus: bool,
+        deadline: Instant,
+    ) -> anyhow::Result<CameraResponse> {
+        let command_time = SystemTime::now();
+
+        self.half_press().await?;
+
+        if require_focus {
+            if let Err(err) = self.wait_for_focus().await {
+                self.iface
+                    .execute(CameraControlCode::S1Button, PtpData::UINT16(0x0001))?;
+                return Err(err);
             }
+        }
 
-            if current_setting.is_enable != 1 || current_setting.get_set != 1 {
-                bail!("changing this property is not supported");
+        info!("capturing burst for {:?}", duration);
+
+        self.iface
+            .execute(CameraControlCode::S2Button, PtpData::UINT16(0x0002))?;
+
+        sleep(duration).await;
+
+        self.iface
+            .execute(CameraControlCode::S2Button, PtpData::UINT16(0x0001))?;
+
+        sleep(Duration::from_millis(200)).await;
+
+        self.iface
+            .execute(CameraControlCode::S1Button, PtpData::UINT16(0x0001))?;
+
+        let save_media = self
+            .iface
+            .get(CameraPropertyCode::SaveMedia)
+            .context("unknown whether image is saved to host or device")?
+            .current;
+
+        match save_media {
+            PtpData::UINT16(save_media) => match CameraSaveMode::from_u16(save_media) {
+                Some(CameraSaveMode::HostDevice) => {}
+                Some(CameraSaveMode::MemoryCard1) => return Ok(CameraResponse::Unit),
+                None => bail!("invalid save media"),
+            },
+            _ => bail!("invalid save media"),
+        }
+
+        let shot_handle = ObjectHandle::from(0xFFFFC001);
+        let mut paths = Vec::new();
+
+        // the camera announces each image separately, and hands them over
+        // one at a time
+        loop {
+            let frame_deadline = (Instant::now() + BURST_FRAME_TIMEOUT).min(deadline);
+            let confirmation =
+                tokio::time::timeout_at(tokio::time::Instant::from_std(frame_deadline), async {
+                    loop {
+                        if let Ok(event) = self.iface.recv() {
+                            match event.code {
+                                ptp::EventCode::Vendor(0xC204) => match event.params[0] {
+                                    Some(1) => break,
+                                    Some(2) => bail!("capture failure"),
+                                    _ => bail!("unknown capture status"),
+                                },
+                                evt => trace!("received event: {:?}", evt),
+                            }
+                        }
+
+                        tokio::task::yield_now().await;
+                    }
+
+                    Ok(())
+                })
+                .await;
+
+            match confirmation {
+                Ok(result) => result?,
+                Err(_) if frame_deadline == deadline => {
+                    warn!("stopping burst before the command times out; some images were not downloaded");
+                    break;
+                }
+                // no more images
+                Err(_) => break,
             }
+
+            let mut report = CaptureReport::new(self.sequence.next(), Some(command_time));
+            report.confirm_time = Some(SystemTime::now());
+            self.geotag(&mut report);
+
+            let result = self.download_capture(shot_handle, &mut report).await;
+            self.finish_report(report, result.as_ref().err());
+            paths.push(result?);
         }
 
-        retry_delay(10, Duration::from_millis(1000), || {
-            debug!("setting {:?} to {:?}", setting, value);
+        if paths.is_empty() {
+            bail!("camera did not confirm any images from the burst");
+        }
 
-            self.iface
-                .set(setting, value.clone())
-                .context(format!("failed to set {:?}", setting))?;
+        info!("captured {} images in burst", paths.len());
 
-            trace!("checking setting {:?}", setting);
+        Ok(CameraResponse::Files { paths })
+    }
 
-            let current_state = self
-                .iface
-                .update()
-                .context("could not get current camera state")?;
+    /// Captures an image at `deadline`. The camera is readied beforehand, so
+    /// that only the shutter release is left when the deadline arrives.
+    async fn capture_at(
+        &mut self,
+        deadline: Instant,
+        report: &mut CaptureReport,
+    ) -> anyhow::Result<CameraResponse> {
+        self.half_press().await?;
+
+        if Instant::now() > deadline {
+            warn!(
+                "camera was ready {:?} after the requested capture time",
+                Instant::now() - deadline
+            );
+        }
 
-            let current_setting = current_state.get(&setting);
+        tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).await;
 
-            trace!("current {:?}: {:?}", setting, current_setting);
+        let fire_time = SystemTime::now();
+        let response = self.shoot(report).await?;
 
-            if let Some(current_setting) = current_setting {
-                if current_setting.current == value {
-                    // we are in the right mode, break
-                    return Ok(());
+        let latency = report
+            .confirm_time
+            .and_then(|confirm_time| confirm_time.duration_since(fire_time).ok())
+            .unwrap_or_default();
+
+        let (fire_time, time_source) = match self.gps_time() {
+            Some(gps_time) => (gps_time.convert(fire_time), TimeSource::Gps),
+            None => (fire_time, TimeSource::Host),
+        };
+
+        let path = match response {
+            CameraResponse::File { path } => Some(path),
+            _ => None,
+        };
+
+        Ok(CameraResponse::TimedCapture {
+            fire_time,
+            latency_ms: latency.as_millis() as u64,
+            time_source,
+            path,
+        })
+    }
+
+    /// Switches to still image mode and presses the shutter button halfway to
+    /// fix the focus.
+    async fn half_press(&mut self) -> anyhow::Result<()> {
+        self.ensure_mode(0x02).await?;
+
+        info!("capturing image");
+
+        // press shutter button halfway to fix the focus
+        self.iface
+            .execute(CameraControlCode::S1Button, PtpData::UINT16(0x0002))?;
+
+        sleep(Duration::from_millis(200)).await;
+
+        Ok(())
+    }
+
+    /// Waits for the autofocus to lock after `half_press`. Does nothing in
+    /// manual focus mode, where the camera never reports a lock.
+    async fn wait_for_focus(&mut self) -> anyhow::Result<()> {
+        let focus_mode = match self.iface.get(CameraPropertyCode::FocusMode) {
+            Some(prop) => match prop.current {
+                PtpData::UINT16(mode) => CameraFocusMode::from_u16(mode),
+                _ => None,
+            },
+            None => None,
+        };
+
+        if focus_mode == Some(CameraFocusMode::Manual) {
+            return Ok(());
+        }
+
+        let timeout = Duration::from_millis(self.config.focus_timeout_ms);
+
+        debug!("waiting for focus");
+
+        tokio::time::timeout(timeout, async {
+            loop {
+                let indication = self
+                    .iface
+                    .update()
+                    .context("failed to query camera properties")?
+                    .get(&CameraPropertyCode::FocusIndication)
+                    .and_then(|prop| match prop.current {
+                        PtpData::UINT8(indication) => CameraFocusIndication::from_u8(indication),
+                        _ => None,
+                    });
+
+                trace!("focus indication: {:?}", indication);
+
+                match indication {
+                    Some(CameraFocusIndication::AFLock)
+                    | Some(CameraFocusIndication::FocusedContinuous) => break,
+                    Some(CameraFocusIndication::AFWarning) => {
+                        bail!("autofocus failed (AF warning); not capturing")
+                    }
+                    _ => {}
                 }
+
+                sleep(Duration::from_millis(50)).await;
             }
 
-            bail!("failed to set {:?}", setting);
+            Ok(())
         })
         .await
+        .map_err(|_| anyhow!("autofocus did not lock within {:?}", timeout))?
     }
 
-    async fn download_image(&mut self, handle: ObjectHandle) -> anyhow::Result<PathBuf> {
-        let shot_info = self
+    /// Takes the image after `half_press` and downloads it if it is being
+    /// saved to the host.
+    async fn shoot(&mut self, report: &mut CaptureReport) -> anyhow::Result<CameraResponse> {
+        // shoot!
+        self.iface
+            .execute(CameraControlCode::S2Button, PtpData::UINT16(0x0002))?;
+
+        sleep(Duration::from_millis(200)).await;
+
+        // release
+        self.iface
+            .execute(CameraControlCode::S2Button, PtpData::UINT16(0x0001))?;
+
+        sleep(Duration::from_millis(200)).await;
+
+        // hell yeah
+        self.iface
+            .execute(CameraControlCode::S1Button, PtpData::UINT16(0x0001))?;
+
+        info!("waiting for image confirmation");
+
+        tokio::time::timeout(Duration::from_millis(3000), async {
+            loop {
+                trace!("checking for events");
+
+                if let Ok(event) = self.iface.recv() {
+                    // 0xC204 = image taken
+                    match event.code {
+                        ptp::EventCode::Vendor(0xC204) => match event.params[0] {
+                            Some(1) => break,
+                            Some(2) => bail!("capture failure"),
+                            _ => bail!("unknown capture status"),
+                        },
+                        evt => trace!("received event: {:?}", evt),
+                    }
+                }
+
+                tokio::task::yield_now().await;
+            }
+
+            Ok(())
+        })
+        .await
+        .context("timed out while waiting for image confirmation")??;
+
+        info!("received image confirmation");
+
+        report.confirm_time = Some(SystemTime::now());
+        self.geotag(report);
+
+        let save_media = self
             .iface
-            .object_info(handle)
-            .context("error while getting image info")?;
+            .get(CameraPropertyCode::SaveMedia)
+            .context("unknown whether image is saved to host or device")?
+            .current;
+
+        match save_media {
+            PtpData::UINT16(save_media) => match CameraSaveMode::from_u16(save_media) {
+                Some(save_media) => match save_media {
+                    // continue
+                    CameraSaveMode::HostDevice => {}
+                    // we're done here
+                    CameraSaveMode::MemoryCard1 => return Ok(CameraResponse::Unit),
+                },
+                None => bail!("invalid save media"),
+            },
+            _ => bail!("invalid save media"),
+        }
 
-        let shot_data = self
+        let shot_handle = ObjectHandle::from(0xFFFFC001);
+
+        let image_path = self.download_capture(shot_handle, report).await?;
+
+        Ok(CameraResponse::File { path: image_path })
+    }
+
+    /// Logs a finished capture report and publishes it to the rest of the
+    /// system.
+    fn finish_report(&self, mut report: CaptureReport, error: Option<&anyhow::Error>) {
+        if let Some(error) = error {
+            report.errors.push(format!("{:?}", error));
+        }
+
+        if let Some(gps_time) = self.gps_time() {
+            report.convert_to_gps_time(&gps_time);
+        }
+
+        if report.errors.is_empty() {
+            debug!("capture report: {:?}", report);
+        } else {
+            warn!("capture report: {:?}", report);
+        }
+
+        let _ = self
+            .channels
+            .camera_event
+            .send(CameraEvent::CaptureReport(report));
+    }
+
+    fn gps_time(&self) -> Option<GpsTime> {
+        self.channels
+            .telemetry
+            .borrow()
+            .as_ref()
+            .and_then(|telemetry| telemetry.gps_time)
+    }
+
+    /// Downloads the image that the camera just took during continuous
+    /// capture, if it is being saved to the host.
+    async fn handle_capture_event(&mut self) -> anyhow::Result<()> {
+        let save_media = self
             .iface
-            .object_data(handle)
-            .context("error while getting image data")?;
+            .get(CameraPropertyCode::SaveMedia)
+            .context("unknown whether image is saved to host or device")?
+            .current;
+
+        match save_media {
+            PtpData::UINT16(save_media) => {
+                match CameraSaveMode::from_u16(save_media) {
+                    Some(save_media) => match save_media {
+                        CameraSaveMode::HostDevice => {
+                            let shot_handle = ObjectHandle::from(0xFFFFC001);
+
+                            let mut report = CaptureReport::new(self.sequence.next(), None);
+                            report.confirm_time = Some(SystemTime::now());
+                            self.geotag(&mut report);
+
+                            let result = self.download_capture(shot_handle, &mut report).await;
+                            self.finish_report(report, result.as_ref().err());
+                            let image_path = result?;
+
+                            info!("saved continuous capture image to {:?}", image_path);
+                        }
 
-        let mut image_path = std::env::current_dir().context("failed to get current directory")?;
+                        CameraSaveMode::MemoryCard1 if self.download_pause.is_some() => debug!("downloads are paused, leaving image on memory card"),
+                        CameraSaveMode::MemoryCard1 => warn!("continuous capture images are being saved to camera; this is not supported"),
+                    },
+                    None => bail!("invalid save media"),
+                }
+            }
+            _ => bail!("invalid save media"),
+        }
 
-        image_path.push(shot_info.filename);
+        Ok(())
+    }
 
-        debug!("writing image to file '{}'", image_path.to_string_lossy());
+    /// Stops continuous capture and waits for the camera to hand over any
+    /// image that it has already started taking, so that shutting down does
+    /// not lose a shot. Gives up after `deadline`.
+    async fn finish_captures(&mut self, deadline: Duration) -> anyhow::Result<()> {
+        if self.mode != CameraClientMode::ContinuousCapture {
+            return Ok(());
+        }
 
-        let mut image_file = tokio::fs::File::create(&image_path)
-            .await
-            .context("failed to create file")?;
+        info!("stopping continuous capture before shutting down");
+        self.stop_continuous_capture()?;
 
-        image_file
-            .write_all(&shot_data[..])
-            .await
-            .context("failed to save image")?;
+        let deadline = Instant::now() + deadline;
 
-        info!("wrote image to file '{}'", image_path.to_string_lossy());
+        while Instant::now() < deadline {
+            // recv() times out after a second without events, which means the
+            // camera has nothing left for us
+            match self.iface.recv() {
+                Ok(event) => match event.code {
+                    ptp::EventCode::Vendor(0xC204) => {
+                        debug!("received image while shutting down");
+                        self.handle_capture_event().await?;
+                    }
+                    _ => {}
+                },
+                Err(_) => return Ok(()),
+            }
+        }
 
-        Ok(image_path)
+        warn!("camera did not finish capturing before the shutdown deadline");
+
+        Ok(())
+    }
+
+    fn start_continuous_capture(&mut self) -> anyhow::Result<()> {
+        self.iface
+            .execute(
+                CameraControlCode::IntervalStillRecording,
+                PtpData::UINT16(0x0002),
+            )
+            .context("failed to start interval recording")?;
+
+        self.mode = CameraClientMode::ContinuousCapture;
+
+        Ok(())
+    }
+
+    fn stop_continuous_capture(&mut self) -> anyhow::Result<()> {
+        self.iface
+            .execute(
+                CameraControlCode::IntervalStillRecording,
+                PtpData::UINT16(0x0001),
+            )
+            .context("failed to stop interval recording")?;
+
+        self.mode = CameraClientMode::Idle;
+
+        Ok(())
+    }
+
+    /// Asks the camera where it is saving images.
+    fn read_save_mode(&mut self) -> anyhow::Result<CameraSaveMode> {
+        let prop = self
+            .iface
+            .update()
+            .context("failed to query camera properties")?
+            .get(&CameraPropertyCode::SaveMedia)
+            .context("failed to query save media")?;
+
+        match prop.current {
+            PtpData::UINT16(mode) => CameraSaveMode::from_u16(mode).context("invalid save media"),
+            _ => bail!("invalid save media"),
+        }
+    }
+
+    /// Stops downloading images from the camera. New images are saved to the
+    /// memory card instead, and we remember what was already on the card so
+    /// that we can download the new ones later.
+    async fn pause_downloads(&mut self) -> anyhow::Result<()> {
+        if self.download_pause.is_some() {
+            debug!("downloads are already paused");
+            return Ok(());
+        }
+
+        let save_mode = match self
+            .iface
+            .get(CameraPropertyCode::SaveMedia)
+            .map(|p| p.current)
+        {
+            Some(PtpData::UINT16(save_mode)) => {
+                CameraSaveMode::from_u16(save_mode).context("invalid save media")?
+            }
+            _ => bail!("unknown whether image is saved to host or device"),
+        };
+
+        // changing operating modes interrupts continuous capture, so stop it
+        // while we do that and then start it again
+        let capturing = self.mode == CameraClientMode::ContinuousCapture;
+
+        if capturing {
+            self.stop_continuous_capture()?;
+        }
+
+        let existing = self
+            .card_objects()
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        self.ensure_mode(0x02).await?;
+        self.ensure_setting(
+            CameraPropertyCode::SaveMedia,
+            PtpData::UINT16(CameraSaveMode::MemoryCard1.to_u16().unwrap()),
+        )
+        .await
+        .context("failed to save images to memory card")?;
+
+        if capturing {
+            self.start_continuous_capture()?;
+        }
+
+        info!(
+            "paused downloads, {} objects already on memory card",
+            existing.len()
+        );
+
+        self.download_pause = Some(DownloadPause {
+            existing,
+            save_mode,
+        });
+
+        Ok(())
+    }
+
+    /// Starts downloading images from the camera again, and downloads all of
+    /// the images that were captured while downloads were paused. If that
+    /// fails, downloads stay paused, and the images that were not downloaded
+    /// yet are downloaded when they are resumed again.
+    async fn resume_downloads(&mut self) -> anyhow::Result<Vec<PathBuf>> {
+        let save_mode = match &self.download_pause {
+            Some(pause) => pause.save_mode,
+            None => {
+                debug!("downloads are not paused");
+                return Ok(Vec::new());
+            }
+        };
+
+        let capturing = self.mode == CameraClientMode::ContinuousCapture;
+
+        if capturing {
+            self.stop_continuous_capture()?;
+        }
+
+        let paths = match self.download_paused_objects(save_mode).await {
+            Ok(paths) => paths,
+            Err(err) => {
+                // keep saving to the memory card, since downloads are still
+                // paused
+                if let Err(err) = self
+                    .ensure_setting(
+                        CameraPropertyCode::SaveMedia,
+                        PtpData::UINT16(CameraSaveMode::MemoryCard1.to_u16().unwrap()),
+                    )
+                    .await
+                {
+                    warn!("failed to save images to memory card again: {:?}", err);
+                }
+
+                return Err(err.context("failed to resume downloads; they are still paused"));
+            }
+        };
+
+        self.download_pause = None;
+
+        self.ensure_mode(0x02).await?;
+
+        if capturing {
+            self.start_continuous_capture()?;
+        }
+
+        Ok(paths)
+    }
+
+    /// Restores the save mode from before downloads were paused, and
+    /// downloads the images that were captured since. Each image is marked as
+    /// downloaded in the pause as soon as it is saved.
+    async fn download_paused_objects(
+        &mut self,
+        save_mode: CameraSaveMode,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        self.ensure_mode(0x02).await?;
+        self.ensure_setting(
+            CameraPropertyCode::SaveMedia,
+            PtpData::UINT16(save_mode.to_u16().unwrap()),
+        )
+        .await
+        .context("failed to restore save media")?;
+
+        let captured = match &self.download_pause {
+            Some(pause) => self
+                .card_objects()
+                .await?
+                .into_iter()
+                .filter(|handle| !pause.existing.contains(handle))
+                .collect::<Vec<_>>(),
+            None => Vec::new(),
+        };
+
+        info!(
+            "resuming downloads, {} objects captured while paused",
+            captured.len()
+        );
+
+        let mut paths = Vec::new();
+
+        for handle in captured {
+            let info = self
+                .iface
+                .object_info(handle)
+                .context("error while getting object info")?;
+
+            let is_folder = matches!(
+                info.object_format,
+                ptp::ObjectFormatCode::Standard(ptp::StandardObjectFormatCode::Association)
+            );
+
+            // check before fetching the data, which is the slow part
+            if is_folder {
+                trace!("not downloading folder {}", info.filename);
+            } else if !should_download(self.config.download_extensions.as_deref(), &info.filename) {
+                debug!("not downloading {}, filtered by extension", info.filename);
+            } else {
+                let (info, data) = self.fetch_object(handle)?;
+                // these were captured while downloads were paused, so we don't
+                // know where
+                let path = self.save_object(&info.filename, &data, None).await?;
+                self.stats.record(data.len() as u64);
+                paths.push(path);
+            }
+
+            if let Some(pause) = &mut self.download_pause {
+                pause.existing.insert(handle);
+            }
+        }
+
+        Ok(paths)
+    }
+
+    /// Finds the most recent file (as opposed to a folder) on the memory card.
+    async fn latest_card_file(&mut self) -> anyhow::Result<ObjectHandle> {
+        for handle in self.card_objects().await?.into_iter().rev() {
+            let info = self
+                .iface
+                .object_info(handle)
+                .context("error while getting file info")?;
+
+            if info.object_compressed_size > 0 {
+                return Ok(handle);
+            }
+        }
+
+        bail!("no files on the memory card")
+    }
+
+    /// Syncs every file saved since the last flush to disk, along with the
+    /// directories that they are in and the capture sequence file, so that a
+    /// power cut can't lose them. Returns how many images were synced.
+    async fn flush(&mut self) -> anyhow::Result<usize> {
+        let mut dirs = HashSet::new();
+        let sequence_path = self.sequence.path().to_owned();
+
+        for path in self.unsynced.iter().chain(std::iter::once(&sequence_path)) {
+            // the sequence file doesn't exist until the first capture
+            let file = match tokio::fs::File::open(path).await {
+                Ok(file) => file,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err).with_context(|| format!("failed to open {:?}", path)),
+            };
+
+            file.sync_all()
+                .await
+                .with_context(|| format!("failed to sync {:?}", path))?;
+
+            // a bare file name has an empty parent
+            match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dirs.insert(dir.to_owned()),
+                _ => dirs.insert(PathBuf::from(".")),
+            };
+        }
+
+        // a new file isn't safe until the directory entry pointing to it is
+        for dir in dirs {
+            tokio::fs::File::open(&dir)
+                .await
+                .with_context(|| format!("failed to open {:?}", dir))?
+                .sync_all()
+                .await
+                .with_context(|| format!("failed to sync {:?}", dir))?;
+        }
+
+        let files = self.unsynced.len();
+        self.unsynced.clear();
+
+        info!("synced {} images to disk", files);
+
+        Ok(files)
+    }
+
+    /// Checks that the camera is ready to fly. Every check runs even if an
+    /// earlier one fails.
+    async fn self_test(&mut self) -> Vec<CameraSelfTestCheck> {
+        let mut results = Vec::new();
+
+        let connected = self
+            .iface
+            .update()
+            .map(|_| ())
+            .context("camera is not responding");
+        results.push(CameraSelfTestCheck::new("connected", connected));
+
+        let mode = self.ensure_mode(0x02).await;
+        results.push(CameraSelfTestCheck::new("operating mode", mode));
+
+        let battery = self.check_battery();
+        results.push(CameraSelfTestCheck::new("battery", battery));
+
+        let storage = self.card_objects().await.map(|_| ());
+        results.push(CameraSelfTestCheck::new("storage", storage));
+
+        let test_frame = self.test_frame().await;
+        results.push(CameraSelfTestCheck::new("test frame", test_frame));
+
+        results
+    }
+
+    fn check_battery(&mut self) -> anyhow::Result<()> {
+        let min_percent = self.config.min_battery_percent;
+
+        let props = self
+            .iface
+            .update()
+            .context("failed to query camera properties")?;
+
+        match props
+            .get(&CameraPropertyCode::BatteryRemain)
+            .map(|prop| &prop.current)
+        {
+            Some(PtpData::UINT8(percent)) if *percent <= 100 => {
+                if *percent < min_percent {
+                    bail!("battery is at {}%, below {}%", percent, min_percent);
+                }
+
+                Ok(())
+            }
+            _ => bail!("camera did not report its battery level"),
+        }
+    }
+
+    /// Takes a picture on the memory card and deletes it again. If the
+    /// camera is saving to the host, it saves to the memory card for the test
+    /// picture only.
+    async fn test_frame(&mut self) -> anyhow::Result<()> {
+        let save_mode = self.read_save_mode()?;
+
+        if save_mode == CameraSaveMode::MemoryCard1 {
+            return self.card_test_frame().await;
+        }
+
+        // the save mode can only be changed in still image mode
+        self.ensure_mode(0x02).await?;
+        self.ensure_setting(
+            CameraPropertyCode::SaveMedia,
+            PtpData::UINT16(CameraSaveMode::MemoryCard1.to_u16().unwrap()),
+        )
+        .await
+        .context("failed to save the test picture to the memory card")?;
+
+        let result = self.card_test_frame().await;
+
+        let restored = async {
+            self.ensure_mode(0x02).await?;
+            self.ensure_setting(
+                CameraPropertyCode::SaveMedia,
+                PtpData::UINT16(save_mode.to_u16().unwrap()),
+            )
+            .await
+        }
+        .await
+        .context("failed to restore save media after the test picture");
+
+        // a failed test picture is more interesting than the save mode
+        result.and(restored)
+    }
+
+    /// Takes a picture while the camera is saving to the memory card, and
+    /// deletes it again.
+    async fn card_test_frame(&mut self) -> anyhow::Result<()> {
+        let existing = self
+            .card_objects()
+            .await?
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        // this isn't a real capture, so it doesn't use up a sequence number
+        let mut report = CaptureReport::new(0, Some(SystemTime::now()));
+        self.capture(&mut report, false).await?;
+
+        // the camera takes a moment to write the picture to the card
+        let mut captured = Vec::new();
+
+        for _ in 0..10 {
+            captured = self
+                .card_objects()
+                .await?
+                .into_iter()
+                .filter(|handle| !existing.contains(handle))
+                .collect();
+
+            if !captured.is_empty() {
+                break;
+            }
+
+            sleep(Duration::from_millis(500)).await;
+        }
+
+        if captured.is_empty() {
+            bail!("test picture did not show up on the memory card");
+        }
+
+        // RAW+JPEG leaves two files
+        for handle in captured {
+            self.iface
+                .delete_object(handle)
+                .context("failed to delete test picture")?;
+        }
+
+        self.ensure_mode(0x02).await
+    }
+
+    /// Lists every object on the camera's memory card. Leaves the camera in
+    /// contents transfer mode.
+    async fn card_objects(&mut self) -> anyhow::Result<Vec<ObjectHandle>> {
+        self.wait_for_storage().await?;
+
+        // no parent means every object in the storage, not just the root
+        self.iface
+            .object_handles(StorageId::from(CARD_STORAGE_ID), None)
+            .context("could not get object handles")
+    }
+
+    /// Switches to contents transfer mode and waits for the memory card to
+    /// show up. Returns the camera's storage IDs, without the placeholder that
+    /// it reports while the card isn't ready.
+    async fn wait_for_storage(&mut self) -> anyhow::Result<Vec<StorageId>> {
+        self.ensure_mode(0x04).await?;
+
+        let storage_ids = retry_delay(10, Duration::from_secs(1), || {
+            trace!("checking for storage ID {:#010x}", CARD_STORAGE_ID);
+
+            let storage_ids = self
+                .iface
+                .storage_ids()
+                .context("could not get storage ids")?;
+
+            if !storage_ids.contains(&StorageId::from(CARD_STORAGE_ID)) {
+                bail!("no storage available");
+            } else {
+                Ok(storage_ids)
+            }
+        })
+        .await?;
+
+        Ok(storage_ids
+            .into_iter()
+            .filter(|&id| id != StorageId::from(PLACEHOLDER_STORAGE_ID))
+            .collect())
+    }
+
+    /// Remembers CAMERA_FEEDBACK messages for geotagging, and starts a new
+    /// flight segment if the plane has been armed since the last check.
+    fn check_pixhawk_events(&mut self) {
+        loop {
+            match self.pixhawk_recv.try_recv() {
+                Ok(PixhawkEvent::Armed { armed }) => {
+                    if !self.config.segment_by_arming {
+                        continue;
+                    }
+
+                    // if we were restarted while the plane was armed, keep
+                    // saving to the segment from before the restart
+                    if armed && self.armed == Some(false) {
+                        let segment = self.sequence.next_segment();
+                        info!("plane armed, saving files to segment {:02}", segment);
+                    }
+
+                    self.armed = Some(armed);
+                }
+                Ok(PixhawkEvent::Image {
+                    time,
+                    coords,
+                    attitude,
+                    ..
+                }) => {
+                    if self.feedback.len() >= FEEDBACK_HISTORY {
+                        self.feedback.pop_front();
+                    }
+
+                    self.feedback.push_back(CaptureFeedback {
+                        time,
+                        coords,
+                        attitude,
+                    });
+                }
+                Ok(_) => {}
+                Err(broadcast::error::TryRecvError::Lagged(_)) => {}
+                Err(_) => break,
+            }
+        }
+    }
+
+    /// Fills in where the plane was when the image in `report` was taken,
+    /// using the CAMERA_FEEDBACK message closest to when the camera confirmed
+    /// the capture. Falls back to the latest telemetry if no feedback is close
+    /// enough, e.g. because the camera's hot shoe isn't wired to the
+    /// autopilot.
+    fn geotag(&mut self, report: &mut CaptureReport) {
+        self.check_pixhawk_events();
+
+        // feedback times come from the autopilot's clock
+        let confirm_time = report.confirm_time.unwrap_or_else(SystemTime::now);
+        let confirm_time = match self.gps_time() {
+            Some(gps_time) => gps_time.convert(confirm_time),
+            None => confirm_time,
+        };
+
+        let tolerance = Duration::from_millis(self.config.feedback_tolerance_ms);
+
+        match take_nearest_feedback(&mut self.feedback, confirm_time, tolerance) {
+            Some(feedback) => {
+                report.coords = Some(feedback.coords);
+                report.attitude = Some(feedback.attitude);
+                report.geotag_source = GeotagSource::Feedback;
+            }
+            None => {
+                debug!("no camera feedback near capture, geotagging with latest telemetry");
+
+                let telemetry = *self.channels.telemetry.borrow();
+                report.coords = telemetry.map(|telemetry| telemetry.position);
+                report.attitude = telemetry.map(|telemetry| telemetry.plane_attitude);
+                report.geotag_source = GeotagSource::Telemetry;
+            }
+        }
+    }
+
+    /// Checks how long it has been since we heard from the ground, and applies
+    /// the configured loss-of-link policy when the link is lost or regained.
+    fn check_link(&mut self) -> anyhow::Result<()> {
+        // without telemetry we have no idea whether the ground is reachable
+        let telemetry = match *self.channels.telemetry.borrow() {
+            Some(telemetry) => telemetry,
+            None => return Ok(()),
+        };
+
+        let config = self.config.loss_of_link;
+        let last_contact = telemetry.last_ground_contact.unwrap_or(self.started_at);
+        let link_lost = last_contact
+            .elapsed()
+            .map(|elapsed| elapsed > Duration::from_secs(config.timeout_secs))
+            .unwrap_or(false);
+
+        if link_lost == self.link_lost {
+            return Ok(());
+        }
+
+        self.link_lost = link_lost;
+
+        if link_lost {
+            warn!(
+                "lost contact with the ground (no contact for {}s), applying policy {:?}",
+                config.timeout_secs, config.policy
+            );
+
+            if self.mode != CameraClientMode::ContinuousCapture {
+                info!("continuous capture is not running, nothing to do");
+                return Ok(());
+            }
+
+            match config.policy {
+                LossOfLinkPolicy::Continue => info!("continuing continuous capture"),
+                LossOfLinkPolicy::Pause => {
+                    self.stop_continuous_capture()?;
+                    self.paused_for_link = true;
+                    warn!("paused continuous capture until contact is regained");
+                }
+                LossOfLinkPolicy::Stop => {
+                    self.stop_continuous_capture()?;
+                    warn!("stopped continuous capture");
+                }
+            }
+        } else {
+            info!("regained contact with the ground");
+
+            if self.paused_for_link {
+                self.paused_for_link = false;
+                self.start_continuous_capture()?;
+                info!("resumed continuous capture");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads every error that the camera is currently reporting.
+    fn current_errors(&self) -> anyhow::Result<Vec<CameraErrorMode>> {
+        match self
+            .iface
+            .get(CameraPropertyCode::Caution)
+            .map(|p| p.current)
+        {
+            Some(PtpData::UINT16(caution_value)) => Ok(CameraErrorMode::from_bits(caution_value)),
+            _ => bail!("camera does not report errors"),
+        }
+    }
+
+    /// Returns the errors that the camera has started reporting since the last
+    /// check. Several errors can be set at once; each one is returned only
+    /// once, until it is cleared and comes back.
+    fn check_errors(&mut self) -> Vec<CameraErrorMode> {
+        let current = match self.current_errors() {
+            Ok(current) => current,
+            Err(_) => return Vec::new(),
+        };
+
+        let new = current
+            .iter()
+            .filter(|err| !self.errors.contains(err))
+            .copied()
+            .collect();
+
+        self.errors = current;
+
+        new
+    }
+
+    /// Switches the camera to the given operating mode and waits until it
+    /// reports being in that mode, asking again now and then in case it
+    /// missed the request. Fails if the camera doesn't switch within
+    /// `mode_timeout_ms`.
+    async fn ensure_mode(&mut self, mode: u8) -> anyhow::Result<()> {
+        let timeout = Duration::from_millis(self.config.mode_timeout_ms);
+        let deadline = Instant::now() + timeout;
+        let mut last_request: Option<Instant> = None;
+
+        loop {
+            trace!("checking operating mode");
+
+            let current_state = self
+                .iface
+                .update()
+                .context("could not get current camera state")?;
+
+            let current_op_mode = match current_state
+                .get(&CameraPropertyCode::OperatingMode)
+                .map(|d| &d.current)
+            {
+                Some(PtpData::UINT8(current_op_mode)) => Some(*current_op_mode),
+                _ => None,
+            };
+
+            trace!("current op mode: {:?}", current_op_mode);
+
+            if current_op_mode == Some(mode) {
+                return Ok(());
+            }
+
+            if Instant::now() >= deadline {
+                bail!(
+                    "camera did not switch to operating mode 0x{:02x} within {:?} (it is in {:?})",
+                    mode,
+                    timeout,
+                    current_op_mode
+                );
+            }
+
+            let request_due = last_request
+                .map(|last_request| last_request.elapsed() >= MODE_REQUEST_INTERVAL)
+                .unwrap_or(true);
+
+            if request_due {
+                debug!("setting operating mode to 0x{:02x}", mode);
+
+                self.iface
+                    .set(CameraPropertyCode::OperatingMode, PtpData::UINT8(mode))
+                    .context("failed to set operating mode of camera")?;
+
+                last_request = Some(Instant::now());
+            }
+
+            sleep(MODE_POLL_INTERVAL).await;
+        }
+    }
+
+    async fn ensure_setting(
+        &mut self,
+        setting: CameraPropertyCode,
+        value: PtpData,
+    ) -> anyhow::Result<()> {
+        let current_setting = self.iface.get(setting);
+
+        trace!("current {:?}: {:?}", setting, current_setting);
+
+        if let Some(current_setting) = current_setting {
+            if current_setting.current == value {
+                // we are in the right mode, break
+                return Ok(());
+            }
+
+            if current_setting.is_enable != 1 || current_setting.get_set != 1 {
+                bail!("changing this property is not supported");
+            }
+        }
+
+        retry_delay(10, Duration::from_millis(1000), || {
+            debug!("setting {:?} to {:?}", setting, value);
+
+            self.iface
+                .set(setting, value.clone())
+                .context(format!("failed to set {:?}", setting))?;
+
+            trace!("checking setting {:?}", setting);
+
+            let current_state = self
+                .iface
+                .update()
+                .context("could not get current camera state")?;
+
+            let current_setting = current_state.get(&setting);
+
+            trace!("current {:?}: {:?}", setting, current_setting);
+
+            if let Some(current_setting) = current_setting {
+                if current_setting.current == value {
+                    // we are in the right mode, break
+                    return Ok(());
+                }
+            }
+
+            bail!("failed to set {:?}", setting);
+        })
+        .await
+    }
+
+    /// Downloads a freshly captured image, recording when each stage finished
+    /// in the capture report.
+    async fn download_capture(
+        &mut self,
+        handle: ObjectHandle,
+        report: &mut CaptureReport,
+    ) -> anyhow::Result<PathBuf> {
+        let (shot_info, shot_data) = self.fetch_object(handle)?;
+        report.download_time = Some(SystemTime::now());
+
+        let image_path = self
+            .save_object(&shot_info.filename, &shot_data, report.coords)
+            .await?;
+        report.save_time = Some(SystemTime::now());
+        report.filename = Some(image_path.clone());
+
+        // record the size of the image so that the remaining shot estimate
+        // gets more accurate over the course of the flight
+        self.stats.record(shot_data.len() as u64);
+
+        if let Some(format) = self.config.convert {
+            if CameraFileFormat::of(&shot_info) == CameraFileFormat::Jpeg {
+                // decoding and re-encoding takes a while, so don't hold up the
+                // next download
+                let image_path = image_path.clone();
+
+                tokio::task::spawn_blocking(move || match convert_image(&image_path, format) {
+                    Ok(converted_path) => debug!("converted image to {:?}", converted_path),
+                    Err(err) => warn!("failed to convert {:?}: {:?}", image_path, err),
+                });
+            }
+        }
+
+        Ok(image_path)
+    }
+
+    async fn download_image(&mut self, handle: ObjectHandle) -> anyhow::Result<PathBuf> {
+        let (shot_info, shot_data) = self.fetch_object_with_progress(handle)?;
+        self.save_object(&shot_info.filename, &shot_data, None)
+            .await
+    }
+
+    /// Transfers an object and information about it from the camera.
+    fn fetch_object(
+        &mut self,
+        handle: ObjectHandle,
+    ) -> anyhow::Result<(ptp::PtpObjectInfo, Vec<u8>)> {
+        let shot_info = self
+            .iface
+            .object_info(handle)
+            .context("error while getting image info")?;
+
+        let shot_data = self
+            .iface
+            .object_data(handle)
+            .context("error while getting image data")?;
+
+        Ok((shot_info, shot_data))
+    }
+
+    /// Like `fetch_object`, but large objects are transferred in chunks, and a
+    /// progress event is broadcast as each chunk arrives.
+    fn fetch_object_with_progress(
+        &mut self,
+        handle: ObjectHandle,
+    ) -> anyhow::Result<(ptp::PtpObjectInfo, Vec<u8>)> {
+        let info = self
+            .iface
+            .object_info(handle)
+            .context("error while getting image info")?;
+
+        let total = info.object_compressed_size;
+
+        if total < DOWNLOAD_PROGRESS_THRESHOLD {
+            let data = self
+                .iface
+                .object_data(handle)
+                .context("error while getting image data")?;
+
+            return Ok((info, data));
+        }
+
+        let mut data = Vec::with_capacity(total as usize);
+        let mut last_progress: Option<Instant> = None;
+
+        while (data.len() as u32) < total {
+            let chunk = self
+                .iface
+                .object_data_partial(handle, data.len() as u32, DOWNLOAD_CHUNK_SIZE)
+                .context("error while getting image data")?;
+
+            if chunk.is_empty() {
+                bail!(
+                    "camera stopped sending data after {} of {} bytes",
+                    data.len(),
+                    total
+                );
+            }
+
+            data.extend_from_slice(&chunk);
+
+            // always report the end of the download, so that listeners know
+            // it is done
+            let done = data.len() as u32 >= total;
+            let due =
+                last_progress.map_or(true, |last| last.elapsed() >= DOWNLOAD_PROGRESS_INTERVAL);
+
+            if done || due {
+                let _ = self
+                    .channels
+                    .camera_event
+                    .send(CameraEvent::DownloadProgress {
+                        handle,
+                        bytes_done: data.len() as u64,
+                        bytes_total: total as u64,
+                    });
+
+                last_progress = Some(Instant::now());
+            }
+        }
+
+        Ok((info, data))
+    }
+
+    /// Writes an object downloaded from the camera to disk. `coords` is where
+    /// the object was captured, if we know, for use in the filename template.
+    async fn save_object(
+        &mut self,
+        filename: &str,
+        data: &[u8],
+        coords: Option<Coords3D>,
+    ) -> anyhow::Result<PathBuf> {
+        let mut image_path = self.save_dir.clone();
+
+        if self.config.segment_by_arming {
+            image_path.push(format!("segment_{:02}", self.sequence.segment()));
+
+            tokio::fs::create_dir_all(&image_path)
+                .await
+                .context("failed to create segment directory")?;
+        }
+
+        let filename = match &self.config.filename_template {
+            Some(template) => {
+                let index = self.sequence.next_file();
+                expand_filename_template(template, filename, coords, index)
+            }
+            None => filename.to_string(),
+        };
+
+        image_path.push(filename);
+
+        debug!("writing image to file '{}'", image_path.to_string_lossy());
+
+        let (mut image_file, image_path) = create_new_file(&image_path).await?;
+
+        image_file
+            .write_all(data)
+            .await
+            .context("failed to save image")?;
+
+        // tokio writes to the file in the background, so a failed write only
+        // shows up when the file is flushed
+        image_file.flush().await.context("failed to save image")?;
+
+        if self.config.sync_on_save {
+            image_file
+                .sync_all()
+                .await
+                .context("failed to sync image to disk")?;
+        }
+
+        // the directory entry still has to be synced by the next flush
+        self.unsynced.push(image_path.clone());
+
+        info!("wrote image to file '{}'", image_path.to_string_lossy());
+
+        Ok(image_path)
+    }
+}
+
+/// Creates a file at `path`, or at `path` with `_1`, `_2`, ... added to the
+/// file name if that file already exists, so that an existing image is never
+/// overwritten. Returns the new file and its path.
+async fn create_new_file(path: &Path) -> anyhow::Result<(tokio::fs::File, PathBuf)> {
+    for attempt in 0..=MAX_NAME_COLLISIONS {
+        let candidate = match attempt {
+            0 => path.to_owned(),
+            _ => numbered_path(path, attempt),
+        };
+
+        let result = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&candidate)
+            .await;
+
+        match result {
+            Ok(file) => return Ok((file, candidate)),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => {
+                debug!("{:?} already exists", candidate);
+            }
+            Err(err) => {
+                return Err(err).with_context(|| format!("failed to create {:?}", candidate))
+            }
+        }
+    }
+
+    bail!(
+        "failed to create {:?}: {} files with that name already exist",
+        path,
+        MAX_NAME_COLLISIONS + 1
+    )
+}
+
+/// Adds `_n` to the end of a file name, before the extension.
+fn numbered_path(path: &Path, n: u32) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+
+    let name = match path.extension() {
+        Some(extension) => format!("{}_{}.{}", stem, n, extension.to_string_lossy()),
+        None => format!("{}_{}", stem, n),
+    };
+
+    path.with_file_name(name)
+}
+
+/// Removes and returns the feedback closest to `time`, if there is one
+/// within `tolerance` of it. Each feedback message is for one image, so it is
+/// only used once.
+fn take_nearest_feedback(
+    feedback: &mut VecDeque<CaptureFeedback>,
+    time: SystemTime,
+    tolerance: Duration,
+) -> Option<CaptureFeedback> {
+    let (i, diff) = feedback
+        .iter()
+        .enumerate()
+        .map(|(i, feedback)| {
+            let diff = match feedback.time.duration_since(time) {
+                Ok(diff) => diff,
+                Err(err) => err.duration(),
+            };
+
+            (i, diff)
+        })
+        .filter(|(_, diff)| *diff <= tolerance)
+        .min_by_key(|(_, diff)| *diff)?;
+
+    trace!("geotagging capture with feedback from {:?} away", diff);
+
+    feedback.remove(i)
+}
+
+/// Checks a filename against the configured download extensions, which may
+/// or may not start with a dot. Every file is downloaded if no extensions are
+/// configured.
+fn should_download(extensions: Option<&[String]>, filename: &str) -> bool {
+    let extensions = match extensions {
+        Some(extensions) if !extensions.is_empty() => extensions,
+        _ => return true,
+    };
+
+    let extension = match Path::new(filename.trim_end_matches('\0')).extension() {
+        Some(extension) => extension.to_string_lossy(),
+        None => return false,
+    };
+
+    extensions.iter().any(|wanted| {
+        wanted
+            .trim_start_matches('.')
+            .eq_ignore_ascii_case(&extension)
+    })
+}
+
+/// Builds a filename from a template by replacing `{timestamp}`, `{lat}`,
+/// `{lon}`, `{alt}`, `{index}` and `{original}`. The position tokens become
+/// `nan` if the position is unknown. The original extension is added if the
+/// template does not have one, and anything that can't go in a filename is
+/// replaced with an underscore.
+fn expand_filename_template(
+    template: &str,
+    original: &str,
+    coords: Option<Coords3D>,
+    index: u64,
+) -> String {
+    let original = original.trim_end_matches('\0');
+
+    let (lat, lon, alt) = match coords {
+        Some(coords) => (
+            format!("{:.6}", coords.latitude),
+            format!("{:.6}", coords.longitude),
+            format!("{:.1}", coords.altitude),
+        ),
+        None => ("nan".to_string(), "nan".to_string(), "nan".to_string()),
+    };
+
+    let timestamp = chrono::Utc::now().format("%Y%m%dT%H%M%S%.3fZ").to_string();
+
+    let mut filename = template
+        .replace("{timestamp}", &timestamp)
+        .replace("{lat}", &lat)
+        .replace("{lon}", &lon)
+        .replace("{alt}", &alt)
+        .replace("{index}", &index.to_string())
+        .replace("{original}", original)
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect::<String>();
+
+    if filename.trim_matches('.').is_empty() {
+        return original.to_string();
+    }
+
+    // check the template rather than the filename, since coordinates have
+    // dots in them
+    let has_extension =
+        template.contains("{original}") || Path::new(template).extension().is_some();
+
+    if !has_extension {
+        if let Some(extension) = Path::new(original).extension() {
+            filename.push('.');
+            filename.push_str(&extension.to_string_lossy());
+        }
+    }
+
+    filename
+}
+
+/// Parses the date and time reported by the camera, which is in the same
+/// format that we use to set it in `CameraClient::init`. If the camera leaves
+/// out the UTC offset, the time is assumed to be local.
+fn parse_camera_time(time_str: &str) -> anyhow::Result<SystemTime> {
+    let time_str = time_str.trim_end_matches('\0');
+
+    if let Ok(time) = chrono::DateTime::parse_from_str(time_str, "%Y%m%dT%H%M%S%.f%:z") {
+        return Ok(time.into());
+    }
+
+    let time = chrono::NaiveDateTime::parse_from_str(time_str, "%Y%m%dT%H%M%S%.f")
+        .with_context(|| format!("invalid camera time '{}'", time_str))?;
+
+    let time = chrono::TimeZone::from_local_datetime(&chrono::Local, &time)
+        .single()
+        .with_context(|| format!("ambiguous camera time '{}'", time_str))?;
+
+    Ok(time.into())
+}
+
+/// Saves a copy of an image in another format, next to the original.
+fn convert_image(path: &Path, format: ImageConvertFormat) -> anyhow::Result<PathBuf> {
+    let (extension, image_format) = match format {
+        ImageConvertFormat::Png => ("png", image::ImageFormat::Png),
+        ImageConvertFormat::Tiff => ("tiff", image::ImageFormat::Tiff),
+    };
+
+    let converted_path = path.with_extension(extension);
+
+    image::open(path)
+        .context("failed to decode image")?
+        .save_with_format(&converted_path, image_format)
+        .context("failed to encode image")?;
+
+    Ok(converted_path)
+}
+
+/// Describes a property and its allowed values for `camera debug`.
+fn describe_property(code: CameraPropertyCode, prop: &ptp::PtpPropInfo) -> CameraPropertyDump {
+    let allowed = match &prop.form {
+        ptp::PtpFormData::None => Vec::new(),
+        ptp::PtpFormData::Range {
+            min_value,
+            max_value,
+            step,
+        } => vec![format!(
+            "{}..={} (step {})",
+            describe_value(code, min_value),
+            describe_value(code, max_value),
+            describe_ptp_data(step)
+        )],
+        ptp::PtpFormData::Enumeration { array } => array
+            .iter()
+            .map(|value| describe_value(code, value))
+            .collect(),
+    };
+
+    CameraPropertyDump {
+        code: code.to_u16().unwrap(),
+        name: format!("{:?}", code),
+        writable: prop.get_set == 1 && prop.is_enable == 1,
+        current: describe_value(code, &prop.current),
+        allowed,
+    }
+}
+
+/// Formats the value of a property, using the name of the value if we know
+/// what it means.
+fn describe_value(code: CameraPropertyCode, data: &PtpData) -> String {
+    let name = match (code, data) {
+        (CameraPropertyCode::ExposureMode, PtpData::UINT16(v)) => {
+            CameraExposureMode::from_u16(*v).map(|v| format!("{:?}", v))
+        }
+        (CameraPropertyCode::DriveMode, PtpData::UINT16(v)) => {
+            CameraDriveMode::from_u16(*v).map(|v| format!("{:?}", v))
+        }
+        (CameraPropertyCode::FocusMode, PtpData::UINT16(v)) => {
+            CameraFocusMode::from_u16(*v).map(|v| format!("{:?}", v))
+        }
+        (CameraPropertyCode::FocusIndication, PtpData::UINT8(v)) => {
+            CameraFocusIndication::from_u8(*v).map(|v| format!("{:?}", v))
+        }
+        (CameraPropertyCode::WhiteBalance, PtpData::UINT16(v)) => {
+            CameraWhiteBalance::from_u16(*v).map(|v| format!("{:?}", v))
+        }
+        (CameraPropertyCode::SaveMedia, PtpData::UINT16(v)) => {
+            CameraSaveMode::from_u16(*v).map(|v| format!("{:?}", v))
+        }
+        (CameraPropertyCode::Compression, PtpData::UINT8(v)) => {
+            CameraCompressionMode::from_u8(*v).map(|v| format!("{:?}", v))
+        }
+        (CameraPropertyCode::LiveViewResolution, PtpData::UINT8(v)) => {
+            CameraLiveViewResolution::from_u8(*v).map(|v| format!("{:?}", v))
+        }
+        (CameraPropertyCode::BatteryLevel, PtpData::INT8(v)) => {
+            CameraBatteryLevel::from_i8(*v).map(|v| format!("{:?}", v))
+        }
+        (CameraPropertyCode::Caution, PtpData::UINT16(v)) => {
+            Some(format!("{:?}", CameraErrorMode::from_bits(*v)))
+        }
+        (CameraPropertyCode::ISO, PtpData::UINT32(v)) => Some(CameraIso::from_u32(*v).to_string()),
+        (CameraPropertyCode::ShutterSpeed, PtpData::UINT32(v)) => {
+            Some(CameraShutterSpeed::from_u32(*v).to_string())
+        }
+        (CameraPropertyCode::ExposureCompensation, PtpData::INT16(v)) => {
+            CameraExposureCompensation::from_millistops(*v).map(|v| v.to_string())
+        }
+        (CameraPropertyCode::FNumber, PtpData::UINT16(v)) => {
+            CameraAperture::from_u16(*v).ok().map(|v| v.to_string())
+        }
+        _ => None,
+    };
+
+    match name {
+        Some(name) => name,
+        None => describe_ptp_data(data),
+    }
+}
+
+fn describe_ptp_data(data: &PtpData) -> String {
+    match data {
+        PtpData::INT8(v) => v.to_string(),
+        PtpData::UINT8(v) => format!("0x{:02x}", v),
+        PtpData::INT16(v) => v.to_string(),
+        PtpData::UINT16(v) => format!("0x{:04x}", v),
+        PtpData::INT32(v) => v.to_string(),
+        PtpData::UINT32(v) => format!("0x{:08x}", v),
+        PtpData::INT64(v) => v.to_string(),
+        PtpData::UINT64(v) => format!("0x{:016x}", v),
+        PtpData::STR(v) => v.trim_end_matches('\0').to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// Whether an error means that the camera was unplugged or lost power.
+fn is_disconnect(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<ptp::Error>(),
+            Some(ptp::Error::Usb(rusb::Error::NoDevice))
+        ) || matches!(
+            cause.downcast_ref::<rusb::Error>(),
+            Some(rusb::Error::NoDevice)
+        )
+    })
+}
+
+/// Checks whether a property value matches a value typed in by the user.
+fn ptp_data_matches(data: &PtpData, expected: &str) -> bool {
+    let number = match data {
+        PtpData::INT8(v) => *v as i128,
+        PtpData::UINT8(v) => *v as i128,
+        PtpData::INT16(v) => *v as i128,
+        PtpData::UINT16(v) => *v as i128,
+        PtpData::INT32(v) => *v as i128,
+        PtpData::UINT32(v) => *v as i128,
+        PtpData::INT64(v) => *v as i128,
+        PtpData::UINT64(v) => *v as i128,
+        PtpData::STR(v) => return v.trim_end_matches('\0') == expected,
+        _ => return false,
+    };
+
+    let expected = match expected
+        .strip_prefix("0x")
+        .or_else(|| expected.strip_prefix("0X"))
+    {
+        Some(hex) => i128::from_str_radix(hex, 16),
+        None => expected.parse::<i128>(),
+    };
+
+    expected.map(|expected| expected == number).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex,
+    };
+
+    use super::super::mock::*;
+    use super::*;
+    use crate::{state::TelemetryInfo, TestChannels};
+
+    /// A camera config for the tests, with the capture sequence kept in a
+    /// temporary directory instead of the working directory.
+    fn test_config(name: &str, mut config: serde_json::Value) -> CameraConfig {
+        let sequence_file = std::env::temp_dir().join(format!(
+            "plane-system-{}-{}-sequence.json",
+            std::process::id(),
+            name
+        ));
+
+        config["sequence_file"] = serde_json::json!(sequence_file);
+        serde_json::from_value(config).unwrap()
+    }
+
+    /// Connects to the fake camera with the given state.
+    fn mock_connector(camera: &Arc<Mutex<MockState>>) -> CameraConnector {
+        let camera = camera.clone();
+        Box::new(move || -> anyhow::Result<Box<dyn CameraDriver>> {
+            Ok(Box::new(MockCamera::new(camera.clone())))
+        })
+    }
+
+    /// A camera client connected to a fake camera.
+    fn mock_client(name: &str, config: serde_json::Value) -> (CameraClient, Arc<Mutex<MockState>>) {
+        mock_client_with(TestChannels::new().channels, name, config)
+    }
+
+    /// A camera client connected to a fake camera, using the given channels.
+    fn mock_client_with(
+        channels: Arc<Channels>,
+        name: &str,
+        config: serde_json::Value,
+    ) -> (CameraClient, Arc<Mutex<MockState>>) {
+        let camera = MockState::shared();
+        let mut iface = MockCamera::new(camera.clone());
+        iface.connect().unwrap();
+
+        let (_, cmd) = mpsc::channel(1);
+        let mut client = CameraClient::with_interface(
+            channels,
+            cmd,
+            test_config(name, config),
+            mock_connector(&camera),
+            Box::new(iface),
+        )
+        .unwrap();
+
+        client.init().unwrap();
+        camera.lock().unwrap().calls.clear();
+
+        (client, camera)
+    }
+
+    /// Fails to connect `failures` times, then connects to the fake camera.
+    /// Counts every attempt in `attempts`.
+    fn flaky_connector(
+        camera: &Arc<Mutex<MockState>>,
+        failures: u32,
+        attempts: Arc<AtomicU32>,
+    ) -> CameraConnector {
+        let mut connector = mock_connector(camera);
+        Box::new(move || {
+            if attempts.fetch_add(1, Ordering::SeqCst) < failures {
+                bail!("could not open Sony R10C usb device");
+            }
+
+            connector()
+        })
+    }
+
+    #[test]
+    fn download_extensions_ignore_case_and_leading_dot() {
+        let extensions = vec!["jpg".to_string(), ".ARW".to_string()];
+        let extensions = Some(&extensions[..]);
+
+        assert!(should_download(extensions, "DSC00001.JPG\0"));
+        assert!(should_download(extensions, "DSC00001.jpg"));
+        assert!(should_download(extensions, "DSC00001.arw"));
+        assert!(!should_download(extensions, "DSC00001.MP4"));
+        assert!(!should_download(extensions, "DSC00001"));
+    }
+
+    #[test]
+    fn everything_is_downloaded_without_download_extensions() {
+        assert!(should_download(None, "DSC00001.MP4"));
+        assert!(should_download(Some(&[]), "DSC00001.MP4"));
+    }
+
+    #[test]
+    fn expands_position_tokens() {
+        let coords = Coords3D::new(42.5, -76.25, 100.0);
+
+        assert_eq!(
+            expand_filename_template("{lat}_{lon}_{alt}", "DSC00001.JPG\0", Some(coords), 7),
+            "42.500000_-76.250000_100.0.JPG"
+        );
+    }
+
+    #[test]
+    fn unknown_position_expands_to_nan() {
+        assert_eq!(
+            expand_filename_template("{lat}_{lon}_{alt}_{index}", "DSC00001.JPG", None, 7),
+            "nan_nan_nan_7.JPG"
+        );
+    }
+
+    #[test]
+    fn replaces_characters_that_cannot_go_in_a_filename() {
+        assert_eq!(
+            expand_filename_template("a/b\\c:d*e?{index}", "DSC00001.ARW", None, 1),
+            "a_b_c_d_e_1.ARW"
+        );
+    }
+
+    #[test]
+    fn keeps_the_extension_from_the_template_or_the_original() {
+        assert_eq!(
+            expand_filename_template("{index}_{original}", "DSC00001.JPG", None, 2),
+            "2_DSC00001.JPG"
+        );
+        assert_eq!(
+            expand_filename_template("img_{index}.jpeg", "DSC00001.JPG", None, 2),
+            "img_2.jpeg"
+        );
+    }
+
+    #[test]
+    fn empty_filename_falls_back_to_the_original() {
+        assert_eq!(
+            expand_filename_template("..", "DSC00001.JPG", None, 0),
+            "DSC00001.JPG"
+        );
+    }
+
+    #[test]
+    fn expands_timestamp() {
+        let filename = expand_filename_template("{timestamp}", "DSC00001.JPG", None, 0);
+
+        // e.g. 20201105T173000.000Z.JPG
+        assert_eq!(filename.len(), "20201105T173000.000Z.JPG".len());
+        assert!(filename.ends_with("Z.JPG"));
+        assert!(filename[..8].chars().all(|c| c.is_ascii_digit()));
+    }
+
+    #[test]
+    fn numbered_paths_keep_the_extension() {
+        assert_eq!(
+            numbered_path(Path::new("segment_00/DSC00001.JPG"), 1),
+            PathBuf::from("segment_00/DSC00001_1.JPG")
+        );
+        assert_eq!(
+            numbered_path(Path::new("image"), 12),
+            PathBuf::from("image_12")
+        );
+    }
+
+    #[tokio::test]
+    async fn new_files_never_overwrite_existing_ones() {
+        let dir = std::env::temp_dir().join(format!("plane-system-{}-collide", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("0.jpg");
+        std::fs::write(&path, b"first").unwrap();
+        std::fs::write(dir.join("0_1.jpg"), b"second").unwrap();
+
+        let (_, created) = create_new_file(&path).await.unwrap();
+
+        assert_eq!(created, dir.join("0_2.jpg"));
+        assert_eq!(std::fs::read(&path).unwrap(), b"first");
+        assert_eq!(std::fs::read(dir.join("0_1.jpg")).unwrap(), b"second");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn connecting_retries_until_the_camera_shows_up() {
+        let camera = MockState::shared();
+        let attempts = Arc::new(AtomicU32::new(0));
+        let config = test_config(
+            "connect-retries",
+            serde_json::json!({ "connect_attempts": 5, "connect_retry_delay_ms": 1 }),
+        );
+        let (_, cmd) = mpsc::channel(1);
+
+        let client = CameraClient::connect_with(
+            TestChannels::new().channels,
+            cmd,
+            config,
+            flaky_connector(&camera, 3, attempts.clone()),
+        )
+        .await
+        .unwrap();
+
+        assert!(client.is_some());
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+        assert_eq!(camera.lock().unwrap().calls, vec![MockCall::Connect]);
+    }
+
+    #[tokio::test]
+    async fn a_required_camera_fails_once_it_runs_out_of_attempts() {
+        let camera = MockState::shared();
+        let attempts = Arc::new(AtomicU32::new(0));
+        let config = test_config(
+            "connect-fails",
+            serde_json::json!({ "connect_attempts": 3, "connect_retry_delay_ms": 1 }),
+        );
+        let (_, cmd) = mpsc::channel(1);
+
+        let result = CameraClient::connect_with(
+            TestChannels::new().channels,
+            cmd,
+            config,
+            flaky_connector(&camera, u32::MAX, attempts.clone()),
+        )
+        .await;
+
+        let err = result
+            .err()
+            .expect("connected to a camera that isn't there");
+        assert!(format!("{:?}", err).contains("after 3 attempts"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn reset_connects_to_the_camera_again() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("reset", serde_json::json!({}));
+        let mut events = client.channels.camera_event.subscribe();
+
+        client.exec(&CameraRequest::Reset).await.unwrap();
+
+        let calls = camera.lock().unwrap().calls.clone();
+        assert_eq!(
+            &calls[..3],
+            &[MockCall::Disconnect, MockCall::Reset, MockCall::Connect]
+        );
+        assert!(camera.lock().unwrap().connected);
+
+        // the new session is usable
+        assert!(client.iface.update().is_ok());
+
+        assert!(matches!(events.try_recv(), Ok(CameraEvent::Disconnected)));
+        assert!(matches!(events.try_recv(), Ok(CameraEvent::Reconnected)));
+    }
+
+    #[tokio::test]
+    async fn reconnect_command_opens_a_new_session() {
+        let (mut client, camera) = mock_client("reconnect-command", serde_json::json!({}));
+
+        client.exec(&CameraRequest::Reconnect).await.unwrap();
+
+        let calls = camera.lock().unwrap().calls.clone();
+        assert_eq!(&calls[..2], &[MockCall::Disconnect, MockCall::Connect]);
+        assert!(camera.lock().unwrap().connected);
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_the_camera_is_unplugged() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("unplugged", serde_json::json!({}));
+        let attempts = Arc::new(AtomicU32::new(0));
+        client.connector = flaky_connector(&camera, 2, attempts.clone());
+
+        let (cmd_sender, cmd) = mpsc::channel(1);
+        client.cmd = cmd;
+        let (pending, response) = CameraCommand::new(CameraRequest::Battery);
+        cmd_sender.try_send(pending).unwrap();
+
+        let mut events = client.channels.camera_event.subscribe();
+        let mut interrupt_recv = client.channels.interrupt.subscribe();
+
+        let unplugged = anyhow::Error::new(rusb::Error::NoDevice).context("failed to update");
+        let reconnected = client
+            .recover(unplugged, &mut interrupt_recv)
+            .await
+            .unwrap();
+
+        assert!(reconnected);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+        assert!(camera.lock().unwrap().connected);
+
+        // commands sent while the camera was gone are told to try again
+        assert!(response.await.unwrap().is_err());
+
+        assert!(matches!(events.try_recv(), Ok(CameraEvent::Disconnected)));
+        assert!(matches!(events.try_recv(), Ok(CameraEvent::Reconnected)));
+    }
+
+    #[tokio::test]
+    async fn other_errors_are_not_treated_as_a_disconnect() {
+        let (mut client, camera) = mock_client("not-unplugged", serde_json::json!({}));
+        let mut interrupt_recv = client.channels.interrupt.subscribe();
+
+        let result = client
+            .recover(anyhow!("invalid save media"), &mut interrupt_recv)
+            .await;
+
+        assert!(result.is_err());
+        assert!(camera.lock().unwrap().calls.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ensure_mode_does_nothing_in_the_right_mode() {
+        let (mut client, camera) = mock_client("mode-already", serde_json::json!({}));
+
+        client.ensure_mode(0x02).await.unwrap();
+
+        assert!(camera
+            .lock()
+            .unwrap()
+            .sets(CameraPropertyCode::OperatingMode)
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn ensure_mode_stops_asking_once_the_mode_matches() {
+        let (mut client, camera) = mock_client("mode-switch", serde_json::json!({}));
+
+        client.ensure_mode(0x04).await.unwrap();
+
+        let camera = camera.lock().unwrap();
+        assert_eq!(
+            camera.sets(CameraPropertyCode::OperatingMode),
+            vec![PtpData::UINT8(0x04)]
+        );
+        assert_eq!(
+            camera.current(CameraPropertyCode::OperatingMode),
+            Some(PtpData::UINT8(0x04))
+        );
+    }
+
+    #[tokio::test]
+    async fn ensure_mode_gives_up_if_the_camera_never_switches() {
+        let (mut client, camera) =
+            mock_client("mode-stuck", serde_json::json!({ "mode_timeout_ms": 300 }));
+        camera
+            .lock()
+            .unwrap()
+            .read_only
+            .insert(CameraPropertyCode::OperatingMode);
+
+        let err = client.ensure_mode(0x04).await.unwrap_err();
+
+        assert!(err
+            .to_string()
+            .contains("did not switch to operating mode 0x04"));
+    }
+
+    #[tokio::test]
+    async fn delete_removes_one_file() {
+        let (mut client, camera) = mock_client("delete", serde_json::json!({}));
+        {
+            let mut camera = camera.lock().unwrap();
+            camera.add_object(1, "DSC00001.JPG", FORMAT_JPEG, vec![0; 16]);
+            camera.add_object(2, "DSC00002.JPG", FORMAT_JPEG, vec![0; 16]);
+        }
+
+        client
+            .exec(&CameraRequest::File(CameraFileRequest::Delete {
+                handle: 1,
+            }))
+            .await
+            .unwrap();
+
+        let camera = camera.lock().unwrap();
+        assert_eq!(camera.objects.keys().copied().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(
+            camera.current(CameraPropertyCode::OperatingMode),
+            Some(PtpData::UINT8(0x04))
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_reports_a_missing_file() {
+        let (mut client, camera) = mock_client("delete-missing", serde_json::json!({}));
+
+        let err = client
+            .exec(&CameraRequest::File(CameraFileRequest::Delete {
+                handle: 0x2a,
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.to_string(), "no file with handle 2a");
+        assert!(!camera
+            .lock()
+            .unwrap()
+            .calls
+            .iter()
+            .any(|call| matches!(call, MockCall::Delete(_))));
+    }
+
+    #[tokio::test]
+    async fn delete_all_needs_confirmation() {
+        let (mut client, camera) = mock_client("delete-all-unconfirmed", serde_json::json!({}));
+        camera
+            .lock()
+            .unwrap()
+            .add_object(1, "DSC00001.JPG", FORMAT_JPEG, vec![0; 16]);
+
+        let result = client
+            .exec(&CameraRequest::File(CameraFileRequest::DeleteAll {
+                confirm: false,
+            }))
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(camera.lock().unwrap().objects.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn delete_all_leaves_folders_alone() {
+        let (mut client, camera) = mock_client("delete-all", serde_json::json!({}));
+        {
+            let mut camera = camera.lock().unwrap();
+            camera.add_object(1, "DCIM", FORMAT_FOLDER, Vec::new());
+            camera.add_object(2, "DSC00001.JPG", FORMAT_JPEG, vec![0; 16]);
+            camera.add_object(3, "DSC00001.ARW", FORMAT_ARW, vec![0; 32]);
+        }
+
+        client
+            .exec(&CameraRequest::File(CameraFileRequest::DeleteAll {
+                confirm: true,
+            }))
+            .await
+            .unwrap();
+
+        let camera = camera.lock().unwrap();
+        assert_eq!(camera.objects.keys().copied().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn waits_for_the_memory_card_and_hides_the_placeholder() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("storage", serde_json::json!({}));
+        camera.lock().unwrap().storage_ids = vec![
+            vec![StorageId::from(PLACEHOLDER_STORAGE_ID)],
+            vec![
+                StorageId::from(PLACEHOLDER_STORAGE_ID),
+                StorageId::from(CARD_STORAGE_ID),
+            ],
+        ]
+        .into();
+
+        let storage_ids = client.wait_for_storage().await.unwrap();
+
+        assert_eq!(storage_ids, vec![StorageId::from(CARD_STORAGE_ID)]);
+    }
+
+    #[tokio::test]
+    async fn placeholder_storage_is_not_a_memory_card() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("storage-placeholder", serde_json::json!({}));
+        camera.lock().unwrap().storage_ids =
+            vec![vec![StorageId::from(PLACEHOLDER_STORAGE_ID)]].into();
+
+        assert!(client.wait_for_storage().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn save_mode_is_read_back_after_setting_it() {
+        let (mut client, camera) = mock_client("save-mode", serde_json::json!({}));
+
+        let response = client
+            .exec(&CameraRequest::SaveMode(CameraSaveModeRequest::Set {
+                mode: CameraSaveMode::MemoryCard1,
+            }))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            response,
+            CameraResponse::SaveMode {
+                save_mode: CameraSaveMode::MemoryCard1
+            }
+        ));
+        assert_eq!(
+            camera
+                .lock()
+                .unwrap()
+                .current(CameraPropertyCode::SaveMedia),
+            Some(PtpData::UINT16(0x0002))
+        );
+    }
+
+    #[tokio::test]
+    async fn save_mode_fails_if_the_camera_reports_something_else() {
+        let (mut client, camera) = mock_client("save-mode-mismatch", serde_json::json!({}));
+
+        // the client last saw the camera saving to the host, but the camera
+        // has switched to the memory card by itself since then
+        camera
+            .lock()
+            .unwrap()
+            .set_prop(CameraPropertyCode::SaveMedia, PtpData::UINT16(0x0002));
+
+        let err = client
+            .exec(&CameraRequest::SaveMode(CameraSaveModeRequest::Set {
+                mode: CameraSaveMode::HostDevice,
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "camera is saving to MemoryCard1 after being set to HostDevice"
+        );
+    }
+
+    /// A fake camera zoomed in digitally to 150, past the end of its optical
+    /// range at 100.
+    fn digitally_zoomed_camera(camera: &Arc<Mutex<MockState>>) {
+        let mut camera = camera.lock().unwrap();
+
+        camera.set_prop(CameraPropertyCode::Zoom, PtpData::UINT8(0x04));
+        camera.properties.insert(
+            CameraPropertyCode::ZoomAbsolutePosition,
+            PropBuilder::new(
+                CameraPropertyCode::ZoomAbsolutePosition,
+                PtpData::UINT16(150),
+            )
+            .range(PtpData::UINT16(0), PtpData::UINT16(100), PtpData::UINT16(1))
+            .build(),
+        );
+    }
+
+    #[tokio::test]
+    async fn optical_zoom_mode_zooms_out_to_the_optical_range() {
+        let (mut client, camera) = mock_client("zoom-clamp", serde_json::json!({}));
+        digitally_zoomed_camera(&camera);
+
+        let response = client
+            .exec(&CameraRequest::Zoom(CameraZoomRequest::Mode(
+                CameraZoomModeRequest::Optical,
+            )))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            response,
+            CameraResponse::ZoomMode {
+                zoom_mode: CameraZoomMode::Optical
+            }
+        ));
+
+        let camera = camera.lock().unwrap();
+        assert_eq!(
+            camera.sets(CameraPropertyCode::ZoomAbsolutePosition),
+            vec![PtpData::UINT16(100)]
+        );
+    }
+
+    #[tokio::test]
+    async fn optical_digital_zoom_mode_keeps_the_zoom_level() {
+        let (mut client, camera) = mock_client("zoom-no-clamp", serde_json::json!({}));
+        digitally_zoomed_camera(&camera);
+
+        client
+            .exec(&CameraRequest::Zoom(CameraZoomRequest::Mode(
+                CameraZoomModeRequest::OpticalDigital,
+            )))
+            .await
+            .unwrap();
+
+        let camera = camera.lock().unwrap();
+        assert!(camera
+            .sets(CameraPropertyCode::ZoomAbsolutePosition)
+            .is_empty());
+        assert_eq!(
+            camera.current(CameraPropertyCode::ZoomAbsolutePosition),
+            Some(PtpData::UINT16(150))
+        );
+    }
+
+    /// Makes the fake camera report `state` once formatting is started.
+    fn format_ends_in(camera: &Arc<Mutex<MockState>>, state: Option<u8>) {
+        camera.lock().unwrap().on_execute = Some(Box::new(move |camera, code, payload| {
+            if code == CameraControlCode::MediaFormat && *payload == PtpData::UINT16(0x0001) {
+                if let Some(state) = state {
+                    camera.set_prop(CameraPropertyCode::MediaFormatState, PtpData::UINT8(state));
+                }
+            }
+        }));
+    }
+
+    #[tokio::test]
+    async fn format_needs_confirmation() {
+        let (mut client, camera) = mock_client("format-confirm", serde_json::json!({}));
+
+        let result = client.exec(&CameraRequest::Format { confirm: false }).await;
+
+        assert!(result.is_err());
+        assert!(camera.lock().unwrap().executed().is_empty());
+    }
+
+    #[tokio::test]
+    async fn format_finishes_once_the_camera_reports_completed() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("format-done", serde_json::json!({}));
+        format_ends_in(&camera, Some(0x02));
+
+        client
+            .exec(&CameraRequest::Format { confirm: true })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            camera.lock().unwrap().executed(),
+            vec![
+                (CameraControlCode::MediaFormat, PtpData::UINT16(0x0002)),
+                (CameraControlCode::MediaFormat, PtpData::UINT16(0x0001)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn format_is_not_finished_while_the_camera_is_formatting() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("format-stuck", serde_json::json!({}));
+        format_ends_in(&camera, Some(0x01));
+
+        let result = client.exec(&CameraRequest::Format { confirm: true }).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn format_fails_if_the_camera_does_not_report_its_progress() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("format-unknown", serde_json::json!({}));
+        format_ends_in(&camera, None);
+
+        let result = client.exec(&CameraRequest::Format { confirm: true }).await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn movie_recording_switches_to_movie_mode_first() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("movie", serde_json::json!({}));
+        {
+            let mut camera = camera.lock().unwrap();
+            camera.set_prop(CameraPropertyCode::MovieRecording, PtpData::UINT8(0));
+            camera.on_execute = Some(Box::new(|camera, code, payload| {
+                if code == CameraControlCode::MovieRecording && *payload == PtpData::UINT16(0x0001)
+                {
+                    camera.set_prop(CameraPropertyCode::MovieRecording, PtpData::UINT8(1));
+                }
+            }));
+        }
+
+        let response = client
+            .exec(&CameraRequest::Movie(CameraMovieRequest::Start))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            response,
+            CameraResponse::Movie { recording: true }
+        ));
+
+        let camera = camera.lock().unwrap();
+        let mode_set = camera
+            .calls
+            .iter()
+            .position(|call| {
+                *call == MockCall::Set(CameraPropertyCode::OperatingMode, PtpData::UINT8(0x03))
+            })
+            .unwrap();
+        let record_pressed = camera
+            .calls
+            .iter()
+            .position(|call| {
+                matches!(
+                    call,
+                    MockCall::Execute(CameraControlCode::MovieRecording, _)
+                )
+            })
+            .unwrap();
+        assert!(mode_set < record_pressed);
+    }
+
+    #[tokio::test]
+    async fn movie_recording_does_not_start_outside_movie_mode() {
+        let (mut client, camera) = mock_client(
+            "movie-wrong-mode",
+            serde_json::json!({ "mode_timeout_ms": 300 }),
+        );
+        {
+            let mut camera = camera.lock().unwrap();
+            camera.set_prop(CameraPropertyCode::MovieRecording, PtpData::UINT8(0));
+            camera.read_only.insert(CameraPropertyCode::OperatingMode);
+        }
+
+        let result = client
+            .exec(&CameraRequest::Movie(CameraMovieRequest::Start))
+            .await;
+
+        assert!(result.is_err());
+        assert!(camera.lock().unwrap().executed().is_empty());
+    }
+
+    #[tokio::test]
+    async fn af_warning_aborts_the_capture() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("af-warning", serde_json::json!({}));
+        camera.lock().unwrap().set_prop(
+            CameraPropertyCode::FocusIndication,
+            PtpData::UINT8(CameraFocusIndication::AFWarning as u8),
+        );
+
+        let err = client
+            .exec(&CameraRequest::Capture {
+                require_focus: true,
+                burst_duration: None,
+                burst_high_speed: false,
+            })
+            .await
+            .unwrap_err();
+
+        assert!(format!("{:#}", err).contains("AF warning"));
+
+        // the shutter is let go without being pressed all the way
+        assert_eq!(
+            camera.lock().unwrap().executed(),
+            vec![
+                (CameraControlCode::S1Button, PtpData::UINT16(0x0002)),
+                (CameraControlCode::S1Button, PtpData::UINT16(0x0001)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn battery_reports_the_level_and_the_icon() {
+        let (mut client, camera) = mock_client("battery", serde_json::json!({}));
+        camera.lock().unwrap().set_prop(
+            CameraPropertyCode::BatteryLevel,
+            PtpData::INT8(CameraBatteryLevel::ThreeQuarters as i8),
+        );
+
+        let response = client.exec(&CameraRequest::Battery).await.unwrap();
+
+        assert!(matches!(
+            response,
+            CameraResponse::Battery {
+                level_percent: 80,
+                indicator: Some(CameraBatteryLevel::ThreeQuarters),
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn battery_rejects_an_unknown_level() {
+        let (mut client, camera) = mock_client("battery-unknown", serde_json::json!({}));
+        camera
+            .lock()
+            .unwrap()
+            .set_prop(CameraPropertyCode::BatteryRemain, PtpData::UINT8(0xFF));
+
+        assert!(client.exec(&CameraRequest::Battery).await.is_err());
+    }
+
+    /// Replaces every property of the fake camera with a drive mode and a
+    /// read-only zoom position.
+    fn few_properties(camera: &Arc<Mutex<MockState>>) {
+        let mut camera = camera.lock().unwrap();
+
+        camera.properties.clear();
+        camera.properties.insert(
+            CameraPropertyCode::ZoomAbsolutePosition,
+            PropBuilder::new(
+                CameraPropertyCode::ZoomAbsolutePosition,
+                PtpData::UINT16(40),
+            )
+            .range(PtpData::UINT16(0), PtpData::UINT16(100), PtpData::UINT16(1))
+            .read_only()
+            .build(),
+        );
+        camera.set_prop(
+            CameraPropertyCode::DriveMode,
+            PtpData::UINT16(CameraDriveMode::Single as u16),
+        );
+    }
+
+    #[tokio::test]
+    async fn debug_dumps_every_property_in_order() {
+        let (mut client, camera) = mock_client("debug", serde_json::json!({}));
+        few_properties(&camera);
+
+        let properties = match client
+            .exec(&CameraRequest::Debug { property: None })
+            .await
+            .unwrap()
+        {
+            CameraResponse::PropertyDump { properties } => properties,
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        assert_eq!(properties.len(), 2);
+
+        assert_eq!(properties[0].code, 0xD6B0);
+        assert_eq!(properties[0].name, "DriveMode");
+        assert!(properties[0].writable);
+        assert_eq!(properties[0].current, "Single");
+        assert!(properties[0].allowed.is_empty());
+
+        assert_eq!(properties[1].code, 0xD6BE);
+        assert_eq!(properties[1].name, "ZoomAbsolutePosition");
+        assert!(!properties[1].writable);
+        assert_eq!(properties[1].current, "0x0028");
+        assert_eq!(properties[1].allowed, vec!["0x0000..=0x0064 (step 0x0001)"]);
+    }
+
+    #[tokio::test]
+    async fn debug_can_dump_one_property() {
+        let (mut client, camera) = mock_client("debug-one", serde_json::json!({}));
+        few_properties(&camera);
+
+        let response = client
+            .exec(&CameraRequest::Debug {
+                property: Some(CameraPropertyCode::DriveMode),
+            })
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            response,
+            CameraResponse::PropertyDump { properties } if properties.len() == 1
+        ));
+
+        let missing = client
+            .exec(&CameraRequest::Debug {
+                property: Some(CameraPropertyCode::FNumber),
+            })
+            .await;
+
+        assert!(missing.is_err());
+    }
+
+    #[tokio::test]
+    async fn large_downloads_report_progress_up_to_the_total() {
+        let (mut client, camera) = mock_client("progress", serde_json::json!({}));
+        let size = DOWNLOAD_PROGRESS_THRESHOLD + 1000;
+        let data = (0..size).map(|i| i as u8).collect::<Vec<_>>();
+        camera
+            .lock()
+            .unwrap()
+            .add_object(1, "DSC00001.ARW", FORMAT_ARW, data.clone());
+
+        let mut events = client.channels.camera_event.subscribe();
+
+        let (_, downloaded) = client
+            .fetch_object_with_progress(ObjectHandle::from(1))
+            .unwrap();
+
+        assert_eq!(downloaded, data);
+
+        // the chunks follow on from each other and add up to the whole file
+        let mut offset = 0;
+        for call in &camera.lock().unwrap().calls {
+            if let MockCall::DataPartial {
+                offset: chunk_offset,
+                max,
+                ..
+            } = call
+            {
+                assert_eq!(*chunk_offset, offset);
+                offset += (*max).min(size - offset);
+            }
+        }
+        assert_eq!(offset, size);
+
+        let mut progress = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            if let CameraEvent::DownloadProgress {
+                bytes_done,
+                bytes_total,
+                ..
+            } = event
+            {
+                assert_eq!(bytes_total, size as u64);
+                progress.push(bytes_done);
+            }
+        }
+
+        assert!(progress.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(progress.last(), Some(&(size as u64)));
+    }
+
+    #[tokio::test]
+    async fn small_downloads_do_not_report_progress() {
+        let (mut client, camera) = mock_client("no-progress", serde_json::json!({}));
+        camera
+            .lock()
+            .unwrap()
+            .add_object(1, "DSC00001.JPG", FORMAT_JPEG, vec![0; 1024]);
+
+        let mut events = client.channels.camera_event.subscribe();
+
+        client
+            .fetch_object_with_progress(ObjectHandle::from(1))
+            .unwrap();
+
+        assert!(events.try_recv().is_err());
+        assert!(camera.lock().unwrap().calls.is_empty());
+    }
+
+    fn send_armed(client: &CameraClient, states: &[bool]) {
+        for &armed in states {
+            client
+                .channels
+                .pixhawk_event
+                .send(PixhawkEvent::Armed { armed })
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn arming_starts_a_new_segment_that_survives_a_restart() {
+        let config = serde_json::json!({ "segment_by_arming": true });
+
+        let (mut client, _camera) = mock_client("segments", config.clone());
+        assert_eq!(client.sequence.segment(), 0);
+
+        // disarming and repeated heartbeats don't start a new segment
+        send_armed(&client, &[false, true, true, false, true]);
+        client.check_pixhawk_events();
+        assert_eq!(client.sequence.segment(), 2);
+
+        // restarted mid-flight: the first heartbeat is already armed
+        let (mut client, _camera) = mock_client("segments", config);
+        assert_eq!(client.sequence.segment(), 2);
+
+        send_armed(&client, &[true, true]);
+        client.check_pixhawk_events();
+        assert_eq!(client.sequence.segment(), 2);
+
+        send_armed(&client, &[false, true]);
+        client.check_pixhawk_events();
+        assert_eq!(client.sequence.segment(), 3);
+
+        std::fs::remove_file(client.sequence.path()).unwrap();
+    }
+
+    async fn downloads_paused(client: &mut CameraClient) -> bool {
+        match client
+            .exec(&CameraRequest::Download(CameraDownloadRequest::Status))
+            .await
+            .unwrap()
+        {
+            CameraResponse::DownloadStatus { paused } => paused,
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn downloads_stay_paused_until_the_backfill_succeeds() {
+        let (mut client, camera) = mock_client("resume", serde_json::json!({}));
+        let save_dir =
+            std::env::temp_dir().join(format!("plane-system-{}-resume", std::process::id()));
+        let _ = std::fs::remove_dir_all(&save_dir);
+        client.save_dir = save_dir.clone();
+
+        camera
+            .lock()
+            .unwrap()
+            .add_object(1, "DSC00001.JPG", FORMAT_JPEG, vec![1; 16]);
+
+        client
+            .exec(&CameraRequest::Download(CameraDownloadRequest::Pause))
+            .await
+            .unwrap();
+
+        {
+            let mut camera = camera.lock().unwrap();
+            assert_eq!(
+                camera.current(CameraPropertyCode::SaveMedia),
+                Some(PtpData::UINT16(CameraSaveMode::MemoryCard1 as u16))
+            );
+
+            camera.add_object(2, "DSC00002.JPG", FORMAT_JPEG, vec![2; 16]);
+            camera.add_object(3, "DSC00003.JPG", FORMAT_JPEG, vec![3; 16]);
+        }
+
+        // the directory to save to doesn't exist, so saving fails
+        let resumed = client
+            .exec(&CameraRequest::Download(CameraDownloadRequest::Resume))
+            .await;
+
+        assert!(resumed.is_err());
+        assert!(downloads_paused(&mut client).await);
+        assert_eq!(
+            camera
+                .lock()
+                .unwrap()
+                .current(CameraPropertyCode::SaveMedia),
+            Some(PtpData::UINT16(CameraSaveMode::MemoryCard1 as u16))
+        );
+
+        std::fs::create_dir_all(&save_dir).unwrap();
+
+        let paths = match client
+            .exec(&CameraRequest::Download(CameraDownloadRequest::Resume))
+            .await
+            .unwrap()
+        {
+            CameraResponse::Files { paths } => paths,
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        assert_eq!(paths.len(), 2);
+        assert_eq!(std::fs::read(&paths[0]).unwrap(), vec![2; 16]);
+        assert_eq!(std::fs::read(&paths[1]).unwrap(), vec![3; 16]);
+
+        assert!(!downloads_paused(&mut client).await);
+        assert_eq!(
+            camera
+                .lock()
+                .unwrap()
+                .current(CameraPropertyCode::SaveMedia),
+            Some(PtpData::UINT16(CameraSaveMode::HostDevice as u16))
+        );
+
+        std::fs::remove_dir_all(&save_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_hung_command_lets_go_of_the_shutter_and_the_next_one_runs() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client(
+            "hung-command",
+            serde_json::json!({ "command_timeout_secs": 1, "focus_timeout_ms": 60000 }),
+        );
+        // the camera never finishes focusing
+        camera.lock().unwrap().set_prop(
+            CameraPropertyCode::FocusIndication,
+            PtpData::UINT8(CameraFocusIndication::Focusing as u8),
+        );
+
+        let err = client
+            .exec_with_timeout(&CameraRequest::Capture {
+                require_focus: true,
+                burst_duration: None,
+                burst_high_speed: false,
+            })
+            .await
+            .unwrap_err();
+
+        assert!(err.to_string().contains("timed out"));
+        assert_eq!(
+            camera.lock().unwrap().executed(),
+            vec![
+                (CameraControlCode::S1Button, PtpData::UINT16(0x0002)),
+                (CameraControlCode::S2Button, PtpData::UINT16(0x0001)),
+                (CameraControlCode::S1Button, PtpData::UINT16(0x0001)),
+            ]
+        );
+
+        let response = client
+            .exec_with_timeout(&CameraRequest::Battery)
+            .await
+            .unwrap();
+
+        assert!(matches!(response, CameraResponse::Battery { .. }));
+        assert_eq!(client.timeouts, 0);
+    }
+
+    fn burst_request() -> CameraRequest {
+        CameraRequest::Capture {
+            require_focus: true,
+            burst_duration: Some(1.),
+            burst_high_speed: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn burst_puts_the_drive_mode_back() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("burst", serde_json::json!({}));
+        camera.lock().unwrap().set_prop(
+            CameraPropertyCode::SaveMedia,
+            PtpData::UINT16(CameraSaveMode::MemoryCard1 as u16),
+        );
+
+        client.exec_with_timeout(&burst_request()).await.unwrap();
+
+        let camera = camera.lock().unwrap();
+        assert_eq!(
+            camera.sets(CameraPropertyCode::DriveMode),
+            vec![
+                PtpData::UINT16(CameraDriveMode::ContinuousShot as u16),
+                PtpData::UINT16(CameraDriveMode::Single as u16),
+            ]
+        );
+        assert_eq!(
+            camera.current(CameraPropertyCode::DriveMode),
+            Some(PtpData::UINT16(CameraDriveMode::Single as u16))
+        );
+    }
+
+    #[tokio::test]
+    async fn burst_puts_the_drive_mode_back_when_it_times_out() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client(
+            "burst-timeout",
+            serde_json::json!({ "command_timeout_secs": 1, "focus_timeout_ms": 60000 }),
+        );
+        camera.lock().unwrap().set_prop(
+            CameraPropertyCode::FocusIndication,
+            PtpData::UINT8(CameraFocusIndication::Focusing as u8),
+        );
+
+        assert!(client.exec_with_timeout(&burst_request()).await.is_err());
+
+        assert_eq!(
+            camera
+                .lock()
+                .unwrap()
+                .current(CameraPropertyCode::DriveMode),
+            Some(PtpData::UINT16(CameraDriveMode::Single as u16))
+        );
+        assert!(client.drive_mode_to_restore.is_none());
+    }
+
+    #[tokio::test]
+    async fn shutdown_disconnects_even_if_the_camera_does_not_turn_off() {
+        let config = serde_json::json!({ "shutdown": { "power_off": true } });
+        let (mut client, camera) = mock_client("power-off-fails", config);
+        camera
+            .lock()
+            .unwrap()
+            .failing_controls
+            .insert(CameraControlCode::PowerOff);
+
+        client.shut_down().await.unwrap();
+
+        let camera = camera.lock().unwrap();
+        assert_eq!(
+            camera.calls,
+            vec![
+                MockCall::Execute(CameraControlCode::PowerOff, PtpData::UINT16(2)),
+                MockCall::Disconnect,
+            ]
+        );
+        assert!(!camera.connected);
+    }
+
+    #[tokio::test]
+    async fn shutdown_gives_up_on_turning_off_at_the_deadline() {
+        tokio::time::pause();
+
+        let config = serde_json::json!({
+            "mode_timeout_ms": 60000,
+            "shutdown": { "power_off": true, "deadline_secs": 1 },
+        });
+        let (mut client, camera) = mock_client("power-off-stuck", config);
+        {
+            // stuck in movie mode, where the camera can't be turned off
+            let mut camera = camera.lock().unwrap();
+            camera.set_prop(CameraPropertyCode::OperatingMode, PtpData::UINT8(0x03));
+            camera.read_only.insert(CameraPropertyCode::OperatingMode);
+        }
+
+        client.shut_down().await.unwrap();
+
+        let camera = camera.lock().unwrap();
+        assert!(camera.executed().is_empty());
+        assert_eq!(camera.calls.last(), Some(&MockCall::Disconnect));
+    }
+
+    /// Makes the fake camera put a picture on the memory card and confirm it
+    /// when the shutter is pressed.
+    fn camera_takes_pictures(camera: &Arc<Mutex<MockState>>) {
+        camera.lock().unwrap().on_execute = Some(Box::new(|camera, code, payload| {
+            if code == CameraControlCode::S2Button && *payload == PtpData::UINT16(0x0002) {
+                let handle = camera.objects.keys().last().map_or(1, |handle| handle + 1);
+                camera.add_object(handle, "DSC00001.JPG", FORMAT_JPEG, vec![0; 16]);
+                camera.events.push_back(capture_event(1));
+            }
+        }));
+    }
+
+    #[tokio::test]
+    async fn self_test_runs_every_check_and_reports_each_result() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client(
+            "self-test",
+            serde_json::json!({ "min_battery_percent": 90 }),
+        );
+        camera_takes_pictures(&camera);
+        camera
+            .lock()
+            .unwrap()
+            .add_object(1, "DSC00000.JPG", FORMAT_JPEG, vec![0; 16]);
+
+        let response = client.exec(&CameraRequest::SelfTest).await.unwrap();
+
+        let (passed, results) = match response {
+            CameraResponse::SelfTest { passed, results } => (passed, results),
+            other => panic!("unexpected response: {:?}", other),
+        };
+
+        // the battery is at 80%
+        assert!(!passed);
+        assert_eq!(
+            results
+                .iter()
+                .map(|check| (check.name.as_str(), check.passed))
+                .collect::<Vec<_>>(),
+            vec![
+                ("connected", true),
+                ("operating mode", true),
+                ("battery", false),
+                ("storage", true),
+                ("test frame", true),
+            ]
+        );
+        assert!(results[2].error.as_ref().unwrap().contains("below 90%"));
+
+        let camera = camera.lock().unwrap();
+
+        // the test picture is deleted, and the camera saves to the host again
+        assert_eq!(camera.objects.keys().copied().collect::<Vec<_>>(), vec![1]);
+        assert_eq!(
+            camera.sets(CameraPropertyCode::SaveMedia),
+            vec![
+                PtpData::UINT16(CameraSaveMode::MemoryCard1 as u16),
+                PtpData::UINT16(CameraSaveMode::HostDevice as u16),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn self_test_restores_the_save_mode_if_the_test_picture_fails() {
+        tokio::time::pause();
+
+        let (mut client, camera) = mock_client("self-test-fails", serde_json::json!({}));
+        camera
+            .lock()
+            .unwrap()
+            .failing_controls
+            .insert(CameraControlCode::S1Button);
+
+        let response = client.exec(&CameraRequest::SelfTest).await.unwrap();
+
+        assert!(matches!(
+            response,
+            CameraResponse::SelfTest { passed: false, .. }
+        ));
+        assert_eq!(
+            camera
+                .lock()
+                .unwrap()
+                .current(CameraPropertyCode::SaveMedia),
+            Some(PtpData::UINT16(CameraSaveMode::HostDevice as u16))
+        );
+    }
+
+    async fn continuous_capture(
+        client: &mut CameraClient,
+        request: CameraContinuousCaptureRequest,
+    ) -> bool {
+        match client
+            .exec(&CameraRequest::ContinuousCapture(request))
+            .await
+            .unwrap()
+        {
+            CameraResponse::ContinuousCapture { active } => active,
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn continuous_capture_start_and_stop_are_idempotent() {
+        let (mut client, camera) = mock_client("cc-idempotent", serde_json::json!({}));
+
+        assert!(!continuous_capture(&mut client, CameraContinuousCaptureRequest::Status).await);
+
+        assert!(continuous_capture(&mut client, CameraContinuousCaptureRequest::Start).await);
+        assert!(continuous_capture(&mut client, CameraContinuousCaptureRequest::Start).await);
+        assert!(continuous_capture(&mut client, CameraContinuousCaptureRequest::Status).await);
+
+        assert!(!continuous_capture(&mut client, CameraContinuousCaptureRequest::Stop).await);
+        assert!(!continuous_capture(&mut client, CameraContinuousCaptureRequest::Stop).await);
+        assert!(!continuous_capture(&mut client, CameraContinuousCaptureRequest::Status).await);
+
+        // the button is a toggle, so it is pressed once to start and once to
+        // stop
+        assert_eq!(
+            camera.lock().unwrap().executed(),
+            vec![
+                (
+                    CameraControlCode::IntervalStillRecording,
+                    PtpData::UINT16(0x0002)
+                ),
+                (
+                    CameraControlCode::IntervalStillRecording,
+                    PtpData::UINT16(0x0001)
+                ),
+            ]
+        );
+    }
+
+    /// Feedback for the image at `ms` milliseconds after the epoch, tagged
+    /// with its time so that the tests can tell which one was picked.
+    fn feedback_at(ms: u64) -> CaptureFeedback {
+        CaptureFeedback {
+            time: SystemTime::UNIX_EPOCH + Duration::from_millis(ms),
+            coords: Coords3D::new(0.0, 0.0, ms as f32),
+            attitude: Attitude::default(),
+        }
+    }
+
+    #[test]
+    fn captures_take_the_nearest_feedback_within_the_tolerance() {
+        let tolerance = Duration::from_millis(100);
+        let at = |ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms);
+        let mut feedback = VecDeque::new();
+
+        // feedback and captures arrive interleaved, and a capture can be
+        // confirmed before or after its feedback
+        feedback.push_back(feedback_at(1_000));
+        feedback.push_back(feedback_at(2_000));
+
+        let taken = take_nearest_feedback(&mut feedback, at(1_960), tolerance).unwrap();
+        assert_eq!(taken.coords.altitude, 2_000.0);
+
+        feedback.push_back(feedback_at(3_000));
+
+        let taken = take_nearest_feedback(&mut feedback, at(1_030), tolerance).unwrap();
+        assert_eq!(taken.coords.altitude, 1_000.0);
+
+        // each feedback is used once, so another capture at the same time
+        // falls back to the telemetry
+        assert!(take_nearest_feedback(&mut feedback, at(1_030), tolerance).is_none());
+
+        // too far from the only feedback left
+        assert!(take_nearest_feedback(&mut feedback, at(3_150), tolerance).is_none());
+
+        let taken = take_nearest_feedback(&mut feedback, at(3_100), tolerance).unwrap();
+        assert_eq!(taken.coords.altitude, 3_000.0);
+        assert!(feedback.is_empty());
+    }
+
+    #[tokio::test]
+    async fn feedback_is_paired_on_the_gps_clock_when_the_host_clock_is_off() {
+        let test_channels = TestChannels::new();
+        let (mut client, _) = mock_client_with(
+            test_channels.channels.clone(),
+            "feedback-skew",
+            serde_json::json!({ "feedback_tolerance_ms": 500 }),
+        );
+
+        // the host clock is 3 s behind the autopilot's GPS clock
+        let host_now = SystemTime::now();
+        let skew = Duration::from_secs(3);
+        test_channels
+            .telemetry
+            .send(Some(TelemetryInfo {
+                gps_time: Some(GpsTime {
+                    utc: host_now + skew,
+                    received: host_now,
+                }),
+                ..Default::default()
+            }))
+            .unwrap();
+
+        // feedback for the image that was just taken, and for one from 3 s
+        // ago, which is what the capture would be paired with on the host
+        // clock
+        let feedback = |time, altitude| CaptureFeedback {
+            time,
+            coords: Coords3D::new(0.0, 0.0, altitude),
+            attitude: Attitude::default(),
+        };
+        client.feedback.push_back(feedback(host_now, 1.0));
+        client.feedback.push_back(feedback(host_now + skew, 2.0));
+
+        let mut report = CaptureReport::new(0, None);
+        report.confirm_time = Some(host_now + Duration::from_millis(200));
+        client.geotag(&mut report);
+
+        assert_eq!(report.geotag_source, GeotagSource::Feedback);
+        assert_eq!(report.coords.unwrap().altitude, 2.0);
+        assert_eq!(client.feedback.len(), 1);
     }
 }
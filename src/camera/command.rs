@@ -1,10 +1,12 @@
 use std::collections::HashMap;
 
+use anyhow::Context;
 use serde::Serialize;
 use structopt::StructOpt;
 
 use crate::Command;
 
+use super::interface::CameraPropertyCode;
 use super::state::*;
 
 pub type CameraCommand = Command<CameraRequest, CameraResponse>;
@@ -18,7 +20,32 @@ pub enum CameraRequest {
     File(CameraFileRequest),
 
     /// capture an image
-    Capture,
+    Capture {
+        /// wait for the autofocus to lock before releasing the shutter, and
+        /// fail if it can't; ignored in manual focus mode
+        #[structopt(long, parse(try_from_str), default_value = "true")]
+        require_focus: bool,
+
+        /// hold the shutter down for this many seconds and capture as many
+        /// images as the camera can, instead of a single image
+        #[structopt(long)]
+        burst_duration: Option<f32>,
+
+        /// use the camera's speed priority continuous mode for the burst,
+        /// which is faster but does not refocus between images
+        #[structopt(long)]
+        burst_high_speed: bool,
+    },
+
+    /// capture an image at a specific time, between 1 and 20 seconds from
+    /// now. The shutter is released within a few milliseconds of this time
+    /// (timer and USB delays); the camera then takes some more time to
+    /// actually expose the image, which is reported as the latency.
+    CaptureAt {
+        /// the time to capture at, in RFC 3339 format (e.g.
+        /// 2020-11-05T17:30:00.000Z), according to GPS time if it is available
+        utc: chrono::DateTime<chrono::Utc>,
+    },
 
     /// power off the camera
     Power(CameraPowerRequest),
@@ -32,6 +59,24 @@ pub enum CameraRequest {
     /// control the camera's exposure mode
     Exposure(CameraExposureRequest),
 
+    /// control the camera's focus
+    Focus(CameraFocusRequest),
+
+    /// control the camera's aperture
+    Aperture(CameraApertureRequest),
+
+    /// control the camera's shutter speed
+    Shutter(CameraShutterRequest),
+
+    /// control the camera's ISO
+    Iso(CameraIsoRequest),
+
+    /// control the camera's white balance
+    WhiteBalance(CameraWhiteBalanceRequest),
+
+    /// record video
+    Movie(CameraMovieRequest),
+
     /// control whether the camera saves to its internal storage or to the host
     SaveMode(CameraSaveModeRequest),
 
@@ -41,6 +86,85 @@ pub enum CameraRequest {
 
     /// perform a usb reset and reconnect
     Reset,
+
+    /// make sure that every image saved so far is written to disk, so that a
+    /// power cut can't lose it
+    Flush,
+
+    /// get the camera's battery level
+    Battery,
+
+    /// erase everything on the camera's memory card
+    Format {
+        /// must be given, since this deletes every image on the card
+        #[structopt(long)]
+        confirm: bool,
+    },
+
+    /// estimate how many more images will fit on the camera's memory card
+    RemainingShots,
+
+    /// pause or resume downloading images from the camera
+    Download(CameraDownloadRequest),
+
+    /// get the current time according to the camera's clock
+    Time,
+
+    /// view or clear the camera's error flags
+    Errors(CameraErrorsRequest),
+
+    /// control the resolution of the camera's live view
+    LiveViewResolution(CameraLiveViewResolutionRequest),
+
+    /// show the camera's properties, with their current and allowed values
+    Debug {
+        /// only show this property (e.g. exposure-mode or 0xD6CC)
+        property: Option<CameraPropertyCode>,
+    },
+
+    /// check that camera properties have the expected values, given as
+    /// property=value pairs (e.g. save-media=1 zoom-absolute=10)
+    VerifySettings {
+        expected: Vec<CameraSettingExpectation>,
+    },
+
+    /// check that the camera is ready to fly: connected, in a known mode,
+    /// charged and with a memory card. Also takes a test picture, which is
+    /// thrown away
+    SelfTest,
+
+    /// measure how fast files can be downloaded from the camera
+    Benchmark {
+        /// the hexadecimal file handle of the file to download; defaults to
+        /// the most recent file on the memory card
+        #[structopt(parse(try_from_str = crate::util::parse_hex_u32))]
+        handle: Option<u32>,
+    },
+}
+
+/// A property along with the value that we expect it to have. Numbers can be
+/// given in decimal or in hexadecimal with a 0x prefix; anything else is
+/// compared as a string.
+#[derive(Debug, Clone)]
+pub struct CameraSettingExpectation {
+    pub property: CameraPropertyCode,
+    pub value: String,
+}
+
+impl std::str::FromStr for CameraSettingExpectation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, '=');
+
+        let property = parts.next().unwrap().parse()?;
+        let value = parts
+            .next()
+            .ok_or_else(|| anyhow!("expected property=value"))?
+            .to_string();
+
+        Ok(CameraSettingExpectation { property, value })
+    }
 }
 
 #[derive(StructOpt, Debug, Clone)]
@@ -57,19 +181,118 @@ pub enum CameraFileRequest {
         /// of the folder will be listed
         #[structopt(parse(try_from_str = crate::util::parse_hex_u32))]
         parent: Option<u32>,
+
+        /// only list files of this format (jpeg, raw, folder, or other)
+        #[structopt(long)]
+        format: Option<CameraFileFormat>,
     },
 
+    /// count the files of each format on the memory card
+    Formats,
+
     /// download a file from the camera
     Get {
         /// the hexadecimal file handle of a file
         #[structopt(parse(try_from_str = crate::util::parse_hex_u32))]
         handle: u32,
     },
+
+    /// delete a file from the camera's memory card
+    Delete {
+        /// the hexadecimal file handle of a file
+        #[structopt(parse(try_from_str = crate::util::parse_hex_u32))]
+        handle: u32,
+    },
+
+    /// delete every file on the camera's memory card
+    DeleteAll {
+        /// required, since this can't be undone
+        #[structopt(long)]
+        confirm: bool,
+    },
+}
+
+impl std::str::FromStr for CameraFileFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jpeg" | "jpg" => Ok(CameraFileFormat::Jpeg),
+            "raw" | "arw" => Ok(CameraFileFormat::Raw),
+            "folder" => Ok(CameraFileFormat::Folder),
+            "other" => Ok(CameraFileFormat::Other),
+            _ => bail!("invalid file format"),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraDownloadRequest {
+    /// stop downloading images; new images are kept on the memory card
+    Pause,
+
+    /// start downloading images again, and download everything that was
+    /// captured while downloads were paused
+    Resume,
+
+    /// check whether downloads are paused
+    Status,
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraErrorsRequest {
+    /// list the errors that the camera is currently reporting
+    Get,
+
+    /// forget errors that were already reported and check again; errors that
+    /// are still present afterwards are returned
+    Clear,
 }
 
 #[derive(StructOpt, Debug, Clone)]
 pub enum CameraExposureRequest {
     Mode(CameraExposureModeRequest),
+    Compensation(CameraExposureCompensationRequest),
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraExposureCompensationRequest {
+    /// get the current exposure compensation
+    Get,
+
+    /// set the exposure compensation, in stops between -3 and +3, in steps of
+    /// 1/3 (e.g. -0.7 or 1.3)
+    Set {
+        #[structopt(allow_hyphen_values = true)]
+        ev: CameraExposureCompensation,
+    },
+}
+
+impl std::str::FromStr for CameraExposureCompensation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let ev: f32 = s
+            .trim_end_matches("ev")
+            .trim_end_matches("EV")
+            .trim()
+            .parse()
+            .context("invalid exposure compensation")?;
+
+        let thirds = (ev * 3.).round();
+
+        // allow for rounding, since 1/3 can't be typed exactly
+        if (ev * 3. - thirds).abs() > 0.11 {
+            bail!("exposure compensation must be a multiple of 1/3 stop");
+        }
+
+        if thirds.abs() > CameraExposureCompensation::MAX_THIRDS as f32 {
+            bail!("exposure compensation must be between -3 and +3 stops");
+        }
+
+        CameraExposureCompensation::from_thirds(thirds as i8)
+            .context("invalid exposure compensation")
+    }
 }
 
 #[derive(StructOpt, Debug, Clone)]
@@ -102,6 +325,178 @@ impl std::str::FromStr for CameraExposureMode {
     }
 }
 
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraFocusRequest {
+    Mode(CameraFocusModeRequest),
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraFocusModeRequest {
+    /// get the current focus mode
+    Get,
+
+    /// set the focus mode (manual, af-s, or af-c); manual focus cannot be
+    /// selected during continuous capture
+    Set { mode: CameraFocusMode },
+}
+
+impl std::str::FromStr for CameraFocusMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "manual" | "mf" => Ok(CameraFocusMode::Manual),
+            "af-s" => Ok(CameraFocusMode::AutoFocusStill),
+            "af-c" => Ok(CameraFocusMode::AutoFocusContinuous),
+            _ => bail!("invalid focus mode"),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraApertureRequest {
+    /// get the current aperture
+    Get,
+
+    /// set the aperture, as an f-number (e.g. f4 or 5.6)
+    Set { aperture: CameraAperture },
+}
+
+impl std::str::FromStr for CameraAperture {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let f_number: f32 = s
+            .trim_start_matches(|c| c == 'f' || c == 'F')
+            .trim_start_matches('/')
+            .parse()
+            .context("invalid aperture")?;
+
+        CameraAperture::from_f_number(f_number).context("invalid aperture")
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraShutterRequest {
+    /// get the current shutter speed
+    Get,
+
+    /// set the shutter speed, as a fraction (1/2000), a number of seconds
+    /// (2.5), or bulb; bulb is only allowed in manual exposure mode
+    Set { speed: CameraShutterSpeed },
+}
+
+impl std::str::FromStr for CameraShutterSpeed {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("bulb") {
+            return Ok(CameraShutterSpeed::Bulb);
+        }
+
+        let (numerator, denominator) = if let Some(idx) = s.find('/') {
+            let numerator = s[..idx].parse().context("invalid shutter speed")?;
+            let denominator = s[idx + 1..].parse().context("invalid shutter speed")?;
+            (numerator, denominator)
+        } else {
+            // the camera counts long exposures in tenths of a second
+            let seconds: f32 = s
+                .trim_end_matches(|c| c == 's' || c == '"')
+                .parse()
+                .context("invalid shutter speed")?;
+            let tenths = (seconds * 10.).round();
+
+            if tenths < 1. || tenths > u16::MAX as f32 {
+                bail!("invalid shutter speed");
+            }
+
+            (tenths as u16, 10)
+        };
+
+        if numerator == 0 || denominator == 0 {
+            bail!("invalid shutter speed");
+        }
+
+        Ok(CameraShutterSpeed::Fraction {
+            numerator,
+            denominator,
+        })
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraIsoRequest {
+    /// get the current ISO
+    Get,
+
+    /// set the ISO to a number or to auto; only allowed in exposure modes
+    /// that support manual ISO
+    Set { iso: CameraIso },
+}
+
+impl std::str::FromStr for CameraIso {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(CameraIso::Auto),
+            _ => match s.parse() {
+                Ok(iso) if iso > 0 && iso < 0x00FF_FFFF => Ok(CameraIso::Value(iso)),
+                _ => bail!("invalid iso"),
+            },
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraWhiteBalanceRequest {
+    /// get the current white balance mode, and the color temperature if it
+    /// is set manually
+    Get,
+
+    /// set the white balance mode
+    Set { mode: CameraWhiteBalance },
+
+    /// set the white balance to a color temperature between 2500 and 9900
+    /// kelvin
+    SetTemperature { kelvin: u16 },
+}
+
+impl std::str::FromStr for CameraWhiteBalance {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" | "awb" => Ok(CameraWhiteBalance::Auto),
+            "daylight" => Ok(CameraWhiteBalance::Daylight),
+            "tungsten" | "incandescent" => Ok(CameraWhiteBalance::Tungsten),
+            "flash" => Ok(CameraWhiteBalance::Flash),
+            "fluorescent-warm-white" => Ok(CameraWhiteBalance::FluorescentWarmWhite),
+            "fluorescent-cool-white" => Ok(CameraWhiteBalance::FluorescentCoolWhite),
+            "fluorescent-day-white" => Ok(CameraWhiteBalance::FluorescentDayWhite),
+            "fluorescent-daylight" => Ok(CameraWhiteBalance::FluorescentDaylight),
+            "cloudy" => Ok(CameraWhiteBalance::Cloudy),
+            "shade" => Ok(CameraWhiteBalance::Shade),
+            "temperature" | "color-temperature" => Ok(CameraWhiteBalance::ColorTemperature),
+            "custom-1" => Ok(CameraWhiteBalance::Custom1),
+            "custom-2" => Ok(CameraWhiteBalance::Custom2),
+            "custom-3" => Ok(CameraWhiteBalance::Custom3),
+            "underwater-auto" => Ok(CameraWhiteBalance::UnderwaterAuto),
+            _ => bail!("invalid white balance mode"),
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraMovieRequest {
+    /// switch to movie mode and start recording; not allowed during
+    /// continuous capture
+    Start,
+
+    /// stop recording
+    Stop,
+}
+
 #[derive(StructOpt, Debug, Clone)]
 pub enum CameraSaveModeRequest {
     /// get the current save mode
@@ -123,21 +518,67 @@ impl std::str::FromStr for CameraSaveMode {
     }
 }
 
+#[derive(StructOpt, Debug, Clone)]
+pub enum CameraLiveViewResolutionRequest {
+    /// get the current live view resolution
+    Get,
+
+    /// set the current live view resolution
+    Set {
+        resolution: CameraLiveViewResolution,
+    },
+}
+
+impl std::str::FromStr for CameraLiveViewResolution {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "low" => Ok(CameraLiveViewResolution::Low),
+            "high" => Ok(CameraLiveViewResolution::High),
+            _ => bail!("invalid live view resolution"),
+        }
+    }
+}
+
 #[derive(StructOpt, Debug, Clone)]
 pub enum CameraZoomRequest {
     Level(CameraZoomLevelRequest),
     Mode(CameraZoomModeRequest),
+
+    /// zoom to a preset level: wide, mid, tele or max
+    Preset {
+        preset: CameraZoomPreset,
+    },
+}
+
+impl std::str::FromStr for CameraZoomPreset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wide" => Ok(CameraZoomPreset::Wide),
+            "mid" => Ok(CameraZoomPreset::Mid),
+            "tele" => Ok(CameraZoomPreset::Tele),
+            "max" => Ok(CameraZoomPreset::Max),
+            _ => bail!("invalid zoom preset, expected wide, mid, tele or max"),
+        }
+    }
 }
 
 #[derive(StructOpt, Debug, Clone)]
 pub enum CameraZoomLevelRequest {
     Get,
-    Set { level: u8 },
+    Set { level: u16 },
 }
 
 #[derive(StructOpt, Debug, Clone)]
 pub enum CameraZoomModeRequest {
+    /// only use the optical zoom; zooms out to the end of the optical range
+    /// if the lens is further in
     Optical,
+
+    /// zoom digitally past the end of the optical range
     OpticalDigital,
 }
 
@@ -149,9 +590,18 @@ pub enum CameraPowerRequest {
 
 #[derive(StructOpt, Debug, Clone)]
 pub enum CameraContinuousCaptureRequest {
+    /// start capturing; does nothing if we are already capturing
     Start,
+
+    /// stop capturing; does nothing if we are not capturing
     Stop,
-    Interval { interval: f32 },
+
+    Interval {
+        interval: f32,
+    },
+
+    /// check whether we are capturing
+    Status,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -163,19 +613,271 @@ pub enum CameraResponse {
     File {
         path: std::path::PathBuf,
     },
+    Files {
+        paths: Vec<std::path::PathBuf>,
+    },
+    DownloadStatus {
+        paused: bool,
+    },
+    ContinuousCapture {
+        active: bool,
+    },
+    Time {
+        #[serde(with = "serde_millis")]
+        time: std::time::SystemTime,
+    },
+    Errors {
+        errors: Vec<CameraErrorMode>,
+    },
+    TimedCapture {
+        /// when the shutter was released
+        #[serde(with = "serde_millis")]
+        fire_time: std::time::SystemTime,
+        /// how long the camera took to confirm the capture after the shutter
+        /// was released
+        latency_ms: u64,
+        time_source: TimeSource,
+        path: Option<std::path::PathBuf>,
+    },
+    PropertyDump {
+        properties: Vec<CameraPropertyDump>,
+    },
+    VerifySettings {
+        /// true if every property has its expected value
+        ok: bool,
+        results: Vec<CameraSettingCheck>,
+    },
+    Flushed {
+        /// how many images were synced to disk
+        files: usize,
+    },
+    SelfTest {
+        /// true if every check passed
+        passed: bool,
+        results: Vec<CameraSelfTestCheck>,
+    },
+    Benchmark {
+        bytes: usize,
+        transfer_time_ms: u64,
+        /// in megabytes per second
+        throughput: f64,
+        /// average time for a command with no payload to go to the camera and
+        /// back
+        latency_ms: f64,
+    },
     StorageInfo {
         storages: HashMap<ptp::StorageId, ptp::PtpStorageInfo>,
     },
     ObjectInfo {
         objects: HashMap<ptp::ObjectHandle, ptp::PtpObjectInfo>,
     },
+    FileFormats {
+        formats: HashMap<CameraFileFormat, usize>,
+    },
     ZoomLevel {
-        zoom_level: u8,
+        zoom_level: u16,
+    },
+    ZoomMode {
+        zoom_mode: CameraZoomMode,
     },
     SaveMode {
         save_mode: CameraSaveMode,
     },
+    LiveViewResolution {
+        resolution: CameraLiveViewResolution,
+    },
     ExposureMode {
         exposure_mode: CameraExposureMode,
     },
+    Iso {
+        iso: CameraIso,
+    },
+    FocusMode {
+        focus_mode: CameraFocusMode,
+    },
+    Aperture {
+        aperture: CameraAperture,
+    },
+    ShutterSpeed {
+        shutter_speed: CameraShutterSpeed,
+    },
+    ExposureCompensation {
+        compensation: CameraExposureCompensation,
+    },
+    WhiteBalance {
+        white_balance: CameraWhiteBalance,
+        /// `None` unless the white balance is set to a color temperature.
+        color_temperature: Option<u16>,
+    },
+    Movie {
+        recording: bool,
+    },
+    Battery {
+        /// the charge left in the battery, in percent
+        level_percent: u8,
+        /// the battery icon shown on the camera, if the camera reports it
+        indicator: Option<CameraBatteryLevel>,
+    },
+    RemainingShots {
+        free_bytes: u64,
+        /// `None` if there is not enough information to make an estimate yet.
+        estimate: Option<u64>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CameraPropertyDump {
+    pub code: u16,
+    pub name: String,
+    /// whether the property can be changed in the camera's current state
+    pub writable: bool,
+    pub current: String,
+    /// the values that the camera will accept, or a single min..=max range;
+    /// empty if the camera does not say
+    pub allowed: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CameraSelfTestCheck {
+    pub name: String,
+    pub passed: bool,
+    /// Why the check failed.
+    pub error: Option<String>,
+}
+
+impl CameraSelfTestCheck {
+    pub fn new(name: &str, result: anyhow::Result<()>) -> Self {
+        CameraSelfTestCheck {
+            name: name.to_owned(),
+            passed: result.is_ok(),
+            error: result.err().map(|err| format!("{:#}", err)),
+        }
+    }
+
+    pub fn all_passed(checks: &[CameraSelfTestCheck]) -> bool {
+        checks.iter().all(|check| check.passed)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CameraSettingCheck {
+    pub property: String,
+    pub expected: String,
+    /// `None` if the camera did not report this property.
+    pub actual: Option<String>,
+    pub matches: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_test_fails_if_any_check_fails() {
+        let checks = vec![
+            CameraSelfTestCheck::new("connected", Ok(())),
+            CameraSelfTestCheck::new(
+                "battery",
+                Err::<(), _>(anyhow!("battery is at 10%, below 20%")).context("battery check"),
+            ),
+            CameraSelfTestCheck::new("storage", Ok(())),
+        ];
+
+        assert!(!CameraSelfTestCheck::all_passed(&checks));
+
+        assert!(checks[0].passed);
+        assert_eq!(checks[0].error, None);
+
+        assert!(!checks[1].passed);
+        assert_eq!(
+            checks[1].error.as_deref(),
+            Some("battery check: battery is at 10%, below 20%")
+        );
+    }
+
+    #[test]
+    fn self_test_passes_if_every_check_passes() {
+        let checks = vec![
+            CameraSelfTestCheck::new("connected", Ok(())),
+            CameraSelfTestCheck::new("storage", Ok(())),
+        ];
+
+        assert!(CameraSelfTestCheck::all_passed(&checks));
+    }
+
+    #[test]
+    fn parses_exposure_compensation() {
+        let parse = |s: &str| s.parse::<CameraExposureCompensation>();
+
+        assert_eq!(parse("+0.3").unwrap().to_millistops(), 300);
+        assert_eq!(parse("-0.7").unwrap().to_millistops(), -700);
+        assert_eq!(parse("1").unwrap().to_millistops(), 1000);
+        assert_eq!(parse("-3 EV").unwrap().to_millistops(), -3000);
+
+        // more than 3 stops
+        assert!(parse("3.3").is_err());
+
+        // not a multiple of a third
+        assert!(parse("0.5").is_err());
+
+        assert!(parse("lots").is_err());
+    }
+
+    #[test]
+    fn parses_iso() {
+        assert_eq!("auto".parse::<CameraIso>().unwrap(), CameraIso::Auto);
+        assert_eq!("100".parse::<CameraIso>().unwrap(), CameraIso::Value(100));
+
+        assert!("0".parse::<CameraIso>().is_err());
+        assert!("-100".parse::<CameraIso>().is_err());
+        assert!("fast".parse::<CameraIso>().is_err());
+    }
+
+    #[test]
+    fn parses_shutter_speed() {
+        let parse = |s: &str| s.parse::<CameraShutterSpeed>();
+
+        assert_eq!(
+            parse("1/2000").unwrap(),
+            CameraShutterSpeed::Fraction {
+                numerator: 1,
+                denominator: 2000
+            }
+        );
+
+        // long exposures are in tenths of a second
+        assert_eq!(
+            parse("2.5").unwrap(),
+            CameraShutterSpeed::Fraction {
+                numerator: 25,
+                denominator: 10
+            }
+        );
+        assert_eq!(parse("2.5s").unwrap(), parse("2.5").unwrap());
+
+        assert_eq!(parse("BULB").unwrap(), CameraShutterSpeed::Bulb);
+
+        assert!(parse("0/1").is_err());
+        assert!(parse("0/x").is_err());
+        assert!(parse("1/0").is_err());
+        assert!(parse("0").is_err());
+        assert!(parse("slow").is_err());
+    }
+
+    #[test]
+    fn parses_aperture() {
+        let parse = |s: &str| {
+            s.parse::<CameraAperture>()
+                .map(|aperture| aperture.to_u16())
+        };
+
+        assert_eq!(parse("f4").unwrap(), 400);
+        assert_eq!(parse("F/2.8").unwrap(), 280);
+        assert_eq!(parse("5.6").unwrap(), 560);
+
+        assert!(parse("f").is_err());
+        assert!(parse("f/").is_err());
+        assert!(parse("5.6.1").is_err());
+        assert!(parse("0").is_err());
+    }
 }
@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+
+use ptp::{ObjectHandle, StorageId};
+
+use super::interface::{CameraControlCode, CameraPropertyCode};
+
+/// The connection that the camera client talks to the camera through. The
+/// R10C over USB implements this; the tests use a fake camera instead, so
+/// that the client can be tested without one plugged in.
+///
+/// Every method blocks until the camera replies, and fails if it doesn't
+/// reply within a few seconds.
+pub trait CameraDriver: Send {
+    /// Opens a session with the camera.
+    fn connect(&mut self) -> anyhow::Result<()>;
+
+    /// Closes the session with the camera.
+    fn disconnect(&mut self) -> anyhow::Result<()>;
+
+    /// Resets the USB connection to the camera, which closes the session.
+    fn reset(&mut self) -> anyhow::Result<()>;
+
+    /// Reads every property from the camera and returns them.
+    fn update(&mut self) -> anyhow::Result<&HashMap<CameraPropertyCode, ptp::PtpPropInfo>>;
+
+    /// Gets a property as of the last call to `update`, without asking the
+    /// camera.
+    fn get(&self, code: CameraPropertyCode) -> Option<ptp::PtpPropInfo>;
+
+    /// Asks the camera to change a property. The camera may ignore this, so
+    /// the property should be read back afterwards.
+    fn set(&mut self, code: CameraPropertyCode, new_value: ptp::PtpData) -> anyhow::Result<()>;
+
+    /// Operates one of the camera's controls, e.g. pressing a button.
+    fn execute(&mut self, code: CameraControlCode, payload: ptp::PtpData) -> anyhow::Result<()>;
+
+    /// Waits a short while for an event from the camera.
+    fn recv(&mut self) -> anyhow::Result<ptp::PtpEvent>;
+
+    fn storage_ids(&mut self) -> anyhow::Result<Vec<StorageId>>;
+
+    fn storage_info(&mut self, storage_id: StorageId) -> anyhow::Result<ptp::PtpStorageInfo>;
+
+    fn object_handles(
+        &mut self,
+        storage_id: StorageId,
+        parent_id: Option<ObjectHandle>,
+    ) -> anyhow::Result<Vec<ObjectHandle>>;
+
+    fn object_info(&mut self, object_id: ObjectHandle) -> anyhow::Result<ptp::PtpObjectInfo>;
+
+    fn object_data(&mut self, object_id: ObjectHandle) -> anyhow::Result<Vec<u8>>;
+
+    /// Downloads up to `max` bytes of an object, starting at `offset`.
+    fn object_data_partial(
+        &mut self,
+        object_id: ObjectHandle,
+        offset: u32,
+        max: u32,
+    ) -> anyhow::Result<Vec<u8>>;
+
+    /// Deletes an object from the camera's memory card.
+    fn delete_object(&mut self, object_id: ObjectHandle) -> anyhow::Result<()>;
+}
@@ -4,6 +4,8 @@ use ptp::{ObjectHandle, PtpRead, StorageId};
 use std::io::Cursor;
 use std::{collections::HashMap, collections::HashSet, fmt::Debug, time::Duration};
 
+use super::driver::CameraDriver;
+
 /// Sony's USB vendor ID
 const SONY_USB_VID: u16 = 0x054C;
 /// Sony R10C camera's product ID
@@ -12,6 +14,10 @@ const SONY_USB_R10C_PID: u16 = 0x0A79;
 const SONY_USB_R10C_PID_CHARGING: u16 = 0x0994;
 /// Sony's PTP extension vendor ID
 const SONY_PTP_VID: u16 = 0x0011;
+/// How long a single USB transfer to the camera may take. The camera is
+/// talked to synchronously, so a command can't be abandoned in the middle of
+/// a transfer; this bounds how far past its timeout a hung command can run.
+pub const USB_TIMEOUT: Duration = Duration::from_secs(5);
 
 #[repr(u16)]
 #[derive(ToPrimitive, FromPrimitive, Copy, Clone, Eq, PartialEq, Debug)]
@@ -84,6 +90,28 @@ pub enum CameraPropertyCode {
     Zoom = 0xD6C9,
 }
 
+impl std::str::FromStr for CameraPropertyCode {
+    type Err = anyhow::Error;
+
+    /// Parses either a property name (case-insensitive, dashes and underscores
+    /// ignored, e.g. "save-media") or a hexadecimal property code (e.g.
+    /// "0xD6BA").
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            let code = u16::from_str_radix(hex, 16).context("invalid property code")?;
+            return CameraPropertyCode::from_u16(code).context("unknown property code");
+        }
+
+        let name = s.replace(|c| c == '-' || c == '_', "").to_lowercase();
+
+        // all of the properties are in the Sony vendor range
+        (0xD600..=0xD6FF)
+            .filter_map(CameraPropertyCode::from_u16)
+            .find(|code| format!("{:?}", code).to_lowercase() == name)
+            .context("unknown property name")
+    }
+}
+
 #[repr(u16)]
 #[derive(ToPrimitive, FromPrimitive, Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum CameraControlCode {
@@ -129,7 +157,7 @@ struct CameraState {
 
 impl CameraInterface {
     pub fn timeout(&self) -> Option<Duration> {
-        Some(Duration::from_secs(5))
+        Some(USB_TIMEOUT)
     }
 
     pub fn new() -> anyhow::Result<Self> {
@@ -143,7 +171,13 @@ impl CameraInterface {
         })
     }
 
-    pub fn connect(&mut self) -> anyhow::Result<()> {
+    pub fn device_info(&mut self) -> anyhow::Result<ptp::PtpDeviceInfo> {
+        Ok(self.camera.get_device_info(self.timeout())?)
+    }
+}
+
+impl CameraDriver for CameraInterface {
+    fn connect(&mut self) -> anyhow::Result<()> {
         self.camera.open_session(self.timeout())?;
 
         let key_code = 0x0000DA01;
@@ -240,7 +274,7 @@ impl CameraInterface {
         Ok(())
     }
 
-    pub fn disconnect(&mut self) -> anyhow::Result<()> {
+    fn disconnect(&mut self) -> anyhow::Result<()> {
         self.camera.close_session(self.timeout())?;
 
         self.state = None;
@@ -248,7 +282,7 @@ impl CameraInterface {
         Ok(())
     }
 
-    pub fn reset(&mut self) -> anyhow::Result<()> {
+    fn reset(&mut self) -> anyhow::Result<()> {
         self.camera.reset()?;
 
         Ok(())
@@ -256,7 +290,7 @@ impl CameraInterface {
 
     /// Queries the camera for its current state and updates the hashmap held by
     /// this interface.
-    pub fn update(&mut self) -> anyhow::Result<&HashMap<CameraPropertyCode, ptp::PtpPropInfo>> {
+    fn update(&mut self) -> anyhow::Result<&HashMap<CameraPropertyCode, ptp::PtpPropInfo>> {
         let timeout = self.timeout();
 
         let state = if let Some(ref mut state) = self.state {
@@ -298,7 +332,7 @@ impl CameraInterface {
 
     /// Gets information about a camera property from the hashmap. This method
     /// does NOT query the camera itself.
-    pub fn get(&self, code: CameraPropertyCode) -> Option<ptp::PtpPropInfo> {
+    fn get(&self, code: CameraPropertyCode) -> Option<ptp::PtpPropInfo> {
         let state = if let Some(ref state) = self.state {
             state
         } else {
@@ -312,7 +346,7 @@ impl CameraInterface {
     /// Sets the value of a camera property. This should be followed by a call
     /// to update() and a check to make sure that the intended result was
     /// achieved.
-    pub fn set(&mut self, code: CameraPropertyCode, new_value: ptp::PtpData) -> anyhow::Result<()> {
+    fn set(&mut self, code: CameraPropertyCode, new_value: ptp::PtpData) -> anyhow::Result<()> {
         let state = if let Some(ref state) = self.state {
             state
         } else {
@@ -344,11 +378,7 @@ impl CameraInterface {
 
     /// Executes a command on the camera. This should be followed by a call to
     /// update() and a check to make sure that the intended result was achieved.
-    pub fn execute(
-        &mut self,
-        code: CameraControlCode,
-        payload: ptp::PtpData,
-    ) -> anyhow::Result<()> {
+    fn execute(&mut self, code: CameraControlCode, payload: ptp::PtpData) -> anyhow::Result<()> {
         if let None = self.state {
             warn!("execute() called when camera is not connected");
         };
@@ -368,26 +398,22 @@ impl CameraInterface {
     }
 
     /// Receives an event from the camera.
-    pub fn recv(&mut self) -> anyhow::Result<ptp::PtpEvent> {
+    fn recv(&mut self) -> anyhow::Result<ptp::PtpEvent> {
         let event = self.camera.event(Some(Duration::from_secs(1)))?;
 
         trace!("received event: {:#?}", &event);
         Ok(event)
     }
 
-    pub fn device_info(&mut self) -> anyhow::Result<ptp::PtpDeviceInfo> {
-        Ok(self.camera.get_device_info(self.timeout())?)
-    }
-
-    pub fn storage_ids(&mut self) -> anyhow::Result<Vec<StorageId>> {
+    fn storage_ids(&mut self) -> anyhow::Result<Vec<StorageId>> {
         Ok(self.camera.get_storage_ids(self.timeout())?)
     }
 
-    pub fn storage_info(&mut self, storage_id: StorageId) -> anyhow::Result<ptp::PtpStorageInfo> {
+    fn storage_info(&mut self, storage_id: StorageId) -> anyhow::Result<ptp::PtpStorageInfo> {
         Ok(self.camera.get_storage_info(storage_id, self.timeout())?)
     }
 
-    pub fn object_handles(
+    fn object_handles(
         &mut self,
         storage_id: StorageId,
         parent_id: Option<ObjectHandle>,
@@ -397,11 +423,37 @@ impl CameraInterface {
             .get_object_handles(storage_id, None, parent_id, self.timeout())?)
     }
 
-    pub fn object_info(&mut self, object_id: ObjectHandle) -> anyhow::Result<ptp::PtpObjectInfo> {
+    fn object_info(&mut self, object_id: ObjectHandle) -> anyhow::Result<ptp::PtpObjectInfo> {
         Ok(self.camera.get_object_info(object_id, self.timeout())?)
     }
 
-    pub fn object_data(&mut self, object_id: ObjectHandle) -> anyhow::Result<Vec<u8>> {
+    fn object_data(&mut self, object_id: ObjectHandle) -> anyhow::Result<Vec<u8>> {
         Ok(self.camera.get_object(object_id, self.timeout())?)
     }
+
+    /// Deletes an object from the camera's memory card.
+    fn delete_object(&mut self, object_id: ObjectHandle) -> anyhow::Result<()> {
+        trace!("sending SDIO_ExtDeviceDeleteObject");
+
+        self.camera.command(
+            SonyCommandCode::SdioExtDeviceDeleteObject.into(),
+            &[u32::from(object_id)],
+            None,
+            self.timeout(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Downloads up to `max` bytes of an object, starting at `offset`.
+    fn object_data_partial(
+        &mut self,
+        object_id: ObjectHandle,
+        offset: u32,
+        max: u32,
+    ) -> anyhow::Result<Vec<u8>> {
+        Ok(self
+            .camera
+            .get_partialobject(object_id, offset, max, self.timeout())?)
+    }
 }
@@ -0,0 +1,435 @@
+//! A fake camera for testing the camera client without a camera plugged in.
+//! The test keeps a handle to the camera's state, so that it can change what
+//! the camera reports and check what the client asked it to do.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    io::Cursor,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use ptp::{ObjectHandle, PtpData, StorageId};
+
+use super::driver::CameraDriver;
+use super::interface::{CameraControlCode, CameraPropertyCode};
+
+/// Something that the client asked the fake camera to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall {
+    Connect,
+    Disconnect,
+    Reset,
+    Set(CameraPropertyCode, PtpData),
+    Execute(CameraControlCode, PtpData),
+    Delete(u32),
+    DataPartial { handle: u32, offset: u32, max: u32 },
+}
+
+/// Reacts to a control being operated, e.g. by changing a property or
+/// sending an event.
+pub type ExecuteHook = Box<dyn FnMut(&mut MockState, CameraControlCode, &PtpData) + Send>;
+
+/// A file on the fake memory card.
+pub struct MockObject {
+    pub info: ptp::PtpObjectInfo,
+    pub data: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct MockState {
+    pub properties: HashMap<CameraPropertyCode, ptp::PtpPropInfo>,
+
+    /// Properties that the camera doesn't change when asked to.
+    pub read_only: HashSet<CameraPropertyCode>,
+
+    /// Controls that fail when they are operated.
+    pub failing_controls: HashSet<CameraControlCode>,
+
+    /// Events waiting to be received, oldest first.
+    pub events: VecDeque<ptp::PtpEvent>,
+
+    /// The storage IDs reported by each call to `storage_ids`; the last list
+    /// is repeated once the others have been used up.
+    pub storage_ids: VecDeque<Vec<StorageId>>,
+
+    pub objects: BTreeMap<u32, MockObject>,
+
+    pub on_execute: Option<ExecuteHook>,
+
+    pub connected: bool,
+
+    /// Everything that the client asked for, in order.
+    pub calls: Vec<MockCall>,
+}
+
+impl MockState {
+    /// A camera in still image mode that saves images to the host, with no
+    /// errors and a memory card that is ready.
+    pub fn shared() -> Arc<Mutex<MockState>> {
+        let mut state = MockState::default();
+
+        state.set_prop(CameraPropertyCode::OperatingMode, PtpData::UINT8(0x02));
+        state.set_prop(CameraPropertyCode::SaveMedia, PtpData::UINT16(0x0001));
+        state.set_prop(CameraPropertyCode::Caution, PtpData::UINT16(0x0000));
+        state.set_prop(CameraPropertyCode::DriveMode, PtpData::UINT16(0x0001));
+        state.set_prop(CameraPropertyCode::FocusMode, PtpData::UINT16(0x0002));
+        state.set_prop(CameraPropertyCode::FocusIndication, PtpData::UINT8(0x02));
+        state.set_prop(CameraPropertyCode::BatteryRemain, PtpData::UINT8(80));
+
+        state
+            .storage_ids
+            .push_back(vec![StorageId::from(0x00010001)]);
+
+        Arc::new(Mutex::new(state))
+    }
+
+    /// Changes the current value of a property, adding it if the camera
+    /// didn't report it before.
+    pub fn set_prop(&mut self, code: CameraPropertyCode, value: PtpData) {
+        let prop = match self.properties.get(&code) {
+            Some(prop) => PropBuilder::of(prop).current(value),
+            None => PropBuilder::new(code, value),
+        };
+
+        self.properties.insert(code, prop.build());
+    }
+
+    pub fn current(&self, code: CameraPropertyCode) -> Option<PtpData> {
+        self.properties.get(&code).map(|prop| prop.current.clone())
+    }
+
+    /// Puts a file on the memory card.
+    pub fn add_object(&mut self, handle: u32, filename: &str, format: u16, data: Vec<u8>) {
+        let info = object_info(filename, format, data.len() as u32);
+        self.objects.insert(handle, MockObject { info, data });
+    }
+
+    /// The controls that were operated, in order.
+    pub fn executed(&self) -> Vec<(CameraControlCode, PtpData)> {
+        self.calls
+            .iter()
+            .filter_map(|call| match call {
+                MockCall::Execute(code, payload) => Some((*code, payload.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The values that a property was set to, in order.
+    pub fn sets(&self, code: CameraPropertyCode) -> Vec<PtpData> {
+        self.calls
+            .iter()
+            .filter_map(|call| match call {
+                MockCall::Set(set_code, value) if *set_code == code => Some(value.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+pub struct MockCamera {
+    state: Arc<Mutex<MockState>>,
+
+    /// The properties as of the last `update`, like the real camera.
+    properties: HashMap<CameraPropertyCode, ptp::PtpPropInfo>,
+}
+
+impl MockCamera {
+    pub fn new(state: Arc<Mutex<MockState>>) -> Self {
+        MockCamera {
+            state,
+            properties: HashMap::new(),
+        }
+    }
+
+    fn call(&self, call: MockCall) -> std::sync::MutexGuard<'_, MockState> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(call);
+        state
+    }
+}
+
+impl CameraDriver for MockCamera {
+    fn connect(&mut self) -> anyhow::Result<()> {
+        self.call(MockCall::Connect).connected = true;
+        Ok(())
+    }
+
+    fn disconnect(&mut self) -> anyhow::Result<()> {
+        self.call(MockCall::Disconnect).connected = false;
+        Ok(())
+    }
+
+    fn reset(&mut self) -> anyhow::Result<()> {
+        self.call(MockCall::Reset).connected = false;
+        Ok(())
+    }
+
+    fn update(&mut self) -> anyhow::Result<&HashMap<CameraPropertyCode, ptp::PtpPropInfo>> {
+        let state = self.state.lock().unwrap();
+
+        if !state.connected {
+            bail!("the camera is not connected");
+        }
+
+        self.properties = state.properties.clone();
+        drop(state);
+
+        Ok(&self.properties)
+    }
+
+    fn get(&self, code: CameraPropertyCode) -> Option<ptp::PtpPropInfo> {
+        self.properties.get(&code).cloned()
+    }
+
+    fn set(&mut self, code: CameraPropertyCode, new_value: PtpData) -> anyhow::Result<()> {
+        let mut state = self.call(MockCall::Set(code, new_value.clone()));
+
+        if !state.read_only.contains(&code) {
+            state.set_prop(code, new_value);
+        }
+
+        Ok(())
+    }
+
+    fn execute(&mut self, code: CameraControlCode, payload: PtpData) -> anyhow::Result<()> {
+        let mut state = self.call(MockCall::Execute(code, payload.clone()));
+
+        if state.failing_controls.contains(&code) {
+            bail!("camera rejected {:?}", code);
+        }
+
+        let mut hook = state.on_execute.take();
+
+        if let Some(hook) = hook.as_mut() {
+            hook(&mut *state, code, &payload);
+        }
+
+        state.on_execute = hook;
+
+        Ok(())
+    }
+
+    fn recv(&mut self) -> anyhow::Result<ptp::PtpEvent> {
+        self.state
+            .lock()
+            .unwrap()
+            .events
+            .pop_front()
+            .context("no events")
+    }
+
+    fn storage_ids(&mut self) -> anyhow::Result<Vec<StorageId>> {
+        let mut state = self.state.lock().unwrap();
+
+        let ids = if state.storage_ids.len() > 1 {
+            state.storage_ids.pop_front()
+        } else {
+            state.storage_ids.front().cloned()
+        };
+
+        Ok(ids.unwrap_or_default())
+    }
+
+    fn storage_info(&mut self, _storage_id: StorageId) -> anyhow::Result<ptp::PtpStorageInfo> {
+        bail!("the fake camera doesn't report storage info")
+    }
+
+    fn object_handles(
+        &mut self,
+        _storage_id: StorageId,
+        _parent_id: Option<ObjectHandle>,
+    ) -> anyhow::Result<Vec<ObjectHandle>> {
+        let state = self.state.lock().unwrap();
+        Ok(state
+            .objects
+            .keys()
+            .map(|&h| ObjectHandle::from(h))
+            .collect())
+    }
+
+    fn object_info(&mut self, object_id: ObjectHandle) -> anyhow::Result<ptp::PtpObjectInfo> {
+        let state = self.state.lock().unwrap();
+
+        match state.objects.get(&u32::from(object_id)) {
+            Some(object) => Ok(object.info.clone()),
+            None => bail!("invalid object handle"),
+        }
+    }
+
+    fn object_data(&mut self, object_id: ObjectHandle) -> anyhow::Result<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+
+        match state.objects.get(&u32::from(object_id)) {
+            Some(object) => Ok(object.data.clone()),
+            None => bail!("invalid object handle"),
+        }
+    }
+
+    fn object_data_partial(
+        &mut self,
+        object_id: ObjectHandle,
+        offset: u32,
+        max: u32,
+    ) -> anyhow::Result<Vec<u8>> {
+        let handle = u32::from(object_id);
+        let state = self.call(MockCall::DataPartial {
+            handle,
+            offset,
+            max,
+        });
+
+        match state.objects.get(&handle) {
+            Some(object) => {
+                let start = (offset as usize).min(object.data.len());
+                let end = (start + max as usize).min(object.data.len());
+                Ok(object.data[start..end].to_vec())
+            }
+            None => bail!("invalid object handle"),
+        }
+    }
+
+    fn delete_object(&mut self, object_id: ObjectHandle) -> anyhow::Result<()> {
+        let handle = u32::from(object_id);
+        let mut state = self.call(MockCall::Delete(handle));
+
+        match state.objects.remove(&handle) {
+            Some(_) => Ok(()),
+            None => bail!("invalid object handle"),
+        }
+    }
+}
+
+/// Builds a property the way the camera describes it: as an SDIO
+/// DevicePropInfo dataset.
+pub struct PropBuilder {
+    code: u16,
+    writable: bool,
+    current: PtpData,
+    range: Option<(PtpData, PtpData, PtpData)>,
+}
+
+impl PropBuilder {
+    pub fn new(code: CameraPropertyCode, current: PtpData) -> Self {
+        PropBuilder {
+            code: code as u16,
+            writable: true,
+            current,
+            range: None,
+        }
+    }
+
+    fn of(prop: &ptp::PtpPropInfo) -> Self {
+        let range = match &prop.form {
+            ptp::PtpFormData::Range {
+                min_value,
+                max_value,
+                step,
+            } => Some((min_value.clone(), max_value.clone(), step.clone())),
+            _ => None,
+        };
+
+        PropBuilder {
+            code: prop.property_code,
+            writable: prop.get_set == 1 && prop.is_enable == 1,
+            current: prop.current.clone(),
+            range,
+        }
+    }
+
+    pub fn current(mut self, current: PtpData) -> Self {
+        self.current = current;
+        self
+    }
+
+    pub fn read_only(mut self) -> Self {
+        self.writable = false;
+        self
+    }
+
+    pub fn range(mut self, min: PtpData, max: PtpData, step: PtpData) -> Self {
+        self.range = Some((min, max, step));
+        self
+    }
+
+    pub fn build(self) -> ptp::PtpPropInfo {
+        let mut buf = Vec::new();
+
+        buf.extend_from_slice(&self.code.to_le_bytes());
+        buf.extend_from_slice(&data_type(&self.current).to_le_bytes());
+        // get/set, then is enabled
+        buf.push(self.writable as u8);
+        buf.push(self.writable as u8);
+        // factory default, then current value
+        buf.extend_from_slice(&self.current.encode());
+        buf.extend_from_slice(&self.current.encode());
+
+        match self.range {
+            Some((min, max, step)) => {
+                buf.push(0x01);
+                buf.extend_from_slice(&min.encode());
+                buf.extend_from_slice(&max.encode());
+                buf.extend_from_slice(&step.encode());
+            }
+            None => buf.push(0x00),
+        }
+
+        ptp::PtpPropInfo::decode(&mut Cursor::new(buf)).expect("invalid property")
+    }
+}
+
+fn data_type(data: &PtpData) -> u16 {
+    match data {
+        PtpData::INT8(_) => 0x0001,
+        PtpData::UINT8(_) => 0x0002,
+        PtpData::INT16(_) => 0x0003,
+        PtpData::UINT16(_) => 0x0004,
+        PtpData::INT32(_) => 0x0005,
+        PtpData::UINT32(_) => 0x0006,
+        PtpData::INT64(_) => 0x0007,
+        PtpData::UINT64(_) => 0x0008,
+        PtpData::STR(_) => 0xFFFF,
+        other => panic!("the fake camera doesn't support {:?}", other),
+    }
+}
+
+/// Object formats, as the camera reports them.
+pub const FORMAT_FOLDER: u16 = 0x3001;
+pub const FORMAT_JPEG: u16 = 0x3801;
+pub const FORMAT_ARW: u16 = 0xB101;
+
+/// Describes a file the way the camera does: as a PTP ObjectInfo dataset.
+pub fn object_info(filename: &str, format: u16, size: u32) -> ptp::PtpObjectInfo {
+    let mut buf = Vec::new();
+
+    // storage ID
+    buf.extend_from_slice(&0x00010001u32.to_le_bytes());
+    buf.extend_from_slice(&format.to_le_bytes());
+    // protection status
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    buf.extend_from_slice(&size.to_le_bytes());
+    // thumbnail format, size, width and height
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    buf.extend_from_slice(&[0; 12]);
+    // image width, height and bit depth, parent object
+    buf.extend_from_slice(&[0; 16]);
+    // association type and description, sequence number
+    buf.extend_from_slice(&0u16.to_le_bytes());
+    buf.extend_from_slice(&[0; 8]);
+
+    for string in &[filename, "", "", ""] {
+        buf.extend_from_slice(&PtpData::STR(string.to_string()).encode());
+    }
+
+    ptp::PtpObjectInfo::decode(&buf).expect("invalid object info")
+}
+
+/// The event that the camera sends once it has taken a picture (status 1)
+/// or failed to (status 2).
+pub fn capture_event(status: u32) -> ptp::PtpEvent {
+    ptp::PtpEvent {
+        code: ptp::EventCode::Vendor(0xC204),
+        params: [Some(status), None, None],
+    }
+}
@@ -1,6 +1,9 @@
 pub mod client;
 pub mod command;
+mod driver;
 mod interface;
+#[cfg(test)]
+mod mock;
 pub mod state;
 
 pub use client::*;
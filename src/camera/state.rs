@@ -1,8 +1,243 @@
-use serde::Serialize;
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use num_traits::FromPrimitive;
+use serde::{Deserialize, Serialize};
+
+use crate::state::{Attitude, Coords3D, GpsTime};
 
 #[derive(Debug, Clone)]
 pub enum CameraEvent {
     Error(CameraErrorMode),
+    CaptureReport(CaptureReport),
+    /// The camera was unplugged or lost power; the camera task is trying to
+    /// reconnect.
+    Disconnected,
+    Reconnected,
+    /// Part of a large file has been downloaded from the camera.
+    DownloadProgress {
+        handle: ptp::ObjectHandle,
+        bytes_done: u64,
+        bytes_total: u64,
+    },
+}
+
+/// Hands out capture sequence numbers, the `{index}` of saved files and the
+/// flight segment. The counters are saved to a file whenever they change, so
+/// that they keep increasing if the plane system is restarted mid-flight.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct CaptureSequence {
+    /// The sequence number of the next capture.
+    next_seq: usize,
+
+    /// The local date (YYYY-MM-DD) that `day_count` refers to.
+    day: String,
+
+    /// How many captures were taken on `day`.
+    day_count: usize,
+
+    /// The `{index}` of the next file that is saved with a filename template.
+    #[serde(default)]
+    next_file: u64,
+
+    /// The flight segment that files are saved under; see
+    /// `segment_by_arming`.
+    #[serde(default)]
+    segment: u32,
+
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl CaptureSequence {
+    /// Loads the counter from `path`, starting from zero if the file does not
+    /// exist yet.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut sequence = match std::fs::read(path) {
+            Ok(data) => serde_json::from_slice::<CaptureSequence>(&data)
+                .context("invalid capture sequence file")?,
+            Err(err) if err.kind() == ErrorKind::NotFound => CaptureSequence::default(),
+            Err(err) => return Err(err).context("failed to read capture sequence file"),
+        };
+
+        sequence.path = path.to_owned();
+
+        debug!(
+            "resuming capture sequence at {} ({} captures on {})",
+            sequence.next_seq, sequence.day_count, sequence.day
+        );
+
+        Ok(sequence)
+    }
+
+    /// Returns the next sequence number and saves the new state of the counter.
+    pub fn next(&mut self) -> usize {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+
+        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+
+        if self.day != today {
+            self.day = today;
+            self.day_count = 0;
+        }
+
+        self.day_count += 1;
+
+        if let Err(err) = self.save() {
+            warn!("failed to save capture sequence: {:?}", err);
+        }
+
+        seq
+    }
+
+    /// Returns the index of the next saved file and saves the new state of
+    /// the counter.
+    pub fn next_file(&mut self) -> u64 {
+        let index = self.next_file;
+        self.next_file += 1;
+
+        if let Err(err) = self.save() {
+            warn!("failed to save capture sequence: {:?}", err);
+        }
+
+        index
+    }
+
+    /// The current flight segment.
+    pub fn segment(&self) -> u32 {
+        self.segment
+    }
+
+    /// Starts a new flight segment, saves the new state of the counter and
+    /// returns the new segment.
+    pub fn next_segment(&mut self) -> u32 {
+        self.segment += 1;
+
+        if let Err(err) = self.save() {
+            warn!("failed to save capture sequence: {:?}", err);
+        }
+
+        self.segment
+    }
+
+    /// The file that the counter is saved to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let data = serde_json::to_vec(self)?;
+
+        // write to a temporary file first so that we never leave a truncated
+        // file behind if we lose power halfway through
+        let temp_path = self.path.with_extension("tmp");
+        std::fs::write(&temp_path, data).context("failed to write capture sequence file")?;
+        std::fs::rename(&temp_path, &self.path)
+            .context("failed to replace capture sequence file")?;
+
+        Ok(())
+    }
+}
+
+/// A record of everything that happened to a single image, from pressing the
+/// shutter to saving the image to disk. Stages that did not happen (because
+/// they were not needed or because an earlier stage failed) are `None`.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureReport {
+    pub seq: usize,
+
+    /// When the capture was requested; `None` for continuous capture, where
+    /// the camera triggers itself.
+    #[serde(with = "serde_millis")]
+    pub command_time: Option<SystemTime>,
+
+    /// When the camera confirmed that it took the image.
+    #[serde(with = "serde_millis")]
+    pub confirm_time: Option<SystemTime>,
+
+    /// When we finished transferring the image from the camera.
+    #[serde(with = "serde_millis")]
+    pub download_time: Option<SystemTime>,
+
+    /// When the image was written to disk.
+    #[serde(with = "serde_millis")]
+    pub save_time: Option<SystemTime>,
+
+    pub filename: Option<PathBuf>,
+
+    /// Where the plane was when the image was taken.
+    pub coords: Option<Coords3D>,
+
+    /// How the plane was oriented when the image was taken.
+    pub attitude: Option<Attitude>,
+
+    /// Where `coords` and `attitude` came from.
+    pub geotag_source: GeotagSource,
+
+    pub errors: Vec<String>,
+
+    /// Which clock the times in this report come from.
+    pub time_source: TimeSource,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GeotagSource {
+    /// The CAMERA_FEEDBACK message that the autopilot sent when the image was
+    /// taken.
+    Feedback,
+    /// The latest telemetry when the camera confirmed the capture, used when
+    /// there was no feedback close enough in time.
+    Telemetry,
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSource {
+    /// The autopilot's GPS time, which is what geotagging should use.
+    Gps,
+    /// The clock of the computer running the plane system, used when we have
+    /// not heard the time from the autopilot.
+    Host,
+}
+
+impl CaptureReport {
+    pub fn new(seq: usize, command_time: Option<SystemTime>) -> Self {
+        CaptureReport {
+            seq,
+            command_time,
+            confirm_time: None,
+            download_time: None,
+            save_time: None,
+            filename: None,
+            coords: None,
+            attitude: None,
+            geotag_source: GeotagSource::Telemetry,
+            errors: Vec::new(),
+            time_source: TimeSource::Host,
+        }
+    }
+
+    /// Converts every time in this report from the host clock to GPS time.
+    pub fn convert_to_gps_time(&mut self, gps_time: &GpsTime) {
+        if self.time_source == TimeSource::Gps {
+            return;
+        }
+
+        let convert = |time: Option<SystemTime>| time.map(|time| gps_time.convert(time));
+
+        self.command_time = convert(self.command_time);
+        self.confirm_time = convert(self.confirm_time);
+        self.download_time = convert(self.download_time);
+        self.save_time = convert(self.save_time);
+
+        self.time_source = TimeSource::Gps;
+    }
 }
 
 #[repr(u16)]
@@ -21,6 +256,293 @@ pub enum CameraExposureMode {
     MovieIntelligentAuto,
 }
 
+impl CameraExposureMode {
+    /// Whether the camera lets us choose the ISO in this mode; the auto modes
+    /// always choose it themselves.
+    pub fn supports_manual_iso(&self) -> bool {
+        !matches!(
+            self,
+            CameraExposureMode::IntelligentAuto
+                | CameraExposureMode::SuperiorAuto
+                | CameraExposureMode::MovieIntelligentAuto
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CameraIso {
+    Auto,
+    Value(u32),
+}
+
+impl CameraIso {
+    const AUTO: u32 = 0x00FF_FFFF;
+
+    /// The upper byte holds flags for multi-frame noise reduction, which we
+    /// don't use.
+    const VALUE_MASK: u32 = 0x00FF_FFFF;
+
+    pub fn from_u32(value: u32) -> Self {
+        match value & Self::VALUE_MASK {
+            Self::AUTO => CameraIso::Auto,
+            iso => CameraIso::Value(iso),
+        }
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            CameraIso::Auto => Self::AUTO,
+            CameraIso::Value(iso) => *iso,
+        }
+    }
+}
+
+impl std::fmt::Display for CameraIso {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CameraIso::Auto => write!(f, "auto"),
+            CameraIso::Value(iso) => write!(f, "{}", iso),
+        }
+    }
+}
+
+/// The camera encodes shutter speeds as a fraction of a second, with the
+/// numerator in the upper 16 bits and the denominator in the lower 16 bits.
+#[derive(Debug, Copy, Clone, Serialize, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CameraShutterSpeed {
+    /// The shutter stays open for as long as it is held down.
+    Bulb,
+    Fraction {
+        numerator: u16,
+        denominator: u16,
+    },
+}
+
+impl CameraShutterSpeed {
+    pub fn from_u32(value: u32) -> Self {
+        match value {
+            0 => CameraShutterSpeed::Bulb,
+            _ => CameraShutterSpeed::Fraction {
+                numerator: (value >> 16) as u16,
+                denominator: value as u16,
+            },
+        }
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            CameraShutterSpeed::Bulb => 0,
+            CameraShutterSpeed::Fraction {
+                numerator,
+                denominator,
+            } => (*numerator as u32) << 16 | *denominator as u32,
+        }
+    }
+}
+
+impl std::fmt::Display for CameraShutterSpeed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CameraShutterSpeed::Bulb => write!(f, "bulb"),
+            CameraShutterSpeed::Fraction {
+                numerator: 1,
+                denominator,
+            } if *denominator > 1 => write!(f, "1/{}", denominator),
+            CameraShutterSpeed::Fraction {
+                numerator,
+                denominator,
+            } => write!(f, "{}s", *numerator as f32 / *denominator as f32),
+        }
+    }
+}
+
+/// An f-number, stored the way that the camera encodes it: multiplied by 100.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CameraAperture(u16);
+
+impl CameraAperture {
+    const CLOSED: u16 = 0xFFFD;
+    const UNDEFINED: u16 = 0xFFFE;
+    const NOT_DISPLAYED: u16 = 0xFFFF;
+
+    pub fn from_f_number(f_number: f32) -> Option<Self> {
+        let value = (f_number * 100.).round();
+
+        if value < 1. || value >= Self::CLOSED as f32 {
+            return None;
+        }
+
+        Some(CameraAperture(value as u16))
+    }
+
+    /// Decodes the value of the f-number property. Fails if the lens is not
+    /// reporting an aperture, which happens when no lens is attached or the
+    /// lens does not have an electronic aperture.
+    pub fn from_u16(value: u16) -> anyhow::Result<Self> {
+        match value {
+            Self::CLOSED => bail!("the iris is closed"),
+            Self::UNDEFINED | Self::NOT_DISPLAYED | 0 => {
+                bail!("the lens is not reporting an aperture")
+            }
+            value => Ok(CameraAperture(value)),
+        }
+    }
+
+    pub fn to_u16(&self) -> u16 {
+        self.0
+    }
+
+    pub fn f_number(&self) -> f32 {
+        self.0 as f32 / 100.
+    }
+}
+
+impl Serialize for CameraAperture {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(self.f_number())
+    }
+}
+
+impl std::fmt::Display for CameraAperture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "F{}", self.f_number())
+    }
+}
+
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, Serialize, Eq, PartialEq)]
+pub enum CameraWhiteBalance {
+    Auto = 0x0002,
+    Daylight = 0x0004,
+    Tungsten = 0x0006,
+    Flash = 0x0007,
+    FluorescentWarmWhite = 0x8001,
+    FluorescentCoolWhite = 0x8002,
+    FluorescentDayWhite = 0x8003,
+    FluorescentDaylight = 0x8004,
+    Cloudy = 0x8010,
+    Shade = 0x8011,
+    /// Uses the color temperature property instead of a preset.
+    ColorTemperature = 0x8012,
+    Custom1 = 0x8020,
+    Custom2 = 0x8021,
+    Custom3 = 0x8022,
+    UnderwaterAuto = 0x8030,
+}
+
+/// Exposure compensation, in thirds of a stop. The camera encodes this in
+/// thousandths of a stop, but rounds the thirds: +1/3 EV is 300 and +2/3 EV is
+/// 700.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct CameraExposureCompensation {
+    thirds: i8,
+}
+
+impl CameraExposureCompensation {
+    /// The camera allows up to 3 stops in either direction.
+    pub const MAX_THIRDS: i8 = 9;
+
+    pub fn from_thirds(thirds: i8) -> Option<Self> {
+        if thirds.abs() > Self::MAX_THIRDS {
+            return None;
+        }
+
+        Some(CameraExposureCompensation { thirds })
+    }
+
+    pub fn from_millistops(millistops: i16) -> Option<Self> {
+        let magnitude = millistops.abs();
+
+        let fraction = match magnitude % 1000 {
+            0 => 0,
+            300 => 1,
+            700 => 2,
+            _ => return None,
+        };
+
+        let thirds = (magnitude / 1000 * 3 + fraction) as i8;
+
+        Self::from_thirds(millistops.signum() as i8 * thirds)
+    }
+
+    pub fn to_millistops(&self) -> i16 {
+        let magnitude = self.thirds.abs() as i16;
+        let fraction = match magnitude % 3 {
+            0 => 0,
+            1 => 300,
+            _ => 700,
+        };
+
+        self.thirds.signum() as i16 * (magnitude / 3 * 1000 + fraction)
+    }
+
+    pub fn ev(&self) -> f32 {
+        self.thirds as f32 / 3.
+    }
+}
+
+impl Serialize for CameraExposureCompensation {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_f32(self.ev())
+    }
+}
+
+impl std::fmt::Display for CameraExposureCompensation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:+.1} EV", self.ev())
+    }
+}
+
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, Serialize, Eq, PartialEq)]
+pub enum CameraDriveMode {
+    Single = 0x0001,
+    ContinuousShotHi = 0x0002,
+    ContinuousShotHiPlus = 0x8010,
+    ContinuousShotLo = 0x8012,
+    ContinuousShot = 0x8013,
+    SpeedPriorityContinuousShot = 0x8014,
+    ContinuousShotMid = 0x8015,
+}
+
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, Serialize, Eq, PartialEq)]
+pub enum CameraFocusMode {
+    Manual = 0x0001,
+    AutoFocusStill = 0x0002,
+    AutoFocusContinuous = 0x8004,
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, Serialize, Eq, PartialEq)]
+pub enum CameraFocusIndication {
+    Unlocked = 0x01,
+    /// Focused in AF-S mode.
+    AFLock = 0x02,
+    /// The camera could not focus in AF-S mode, e.g. due to low contrast.
+    AFWarning = 0x03,
+    Focusing = 0x05,
+    /// Focused in AF-C mode.
+    FocusedContinuous = 0x06,
+    NotFocusedContinuous = 0x07,
+}
+
+/// The battery icon shown on the camera.
+#[repr(i8)]
+#[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, Serialize, Eq, PartialEq)]
+pub enum CameraBatteryLevel {
+    /// The battery is almost empty.
+    PreEnd = 0x01,
+    OneQuarter = 0x02,
+    TwoQuarters = 0x03,
+    ThreeQuarters = 0x04,
+    Full = 0x05,
+    /// Running from USB power rather than the battery.
+    UsbPower = 0x10,
+}
+
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, Serialize, Eq, PartialEq)]
 pub enum CameraCompressionMode {
@@ -29,6 +551,44 @@ pub enum CameraCompressionMode {
     RawJpeg = 0x13,
 }
 
+/// A zoom level that is easier to remember than a number.
+#[derive(Debug, Copy, Clone, Serialize, Eq, PartialEq)]
+pub enum CameraZoomPreset {
+    Wide,
+    Mid,
+    Tele,
+    Max,
+}
+
+impl CameraZoomPreset {
+    /// How far along the camera's zoom range this preset is, from 0 (all the
+    /// way out) to 1 (all the way in).
+    pub fn fraction(&self) -> f32 {
+        match self {
+            CameraZoomPreset::Wide => 0.0,
+            CameraZoomPreset::Mid => 1.0 / 3.0,
+            CameraZoomPreset::Tele => 2.0 / 3.0,
+            CameraZoomPreset::Max => 1.0,
+        }
+    }
+}
+
+/// Whether the camera is allowed to zoom digitally past the end of the
+/// optical zoom range.
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, Serialize, Eq, PartialEq)]
+pub enum CameraZoomMode {
+    Optical = 0x01,
+    OpticalDigital = 0x04,
+}
+
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, Serialize, Eq, PartialEq)]
+pub enum CameraLiveViewResolution {
+    Low = 0x01,
+    High = 0x02,
+}
+
 #[repr(u16)]
 #[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, Serialize, Eq, PartialEq)]
 pub enum CameraSaveMode {
@@ -36,6 +596,32 @@ pub enum CameraSaveMode {
     MemoryCard1 = 0x0002,
 }
 
+/// The kinds of file that can be stored on the camera, as far as we care.
+#[derive(Debug, Copy, Clone, Serialize, Eq, PartialEq, Hash)]
+pub enum CameraFileFormat {
+    Jpeg,
+    Raw,
+    Folder,
+    Other,
+}
+
+impl CameraFileFormat {
+    pub fn of(info: &ptp::PtpObjectInfo) -> Self {
+        match info.object_format {
+            ptp::ObjectFormatCode::Standard(ptp::StandardObjectFormatCode::ExifJpeg)
+            | ptp::ObjectFormatCode::Standard(ptp::StandardObjectFormatCode::Jfif) => {
+                CameraFileFormat::Jpeg
+            }
+            ptp::ObjectFormatCode::Standard(ptp::StandardObjectFormatCode::Association) => {
+                CameraFileFormat::Folder
+            }
+            // Sony ARW
+            ptp::ObjectFormatCode::Vendor(0xB101) => CameraFileFormat::Raw,
+            _ => CameraFileFormat::Other,
+        }
+    }
+}
+
 #[repr(u16)]
 #[derive(Debug, Copy, Clone, FromPrimitive, ToPrimitive, Serialize, Eq, PartialEq)]
 pub enum CameraErrorMode {
@@ -80,3 +666,102 @@ pub enum CameraErrorMode {
 
     SettingFailure = 0x0001,
 }
+
+impl CameraErrorMode {
+    /// Decodes the value of the caution property, which can have several
+    /// errors set at once. Unknown bits are ignored.
+    pub fn from_bits(bits: u16) -> Vec<CameraErrorMode> {
+        (0..16)
+            .map(|i| 1u16 << i)
+            .filter(|bit| bits & bit != 0)
+            .filter_map(CameraErrorMode::from_u16)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exposure_compensation_round_trips_through_millistops() {
+        for &(millistops, thirds) in &[
+            (0, 0),
+            (300, 1),
+            (700, 2),
+            (1000, 3),
+            (-2300, -7),
+            (3000, 9),
+        ] {
+            let compensation = CameraExposureCompensation::from_millistops(millistops).unwrap();
+
+            assert_eq!(
+                compensation,
+                CameraExposureCompensation::from_thirds(thirds).unwrap()
+            );
+            assert_eq!(compensation.to_millistops(), millistops);
+        }
+    }
+
+    #[test]
+    fn exposure_compensation_rejects_values_the_camera_does_not_use() {
+        // not a third of a stop
+        assert_eq!(CameraExposureCompensation::from_millistops(500), None);
+        assert_eq!(CameraExposureCompensation::from_millistops(333), None);
+
+        // more than 3 stops
+        assert_eq!(CameraExposureCompensation::from_millistops(3300), None);
+        assert_eq!(CameraExposureCompensation::from_thirds(-10), None);
+    }
+
+    #[test]
+    fn decodes_several_errors_at_once() {
+        assert_eq!(
+            CameraErrorMode::from_bits(0x1100),
+            vec![
+                CameraErrorMode::NoMedia,
+                CameraErrorMode::RecordingFailedMediaFull
+            ]
+        );
+        assert_eq!(CameraErrorMode::from_bits(0), vec![]);
+    }
+
+    #[test]
+    fn file_index_keeps_counting_after_a_restart() {
+        let path =
+            std::env::temp_dir().join(format!("plane-system-{}-sequence.json", std::process::id()));
+
+        // a sequence file from before file indices were saved
+        std::fs::write(&path, r#"{"next_seq":7,"day":"2020-01-01","day_count":7}"#).unwrap();
+
+        let mut sequence = CaptureSequence::load(&path).unwrap();
+        assert_eq!(sequence.next_file(), 0);
+        assert_eq!(sequence.next_file(), 1);
+
+        let mut sequence = CaptureSequence::load(&path).unwrap();
+        assert_eq!(sequence.next_file(), 2);
+        assert_eq!(sequence.next(), 7);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn segment_keeps_counting_after_a_restart() {
+        let path = std::env::temp_dir().join(format!(
+            "plane-system-{}-segment-sequence.json",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+
+        let mut sequence = CaptureSequence::load(&path).unwrap();
+        assert_eq!(sequence.segment(), 0);
+        assert_eq!(sequence.next_segment(), 1);
+        assert_eq!(sequence.next_segment(), 2);
+
+        let mut sequence = CaptureSequence::load(&path).unwrap();
+        assert_eq!(sequence.segment(), 2);
+        assert_eq!(sequence.next_segment(), 3);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -8,4 +8,9 @@ pub struct MainArgs {
     /// plane-system.json by default.
     #[structopt(parse(from_os_str), long, short)]
     pub config: Option<PathBuf>,
+
+    /// A config file whose keys replace the ones in the main config file,
+    /// e.g. for settings that change from flight to flight.
+    #[structopt(parse(from_os_str), long = "override")]
+    pub override_config: Option<PathBuf>,
 }
@@ -1,53 +1,1295 @@
 use std::path::PathBuf;
 
+use anyhow::Context;
 use config::{Config, ConfigError};
 use mavlink::MavlinkVersion;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
-use crate::state::Coords2D;
+use crate::{
+    gimbal::GimbalAxis,
+    pixhawk::{client::MavTarget, interface::PixhawkTransport},
+    state::Coords2D,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct PixhawkConfig {
+    /// The address to listen on for packets forwarded by MAVProxy.
     pub address: Option<String>,
+
+    /// The serial port that the Pixhawk is plugged into, if it is connected
+    /// directly instead of through MAVProxy.
+    pub serial: Option<PixhawkSerialConfig>,
+
     pub mavlink: MavlinkVersion,
+
+    /// How the autopilot triggers the camera; this depends on how the
+    /// airframe is wired.
+    #[serde(default)]
+    pub camera_trigger: CameraTriggerConfig,
+
+    /// The system ID that we send messages as.
+    #[serde(default = "default_pixhawk_system_id")]
+    pub system_id: u8,
+
+    /// The component ID that we send messages as.
+    #[serde(default = "default_pixhawk_component_id")]
+    pub component_id: u8,
+
+    /// The system ID of the autopilot, which parameters and commands are sent
+    /// to.
+    #[serde(default = "default_pixhawk_target_system")]
+    pub target_system: u8,
+
+    /// The component ID of the autopilot, which parameters and commands are
+    /// sent to.
+    #[serde(default = "default_pixhawk_target_component")]
+    pub target_component: u8,
+
+    /// How many times to try to reconnect after losing the link to the
+    /// Pixhawk before giving up.
+    #[serde(default = "default_pixhawk_reconnect_attempts")]
+    pub reconnect_attempts: u32,
+
+    /// How long to wait after the first failed reconnection attempt; this
+    /// doubles after each failure.
+    #[serde(default = "default_pixhawk_reconnect_delay_ms")]
+    pub reconnect_delay_ms: u64,
+
+    /// Message rates to request from the autopilot on top of the defaults.
+    #[serde(default)]
+    pub message_intervals: Vec<MessageIntervalConfig>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct MessageIntervalConfig {
+    /// The ID of the message, e.g. 30 for ATTITUDE.
+    pub message_id: u32,
+
+    /// Microseconds between messages; 0 restores the default rate and a
+    /// negative number stops the message.
+    pub interval_us: i32,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct PixhawkSerialConfig {
+    /// The path to the serial device, e.g. `/dev/ttyACM0`.
+    pub path: String,
+
+    #[serde(default = "default_pixhawk_serial_baud")]
+    pub baud: u32,
+}
+
+fn default_pixhawk_serial_baud() -> u32 {
+    57_600
+}
+
+impl PixhawkConfig {
+    /// Works out how to connect to the Pixhawk. Returns `None` if the Pixhawk
+    /// is disabled.
+    pub fn transport(&self) -> anyhow::Result<Option<PixhawkTransport>> {
+        match (&self.address, &self.serial) {
+            (Some(_), Some(_)) => bail!("pixhawk address and serial port can't both be set"),
+            (Some(address), None) => {
+                let address = address.parse().context("invalid pixhawk address")?;
+                Ok(Some(PixhawkTransport::Udp(address)))
+            }
+            (None, Some(serial)) => Ok(Some(PixhawkTransport::Serial {
+                path: serial.path.clone(),
+                baud: serial.baud,
+            })),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+fn default_pixhawk_system_id() -> u8 {
+    1
+}
+
+fn default_pixhawk_component_id() -> u8 {
+    1
+}
+
+fn default_pixhawk_target_system() -> u8 {
+    MavTarget::AUTOPILOT.system
+}
+
+fn default_pixhawk_target_component() -> u8 {
+    MavTarget::AUTOPILOT.component
+}
+
+fn default_pixhawk_reconnect_attempts() -> u32 {
+    5
+}
+
+fn default_pixhawk_reconnect_delay_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Deserialize, Serialize, Copy, Clone)]
+pub struct CameraTriggerConfig {
+    /// How long to hold the shutter, in tenths of a second (`CAM_DURATION`).
+    #[serde(default = "default_camera_trigger_duration")]
+    pub duration: f32,
+
+    /// The pin that the camera feedback signal is wired to, or -1 to disable
+    /// feedback (`CAM_FEEDBACK_PIN`).
+    #[serde(default = "default_camera_trigger_feedback_pin")]
+    pub feedback_pin: i8,
+
+    /// 1 if the feedback pin goes high when the camera fires, 0 if it goes
+    /// low (`CAM_FEEDBACK_POL`).
+    #[serde(default = "default_camera_trigger_feedback_polarity")]
+    pub feedback_polarity: u8,
+}
+
+fn default_camera_trigger_duration() -> f32 {
+    10.0
+}
+
+fn default_camera_trigger_feedback_pin() -> i8 {
+    54
+}
+
+fn default_camera_trigger_feedback_polarity() -> u8 {
+    1
+}
+
+impl Default for CameraTriggerConfig {
+    fn default() -> Self {
+        CameraTriggerConfig {
+            duration: default_camera_trigger_duration(),
+            feedback_pin: default_camera_trigger_feedback_pin(),
+            feedback_polarity: default_camera_trigger_feedback_polarity(),
+        }
+    }
+}
+
+impl CameraTriggerConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(0.0..=50.0).contains(&self.duration) {
+            bail!("camera trigger duration must be between 0 and 50 tenths of a second");
+        }
+
+        // -1 disables feedback, 50-55 are AUX1-AUX6
+        if self.feedback_pin != -1 && !(50..=55).contains(&self.feedback_pin) {
+            bail!("camera feedback pin must be -1 (disabled) or between 50 and 55 (AUX1-AUX6)");
+        }
+
+        if self.feedback_polarity > 1 {
+            bail!("camera feedback polarity must be 0 or 1");
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct ServerConfig {
+    /// The address to listen on. The default listens on every interface, so
+    /// that the ground station can reach the server.
+    #[serde(default = "default_server_address")]
     pub address: String,
+
+    /// The area that we are allowed to fly in. ROIs outside of it are
+    /// rejected.
+    #[serde(default)]
+    pub boundary: Option<BoundaryConfig>,
+
+    /// If set, every request has to include an `Authorization: Bearer`
+    /// header with this token.
+    #[serde(default)]
+    pub auth_token: Option<String>,
+}
+
+fn default_server_address() -> String {
+    "0.0.0.0:8080".to_owned()
+}
+
+impl ServerConfig {
+    pub fn socket_address(&self) -> anyhow::Result<std::net::SocketAddr> {
+        self.address
+            .parse()
+            .with_context(|| format!("invalid server address {:?}", self.address))
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: default_server_address(),
+            boundary: None,
+            auth_token: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct BoundaryConfig {
+    /// The corners of the flight area, in order.
+    pub points: Vec<Coords2D>,
+
+    /// How far outside the flight area an ROI can be and still be accepted,
+    /// since the camera can see past the edge of the area.
+    #[serde(default = "default_boundary_margin_m")]
+    pub margin_m: f64,
+}
+
+fn default_boundary_margin_m() -> f64 {
+    100.0
 }
 
 #[derive(Debug, Deserialize)]
 pub struct SchedulerConfig {
     pub enabled: bool,
     pub gps: Coords2D,
+
+    /// The most ROIs that the scheduler will keep track of at once.
+    #[serde(default = "default_scheduler_max_rois")]
+    pub max_rois: usize,
+
+    /// Which ROI to drop when a new one arrives and the scheduler is full.
+    #[serde(default)]
+    pub eviction: ROIEvictionPolicy,
+
+    /// How close the plane has to be to an ROI, in meters, before the
+    /// scheduler tries to photograph it.
+    #[serde(default = "default_scheduler_capture_radius_m")]
+    pub capture_radius_m: f64,
+
+    /// How many passes over an ROI can fail to photograph it before the
+    /// scheduler gives up on it.
+    #[serde(default = "default_scheduler_max_roi_attempts")]
+    pub max_roi_attempts: u32,
+
+    /// New ROIs closer than this to an ROI that the scheduler already has,
+    /// in meters, are ignored.
+    #[serde(default = "default_scheduler_duplicate_radius_m")]
+    pub duplicate_radius_m: f64,
+
+    /// How close the gimbal has to get to its target, in degrees, before the
+    /// scheduler photographs an ROI.
+    #[serde(default = "default_scheduler_gimbal_tolerance_deg")]
+    pub gimbal_tolerance_deg: f64,
+
+    /// How long the gimbal gets to reach its target before the attempt to
+    /// photograph an ROI counts as failed.
+    #[serde(default = "default_scheduler_gimbal_timeout_ms")]
+    pub gimbal_timeout_ms: u64,
+}
+
+fn default_scheduler_max_rois() -> usize {
+    100
+}
+
+fn default_scheduler_capture_radius_m() -> f64 {
+    150.0
+}
+
+fn default_scheduler_max_roi_attempts() -> u32 {
+    3
+}
+
+fn default_scheduler_duplicate_radius_m() -> f64 {
+    5.0
+}
+
+fn default_scheduler_gimbal_tolerance_deg() -> f64 {
+    2.0
+}
+
+fn default_scheduler_gimbal_timeout_ms() -> u64 {
+    3000
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ROIEvictionPolicy {
+    /// Drop the ROI that was added first.
+    Oldest,
+    /// Drop the least important ROI, or the oldest one if there is a tie. A
+    /// new ROI that is less important than every existing ROI is rejected.
+    Priority,
+}
+
+impl Default for ROIEvictionPolicy {
+    fn default() -> Self {
+        ROIEvictionPolicy::Oldest
+    }
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LossOfLinkPolicy {
+    /// Keep capturing as if nothing happened.
+    Continue,
+    /// Stop continuous capture until contact is regained, then resume it.
+    Pause,
+    /// Stop continuous capture and leave it stopped.
+    Stop,
+}
+
+impl Default for LossOfLinkPolicy {
+    fn default() -> Self {
+        LossOfLinkPolicy::Continue
+    }
+}
+
+#[derive(Debug, Deserialize, Copy, Clone)]
+pub struct LossOfLinkConfig {
+    #[serde(default)]
+    pub policy: LossOfLinkPolicy,
+
+    /// How long we can go without hearing from the ground before the link is
+    /// considered lost.
+    #[serde(default = "default_loss_of_link_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_loss_of_link_timeout_secs() -> u64 {
+    30
+}
+
+impl Default for LossOfLinkConfig {
+    fn default() -> Self {
+        LossOfLinkConfig {
+            policy: LossOfLinkPolicy::default(),
+            timeout_secs: default_loss_of_link_timeout_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShutdownPolicy {
+    /// Stop continuous capture and download any image that is still on its
+    /// way before exiting.
+    Finish,
+    /// Exit right away, even if an image is about to arrive.
+    Abort,
+}
+
+impl Default for ShutdownPolicy {
+    fn default() -> Self {
+        ShutdownPolicy::Finish
+    }
+}
+
+#[derive(Debug, Deserialize, Copy, Clone)]
+pub struct ShutdownConfig {
+    #[serde(default)]
+    pub policy: ShutdownPolicy,
+
+    /// How long we are willing to wait for in-flight images when finishing.
+    /// Keep this under 5 seconds, since that is how long the process waits
+    /// for tasks to end after one of them fails.
+    #[serde(default = "default_shutdown_deadline_secs")]
+    pub deadline_secs: u64,
+
+    /// Whether to turn the camera off before disconnecting from it, so that
+    /// it doesn't drain its battery after the plane system exits.
+    #[serde(default)]
+    pub power_off: bool,
+}
+
+fn default_shutdown_deadline_secs() -> u64 {
+    4
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        ShutdownConfig {
+            policy: ShutdownPolicy::default(),
+            deadline_secs: default_shutdown_deadline_secs(),
+            power_off: false,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageConvertFormat {
+    Png,
+    Tiff,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CameraConfig {
+    /// What the camera should do if we lose contact with the ground.
+    #[serde(default)]
+    pub loss_of_link: LossOfLinkConfig,
+
+    /// What the camera should do with in-flight images when shutting down.
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+
+    /// Whether the system should fail if the camera is not connected. If
+    /// false, the system runs without the camera until it is plugged in.
+    #[serde(default = "default_camera_required")]
+    pub required: bool,
+
+    /// How long a single camera command may take before it is abandoned.
+    /// A command that is in the middle of talking to the camera is abandoned
+    /// once that USB transfer finishes or times out, up to 5 seconds later.
+    /// The shutter button is let go afterwards, in case it was held down.
+    #[serde(default = "default_camera_command_timeout_secs")]
+    pub command_timeout_secs: u64,
+
+    /// How many commands in a row can time out before the camera is reset.
+    #[serde(default = "default_camera_max_timeouts")]
+    pub max_timeouts: u32,
+
+    /// If set, every JPEG that is downloaded is also saved in this format,
+    /// next to the original. This is slow, so it is off by default.
+    #[serde(default)]
+    pub convert: Option<ImageConvertFormat>,
+
+    /// If set, only files with these extensions (e.g. ["jpg"]) are downloaded
+    /// from the memory card when downloads resume; the rest stay on the card.
+    #[serde(default)]
+    pub download_extensions: Option<Vec<String>>,
+
+    /// If set, downloaded files are named using this template instead of the
+    /// name that the camera gives them. See the README for the tokens that can
+    /// be used.
+    #[serde(default)]
+    pub filename_template: Option<String>,
+
+    /// If true, downloaded files are saved in a new segment_NN directory each
+    /// time the plane is armed, starting with segment_00 before the first
+    /// arming.
+    #[serde(default)]
+    pub segment_by_arming: bool,
+
+    /// If true, every downloaded file is synced to disk as soon as it is
+    /// saved instead of waiting for `camera flush`. This makes downloads
+    /// slower.
+    #[serde(default)]
+    pub sync_on_save: bool,
+
+    /// Where to keep the capture sequence counter between runs.
+    #[serde(default = "default_sequence_file")]
+    pub sequence_file: PathBuf,
+
+    /// How long a capture waits for the autofocus to lock after the shutter
+    /// is pressed halfway.
+    #[serde(default = "default_camera_focus_timeout_ms")]
+    pub focus_timeout_ms: u64,
+
+    /// How many times to try connecting to a required camera before giving
+    /// up. The camera can take a few seconds to show up after power on.
+    #[serde(default = "default_camera_connect_attempts")]
+    pub connect_attempts: u32,
+
+    /// How long to wait after the first failed connection attempt; the wait
+    /// doubles after each attempt.
+    #[serde(default = "default_camera_connect_retry_delay_ms")]
+    pub connect_retry_delay_ms: u64,
+
+    /// How far apart a capture and a CAMERA_FEEDBACK message from the
+    /// autopilot can be for the feedback's position to be used for that
+    /// capture.
+    #[serde(default = "default_camera_feedback_tolerance_ms")]
+    pub feedback_tolerance_ms: u64,
+
+    /// How long to wait for the camera to switch operating modes (e.g. into
+    /// contents transfer mode before downloading) before giving up.
+    #[serde(default = "default_camera_mode_timeout_ms")]
+    pub mode_timeout_ms: u64,
+
+    /// `camera self-test` fails if the camera's battery is below this
+    /// percentage.
+    #[serde(default = "default_camera_min_battery_percent")]
+    pub min_battery_percent: u8,
+}
+
+fn default_camera_required() -> bool {
+    true
+}
+
+fn default_camera_command_timeout_secs() -> u64 {
+    30
+}
+
+fn default_camera_max_timeouts() -> u32 {
+    3
+}
+
+fn default_camera_focus_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_camera_connect_attempts() -> u32 {
+    5
+}
+
+fn default_camera_connect_retry_delay_ms() -> u64 {
+    500
+}
+
+fn default_camera_feedback_tolerance_ms() -> u64 {
+    500
+}
+
+fn default_camera_mode_timeout_ms() -> u64 {
+    15000
+}
+
+fn default_camera_min_battery_percent() -> u8 {
+    20
+}
+
+fn default_sequence_file() -> PathBuf {
+    PathBuf::from("capture-sequence.json")
+}
+
+#[derive(Debug, Deserialize, Copy, Clone)]
+pub struct GimbalAxisConfig {
+    /// The commanded axis that drives this axis of the gimbal.
+    pub source: GimbalAxis,
+
+    /// Whether this axis of the gimbal moves opposite to the commanded angle.
+    #[serde(default)]
+    pub invert: bool,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum GimbalKind {
+    /// A gimbal with a SimpleBGC controller, connected over USB.
+    SimpleBGC,
+}
+
+/// Describes how the gimbal is mounted, so that commanded angles can be mapped
+/// onto the gimbal's own axes. Only roll and pitch are mapped because those
+/// are the only axes that we control.
+#[derive(Debug, Deserialize, Copy, Clone)]
+pub struct GimbalConfig {
+    /// What kind of gimbal is attached.
+    #[serde(default = "default_gimbal_kind")]
+    pub kind: GimbalKind,
+
+    #[serde(default = "default_gimbal_roll")]
+    pub roll: GimbalAxisConfig,
+
+    #[serde(default = "default_gimbal_pitch")]
+    pub pitch: GimbalAxisConfig,
+
+    /// The fastest that the gimbal is moved, in degrees per second. Large
+    /// moves are broken up into smaller ones so that the gimbal doesn't
+    /// overshoot. If this is missing or zero, the gimbal is sent straight to
+    /// the new angle.
+    #[serde(default)]
+    pub slew_rate: Option<f64>,
+}
+
+fn default_gimbal_kind() -> GimbalKind {
+    GimbalKind::SimpleBGC
+}
+
+fn default_gimbal_roll() -> GimbalAxisConfig {
+    GimbalAxisConfig {
+        source: GimbalAxis::Roll,
+        invert: false,
+    }
+}
+
+fn default_gimbal_pitch() -> GimbalAxisConfig {
+    GimbalAxisConfig {
+        source: GimbalAxis::Pitch,
+        invert: false,
+    }
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StartupFailurePolicy {
+    /// Skip the rest of the startup commands.
+    Abort,
+    /// Log the failure and run the next command anyway.
+    Continue,
+}
+
+impl Default for StartupFailurePolicy {
+    fn default() -> Self {
+        StartupFailurePolicy::Abort
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StartupConfig {
+    /// Commands to run at startup, written the same way as in the REPL.
+    pub commands: Vec<String>,
+
+    #[serde(default)]
+    pub on_failure: StartupFailurePolicy,
+
+    /// How long to wait for each command to finish before counting it as
+    /// failed.
+    #[serde(default = "default_startup_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_startup_timeout_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct SelfTestConfig {
+    /// How often to check the device links.
+    #[serde(default = "default_self_test_interval_secs")]
+    pub interval_secs: u64,
+
+    /// The battery check fails if the autopilot says that less than this
+    /// percentage of the battery is left.
+    #[serde(default = "default_self_test_min_battery_percent")]
+    pub min_battery_percent: u8,
+}
+
+fn default_self_test_interval_secs() -> u64 {
+    30
+}
+
+fn default_self_test_min_battery_percent() -> u8 {
+    20
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Colored, human-readable lines.
+    Pretty,
+
+    /// One JSON object per line, for sending to a log collector.
+    Json,
+}
+
+impl Default for LogFormat {
+    fn default() -> Self {
+        LogFormat::Pretty
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default)]
+pub struct LoggingConfig {
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum DummyPath {
+    /// Fly in a circle around the center.
+    Circle,
+
+    /// Fly back and forth across the circle.
+    Lawnmower,
+}
+
+impl Default for DummyPath {
+    fn default() -> Self {
+        DummyPath::Circle
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct DummyConfig {
+    pub center: Coords2D,
+
+    #[serde(default)]
+    pub path: DummyPath,
+
+    /// The radius of the circle that the plane stays inside, in meters.
+    #[serde(default = "default_dummy_radius_m")]
+    pub radius_m: f64,
+
+    /// How far apart the lanes of the lawnmower pattern are, in meters.
+    #[serde(default = "default_dummy_lane_spacing_m")]
+    pub lane_spacing_m: f64,
+
+    #[serde(default = "default_dummy_speed_m_s")]
+    pub speed_m_s: f64,
+
+    /// Altitude above home, in meters.
+    #[serde(default = "default_dummy_altitude_m")]
+    pub altitude_m: f64,
+
+    /// How many position and attitude updates to send per second.
+    #[serde(default = "default_dummy_rate_hz")]
+    pub rate_hz: f64,
+}
+
+fn default_dummy_radius_m() -> f64 {
+    200.0
+}
+
+fn default_dummy_lane_spacing_m() -> f64 {
+    50.0
+}
+
+fn default_dummy_speed_m_s() -> f64 {
+    20.0
+}
+
+fn default_dummy_altitude_m() -> f64 {
+    100.0
+}
+
+fn default_dummy_rate_hz() -> f64 {
+    10.0
+}
+
+#[derive(Debug, Deserialize, Clone, Copy)]
+pub struct TelemetryConfig {
+    /// Telemetry is marked as stale if the pixhawk hasn't sent a position or
+    /// attitude in this long.
+    #[serde(default = "default_telemetry_stale_after_ms")]
+    pub stale_after_ms: u64,
+}
+
+fn default_telemetry_stale_after_ms() -> u64 {
+    3000
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        TelemetryConfig {
+            stale_after_ms: default_telemetry_stale_after_ms(),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 pub struct PlaneSystemConfig {
     pub pixhawk: PixhawkConfig,
+    #[serde(default)]
     pub server: ServerConfig,
-    pub camera: bool,
-    pub gimbal: bool,
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    pub camera: Option<CameraConfig>,
+    pub gimbal: Option<GimbalConfig>,
     pub scheduler: SchedulerConfig,
+    pub startup: Option<StartupConfig>,
+    pub self_test: Option<SelfTestConfig>,
+    pub dummy: Option<DummyConfig>,
 }
 
 impl PlaneSystemConfig {
-    pub fn read() -> Result<Self, ConfigError> {
+    /// Checks the values that deserializing can't, so that a bad config fails
+    /// at startup with errors that say which key to fix. Every problem is
+    /// reported at once.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+
+        let mut check = |ok: bool, error: String| {
+            if !ok {
+                errors.push(error);
+            }
+        };
+
+        let pixhawk = &self.pixhawk;
+
+        check(
+            !(pixhawk.address.is_some() && pixhawk.serial.is_some()),
+            "pixhawk.address and pixhawk.serial can't both be set; remove one of them".to_owned(),
+        );
+
+        if let Some(address) = &pixhawk.address {
+            check(
+                address.parse::<std::net::SocketAddr>().is_ok(),
+                format!(
+                    "pixhawk.address: {:?} is not an address; use something like \"0.0.0.0:14551\"",
+                    address
+                ),
+            );
+        }
+
+        if let Some(serial) = &pixhawk.serial {
+            check(
+                serial.baud > 0,
+                "pixhawk.serial.baud must be more than 0".to_owned(),
+            );
+        }
+
+        check(
+            self.server.address.parse::<std::net::SocketAddr>().is_ok(),
+            format!(
+                "server.address: {:?} is not an address; use something like \"0.0.0.0:8080\"",
+                self.server.address
+            ),
+        );
+
+        if let Some(boundary) = &self.server.boundary {
+            check(
+                boundary.points.len() >= 3,
+                "server.boundary.points needs at least 3 corners".to_owned(),
+            );
+            check(
+                boundary.margin_m >= 0.0,
+                "server.boundary.margin_m can't be negative".to_owned(),
+            );
+        }
+
+        if let Some(camera) = &self.camera {
+            check(
+                camera.command_timeout_secs > 0,
+                "camera.command_timeout_secs must be more than 0".to_owned(),
+            );
+            check(
+                camera.mode_timeout_ms > 0,
+                "camera.mode_timeout_ms must be more than 0".to_owned(),
+            );
+            check(
+                camera.connect_attempts > 0,
+                "camera.connect_attempts must be at least 1".to_owned(),
+            );
+            check(
+                camera.min_battery_percent <= 100,
+                "camera.min_battery_percent must be at most 100".to_owned(),
+            );
+            check(
+                camera.shutdown.deadline_secs < 5,
+                "camera.shutdown.deadline_secs must be less than 5, since the process only waits 5 seconds for tasks to end".to_owned(),
+            );
+
+            // the sequence file is written after every capture, so find out
+            // now if that won't work
+            let sequence_dir = match camera.sequence_file.parent() {
+                Some(parent) if parent != std::path::Path::new("") => parent.to_owned(),
+                _ => PathBuf::from("."),
+            };
+
+            let writable = std::fs::metadata(&sequence_dir)
+                .map(|metadata| metadata.is_dir() && !metadata.permissions().readonly())
+                .unwrap_or(false);
+
+            check(
+                writable,
+                format!(
+                    "camera.sequence_file: {:?} is not a writable directory; create it or pick another path",
+                    sequence_dir
+                ),
+            );
+        }
+
+        if let Some(gimbal) = &self.gimbal {
+            if let Some(slew_rate) = gimbal.slew_rate {
+                check(
+                    slew_rate.is_finite() && slew_rate >= 0.0,
+                    "gimbal.slew_rate can't be negative; use 0 to turn it off".to_owned(),
+                );
+            }
+        }
+
+        let scheduler = &self.scheduler;
+
+        check(
+            scheduler.max_rois > 0,
+            "scheduler.max_rois must be at least 1".to_owned(),
+        );
+        check(
+            scheduler.capture_radius_m > 0.0,
+            "scheduler.capture_radius_m must be more than 0".to_owned(),
+        );
+        check(
+            scheduler.max_roi_attempts > 0,
+            "scheduler.max_roi_attempts must be at least 1".to_owned(),
+        );
+        check(
+            scheduler.duplicate_radius_m >= 0.0,
+            "scheduler.duplicate_radius_m can't be negative".to_owned(),
+        );
+        check(
+            scheduler.gimbal_tolerance_deg > 0.0,
+            "scheduler.gimbal_tolerance_deg must be more than 0".to_owned(),
+        );
+        check(
+            scheduler.gimbal_timeout_ms > 0 && scheduler.gimbal_timeout_ms < 10000,
+            "scheduler.gimbal_timeout_ms must be more than 0 and less than 10000, since the scheduler only waits 10 seconds for the gimbal".to_owned(),
+        );
+
+        check(
+            self.telemetry.stale_after_ms > 0,
+            "telemetry.stale_after_ms must be more than 0".to_owned(),
+        );
+
+        if let Some(startup) = &self.startup {
+            check(
+                startup.timeout_secs > 0,
+                "startup.timeout_secs must be more than 0".to_owned(),
+            );
+        }
+
+        if let Some(self_test) = &self.self_test {
+            check(
+                self_test.interval_secs > 0,
+                "self_test.interval_secs must be more than 0".to_owned(),
+            );
+            check(
+                self_test.min_battery_percent <= 100,
+                "self_test.min_battery_percent can't be more than 100".to_owned(),
+            );
+        }
+
+        if let Some(dummy) = &self.dummy {
+            check(
+                dummy.radius_m > 0.0,
+                "dummy.radius_m must be more than 0".to_owned(),
+            );
+            check(
+                dummy.lane_spacing_m > 0.0,
+                "dummy.lane_spacing_m must be more than 0".to_owned(),
+            );
+            check(
+                dummy.speed_m_s > 0.0,
+                "dummy.speed_m_s must be more than 0".to_owned(),
+            );
+            check(
+                dummy.rate_hz > 0.0,
+                "dummy.rate_hz must be more than 0".to_owned(),
+            );
+        }
+
+        if !errors.is_empty() {
+            bail!("invalid config:\n  {}", errors.join("\n  "));
+        }
+
+        Ok(())
+    }
+
+    /// Reads the base config (`plane-system.*` if `path` is `None`), then
+    /// the override file if there is one, then `PLANE_SYSTEM_*` environment
+    /// variables. Later sources win, and nested sections are merged key by
+    /// key, so an override only needs the keys that it changes. If a key has
+    /// the wrong type, the error says which source it came from.
+    pub fn read_layered(
+        path: Option<PathBuf>,
+        override_path: Option<PathBuf>,
+    ) -> anyhow::Result<Self> {
+        let mut layers = vec![ConfigLayer::Base(path)];
+        layers.extend(override_path.map(ConfigLayer::Override));
+        layers.push(ConfigLayer::Environment);
+
         let mut c = Config::new();
 
-        c.merge(config::File::with_name("plane-system"))?;
-        c.merge(config::Environment::with_prefix("PLANE_SYSTEM"))?;
+        for layer in &layers {
+            layer
+                .merge_into(&mut c)
+                .with_context(|| format!("failed to read {}", layer))?;
+        }
+
+        c.try_into().map_err(|err| {
+            let key = match &err {
+                ConfigError::Type { key: Some(key), .. } => key.clone(),
+                _ => return anyhow::Error::new(err),
+            };
 
-        c.try_into()
+            // the last source that sets the key is the one whose value was
+            // used
+            let mut sources = layers
+                .iter()
+                .filter(|layer| layer.sets(&key))
+                .map(|layer| layer.to_string())
+                .collect::<Vec<_>>();
+
+            let context = match sources.pop() {
+                Some(source) if sources.is_empty() => format!("`{}` comes from {}", key, source),
+                Some(source) => format!(
+                    "`{}` comes from {}, which overrides {}",
+                    key,
+                    source,
+                    sources.join(" and ")
+                ),
+                None => return anyhow::Error::new(err),
+            };
+
+            anyhow::Error::new(err).context(context)
+        })
+    }
+}
+
+/// One of the sources that the config is read from, in the order that they
+/// are read.
+enum ConfigLayer {
+    Base(Option<PathBuf>),
+    Override(PathBuf),
+    Environment,
+}
+
+impl ConfigLayer {
+    fn merge_into(&self, c: &mut Config) -> Result<(), ConfigError> {
+        match self {
+            ConfigLayer::Base(Some(path)) => c.merge(config::File::from(path.clone()))?,
+            ConfigLayer::Base(None) => c.merge(config::File::with_name("plane-system"))?,
+            ConfigLayer::Override(path) => c.merge(config::File::from(path.clone()))?,
+            // double underscores separate sections, e.g.
+            // PLANE_SYSTEM_SERVER__ADDRESS sets server.address
+            ConfigLayer::Environment => {
+                c.merge(config::Environment::with_prefix("PLANE_SYSTEM").separator("__"))?
+            }
+        };
+
+        Ok(())
     }
 
-    pub fn read_from_path(path: PathBuf) -> Result<Self, ConfigError> {
+    /// Whether this source sets `key` by itself.
+    fn sets(&self, key: &str) -> bool {
         let mut c = Config::new();
+        self.merge_into(&mut c).is_ok() && c.get::<config::Value>(key).is_ok()
+    }
+}
+
+impl std::fmt::Display for ConfigLayer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigLayer::Base(Some(path)) => write!(f, "the config file {:?}", path),
+            ConfigLayer::Base(None) => write!(f, "the config file plane-system.*"),
+            ConfigLayer::Override(path) => write!(f, "the override file {:?}", path),
+            ConfigLayer::Environment => write!(f, "the PLANE_SYSTEM_* environment variables"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use config::{File, FileFormat};
+
+    use super::*;
+
+    const BASE: &str = r#"{
+        "pixhawk": { "address": "0.0.0.0:14551", "mavlink": { "type": "V2" } },
+        "server": { "address": "[::]:8080" },
+        "scheduler": {
+            "enabled": false,
+            "gps": { "latitude": -35.36, "longitude": 149.16 }
+        }
+    }"#;
+
+    fn parse(sources: &[&str]) -> PlaneSystemConfig {
+        let mut c = Config::new();
+
+        for source in sources {
+            c.merge(File::from_str(source, FileFormat::Json)).unwrap();
+        }
+
+        c.try_into().unwrap()
+    }
+
+    #[test]
+    fn base_config_is_valid() {
+        parse(&[BASE]).validate().unwrap();
+    }
+
+    #[test]
+    fn reports_bad_value_in_each_section() {
+        let cases = [
+            (
+                r#"{ "pixhawk": { "address": "localhost" } }"#,
+                r#"pixhawk.address: "localhost" is not an address; use something like "0.0.0.0:14551""#,
+            ),
+            (
+                r#"{ "server": { "address": "8080" } }"#,
+                r#"server.address: "8080" is not an address; use something like "0.0.0.0:8080""#,
+            ),
+            (
+                r#"{ "camera": { "command_timeout_secs": 0 } }"#,
+                "camera.command_timeout_secs must be more than 0",
+            ),
+            (
+                r#"{ "gimbal": { "slew_rate": -1.0 } }"#,
+                "gimbal.slew_rate can't be negative; use 0 to turn it off",
+            ),
+            (
+                r#"{ "scheduler": { "max_rois": 0 } }"#,
+                "scheduler.max_rois must be at least 1",
+            ),
+            (
+                r#"{ "telemetry": { "stale_after_ms": 0 } }"#,
+                "telemetry.stale_after_ms must be more than 0",
+            ),
+            (
+                r#"{ "startup": { "commands": [], "timeout_secs": 0 } }"#,
+                "startup.timeout_secs must be more than 0",
+            ),
+            (
+                r#"{ "self_test": { "min_battery_percent": 101 } }"#,
+                "self_test.min_battery_percent can't be more than 100",
+            ),
+            (
+                r#"{ "dummy": { "center": { "latitude": 0.0, "longitude": 0.0 }, "rate_hz": 0.0 } }"#,
+                "dummy.rate_hz must be more than 0",
+            ),
+        ];
+
+        for (overrides, expected) in &cases {
+            let err = parse(&[BASE, overrides]).validate().unwrap_err();
+            assert_eq!(err.to_string(), format!("invalid config:\n  {}", expected));
+        }
+    }
+
+    #[test]
+    fn reports_every_problem_at_once() {
+        let overrides = r#"{
+            "scheduler": { "max_rois": 0 },
+            "telemetry": { "stale_after_ms": 0 }
+        }"#;
+
+        let err = parse(&[BASE, overrides]).validate().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid config:\n  scheduler.max_rois must be at least 1\n  telemetry.stale_after_ms must be more than 0"
+        );
+    }
+
+    /// The same config as `BASE`, as the TOML file that a flight would use.
+    const BASE_TOML: &str = r#"
+        [pixhawk]
+        address = "0.0.0.0:14551"
+        mavlink = { type = "V2" }
+
+        [server]
+        address = "[::]:8080"
+
+        [scheduler]
+        enabled = false
+        gps = { latitude = -35.36, longitude = 149.16 }
+    "#;
+
+    /// A TOML config file in the temporary directory that is deleted when the
+    /// test ends.
+    struct TempConfig(PathBuf);
+
+    impl TempConfig {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "plane-system-{}-{}.toml",
+                std::process::id(),
+                name
+            ));
+            std::fs::write(&path, contents).unwrap();
+            TempConfig(path)
+        }
+    }
+
+    impl Drop for TempConfig {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn reads_base_file_without_override() {
+        let base = TempConfig::new("base-only", BASE_TOML);
+        let config = PlaneSystemConfig::read_layered(Some(base.0.clone()), None).unwrap();
+
+        assert_eq!(config.server.address, "[::]:8080");
+        assert_eq!(config.scheduler.max_rois, default_scheduler_max_rois());
+    }
+
+    #[test]
+    fn override_file_wins_key_by_key() {
+        let base = TempConfig::new("base", BASE_TOML);
+        let flight = TempConfig::new(
+            "flight",
+            r#"
+                [server]
+                address = "127.0.0.1:9000"
+
+                [scheduler]
+                max_rois = 10
+            "#,
+        );
+
+        let config =
+            PlaneSystemConfig::read_layered(Some(base.0.clone()), Some(flight.0.clone())).unwrap();
+
+        // changed by the override
+        assert_eq!(config.server.address, "127.0.0.1:9000");
+        assert_eq!(config.scheduler.max_rois, 10);
+
+        // only in the base, including keys next to the ones that changed
+        assert_eq!(config.pixhawk.address.as_deref(), Some("0.0.0.0:14551"));
+        assert!(!config.scheduler.enabled);
+        assert_eq!(config.scheduler.gps.latitude, -35.36);
+    }
+
+    #[test]
+    fn missing_override_file_is_an_error() {
+        let base = TempConfig::new("base-missing-override", BASE_TOML);
+        let missing = std::env::temp_dir().join("plane-system-does-not-exist.json");
+
+        assert!(PlaneSystemConfig::read_layered(Some(base.0.clone()), Some(missing)).is_err());
+    }
+
+    #[test]
+    fn environment_wins_over_both_files() {
+        let base = TempConfig::new("base-env", BASE_TOML);
+        let flight = TempConfig::new(
+            "flight-env",
+            r#"
+                [scheduler]
+                capture_radius_m = 200.0
+                duplicate_radius_m = 8.0
+            "#,
+        );
+
+        // no other test reads this key, so setting it doesn't race with them
+        std::env::set_var("PLANE_SYSTEM_SCHEDULER__CAPTURE_RADIUS_M", "250");
+        let config = PlaneSystemConfig::read_layered(Some(base.0.clone()), Some(flight.0.clone()));
+        std::env::remove_var("PLANE_SYSTEM_SCHEDULER__CAPTURE_RADIUS_M");
+
+        let config = config.unwrap();
+        assert_eq!(config.scheduler.capture_radius_m, 250.0);
+        assert_eq!(config.scheduler.duplicate_radius_m, 8.0);
+        assert_eq!(config.server.address, "[::]:8080");
+    }
+
+    #[test]
+    fn a_bad_value_names_the_file_it_came_from() {
+        let base = TempConfig::new("base-bad", BASE_TOML);
+        let flight = TempConfig::new(
+            "flight-bad",
+            r#"
+                [scheduler]
+                max_rois = "lots"
+            "#,
+        );
+
+        let err = PlaneSystemConfig::read_layered(Some(base.0.clone()), Some(flight.0.clone()))
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("`scheduler.max_rois`"), "{}", err);
+        assert!(err.contains("the override file"), "{}", err);
+        assert!(err.contains("flight-bad.toml"), "{}", err);
+    }
+
+    #[test]
+    fn a_bad_override_names_what_it_overrides() {
+        let base = TempConfig::new(
+            "base-conflict",
+            &format!("{}\n[camera]\ncommand_timeout_secs = 30\n", BASE_TOML),
+        );
+        let flight = TempConfig::new(
+            "flight-conflict",
+            r#"
+                [camera]
+                command_timeout_secs = "soon"
+            "#,
+        );
 
-        c.merge(config::File::from(path))?;
-        c.merge(config::Environment::with_prefix("PLANE_SYSTEM"))?;
+        let err = PlaneSystemConfig::read_layered(Some(base.0.clone()), Some(flight.0.clone()))
+            .unwrap_err()
+            .to_string();
 
-        c.try_into()
+        assert!(
+            err.contains("`camera.command_timeout_secs` comes from the override file"),
+            "{}",
+            err
+        );
+        assert!(err.contains("which overrides the config file"), "{}", err);
+        assert!(err.contains("base-conflict.toml"), "{}", err);
     }
 }
@@ -0,0 +1,84 @@
+use std::io::Write;
+
+use chrono::{DateTime, Utc};
+
+use crate::cli::config::{LogFormat, LoggingConfig};
+
+/// Sets up the logger. `RUST_LOG` picks which messages are logged, the same
+/// way for both formats.
+pub fn init(config: &LoggingConfig) {
+    let mut builder = pretty_env_logger::formatted_timed_builder();
+
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+
+    if config.format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json_line(record, Utc::now())));
+    }
+
+    builder.init();
+}
+
+/// Formats a log record as a single JSON object, so that a log collector can
+/// parse one per line.
+fn json_line(record: &log::Record, timestamp: DateTime<Utc>) -> String {
+    serde_json::json!({
+        "timestamp": timestamp.to_rfc3339(),
+        "level": record.level().to_string(),
+        "target": record.target(),
+        "message": record.args().to_string(),
+        "file": record.file(),
+        "line": record.line(),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn json_lines_parse_back_into_the_record() {
+        let timestamp = Utc.ymd(2020, 10, 3).and_hms(14, 30, 0);
+
+        let line = json_line(
+            &log::Record::builder()
+                .args(format_args!("captured \"{}\"\nnext line", "IMG_0001.JPG"))
+                .level(log::Level::Warn)
+                .target("plane_system::camera")
+                .file(Some("src/camera/client.rs"))
+                .line(Some(42))
+                .build(),
+            timestamp,
+        );
+
+        // a newline in the message can't split the record across lines
+        assert_eq!(line.lines().count(), 1);
+
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["timestamp"], "2020-10-03T14:30:00+00:00");
+        assert_eq!(json["level"], "WARN");
+        assert_eq!(json["target"], "plane_system::camera");
+        assert_eq!(json["message"], "captured \"IMG_0001.JPG\"\nnext line");
+        assert_eq!(json["file"], "src/camera/client.rs");
+        assert_eq!(json["line"], 42);
+    }
+
+    #[test]
+    fn json_lines_have_null_for_a_missing_location() {
+        let line = json_line(
+            &log::Record::builder()
+                .args(format_args!("hello"))
+                .level(log::Level::Info)
+                .target("plane_system")
+                .build(),
+            Utc::now(),
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert!(json["file"].is_null());
+        assert!(json["line"].is_null());
+    }
+}
@@ -1,3 +1,5 @@
 pub mod args;
 pub mod config;
+pub mod logging;
 pub mod repl;
+pub mod startup;
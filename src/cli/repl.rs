@@ -1,4 +1,7 @@
-use std::sync::Arc;
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
 use anyhow::Context;
 use colored::Colorize;
@@ -7,15 +10,24 @@ use prettytable::{cell, row, Table};
 use structopt::StructOpt;
 
 use crate::{
-    camera::CameraRequest, camera::CameraResponse, gimbal::GimbalRequest, Channels, Command,
+    camera::CameraRequest, camera::CameraResponse, gimbal::GimbalRequest, gimbal::GimbalResponse,
+    pixhawk::state::PixhawkEvent, pixhawk::PixhawkRequest, pixhawk::PixhawkResponse,
+    util::ReceiverExt, Channels, Command,
 };
 
 #[derive(StructOpt, Debug)]
 #[structopt(setting(clap::AppSettings::NoBinaryName))]
 #[structopt(rename_all = "kebab-case")]
-enum ReplRequest {
+pub(super) enum ReplRequest {
     Camera(CameraRequest),
     Gimbal(GimbalRequest),
+    Pixhawk(PixhawkRequest),
+    /// compare the clocks of the camera and the pixhawk against this computer
+    TimeSync {
+        /// offsets larger than this (in milliseconds) are flagged
+        #[structopt(long, default_value = "500")]
+        threshold_ms: u64,
+    },
     Exit,
 }
 
@@ -63,7 +75,25 @@ pub async fn run(channels: Arc<Channels>) -> anyhow::Result<()> {
             ReplRequest::Gimbal(request) => {
                 let (cmd, chan) = Command::new(request);
                 channels.gimbal_cmd.clone().send(cmd).await?;
-                let _ = chan.await?;
+                let result = chan.await?;
+
+                match result {
+                    Ok(response) => format_gimbal_response(response),
+                    Err(err) => println!("{}", format!("error: {}", err).red()),
+                };
+            }
+            ReplRequest::Pixhawk(request) => {
+                let (cmd, chan) = Command::new(request);
+                channels.pixhawk_cmd.clone().send(cmd).await?;
+                let result = chan.await?;
+
+                match result {
+                    Ok(response) => format_pixhawk_response(response),
+                    Err(err) => println!("{}", format!("error: {}", err).red()),
+                };
+            }
+            ReplRequest::TimeSync { threshold_ms } => {
+                time_sync(&channels, Duration::from_millis(threshold_ms)).await?;
             }
             ReplRequest::Exit => {
                 let _ = channels.interrupt.send(());
@@ -75,6 +105,78 @@ pub async fn run(channels: Arc<Channels>) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// How long to wait for the pixhawk to report its time.
+const PIXHAWK_TIME_TIMEOUT: Duration = Duration::from_secs(3);
+
+pub(super) async fn time_sync(channels: &Channels, threshold: Duration) -> anyhow::Result<()> {
+    let mut pixhawk_recv = channels.pixhawk_event.subscribe();
+
+    let pixhawk_time = tokio::time::timeout(PIXHAWK_TIME_TIMEOUT, async {
+        while let Some(event) = pixhawk_recv.recv_skip().await {
+            if let PixhawkEvent::SystemTime { time } = event {
+                return Some(time);
+            }
+        }
+
+        None
+    })
+    .await
+    .ok()
+    .flatten();
+
+    // compare against the time at which the reply arrived, since the pixhawk
+    // only sends its time once per second
+    print_time_offset("pixhawk", pixhawk_time, SystemTime::now(), threshold);
+
+    let (cmd, chan) = Command::new(CameraRequest::Time);
+    let camera_time = match channels.camera_cmd.clone().send(cmd).await {
+        Ok(()) => match chan.await? {
+            Ok(CameraResponse::Time { time }) => Some(time),
+            Ok(_) => None,
+            Err(err) => {
+                println!("{}", format!("camera: error: {}", err).red());
+                None
+            }
+        },
+        Err(_) => None,
+    };
+
+    print_time_offset("camera", camera_time, SystemTime::now(), threshold);
+
+    // the simplebgc protocol has no notion of wall-clock time
+    println!("gimbal: no clock available");
+
+    Ok(())
+}
+
+fn print_time_offset(
+    name: &str,
+    time: Option<SystemTime>,
+    reference: SystemTime,
+    threshold: Duration,
+) {
+    let time = match time {
+        Some(time) => time,
+        None => {
+            println!("{}", format!("{}: time unavailable", name).yellow());
+            return;
+        }
+    };
+
+    let offset_ms = match time.duration_since(reference) {
+        Ok(ahead) => ahead.as_millis() as i128,
+        Err(err) => -(err.duration().as_millis() as i128),
+    };
+
+    let message = format!("{}: offset {:+} ms", name, offset_ms);
+
+    if offset_ms.abs() as u128 > threshold.as_millis() {
+        println!("{}", message.red());
+    } else {
+        println!("{}", message.green());
+    }
+}
+
 fn table_format() -> prettytable::format::TableFormat {
     prettytable::format::FormatBuilder::new()
         .column_separator('|')
@@ -90,6 +192,31 @@ fn table_format() -> prettytable::format::TableFormat {
         .build()
 }
 
+fn format_gimbal_response(response: GimbalResponse) -> () {
+    match response {
+        GimbalResponse::Unit => println!("done"),
+        GimbalResponse::Homed => println!("gimbal homed"),
+        GimbalResponse::Calibrated => println!("gimbal calibrated and homed"),
+        GimbalResponse::Angles { roll, pitch } => {
+            println!("roll: {:.2} deg, pitch: {:.2} deg", roll, pitch)
+        }
+    }
+}
+
+fn format_pixhawk_response(response: PixhawkResponse) -> () {
+    match response {
+        PixhawkResponse::Unit => println!("done"),
+        PixhawkResponse::CameraTrigger { config } => {
+            println!("duration: {} ds", config.duration);
+            println!("feedback pin: {}", config.feedback_pin);
+            println!("feedback polarity: {}", config.feedback_polarity);
+        }
+        PixhawkResponse::UploadMission { count } => {
+            println!("uploaded mission with {} items", count)
+        }
+    }
+}
+
 fn format_camera_response(response: CameraResponse) -> () {
     match response {
         CameraResponse::Unit => println!("done"),
@@ -107,6 +234,151 @@ fn format_camera_response(response: CameraResponse) -> () {
             println!("received file: {}", path.to_string_lossy());
         }
 
+        CameraResponse::Files { paths } => {
+            println!("received {} files", paths.len());
+
+            for path in paths {
+                println!("{}", path.to_string_lossy());
+            }
+        }
+
+        CameraResponse::Time { time } => {
+            println!(
+                "{}",
+                chrono::DateTime::<chrono::Local>::from(time).to_rfc3339()
+            );
+        }
+
+        CameraResponse::TimedCapture {
+            fire_time,
+            latency_ms,
+            time_source,
+            path,
+        } => {
+            println!(
+                "fired at {} ({:?} time), confirmed {} ms later",
+                chrono::DateTime::<chrono::Utc>::from(fire_time).to_rfc3339(),
+                time_source,
+                latency_ms
+            );
+
+            if let Some(path) = path {
+                println!("received file: {}", path.to_string_lossy());
+            }
+        }
+
+        CameraResponse::VerifySettings { ok, results } => {
+            let mut table = Table::new();
+            table.add_row(row!["property", "expected", "actual", "ok"]);
+
+            for check in results {
+                table.add_row(row![
+                    check.property,
+                    check.expected,
+                    check.actual.unwrap_or_else(|| "-".to_string()),
+                    if check.matches { "yes" } else { "no" }
+                ]);
+            }
+
+            table.set_format(table_format());
+            table.printstd();
+
+            if ok {
+                println!("{}", "all settings match".green());
+            } else {
+                println!("{}", "some settings do not match".red());
+            }
+        }
+
+        CameraResponse::Flushed { files } => {
+            println!("synced {} images to disk", files);
+        }
+
+        CameraResponse::SelfTest { passed, results } => {
+            let mut table = Table::new();
+            table.add_row(row!["check", "ok", "error"]);
+
+            for check in results {
+                table.add_row(row![
+                    check.name,
+                    if check.passed { "yes" } else { "no" },
+                    check.error.unwrap_or_else(|| "-".to_string())
+                ]);
+            }
+
+            table.set_format(table_format());
+            table.printstd();
+
+            if passed {
+                println!("{}", "camera self-test passed".green());
+            } else {
+                println!("{}", "camera self-test failed".red());
+            }
+        }
+
+        CameraResponse::Benchmark {
+            bytes,
+            transfer_time_ms,
+            throughput,
+            latency_ms,
+        } => {
+            let size = bytes.file_size(humansize::file_size_opts::BINARY).unwrap();
+
+            println!(
+                "downloaded {} in {} ms ({:.2} MB/s)",
+                size, transfer_time_ms, throughput
+            );
+            println!("round-trip latency: {:.1} ms", latency_ms);
+        }
+
+        CameraResponse::FileFormats { formats } => {
+            let mut table = Table::new();
+            table.add_row(row!["format", "count"]);
+
+            for (format, count) in formats {
+                table.add_row(row![format!("{:?}", format).to_lowercase(), count]);
+            }
+
+            table.set_format(table_format());
+            table.printstd();
+        }
+
+        CameraResponse::PropertyDump { properties } => {
+            let mut table = Table::new();
+            table.add_row(row!["code", "name", "writable", "current", "allowed"]);
+
+            for property in properties {
+                table.add_row(row![
+                    format!("0x{:04x}", property.code),
+                    property.name,
+                    property.writable,
+                    property.current,
+                    property.allowed.join(", ")
+                ]);
+            }
+
+            table.set_format(table_format());
+            table.printstd();
+        }
+
+        CameraResponse::Errors { errors } => {
+            if errors.is_empty() {
+                println!("no errors");
+            }
+
+            for error in errors {
+                println!("{}", format!("{:?}", error).red());
+            }
+        }
+
+        CameraResponse::DownloadStatus { paused } => {
+            if paused {
+                println!("downloads are paused");
+            } else {
+                println!("downloads are running");
+            }
+        }
+
         CameraResponse::StorageInfo { storages } => {
             let mut table = Table::new();
             table.add_row(row![
@@ -275,9 +547,29 @@ fn format_camera_response(response: CameraResponse) -> () {
             table.printstd();
         }
 
+        CameraResponse::ContinuousCapture { active } => {
+            if active {
+                println!("continuous capture is running");
+            } else {
+                println!("continuous capture is stopped");
+            }
+        }
+
         CameraResponse::ZoomLevel { zoom_level } => {
             println!("zoom level: {}", zoom_level);
         }
+        CameraResponse::ZoomMode { zoom_mode } => match zoom_mode {
+            crate::camera::CameraZoomMode::Optical => {
+                println!("using optical zoom only");
+            }
+            crate::camera::CameraZoomMode::OpticalDigital => {
+                println!("using optical and digital zoom");
+            }
+        },
+        CameraResponse::LiveViewResolution { resolution } => {
+            println!("live view resolution: {:?}", resolution);
+        }
+
         CameraResponse::SaveMode { save_mode } => match save_mode {
             crate::camera::CameraSaveMode::HostDevice => {
                 println!("saving to host device");
@@ -289,5 +581,56 @@ fn format_camera_response(response: CameraResponse) -> () {
         CameraResponse::ExposureMode { exposure_mode } => {
             println!("new exposure mode: {:?}", exposure_mode);
         }
+        CameraResponse::Iso { iso } => {
+            println!("iso: {}", iso);
+        }
+        CameraResponse::FocusMode { focus_mode } => {
+            println!("focus mode: {:?}", focus_mode);
+        }
+        CameraResponse::Aperture { aperture } => {
+            println!("aperture: {}", aperture);
+        }
+        CameraResponse::ShutterSpeed { shutter_speed } => {
+            println!("shutter speed: {}", shutter_speed);
+        }
+        CameraResponse::ExposureCompensation { compensation } => {
+            println!("exposure compensation: {}", compensation);
+        }
+        CameraResponse::WhiteBalance {
+            white_balance,
+            color_temperature,
+        } => match color_temperature {
+            Some(kelvin) => println!("white balance: {:?} ({}K)", white_balance, kelvin),
+            None => println!("white balance: {:?}", white_balance),
+        },
+        CameraResponse::Movie { recording } => {
+            if recording {
+                println!("recording movie");
+            } else {
+                println!("not recording movie");
+            }
+        }
+        CameraResponse::Battery {
+            level_percent,
+            indicator,
+        } => match indicator {
+            Some(indicator) => println!("battery: {}% ({:?})", level_percent, indicator),
+            None => println!("battery: {}%", level_percent),
+        },
+        CameraResponse::RemainingShots {
+            free_bytes,
+            estimate,
+        } => {
+            let free_space = free_bytes
+                .file_size(humansize::file_size_opts::BINARY)
+                .unwrap();
+
+            println!("free space: {}", free_space);
+
+            match estimate {
+                Some(estimate) => println!("estimated remaining shots: {}", estimate),
+                None => println!("estimated remaining shots: unknown"),
+            }
+        }
     }
 }
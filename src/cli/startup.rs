@@ -0,0 +1,81 @@
+use std::{sync::Arc, time::Duration};
+
+use structopt::StructOpt;
+
+use crate::{camera::CameraResponse, Channels, Command};
+
+use super::{
+    config::{StartupConfig, StartupFailurePolicy},
+    repl::{time_sync, ReplRequest},
+};
+
+/// Runs the commands listed in the startup section of the config, in order,
+/// as if they had been typed into the REPL.
+pub async fn run(channels: Arc<Channels>, config: StartupConfig) -> anyhow::Result<()> {
+    let timeout = Duration::from_secs(config.timeout_secs);
+
+    for (i, line) in config.commands.iter().enumerate() {
+        info!("running startup command {}: '{}'", i + 1, line);
+
+        let result = match tokio::time::timeout(timeout, exec(&channels, line)).await {
+            Ok(result) => result,
+            Err(_) => Err(anyhow!("timed out after {:?}", timeout)),
+        };
+
+        match result {
+            Ok(()) => info!("startup command {} succeeded", i + 1),
+            Err(err) => {
+                error!("startup command {} ('{}') failed: {:?}", i + 1, line, err);
+
+                if config.on_failure == StartupFailurePolicy::Abort {
+                    warn!("skipping remaining startup commands");
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    info!("finished startup commands");
+
+    Ok(())
+}
+
+async fn exec(channels: &Channels, line: &str) -> anyhow::Result<()> {
+    let request = <ReplRequest as StructOpt>::from_iter_safe(line.split_ascii_whitespace())
+        .map_err(|err| anyhow!("invalid command: {}", err.message))?;
+
+    match request {
+        ReplRequest::Camera(request) => {
+            let (cmd, chan) = Command::new(request);
+            channels.camera_cmd.clone().send(cmd).await?;
+            let response = chan.await??;
+            debug!("camera response: {:?}", response);
+
+            // a script that verifies its settings should stop if they did not
+            // take effect
+            if let CameraResponse::VerifySettings { ok: false, results } = response {
+                bail!("camera settings do not match: {:?}", results);
+            }
+        }
+        ReplRequest::Gimbal(request) => {
+            let (cmd, chan) = Command::new(request);
+            channels.gimbal_cmd.clone().send(cmd).await?;
+            let response = chan.await??;
+            debug!("gimbal response: {:?}", response);
+        }
+        ReplRequest::Pixhawk(request) => {
+            let (cmd, chan) = Command::new(request);
+            channels.pixhawk_cmd.clone().send(cmd).await?;
+            let response = chan.await??;
+            debug!("pixhawk response: {:?}", response);
+        }
+        ReplRequest::TimeSync { threshold_ms } => {
+            time_sync(channels, Duration::from_millis(threshold_ms)).await?;
+        }
+        ReplRequest::Exit => {
+            let _ = channels.interrupt.send(());
+        }
+    }
+
+    Ok(())
+}
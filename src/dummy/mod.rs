@@ -0,0 +1,221 @@
+use std::{f64::consts::PI, sync::Arc, time::Duration};
+
+use tokio::time::{interval, Instant};
+
+use crate::{
+    cli::config::{DummyConfig, DummyPath},
+    pixhawk::state::PixhawkEvent,
+    state::{Attitude, Coords3D},
+    Channels,
+};
+
+/// Meters per degree of latitude, close enough for a flight area.
+const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Pretends to be a pixhawk flying a fixed path, so that the telemetry stream,
+/// the scheduler and the server can be tried out on the ground.
+pub struct DummyTelemetry {
+    channels: Arc<Channels>,
+    config: DummyConfig,
+}
+
+impl DummyTelemetry {
+    pub fn new(channels: Arc<Channels>, config: DummyConfig) -> Self {
+        DummyTelemetry { channels, config }
+    }
+
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let mut interrupt_recv = self.channels.interrupt.subscribe();
+        let mut interval = interval(Duration::from_secs_f64(1.0 / self.config.rate_hz.max(0.1)));
+        let started = Instant::now();
+
+        loop {
+            tokio::select! {
+                _ = interrupt_recv.recv() => break,
+                _ = interval.tick() => {}
+            }
+
+            let distance = started.elapsed().as_secs_f64() * self.config.speed_m_s;
+            let (coords, attitude) = self.position_at(distance);
+
+            let _ = self
+                .channels
+                .pixhawk_event
+                .send(PixhawkEvent::Gps { coords });
+            let _ = self
+                .channels
+                .pixhawk_event
+                .send(PixhawkEvent::Orientation { attitude });
+        }
+
+        Ok(())
+    }
+
+    /// Works out where the plane is after flying `distance` meters along the
+    /// path.
+    fn position_at(&self, distance: f64) -> (Coords3D, Attitude) {
+        let (north, east, heading) = path_offset(&self.config, distance);
+
+        let center_latitude = self.config.center.latitude as f64;
+        let center_longitude = self.config.center.longitude as f64;
+
+        let latitude = center_latitude + north / METERS_PER_DEGREE;
+        let longitude =
+            center_longitude + east / (METERS_PER_DEGREE * center_latitude.to_radians().cos());
+
+        let coords = Coords3D::new(
+            latitude as f32,
+            longitude as f32,
+            self.config.altitude_m as f32,
+        );
+
+        // heading is in [0, 360) like the pixhawk's yaw
+        let attitude = Attitude::new(0.0, 0.0, heading.rem_euclid(360.0) as f32);
+
+        (coords, attitude)
+    }
+}
+
+/// Works out where the plane is after flying `distance` meters along the
+/// path, as offsets north and east of the center in meters, and a heading in
+/// degrees. The path never leaves the circle of radius `radius_m`.
+fn path_offset(config: &DummyConfig, distance: f64) -> (f64, f64, f64) {
+    let radius = config.radius_m;
+
+    match config.path {
+        DummyPath::Circle => {
+            // clockwise, starting due north of the center
+            let angle = distance / radius;
+            (
+                radius * angle.cos(),
+                radius * angle.sin(),
+                (angle + PI / 2.0).to_degrees(),
+            )
+        }
+        DummyPath::Lawnmower => {
+            // back and forth along north-south lanes, starting in the west;
+            // each lane is cut off where it meets the circle
+            let lanes = ((2.0 * radius / config.lane_spacing_m).round() as usize).max(1);
+            let lane_spacing = 2.0 * radius / lanes as f64;
+
+            // (north, east) of the start and end of a lane
+            let lane_ends = |lane: usize| {
+                let east = -radius + (lane as f64 + 0.5) * lane_spacing;
+                let half_length = (radius * radius - east * east).max(0.0).sqrt();
+
+                if lane % 2 == 0 {
+                    ((-half_length, east), (half_length, east))
+                } else {
+                    ((half_length, east), (-half_length, east))
+                }
+            };
+
+            // fly each lane and then straight to the start of the next one,
+            // and from the last lane back to the first; a straight line
+            // between two points on the circle stays inside it
+            let legs = (0..lanes)
+                .flat_map(|lane| {
+                    let (start, end) = lane_ends(lane);
+                    let (next_start, _) = lane_ends((lane + 1) % lanes);
+                    vec![(start, end), (end, next_start)]
+                })
+                .collect::<Vec<_>>();
+
+            follow_legs(&legs, distance)
+        }
+    }
+}
+
+/// The start and end of a straight part of a path, as (north, east) in meters.
+type Leg = ((f64, f64), (f64, f64));
+
+/// Works out where the plane is after flying `distance` meters along a loop
+/// of straight legs, each given as its start and end (north, east). Returns
+/// the same as `path_offset`.
+fn follow_legs(legs: &[Leg], distance: f64) -> (f64, f64, f64) {
+    let leg_length = |((start_north, start_east), (end_north, end_east)): Leg| {
+        (end_north - start_north).hypot(end_east - start_east)
+    };
+
+    let path_length: f64 = legs.iter().map(|&leg| leg_length(leg)).sum();
+    let mut remaining = distance % path_length;
+
+    for &leg in legs {
+        let length = leg_length(leg);
+        let ((start_north, start_east), (end_north, end_east)) = leg;
+
+        if remaining < length {
+            let along = remaining / length;
+            let heading = (end_east - start_east)
+                .atan2(end_north - start_north)
+                .to_degrees();
+
+            return (
+                start_north + along * (end_north - start_north),
+                start_east + along * (end_east - start_east),
+                heading,
+            );
+        }
+
+        remaining -= length;
+    }
+
+    // rounding can leave us just past the end of the last leg, which is the
+    // start of the first one
+    let ((north, east), _) = legs[0];
+    (north, east, 0.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Coords2D;
+
+    fn config(path: DummyPath) -> DummyConfig {
+        DummyConfig {
+            center: Coords2D::new(42.45, -76.48),
+            path,
+            radius_m: 200.0,
+            lane_spacing_m: 50.0,
+            speed_m_s: 20.0,
+            altitude_m: 100.0,
+            rate_hz: 10.0,
+        }
+    }
+
+    /// Checks every meter of several laps of the path.
+    fn assert_within_radius(config: &DummyConfig) {
+        for distance in 0..20_000 {
+            let (north, east, _) = path_offset(config, distance as f64);
+            let from_center = north.hypot(east);
+
+            assert!(
+                from_center <= config.radius_m + 1e-6,
+                "{:?} path is {} m from the center after {} m",
+                config.path,
+                from_center,
+                distance
+            );
+        }
+    }
+
+    #[test]
+    fn circle_stays_within_radius() {
+        assert_within_radius(&config(DummyPath::Circle));
+    }
+
+    #[test]
+    fn lawnmower_stays_within_radius() {
+        assert_within_radius(&config(DummyPath::Lawnmower));
+    }
+
+    #[test]
+    fn lawnmower_with_wide_lanes_stays_within_radius() {
+        let config = DummyConfig {
+            lane_spacing_m: 500.0,
+            ..config(DummyPath::Lawnmower)
+        };
+
+        assert_within_radius(&config);
+    }
+}
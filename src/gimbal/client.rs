@@ -1,41 +1,100 @@
 use anyhow::Context;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use tokio::sync::mpsc;
 
-use crate::Channels;
+use crate::{
+    cli::config::{GimbalAxisConfig, GimbalConfig, GimbalKind},
+    Channels,
+};
 
+use super::driver::GimbalDriver;
 use super::interface::*;
+use super::state::GimbalEvent;
 use super::*;
 
+/// How often a slewing gimbal is sent its next setpoint.
+const SLEW_TICK: Duration = Duration::from_millis(50);
+
+/// How long to give the gimbal to reach its reference position.
+const HOME_SETTLE_TIME: Duration = Duration::from_secs(2);
+
+/// How long the gimbal takes to calibrate its gyroscope.
+const CALIBRATE_TIME: Duration = Duration::from_secs(5);
+
+/// How often to read the gimbal's angles for telemetry.
+const ANGLE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
 pub struct GimbalClient {
-    iface: GimbalInterface,
+    iface: Box<dyn GimbalDriver>,
     channels: Arc<Channels>,
     cmd: mpsc::Receiver<GimbalCommand>,
+    config: GimbalConfig,
+
+    /// The last angles that were sent to the gimbal, as (roll, pitch).
+    position: (f64, f64),
+
+    /// Where the gimbal is slewing to, if it hasn't gotten there yet.
+    slew_target: Option<(f64, f64)>,
+    last_slew: Instant,
+
+    last_angle_poll: Instant,
+
+    /// Whether the last attempt to read the gimbal's angles worked.
+    angles_available: bool,
 }
 
 impl GimbalClient {
     pub fn connect(
         channels: Arc<Channels>,
         cmd: mpsc::Receiver<GimbalCommand>,
+        config: GimbalConfig,
     ) -> anyhow::Result<Self> {
-        let iface = GimbalInterface::new().context("failed to create gimbal interface")?;
+        let iface: Box<dyn GimbalDriver> = match config.kind {
+            GimbalKind::SimpleBGC => {
+                Box::new(SimpleBgcInterface::new().context("failed to create gimbal interface")?)
+            }
+        };
 
-        Ok(Self {
+        Ok(Self::with_driver(channels, cmd, config, iface))
+    }
+
+    /// Creates a client that talks to the gimbal through `iface`, whatever
+    /// kind of gimbal the config says is attached.
+    pub fn with_driver(
+        channels: Arc<Channels>,
+        cmd: mpsc::Receiver<GimbalCommand>,
+        config: GimbalConfig,
+        iface: Box<dyn GimbalDriver>,
+    ) -> Self {
+        Self {
             iface,
             channels,
             cmd,
-        })
+            config,
+            position: (0.0, 0.0),
+            slew_target: None,
+            last_slew: Instant::now(),
+            last_angle_poll: Instant::now(),
+            angles_available: false,
+        }
     }
 
-    pub fn init(&self) -> anyhow::Result<()> {
+    pub async fn init(&mut self) -> anyhow::Result<()> {
         trace!("initializing gimbal");
+
+        // start from a known position; the scheduler will move the gimbal
+        // wherever it needs to later, so this isn't fatal
+        if let Err(err) = self.home().await {
+            warn!("failed to home gimbal: {:?}", err);
+        }
+
         Ok(())
     }
 
     pub async fn run(&mut self) -> anyhow::Result<()> {
-        self.init()?;
+        self.init().await?;
 
         let mut interrupt_recv = self.channels.interrupt.subscribe();
 
@@ -49,6 +108,17 @@ impl GimbalClient {
                 break;
             }
 
+            if self.slew_target.is_some() && self.last_slew.elapsed() >= SLEW_TICK {
+                if let Err(err) = self.step_slew() {
+                    warn!("failed to slew gimbal, stopping: {:?}", err);
+                    self.slew_target = None;
+                }
+            }
+
+            if self.last_angle_poll.elapsed() >= ANGLE_POLL_INTERVAL {
+                self.publish_angles();
+            }
+
             tokio::time::sleep(Duration::from_millis(10)).await;
         }
         Ok(())
@@ -56,8 +126,445 @@ impl GimbalClient {
 
     async fn exec(&mut self, cmd: &GimbalRequest) -> anyhow::Result<GimbalResponse> {
         match cmd {
-            GimbalRequest::Control { roll, pitch } => self.iface.control_angles(*roll, *pitch)?,
+            GimbalRequest::Control {
+                roll,
+                pitch,
+                tolerance,
+                timeout_ms,
+            } => {
+                self.slew_to(*roll, *pitch)?;
+
+                if let Some(tolerance) = tolerance {
+                    self.wait_for_angles(
+                        (*roll, *pitch),
+                        *tolerance,
+                        Duration::from_millis(*timeout_ms),
+                    )
+                    .await?;
+                }
+            }
+            GimbalRequest::TestAxis { axis, angle } => {
+                let (roll, pitch) = match axis {
+                    GimbalAxis::Roll => (*angle, 0.0),
+                    GimbalAxis::Pitch => (0.0, *angle),
+                };
+
+                // this move is made directly, so stop any slew in progress
+                self.slew_target = None;
+
+                info!("moving gimbal {:?} to {} degrees", axis, angle);
+                self.control(roll, pitch)?;
+                tokio::time::sleep(Duration::from_secs(2)).await;
+
+                info!("recentering gimbal");
+                self.control(0.0, 0.0)?;
+            }
+            GimbalRequest::Ping => {
+                if !self.iface.is_connected()? {
+                    bail!("gimbal is not connected");
+                }
+
+                let firmware = self
+                    .iface
+                    .firmware_version()
+                    .context("gimbal did not report its firmware version")?;
+                return Ok(GimbalResponse::Version { firmware });
+            }
+            GimbalRequest::Angles => {
+                let (roll, pitch) = self.read_angles()?;
+                return Ok(GimbalResponse::Angles { roll, pitch });
+            }
+            GimbalRequest::Home => {
+                self.home().await?;
+                return Ok(GimbalResponse::Homed);
+            }
+            GimbalRequest::Calibrate => {
+                self.iface
+                    .calibrate()
+                    .context("failed to start gimbal calibration")?;
+                tokio::time::sleep(CALIBRATE_TIME).await;
+
+                self.home().await?;
+                return Ok(GimbalResponse::Calibrated);
+            }
         }
         Ok(GimbalResponse::Unit)
     }
+
+    /// Moves the gimbal to its reference position and waits for it to get
+    /// there.
+    async fn home(&mut self) -> anyhow::Result<()> {
+        info!("homing gimbal");
+        self.slew_target = None;
+        self.iface.home().context("failed to home gimbal")?;
+        self.position = (0.0, 0.0);
+
+        tokio::time::sleep(HOME_SETTLE_TIME).await;
+        Ok(())
+    }
+
+    /// Moves the gimbal to the given angles, no faster than the configured
+    /// slew rate. This returns after sending the first setpoint; the rest are
+    /// sent from the main loop, and a newer target replaces this one.
+    fn slew_to(&mut self, roll: f64, pitch: f64) -> anyhow::Result<()> {
+        match self.config.slew_rate {
+            Some(rate) if rate > 0.0 => {
+                self.slew_target = Some((roll, pitch));
+                self.step_slew()
+            }
+            _ => self.control(roll, pitch),
+        }
+    }
+
+    /// Waits until the gimbal is measured to be within `tolerance` degrees of
+    /// `target` on both axes, carrying on with any slew in the meantime.
+    async fn wait_for_angles(
+        &mut self,
+        target: (f64, f64),
+        tolerance: f64,
+        timeout: Duration,
+    ) -> anyhow::Result<()> {
+        wait_until_within(
+            || {
+                if self.slew_target.is_some() && self.last_slew.elapsed() >= SLEW_TICK {
+                    self.step_slew()?;
+                }
+
+                self.read_angles()
+            },
+            target,
+            tolerance,
+            timeout,
+        )
+        .await
+    }
+
+    /// Sends the next setpoint on the way to the slew target.
+    fn step_slew(&mut self) -> anyhow::Result<()> {
+        let (target_roll, target_pitch) = match self.slew_target {
+            Some(target) => target,
+            None => return Ok(()),
+        };
+
+        let max_step = self.config.slew_rate.unwrap_or(0.0) * SLEW_TICK.as_secs_f64();
+        let (next_roll, next_pitch) =
+            slew_step(self.position, (target_roll, target_pitch), max_step);
+
+        self.last_slew = Instant::now();
+        self.control(next_roll, next_pitch)?;
+
+        if (next_roll, next_pitch) == (target_roll, target_pitch) {
+            self.slew_target = None;
+        }
+
+        Ok(())
+    }
+
+    /// Sends commanded angles to the gimbal after mapping them onto the
+    /// gimbal's axes according to how it is mounted.
+    fn control(&mut self, roll: f64, pitch: f64) -> anyhow::Result<()> {
+        let map_axis = |axis: GimbalAxisConfig| {
+            let angle = match axis.source {
+                GimbalAxis::Roll => roll,
+                GimbalAxis::Pitch => pitch,
+            };
+
+            if axis.invert {
+                -angle
+            } else {
+                angle
+            }
+        };
+
+        let gimbal_roll = map_axis(self.config.roll);
+        let gimbal_pitch = map_axis(self.config.pitch);
+
+        trace!(
+            "mapped ({}, {}) to gimbal axes ({}, {})",
+            roll,
+            pitch,
+            gimbal_roll,
+            gimbal_pitch
+        );
+
+        self.iface.control(gimbal_roll, gimbal_pitch)?;
+        self.position = (roll, pitch);
+
+        Ok(())
+    }
+
+    /// Reads where the gimbal is actually pointing and sends it out for
+    /// telemetry. This can differ from `position` while the gimbal is still
+    /// moving, or if it is stuck.
+    fn publish_angles(&mut self) {
+        self.last_angle_poll = Instant::now();
+
+        match self.read_angles() {
+            Ok((roll, pitch)) => {
+                self.angles_available = true;
+                let _ = self
+                    .channels
+                    .gimbal_event
+                    .send(GimbalEvent::Angles { roll, pitch });
+            }
+            Err(err) => {
+                // only report the first failure, so that an unplugged gimbal
+                // doesn't flood the log
+                if self.angles_available {
+                    warn!("failed to read gimbal angles: {:?}", err);
+                    self.angles_available = false;
+                    let _ = self.channels.gimbal_event.send(GimbalEvent::Unavailable);
+                } else {
+                    trace!("failed to read gimbal angles: {:?}", err);
+                }
+            }
+        }
+    }
+
+    /// Reads the gimbal's angles and maps them from the gimbal's axes back
+    /// onto the commanded axes.
+    fn read_angles(&mut self) -> anyhow::Result<(f64, f64)> {
+        let (gimbal_roll, gimbal_pitch) = self
+            .iface
+            .read_angles()
+            .context("failed to read gimbal angles")?;
+
+        let mut roll = 0.0;
+        let mut pitch = 0.0;
+
+        for (axis, angle) in [
+            (self.config.roll, gimbal_roll),
+            (self.config.pitch, gimbal_pitch),
+        ]
+        .iter()
+        {
+            let angle = if axis.invert { -angle } else { *angle };
+
+            match axis.source {
+                GimbalAxis::Roll => roll = angle,
+                GimbalAxis::Pitch => pitch = angle,
+            }
+        }
+
+        Ok((roll, pitch))
+    }
+}
+
+/// The next setpoint on the way from `from` to `to`, moving each axis by no
+/// more than `max_step` degrees.
+fn slew_step(from: (f64, f64), to: (f64, f64), max_step: f64) -> (f64, f64) {
+    let step = |from: f64, to: f64| {
+        if (to - from).abs() <= max_step {
+            to
+        } else {
+            from + (to - from).signum() * max_step
+        }
+    };
+
+    (step(from.0, to.0), step(from.1, to.1))
+}
+
+/// Calls `read` every `SLEW_TICK` until the angles it returns are within
+/// `tolerance` degrees of `target` on both axes. Fails if that doesn't happen
+/// within `timeout`.
+async fn wait_until_within<F>(
+    mut read: F,
+    target: (f64, f64),
+    tolerance: f64,
+    timeout: Duration,
+) -> anyhow::Result<()>
+where
+    F: FnMut() -> anyhow::Result<(f64, f64)>,
+{
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        let (roll, pitch) = read()?;
+
+        if (roll - target.0).abs() <= tolerance && (pitch - target.1).abs() <= tolerance {
+            return Ok(());
+        }
+
+        if Instant::now() >= deadline {
+            bail!(
+                "gimbal did not reach ({}, {}) within {:?}; it is at ({:.1}, {:.1})",
+                target.0,
+                target.1,
+                timeout,
+                roll,
+                pitch
+            );
+        }
+
+        tokio::time::sleep(SLEW_TICK).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::super::mock::*;
+    use super::*;
+    use crate::TestChannels;
+
+    /// A gimbal client connected to a fake gimbal.
+    fn mock_client(config: serde_json::Value) -> (GimbalClient, Arc<Mutex<MockState>>) {
+        let gimbal = MockState::shared();
+        let (_, cmd) = mpsc::channel(1);
+
+        let client = GimbalClient::with_driver(
+            TestChannels::new().channels,
+            cmd,
+            serde_json::from_value(config).unwrap(),
+            Box::new(MockGimbal::new(gimbal.clone())),
+        );
+
+        (client, gimbal)
+    }
+
+    fn control(roll: f64, pitch: f64) -> GimbalRequest {
+        GimbalRequest::Control {
+            roll,
+            pitch,
+            tolerance: None,
+            timeout_ms: 3000,
+        }
+    }
+
+    #[tokio::test]
+    async fn wait_until_within_returns_once_the_gimbal_converges() {
+        // a simulated gimbal that halves the distance to the target each time
+        // it is read
+        let mut position = (0.0, 0.0);
+        let mut reads = 0;
+
+        let result = wait_until_within(
+            || {
+                position = ((position.0 + 20.0) / 2.0, (position.1 - 10.0) / 2.0);
+                reads += 1;
+                Ok(position)
+            },
+            (20.0, -10.0),
+            1.0,
+            Duration::from_secs(5),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(reads, 5);
+    }
+
+    #[tokio::test]
+    async fn wait_until_within_fails_if_the_gimbal_is_stuck() {
+        let result = wait_until_within(
+            || Ok((0.0, 0.0)),
+            (20.0, 0.0),
+            1.0,
+            Duration::from_millis(200),
+        )
+        .await;
+
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn commands_are_mapped_onto_the_gimbal_axes() {
+        // mounted with the gimbal's roll axis pointing along the plane's pitch
+        // axis, and the other way around
+        let (mut client, gimbal) = mock_client(serde_json::json!({
+            "roll": { "source": "pitch", "invert": true },
+            "pitch": { "source": "roll" }
+        }));
+
+        client.exec(&control(10.0, -20.0)).await.unwrap();
+        assert_eq!(
+            gimbal.lock().unwrap().calls,
+            vec![MockCall::Control(20.0, 10.0)]
+        );
+
+        // the angles that the gimbal reads back are mapped the other way
+        let response = client.exec(&GimbalRequest::Angles).await.unwrap();
+        assert!(matches!(
+            response,
+            GimbalResponse::Angles {
+                roll,
+                pitch,
+            } if roll == 10.0 && pitch == -20.0
+        ));
+    }
+
+    #[tokio::test]
+    async fn homing_resets_the_position() {
+        tokio::time::pause();
+
+        let (mut client, gimbal) = mock_client(serde_json::json!({}));
+        client.exec(&control(5.0, 5.0)).await.unwrap();
+
+        let response = client.exec(&GimbalRequest::Home).await.unwrap();
+
+        assert!(matches!(response, GimbalResponse::Homed));
+        assert_eq!(client.position, (0.0, 0.0));
+        assert_eq!(
+            gimbal.lock().unwrap().calls,
+            vec![MockCall::Control(5.0, 5.0), MockCall::Home]
+        );
+    }
+
+    #[tokio::test]
+    async fn ping_reports_the_firmware_version() {
+        let (mut client, gimbal) = mock_client(serde_json::json!({}));
+
+        let response = client.exec(&GimbalRequest::Ping).await.unwrap();
+        assert!(matches!(
+            response,
+            GimbalResponse::Version { firmware } if firmware == "2.70b4"
+        ));
+
+        gimbal.lock().unwrap().connected = false;
+        assert!(client.exec(&GimbalRequest::Ping).await.is_err());
+    }
+
+    #[test]
+    fn a_60_degree_slew_at_30_degrees_per_second_takes_2_seconds() {
+        let max_step = 30.0 * SLEW_TICK.as_secs_f64();
+        let mut position = (0.0, 0.0);
+        let mut ticks = 0;
+
+        while position != (0.0, 60.0) {
+            let next = slew_step(position, (0.0, 60.0), max_step);
+            assert!(next.1 - position.1 <= max_step);
+
+            position = next;
+            ticks += 1;
+        }
+
+        assert_eq!(SLEW_TICK * ticks, Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn a_newer_target_replaces_the_slew_in_progress() {
+        let (mut client, gimbal) = mock_client(serde_json::json!({ "slew_rate": 30.0 }));
+
+        client.exec(&control(0.0, 60.0)).await.unwrap();
+        client.step_slew().unwrap();
+
+        // turn back before getting anywhere near 60 degrees
+        client.exec(&control(0.0, -3.0)).await.unwrap();
+        while client.slew_target.is_some() {
+            client.step_slew().unwrap();
+        }
+
+        assert_eq!(
+            gimbal.lock().unwrap().controls(),
+            vec![
+                (0.0, 1.5),
+                (0.0, 3.0),
+                (0.0, 1.5),
+                (0.0, 0.0),
+                (0.0, -1.5),
+                (0.0, -3.0),
+            ]
+        );
+    }
 }
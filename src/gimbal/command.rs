@@ -1,5 +1,5 @@
 use clap::AppSettings;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
 use crate::Command;
@@ -10,10 +10,70 @@ pub type GimbalCommand = Command<GimbalRequest, GimbalResponse>;
 #[structopt(setting(AppSettings::NoBinaryName))]
 #[structopt(rename_all = "kebab-case")]
 pub enum GimbalRequest {
-    Control { roll: f64, pitch: f64 },
+    Control {
+        roll: f64,
+        pitch: f64,
+
+        /// wait until the gimbal is within this many degrees of the target on
+        /// both axes, and fail if it doesn't get there in time
+        #[structopt(long)]
+        tolerance: Option<f64>,
+
+        /// how long to wait for the gimbal to get within the tolerance, in
+        /// milliseconds
+        #[structopt(long, default_value = "3000")]
+        timeout_ms: u64,
+    },
+
+    /// move a single axis away from center and back again, to check that it
+    /// moves in the right direction
+    TestAxis {
+        axis: GimbalAxis,
+
+        /// the angle to move the axis to, in degrees
+        #[structopt(default_value = "20")]
+        angle: f64,
+    },
+
+    /// check that the gimbal is still connected and answering, and get its
+    /// firmware version
+    Ping,
+
+    /// read the angles that the gimbal is pointing at
+    Angles,
+
+    /// move the gimbal back to its reference position (level and centered)
+    Home,
+
+    /// calibrate the gimbal's gyroscope and then home it; the plane must be
+    /// kept still while this runs
+    Calibrate,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GimbalAxis {
+    Roll,
+    Pitch,
+}
+
+impl std::str::FromStr for GimbalAxis {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "roll" => Ok(GimbalAxis::Roll),
+            "pitch" => Ok(GimbalAxis::Pitch),
+            _ => bail!("invalid gimbal axis"),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub enum GimbalResponse {
     Unit,
+    Homed,
+    Calibrated,
+    Angles { roll: f64, pitch: f64 },
+    Version { firmware: String },
 }
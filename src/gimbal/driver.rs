@@ -0,0 +1,30 @@
+/// The hardware-specific half of the gimbal client. Each kind of gimbal that
+/// we support implements this, so that the client doesn't need to know how to
+/// talk to it.
+///
+/// Angles are in degrees and are in terms of the gimbal's own axes; the
+/// client takes care of mapping them to and from the plane's axes.
+pub trait GimbalDriver: Send {
+    /// Points the gimbal at the given angles.
+    fn control(&mut self, roll: f64, pitch: f64) -> anyhow::Result<()>;
+
+    /// Moves the gimbal to its reference position.
+    fn home(&mut self) -> anyhow::Result<()> {
+        self.control(0.0, 0.0)
+    }
+
+    /// Reads the gimbal's current angles as (roll, pitch).
+    fn read_angles(&mut self) -> anyhow::Result<(f64, f64)>;
+
+    /// Starts calibrating the gimbal's sensors.
+    fn calibrate(&mut self) -> anyhow::Result<()> {
+        bail!("this gimbal can't be calibrated")
+    }
+
+    /// Checks that the gimbal is still connected.
+    fn is_connected(&self) -> anyhow::Result<bool>;
+
+    /// Asks the gimbal for its firmware version, which also checks that it
+    /// is answering.
+    fn firmware_version(&mut self) -> anyhow::Result<String>;
+}
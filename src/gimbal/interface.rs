@@ -1,16 +1,38 @@
 use num_traits::FromPrimitive;
 use simplebgc::*;
 use std::io::{Read, Write};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+use super::driver::GimbalDriver;
 
 const SBGC_VID: u16 = 0x10C4;
 const SBGC_PID: u16 = 0xEA60;
 
-pub struct GimbalInterface {
-    port: serialport::TTYPort,
+/// SimpleBGC command ID for calibrating the gyroscope. simplebgc-rs doesn't
+/// have this command yet, so we frame it ourselves.
+const SBGC_CMD_CALIB_GYRO: u8 = 103;
+
+/// SimpleBGC command ID for reading the gimbal's angles, which simplebgc-rs
+/// doesn't have yet either.
+const SBGC_CMD_GET_ANGLES: u8 = 73;
+
+/// SimpleBGC command ID for reading the board and firmware versions, which
+/// simplebgc-rs doesn't have yet either.
+const SBGC_CMD_BOARD_INFO: u8 = 86;
+
+/// SimpleBGC angles are in units of 360 / 2^14 degrees.
+const SBGC_DEGREES_PER_UNIT: f64 = 360.0 / 16384.0;
+
+/// How long to wait for the gimbal to answer a request.
+const SBGC_RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Talks to a SimpleBGC gimbal controller over USB. The port is only generic
+/// so that the tests can stand in for the serial port.
+pub struct SimpleBgcInterface<P = serialport::TTYPort> {
+    port: P,
 }
 
-impl GimbalInterface {
+impl SimpleBgcInterface {
     pub fn new() -> anyhow::Result<Self> {
         if let Some(device_name) = Self::find_usb_device_name()? {
             let port = serialport::new(device_name, 115_200)
@@ -37,13 +59,23 @@ impl GimbalInterface {
         }
         Ok(None)
     }
+}
 
+impl<P: Read + Write> SimpleBgcInterface<P> {
     fn send_command(&mut self, cmd: OutgoingCommand) -> anyhow::Result<()> {
         let bytes = cmd.to_v1_bytes();
         self.port.write(&bytes[..])?;
         Ok(())
     }
 
+    /// Sends a command that simplebgc-rs doesn't support, framed according
+    /// to version 1 of the SimpleBGC serial protocol.
+    fn send_raw_command(&mut self, id: u8, payload: &[u8]) -> anyhow::Result<()> {
+        let bytes = raw_v1_bytes(id, payload);
+        self.port.write_all(&bytes[..])?;
+        Ok(())
+    }
+
     fn get_response(&mut self) -> anyhow::Result<IncomingCommand> {
         let mut buf: Vec<u8> = vec![0; 4096];
         let marker = self.port.read(buf.as_mut_slice())?;
@@ -51,7 +83,31 @@ impl GimbalInterface {
         Ok(cmd)
     }
 
-    pub fn control_angles(&mut self, mut roll: f64, mut pitch: f64) -> anyhow::Result<()> {
+    /// Reads from the gimbal until it sends a frame with the given command
+    /// ID, and returns that frame's payload.
+    fn get_raw_response(&mut self, id: u8) -> anyhow::Result<Vec<u8>> {
+        let deadline = Instant::now() + SBGC_RESPONSE_TIMEOUT;
+        let mut buf = Vec::new();
+        let mut chunk = vec![0; 256];
+
+        while Instant::now() < deadline {
+            match self.port.read(&mut chunk[..]) {
+                Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                Err(err) if err.kind() == std::io::ErrorKind::TimedOut => continue,
+                Err(err) => return Err(err.into()),
+            }
+
+            if let Some(payload) = find_raw_v1_frame(&buf, id) {
+                return Ok(payload);
+            }
+        }
+
+        bail!("timed out waiting for response from gimbal")
+    }
+}
+
+impl<P: Read + Write + Send> GimbalDriver for SimpleBgcInterface<P> {
+    fn control(&mut self, mut roll: f64, mut pitch: f64) -> anyhow::Result<()> {
         info!("Got request for {}, {}", roll, pitch);
         if roll.abs() > 50.0 || pitch.abs() > 50.0 {
             roll = 0.0;
@@ -81,4 +137,185 @@ impl GimbalInterface {
         // let response = self.get_response()?;
         Ok(())
     }
+
+    fn read_angles(&mut self) -> anyhow::Result<(f64, f64)> {
+        self.send_raw_command(SBGC_CMD_GET_ANGLES, &[])?;
+        let payload = self.get_raw_response(SBGC_CMD_GET_ANGLES)?;
+
+        // each axis (roll, pitch, yaw) has its IMU angle, target angle and
+        // target speed as little-endian i16s; we want the IMU angles
+        if payload.len() < 18 {
+            bail!("angle response from gimbal is too short");
+        }
+
+        let angle = |offset: usize| {
+            i16::from_le_bytes([payload[offset], payload[offset + 1]]) as f64
+                * SBGC_DEGREES_PER_UNIT
+        };
+
+        Ok((angle(0), angle(6)))
+    }
+
+    /// Starts the gimbal's gyroscope calibration. The gimbal must be kept
+    /// still until the calibration is done, which takes a few seconds.
+    fn calibrate(&mut self) -> anyhow::Result<()> {
+        info!("calibrating gimbal gyroscope");
+        self.send_raw_command(SBGC_CMD_CALIB_GYRO, &[])
+    }
+
+    /// Checks that the gimbal's USB device is still present.
+    fn is_connected(&self) -> anyhow::Result<bool> {
+        Ok(SimpleBgcInterface::<serialport::TTYPort>::find_usb_device_name()?.is_some())
+    }
+
+    fn firmware_version(&mut self) -> anyhow::Result<String> {
+        self.send_raw_command(SBGC_CMD_BOARD_INFO, &[])?;
+        let payload = self.get_raw_response(SBGC_CMD_BOARD_INFO)?;
+        parse_firmware_version(&payload)
+    }
+}
+
+/// Reads the firmware version out of a CMD_BOARD_INFO response, which starts
+/// with the board version as a u8 and the firmware version as a little-endian
+/// u16, e.g. 2688 for 2.68b8.
+fn parse_firmware_version(payload: &[u8]) -> anyhow::Result<String> {
+    if payload.len() < 3 {
+        bail!("board info response from gimbal is too short");
+    }
+
+    let version = u16::from_le_bytes([payload[1], payload[2]]);
+
+    Ok(format!(
+        "{}.{}b{}",
+        version / 1000,
+        version % 1000 / 10,
+        version % 10
+    ))
+}
+
+/// Frames a command as `>`, the command ID, the payload size, a header
+/// checksum, the payload and a payload checksum.
+fn raw_v1_bytes(id: u8, payload: &[u8]) -> Vec<u8> {
+    let size = payload.len() as u8;
+    let header_checksum = id.wrapping_add(size);
+    let payload_checksum = payload.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+    let mut bytes = Vec::with_capacity(payload.len() + 5);
+    bytes.push(b'>');
+    bytes.push(id);
+    bytes.push(size);
+    bytes.push(header_checksum);
+    bytes.extend_from_slice(payload);
+    bytes.push(payload_checksum);
+    bytes
+}
+
+/// Finds the first well-formed frame with the given command ID in `buf` and
+/// returns its payload.
+fn find_raw_v1_frame(buf: &[u8], id: u8) -> Option<Vec<u8>> {
+    for start in 0..buf.len() {
+        let header = match buf.get(start..start + 4) {
+            Some(header) => header,
+            None => break,
+        };
+
+        let size = header[2] as usize;
+
+        if header[0] != b'>' || header[1] != id || header[1].wrapping_add(header[2]) != header[3] {
+            continue;
+        }
+
+        let payload = match buf.get(start + 4..start + 4 + size) {
+            Some(payload) => payload,
+            None => break,
+        };
+
+        let checksum = payload.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+
+        if buf.get(start + 4 + size) == Some(&checksum) {
+            return Some(payload.to_vec());
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SimpleBGC command ID for CMD_CONTROL.
+    const SBGC_CMD_CONTROL: u8 = 67;
+
+    /// Stands in for the gimbal's serial port, keeping everything written to
+    /// it. Reads time out like a serial port with nothing to read.
+    #[derive(Default)]
+    struct MockPort {
+        written: Vec<u8>,
+    }
+
+    impl Read for MockPort {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            Err(std::io::ErrorKind::TimedOut.into())
+        }
+    }
+
+    impl Write for MockPort {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.written.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn homing_sends_a_control_frame_to_the_center() {
+        let mut iface = SimpleBgcInterface {
+            port: MockPort::default(),
+        };
+
+        iface.home().unwrap();
+
+        let written = &iface.port.written;
+        let payload = find_raw_v1_frame(written, SBGC_CMD_CONTROL).unwrap();
+
+        // one frame and nothing else
+        assert_eq!(written, &raw_v1_bytes(SBGC_CMD_CONTROL, &payload));
+
+        // the control mode, then the speed and angle of roll, pitch and yaw
+        let field = |offset: usize| i16::from_le_bytes([payload[offset], payload[offset + 1]]);
+        assert_eq!(payload.len(), 13);
+        assert_eq!(payload[0], 0x02);
+        assert_eq!((field(1), field(3)), (1200, 0));
+        assert_eq!((field(5), field(7)), (2400, 0));
+        assert_eq!((field(9), field(11)), (0, 0));
+    }
+
+    #[test]
+    fn calibration_is_a_bare_calib_gyro_frame() {
+        let mut iface = SimpleBgcInterface {
+            port: MockPort::default(),
+        };
+
+        iface.calibrate().unwrap();
+
+        assert_eq!(
+            iface.port.written,
+            vec![b'>', SBGC_CMD_CALIB_GYRO, 0, SBGC_CMD_CALIB_GYRO, 0]
+        );
+    }
+
+    #[test]
+    fn firmware_version_is_read_from_board_info() {
+        // board 3.6, firmware 2.68b8, then the rest of the board info
+        let mut payload = vec![36];
+        payload.extend_from_slice(&2688u16.to_le_bytes());
+        payload.extend_from_slice(&[0; 15]);
+
+        assert_eq!(parse_firmware_version(&payload).unwrap(), "2.68b8");
+        assert!(parse_firmware_version(&payload[..2]).is_err());
+    }
 }
@@ -0,0 +1,95 @@
+//! A fake gimbal for testing the gimbal client without a gimbal plugged in.
+//! The test keeps a handle to the gimbal's state, so that it can check what
+//! the client asked the gimbal to do.
+
+use std::sync::{Arc, Mutex};
+
+use super::driver::GimbalDriver;
+
+/// Something that the client asked the fake gimbal to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MockCall {
+    Control(f64, f64),
+    Home,
+    Calibrate,
+}
+
+#[derive(Default)]
+pub struct MockState {
+    /// The gimbal's angles as (roll, pitch), on its own axes. The fake gimbal
+    /// gets wherever it is sent straight away.
+    pub angles: (f64, f64),
+
+    pub connected: bool,
+
+    /// Everything that the client asked for, in order.
+    pub calls: Vec<MockCall>,
+}
+
+impl MockState {
+    /// A connected gimbal at its reference position.
+    pub fn shared() -> Arc<Mutex<MockState>> {
+        Arc::new(Mutex::new(MockState {
+            connected: true,
+            ..Default::default()
+        }))
+    }
+
+    /// The angles that the gimbal was sent, in order.
+    pub fn controls(&self) -> Vec<(f64, f64)> {
+        self.calls
+            .iter()
+            .filter_map(|call| match call {
+                MockCall::Control(roll, pitch) => Some((*roll, *pitch)),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+pub struct MockGimbal {
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockGimbal {
+    pub fn new(state: Arc<Mutex<MockState>>) -> Self {
+        MockGimbal { state }
+    }
+}
+
+impl GimbalDriver for MockGimbal {
+    fn control(&mut self, roll: f64, pitch: f64) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(MockCall::Control(roll, pitch));
+        state.angles = (roll, pitch);
+        Ok(())
+    }
+
+    fn home(&mut self) -> anyhow::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.calls.push(MockCall::Home);
+        state.angles = (0.0, 0.0);
+        Ok(())
+    }
+
+    fn read_angles(&mut self) -> anyhow::Result<(f64, f64)> {
+        Ok(self.state.lock().unwrap().angles)
+    }
+
+    fn calibrate(&mut self) -> anyhow::Result<()> {
+        self.state.lock().unwrap().calls.push(MockCall::Calibrate);
+        Ok(())
+    }
+
+    fn is_connected(&self) -> anyhow::Result<bool> {
+        Ok(self.state.lock().unwrap().connected)
+    }
+
+    fn firmware_version(&mut self) -> anyhow::Result<String> {
+        if !self.state.lock().unwrap().connected {
+            bail!("gimbal did not answer");
+        }
+
+        Ok("2.70b4".to_owned())
+    }
+}
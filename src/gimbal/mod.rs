@@ -1,6 +1,10 @@
 pub mod client;
 pub mod command;
+pub mod driver;
 mod interface;
+#[cfg(test)]
+mod mock;
+pub mod state;
 
 pub use client::*;
 pub use command::*;
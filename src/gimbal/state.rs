@@ -0,0 +1,10 @@
+#[derive(Debug, Clone)]
+pub enum GimbalEvent {
+    /// The gimbal is pointing at these angles, in degrees, as measured by its
+    /// IMU. They are mapped back from the gimbal's own axes onto the
+    /// commanded axes.
+    Angles { roll: f64, pitch: f64 },
+
+    /// The gimbal stopped answering, so where it is pointing is unknown.
+    Unavailable,
+}
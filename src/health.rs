@@ -0,0 +1,130 @@
+use std::{collections::HashMap, sync::RwLock};
+
+use serde::Serialize;
+
+/// Tasks that are allowed to end without the system being unhealthy.
+const NON_CRITICAL_TASKS: &[&str] = &["startup", "cli", "self-test"];
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TaskState {
+    Running,
+    Stopped,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskHealth {
+    pub state: TaskState,
+
+    /// Whether the system is unhealthy when this task isn't running.
+    pub critical: bool,
+
+    /// The error that the task ended with, if it failed.
+    pub last_error: Option<String>,
+}
+
+/// The result of the last self-test check of a device link.
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckHealth {
+    pub passed: bool,
+
+    /// Why the check failed.
+    pub error: Option<String>,
+}
+
+/// Keeps track of which tasks are running and how the self-test checks went,
+/// so that the server can report it.
+#[derive(Debug, Default)]
+pub struct HealthRegistry {
+    tasks: RwLock<HashMap<&'static str, TaskHealth>>,
+    checks: RwLock<HashMap<&'static str, CheckHealth>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    /// Whether every critical task is running and every check passed.
+    pub healthy: bool,
+    pub tasks: HashMap<&'static str, TaskHealth>,
+    pub checks: HashMap<&'static str, CheckHealth>,
+}
+
+impl HealthRegistry {
+    pub fn started(&self, name: &'static str) {
+        self.set(name, TaskState::Running, None);
+    }
+
+    pub fn stopped(&self, name: &'static str) {
+        self.set(name, TaskState::Stopped, None);
+    }
+
+    pub fn failed(&self, name: &'static str, error: String) {
+        self.set(name, TaskState::Failed, Some(error));
+    }
+
+    fn set(&self, name: &'static str, state: TaskState, last_error: Option<String>) {
+        let mut tasks = self.tasks.write().unwrap();
+
+        tasks.insert(
+            name,
+            TaskHealth {
+                state,
+                critical: !NON_CRITICAL_TASKS.contains(&name),
+                last_error,
+            },
+        );
+    }
+
+    /// Records the result of a self-test check, replacing the last one.
+    pub fn checked(&self, name: &'static str, result: &anyhow::Result<()>) {
+        let mut checks = self.checks.write().unwrap();
+
+        checks.insert(
+            name,
+            CheckHealth {
+                passed: result.is_ok(),
+                error: result.as_ref().err().map(|err| format!("{:#}", err)),
+            },
+        );
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let tasks = self.tasks.read().unwrap().clone();
+        let checks = self.checks.read().unwrap().clone();
+        let healthy = tasks
+            .values()
+            .all(|task| !task.critical || task.state == TaskState::Running)
+            && checks.values().all(|check| check.passed);
+
+        HealthReport {
+            healthy,
+            tasks,
+            checks,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_failed_check_makes_the_system_unhealthy_until_it_passes() {
+        let health = HealthRegistry::default();
+        health.started("camera");
+        health.checked("camera", &Ok(()));
+        assert!(health.report().healthy);
+
+        health.checked("camera", &Err(anyhow!("camera did not respond")));
+
+        let report = health.report();
+        assert!(!report.healthy);
+        assert_eq!(
+            report.checks["camera"].error.as_deref(),
+            Some("camera did not respond")
+        );
+
+        health.checked("camera", &Ok(()));
+        assert!(health.report().healthy);
+    }
+}
@@ -1,4 +1,4 @@
-use std::{sync::Arc, process::exit};
+use std::{process::exit, sync::Arc};
 
 use anyhow::Context;
 use camera::{client::CameraClient, state::CameraEvent};
@@ -6,6 +6,7 @@ use ctrlc;
 use gimbal::client::GimbalClient;
 use pixhawk::{client::PixhawkClient, state::PixhawkEvent};
 use scheduler::Scheduler;
+use selftest::{SelfTest, SelfTestDevices};
 use state::TelemetryInfo;
 use std::time::Duration;
 use structopt::StructOpt;
@@ -23,9 +24,12 @@ extern crate async_trait;
 
 mod camera;
 mod cli;
+mod dummy;
 mod gimbal;
+mod health;
 mod pixhawk;
 mod scheduler;
+mod selftest;
 mod server;
 mod state;
 mod telemetry;
@@ -53,6 +57,18 @@ pub struct Channels {
 
     /// Channel for sending instructions to the gimbal.
     gimbal_cmd: mpsc::Sender<gimbal::GimbalCommand>,
+
+    /// Channel for broadcasting updates to the state of the gimbal.
+    gimbal_event: broadcast::Sender<gimbal::state::GimbalEvent>,
+
+    /// Channel for sending instructions to the scheduler.
+    scheduler_cmd: mpsc::Sender<scheduler::SchedulerCommand>,
+
+    /// Channel for broadcasting updates to the state of the scheduler.
+    scheduler_event: broadcast::Sender<scheduler::state::SchedulerEvent>,
+
+    /// Which tasks are running, for the health check.
+    health: health::HealthRegistry,
 }
 
 #[derive(Debug)]
@@ -94,21 +110,77 @@ impl<Req, Res, Err> Command<Req, Res, Err> {
     }
 }
 
+/// Channels for a test, along with the ends of them that the rest of the
+/// system would normally hold.
+#[cfg(test)]
+struct TestChannels {
+    channels: Arc<Channels>,
+    telemetry: watch::Sender<Option<TelemetryInfo>>,
+    camera_cmd: mpsc::Receiver<camera::CameraCommand>,
+    scheduler_cmd: mpsc::Receiver<scheduler::SchedulerCommand>,
+}
+
+#[cfg(test)]
+impl TestChannels {
+    fn new() -> Self {
+        let (interrupt_sender, _) = broadcast::channel(1);
+        let (telemetry_sender, telemetry_receiver) = watch::channel(None);
+        let (pixhawk_event_sender, _) = broadcast::channel(64);
+        let (pixhawk_cmd_sender, _) = mpsc::channel(64);
+        let (camera_event_sender, _) = broadcast::channel(256);
+        let (camera_cmd_sender, camera_cmd_receiver) = mpsc::channel(256);
+        let (gimbal_cmd_sender, _) = mpsc::channel(256);
+        let (gimbal_event_sender, _) = broadcast::channel(64);
+        let (scheduler_cmd_sender, scheduler_cmd_receiver) = mpsc::channel(64);
+        let (scheduler_event_sender, _) = broadcast::channel(64);
+
+        let channels = Arc::new(Channels {
+            interrupt: interrupt_sender,
+            telemetry: telemetry_receiver,
+            pixhawk_event: pixhawk_event_sender,
+            pixhawk_cmd: pixhawk_cmd_sender,
+            camera_event: camera_event_sender,
+            camera_cmd: camera_cmd_sender,
+            gimbal_cmd: gimbal_cmd_sender,
+            gimbal_event: gimbal_event_sender,
+            scheduler_cmd: scheduler_cmd_sender,
+            scheduler_event: scheduler_event_sender,
+            health: health::HealthRegistry::default(),
+        });
+
+        TestChannels {
+            channels,
+            telemetry: telemetry_sender,
+            camera_cmd: camera_cmd_receiver,
+            scheduler_cmd: scheduler_cmd_receiver,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    pretty_env_logger::init_timed();
-
     let main_args: cli::args::MainArgs = cli::args::MainArgs::from_args();
 
-    let config = if let Some(config_path) = main_args.config {
-        debug!("reading config from {:?}", &config_path);
-        cli::config::PlaneSystemConfig::read_from_path(config_path)
-    } else {
-        debug!("reading config from default location");
-        cli::config::PlaneSystemConfig::read()
-    };
+    // the config says how to log, so it has to be read before the logger is
+    // set up
+    let config = cli::config::PlaneSystemConfig::read_layered(
+        main_args.config.clone(),
+        main_args.override_config.clone(),
+    )
+    .context("failed to read config")?;
+
+    cli::logging::init(&config.logging);
+
+    match &main_args.config {
+        Some(config_path) => debug!("read config from {:?}", config_path),
+        None => debug!("read config from default location"),
+    }
+
+    if let Some(override_path) = &main_args.override_config {
+        debug!("applied config overrides from {:?}", override_path);
+    }
 
-    let config = config.context("failed to read config file")?;
+    config.validate()?;
 
     let (interrupt_sender, _) = broadcast::channel(1);
     let (telemetry_sender, telemetry_receiver) = watch::channel(None);
@@ -117,6 +189,9 @@ async fn main() -> anyhow::Result<()> {
     let (camera_event_sender, _) = broadcast::channel(256);
     let (camera_cmd_sender, camera_cmd_receiver) = mpsc::channel(256);
     let (gimbal_cmd_sender, gimbal_cmd_receiver) = mpsc::channel(256);
+    let (gimbal_event_sender, _) = broadcast::channel(64);
+    let (scheduler_cmd_sender, scheduler_cmd_receiver) = mpsc::channel(64);
+    let (scheduler_event_sender, _) = broadcast::channel(64);
 
     let channels = Arc::new(Channels {
         interrupt: interrupt_sender.clone(),
@@ -126,6 +201,10 @@ async fn main() -> anyhow::Result<()> {
         camera_event: camera_event_sender,
         camera_cmd: camera_cmd_sender,
         gimbal_cmd: gimbal_cmd_sender,
+        gimbal_event: gimbal_event_sender,
+        scheduler_cmd: scheduler_cmd_sender,
+        scheduler_event: scheduler_event_sender,
+        health: health::HealthRegistry::default(),
     });
 
     let mut task_names = Vec::new();
@@ -140,14 +219,24 @@ async fn main() -> anyhow::Result<()> {
     })
     .expect("could not set ctrl+c handler");
 
-    if let Some(pixhawk_address) = config.pixhawk.address {
-        info!("connecting to pixhawk at {}", pixhawk_address);
+    let pixhawk_transport = config.pixhawk.transport()?;
+
+    let server_address = config.server.socket_address()?;
+
+    let self_test_devices = SelfTestDevices {
+        pixhawk: pixhawk_transport.is_some(),
+        camera: config.camera.is_some(),
+        gimbal: config.gimbal.is_some(),
+    };
+
+    if let Some(pixhawk_transport) = pixhawk_transport {
+        info!("connecting to pixhawk at {:?}", pixhawk_transport);
         let pixhawk_task = spawn({
             let mut pixhawk_client = PixhawkClient::connect(
                 channels.clone(),
                 pixhawk_cmd_receiver,
-                pixhawk_address,
-                config.pixhawk.mavlink,
+                pixhawk_transport,
+                &config.pixhawk,
             )
             .await?;
             async move { pixhawk_client.run().await }
@@ -157,29 +246,57 @@ async fn main() -> anyhow::Result<()> {
 
         info!("initializing telemetry stream");
         let telemetry_task = spawn({
-            let telemetry = TelemetryStream::new(channels.clone(), telemetry_sender);
+            let telemetry =
+                TelemetryStream::new(channels.clone(), telemetry_sender, config.telemetry);
+            async move { telemetry.run().await }
+        });
+        task_names.push("telemetry");
+        futures.push(telemetry_task);
+    } else if let Some(dummy_config) = config.dummy {
+        info!("pixhawk not specified, generating dummy telemetry");
+        let dummy_task = spawn({
+            let dummy = dummy::DummyTelemetry::new(channels.clone(), dummy_config);
+            async move { dummy.run().await }
+        });
+        task_names.push("dummy");
+        futures.push(dummy_task);
+
+        info!("initializing telemetry stream");
+        let telemetry_task = spawn({
+            let telemetry =
+                TelemetryStream::new(channels.clone(), telemetry_sender, config.telemetry);
             async move { telemetry.run().await }
         });
         task_names.push("telemetry");
         futures.push(telemetry_task);
     } else {
-        info!("pixhawk address not specified, disabling pixhawk connection and telemetry stream");
+        info!("pixhawk address or serial port not specified, disabling pixhawk connection and telemetry stream");
     }
 
-    if config.camera {
+    if let Some(camera_config) = config.camera {
         info!("connecting to camera");
         let camera_task = spawn({
-            let mut camera_client = CameraClient::connect(channels.clone(), camera_cmd_receiver)?;
-            async move { camera_client.run().await }
+            let camera_client = CameraClient::connect_when_available(
+                channels.clone(),
+                camera_cmd_receiver,
+                camera_config,
+            );
+            async move {
+                match camera_client.await? {
+                    Some(mut camera_client) => camera_client.run().await,
+                    None => Ok(()),
+                }
+            }
         });
         task_names.push("camera");
         futures.push(camera_task);
     }
 
-    if config.gimbal {
+    if let Some(gimbal_config) = config.gimbal {
         info!("initializing gimbal");
         let gimbal_task = spawn({
-            let mut gimbal_client = GimbalClient::connect(channels.clone(), gimbal_cmd_receiver)?;
+            let mut gimbal_client =
+                GimbalClient::connect(channels.clone(), gimbal_cmd_receiver, gimbal_config)?;
             async move { gimbal_client.run().await }
         });
         task_names.push("gimbal");
@@ -189,26 +306,51 @@ async fn main() -> anyhow::Result<()> {
     if config.scheduler.enabled {
         info!("initializing scheduler");
         let scheduler_task = spawn({
-            let mut scheduler = Scheduler::new(channels.clone(), config.scheduler.gps);
+            let mut scheduler =
+                Scheduler::new(channels.clone(), scheduler_cmd_receiver, config.scheduler);
             async move { scheduler.run().await }
         });
         task_names.push("scheduler");
         futures.push(scheduler_task);
+    } else {
+        // nobody is going to answer scheduler commands, so make sure that
+        // they fail instead of waiting forever
+        drop(scheduler_cmd_receiver);
+    }
+
+    if let Some(self_test_config) = config.self_test {
+        info!("initializing self-test");
+        let self_test_task = spawn({
+            let self_test = SelfTest::new(channels.clone(), self_test_config, self_test_devices);
+            async move { self_test.run().await }
+        });
+        task_names.push("self-test");
+        futures.push(self_test_task);
     }
 
     info!("initializing server");
-    let server_address = config
-        .server
-        .address
-        .parse()
-        .context("invalid server address")?;
     let server_task = spawn({
         let channels = channels.clone();
-        server::serve(channels, server_address)
+        server::serve(
+            channels,
+            server_address,
+            config.server.boundary,
+            config.server.auth_token,
+        )
     });
     task_names.push("server");
     futures.push(server_task);
 
+    if let Some(startup_config) = config.startup {
+        info!("running startup commands");
+        let startup_task = spawn({
+            let channels = channels.clone();
+            cli::startup::run(channels, startup_config)
+        });
+        task_names.push("startup");
+        futures.push(startup_task);
+    }
+
     info!("intializing cli");
     let cli_task = spawn({
         let channels = channels.clone();
@@ -217,6 +359,10 @@ async fn main() -> anyhow::Result<()> {
     task_names.push("cli");
     futures.push(cli_task);
 
+    for task_name in &task_names {
+        channels.health.started(*task_name);
+    }
+
     while futures.len() > 0 {
         // wait for each task to end
         let (result, i, remaining) = futures::future::select_all(futures).await;
@@ -229,13 +375,28 @@ async fn main() -> anyhow::Result<()> {
             remaining.len()
         );
 
-        // if a task ended with an error or did not join properly, end the process
-        // with an interrupt
-        if let Err(err) = result? {
-            error!(
-                "got error from {} task, sending interrupt: {:?}",
-                task_name, err
-            );
+        let error = match result {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(format!("{:?}", err)),
+            Err(err) => Some(format!("task panicked: {}", err)),
+        };
+
+        let error = match error {
+            Some(error) => error,
+            None => {
+                channels.health.stopped(task_name);
+                futures = remaining;
+                continue;
+            }
+        };
+
+        channels.health.failed(task_name, error.clone());
+
+        // if the server is gone, nothing can report the failure, so end the
+        // process with an interrupt; otherwise keep the rest of the system up
+        // so that the health check reports the failed task
+        if task_name == "server" {
+            error!("got error from server task, sending interrupt: {}", error);
 
             info!("remaining tasks: {:?}", task_names.join(", "));
 
@@ -246,6 +407,11 @@ async fn main() -> anyhow::Result<()> {
                 warn!("tasks did not end after 5 seconds, force-quitting");
                 exit(1);
             });
+        } else {
+            error!(
+                "got error from {} task, reporting it in the health check: {}",
+                task_name, error
+            );
         }
 
         futures = remaining;
@@ -1,4 +1,5 @@
 use std::{
+    convert::TryFrom,
     sync::atomic::AtomicU8,
     sync::atomic::Ordering,
     sync::Arc,
@@ -7,7 +8,7 @@ use std::{
 
 use anyhow::Context;
 use bytes::{Buf, BytesMut};
-use tokio::{net::ToSocketAddrs, sync::mpsc};
+use tokio::sync::{broadcast, mpsc};
 
 use mavlink::{
     ardupilotmega as apm, common, error::MessageReadError, error::ParserError, MavHeader,
@@ -15,68 +16,212 @@ use mavlink::{
 };
 
 use crate::{
+    cli::config::{CameraTriggerConfig, MessageIntervalConfig, PixhawkConfig},
     state::{Attitude, Coords3D},
     Channels,
 };
 
-use super::{state::PixhawkEvent, PixhawkCommand};
+use super::{
+    interface::{PixhawkInterface, PixhawkTransport},
+    state::PixhawkEvent,
+    *,
+};
+
+/// Identifies a node on the MAVLink bus by its system and component IDs. An ID
+/// of 0 is a broadcast and addresses every system or component.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MavTarget {
+    pub system: u8,
+    pub component: u8,
+}
+
+impl MavTarget {
+    /// The default ID of the flight controller, which is what commands are
+    /// addressed to unless specified otherwise.
+    pub const AUTOPILOT: MavTarget = MavTarget {
+        system: 1,
+        component: 1,
+    };
+
+    pub fn new(system: u8, component: u8) -> Self {
+        MavTarget { system, component }
+    }
+
+    /// Checks whether a message with the given header could have been sent by
+    /// this target.
+    pub fn matches(&self, header: &MavHeader) -> bool {
+        (self.system == 0 || self.system == header.system_id)
+            && (self.component == 0 || self.component == header.component_id)
+    }
+}
+
+const MAVLINK_V1_MAGIC: u8 = 0xFE;
+const MAVLINK_V2_MAGIC: u8 = 0xFD;
+
+/// Set in the incompatibility flags of a MAVLink v2 message that is followed
+/// by a signature.
+const MAVLINK_IFLAG_SIGNED: u8 = 0x01;
+const MAVLINK_SIGNATURE_LEN: usize = 13;
+
+/// How long to wait for the autopilot to ask for the next mission item before
+/// sending the last message again.
+const MISSION_TIMEOUT: Duration = Duration::from_secs(2);
+const MISSION_RETRIES: u32 = 5;
+
+/// How long to wait for a packet before deciding that the link to the Pixhawk
+/// is lost.
+const LINK_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub struct PixhawkClient {
-    sock: tokio::net::UdpSocket,
+    iface: PixhawkInterface,
+    transport: PixhawkTransport,
     buf: BytesMut,
     sequence: AtomicU8,
     channels: Arc<Channels>,
     cmd: mpsc::Receiver<PixhawkCommand>,
     version: MavlinkVersion,
+    camera_trigger: CameraTriggerConfig,
+
+    /// The system and component IDs that we send messages as.
+    identity: MavTarget,
+
+    /// The autopilot, which parameters and commands are sent to.
+    target: MavTarget,
+
+    reconnect_attempts: u32,
+    reconnect_delay: Duration,
+
+    /// Message rates requested in the config, which are set during init.
+    message_intervals: Vec<MessageIntervalConfig>,
 }
 
 impl PixhawkClient {
-    pub async fn connect<A: ToSocketAddrs + Clone>(
+    pub async fn connect(
         channels: Arc<Channels>,
         cmd: mpsc::Receiver<PixhawkCommand>,
-        addr: A,
-        version: MavlinkVersion,
+        transport: PixhawkTransport,
+        config: &PixhawkConfig,
     ) -> anyhow::Result<Self> {
-        let sock = tokio::net::UdpSocket::bind(addr)
-            .await
-            .context("failed to connect to pixhawk")?;
+        config
+            .camera_trigger
+            .validate()
+            .context("invalid camera trigger config")?;
 
-        debug!("waiting for packet from mavproxy");
+        let iface = PixhawkInterface::open(&transport).await?;
 
-        let (_, remote_addr) =
-            tokio::time::timeout(Duration::from_secs(60), sock.recv_from(&mut []))
-                .await
-                .context("timed out while waiting for packet from mavproxy")?
-                .context("error retrieving packet from mavproxy")?;
-
-        info!(
-            "received packet from {:?}, locking to this address",
-            remote_addr
-        );
+        Ok(Self::with_interface(
+            channels, cmd, transport, iface, config,
+        ))
+    }
 
-        sock.connect(remote_addr)
-            .await
-            .context("failed to lock to address")?;
+    /// Creates a client that talks to the Pixhawk over a connection that is
+    /// already open. `transport` is used to open it again if the link is
+    /// lost.
+    pub fn with_interface(
+        channels: Arc<Channels>,
+        cmd: mpsc::Receiver<PixhawkCommand>,
+        transport: PixhawkTransport,
+        iface: PixhawkInterface,
+        config: &PixhawkConfig,
+    ) -> Self {
+        let version = config.mavlink;
 
         match version {
             MavlinkVersion::V1 => debug!("using mavlink v1"),
             MavlinkVersion::V2 => debug!("using mavlink v2"),
         };
 
-        Ok(PixhawkClient {
-            sock,
+        let identity = MavTarget::new(config.system_id, config.component_id);
+        let target = MavTarget::new(config.target_system, config.target_component);
+
+        debug!("sending as {:?} to {:?}", identity, target);
+
+        PixhawkClient {
+            iface,
+            transport,
             buf: BytesMut::with_capacity(1024),
             sequence: AtomicU8::default(),
             channels,
             cmd,
             version,
-        })
+            camera_trigger: config.camera_trigger,
+            identity,
+            target,
+            reconnect_attempts: config.reconnect_attempts,
+            reconnect_delay: Duration::from_millis(config.reconnect_delay_ms),
+            message_intervals: config.message_intervals.clone(),
+        }
+    }
+
+    /// Reconnects to the Pixhawk after the link was lost. Over UDP, this also
+    /// handles MAVProxy being restarted and sending from a different address.
+    /// Gives up after the configured number of attempts. Returns false if the
+    /// system is interrupted first.
+    async fn reconnect(
+        &mut self,
+        interrupt_recv: &mut broadcast::Receiver<()>,
+    ) -> anyhow::Result<bool> {
+        error!("lost connection to pixhawk, reconnecting");
+
+        let mut delay = self.reconnect_delay;
+
+        for attempt in 1..=self.reconnect_attempts {
+            // don't leave commands hanging while the pixhawk is gone
+            while let Ok(cmd) = self.cmd.try_recv() {
+                let _ = cmd.error(anyhow!("pixhawk disconnected, reconnecting; try again"));
+            }
+
+            info!(
+                "reconnecting to pixhawk (attempt {} of {})",
+                attempt, self.reconnect_attempts
+            );
+
+            let result = tokio::select! {
+                _ = interrupt_recv.recv() => return Ok(false),
+                result = self.reopen() => result,
+            };
+
+            match result {
+                Ok(()) => {
+                    info!("reconnected to pixhawk");
+                    return Ok(true);
+                }
+                Err(err) => warn!(
+                    "failed to reconnect to pixhawk, retrying in {:?}: {:?}",
+                    delay, err
+                ),
+            }
+
+            tokio::select! {
+                _ = interrupt_recv.recv() => return Ok(false),
+                _ = tokio::time::sleep(delay) => {}
+            };
+
+            delay *= 2;
+        }
+
+        bail!(
+            "could not reconnect to pixhawk after {} attempts",
+            self.reconnect_attempts
+        )
+    }
+
+    /// Closes the connection to the Pixhawk, opens it again and initializes
+    /// the Pixhawk again.
+    async fn reopen(&mut self) -> anyhow::Result<()> {
+        // the old connection has to be closed before its address or serial
+        // port can be opened again
+        self.iface = PixhawkInterface::Closed;
+        self.iface = PixhawkInterface::open(&self.transport).await?;
+        self.buf.clear();
+
+        self.init().await
     }
 
     pub async fn init(&mut self) -> anyhow::Result<()> {
         info!("waiting for heartbeat");
         self.wait_for_message(
-            |message| match message {
+            |_, message| match message {
                 apm::MavMessage::common(common::MavMessage::HEARTBEAT(_)) => true,
                 _ => false,
             },
@@ -88,24 +233,24 @@ impl PixhawkClient {
         info!("received heartbeat");
         info!("setting parameters");
 
-        self.set_param_f32("CAM_DURATION", 10.0).await?;
-        self.set_param_u8("CAM_FEEDBACK_PIN", 54).await?;
-        self.set_param_u8("CAM_FEEDBACK_POL", 1).await?;
+        self.set_camera_trigger(self.camera_trigger).await?;
         self.send_command(
             common::MavCmd::MAV_CMD_DO_DIGICAM_CONTROL,
             [0., 0., 0., 0., 1., 0., 0.],
         )
         .await?;
-        self.send_command(
-            common::MavCmd::MAV_CMD_SET_MESSAGE_INTERVAL,
-            [33., 1000., 0., 0., 0., 0., 0.],
-        )
-        .await?;
-        self.send_command(
-            common::MavCmd::MAV_CMD_SET_MESSAGE_INTERVAL,
-            [30., 1000., 0., 0., 0., 0., 0.],
-        )
-        .await?;
+        // GLOBAL_POSITION_INT and ATTITUDE
+        self.set_message_interval(33, 1000).await?;
+        self.set_message_interval(30, 1000).await?;
+        // SYSTEM_TIME at 1 Hz, so we can check the clocks
+        self.set_message_interval(2, 1_000_000).await?;
+        // SYS_STATUS at 1 Hz, for the battery
+        self.set_message_interval(1, 1_000_000).await?;
+
+        for interval in self.message_intervals.clone() {
+            self.set_message_interval(interval.message_id, interval.interval_us)
+                .await?;
+        }
 
         info!("finished initialization");
 
@@ -120,32 +265,52 @@ impl PixhawkClient {
 
         let header = MavHeader {
             sequence,
-            system_id: 1,
-            component_id: 1,
+            system_id: self.identity.system,
+            component_id: self.identity.component,
         };
 
         let mut buf = Vec::with_capacity(1024);
 
         mavlink::write_versioned_msg(&mut buf, self.version, header, &message)?;
-        self.sock.send(buf.as_ref()).await?;
+        self.iface.send(buf.as_ref()).await?;
 
         Ok(())
     }
 
+    /// Reads the next bytes from the Pixhawk. The autopilot sends a heartbeat
+    /// every second, so if we don't hear anything for a while the link is
+    /// treated as lost.
+    async fn recv_chunk(&mut self, chunk: &mut [u8]) -> std::io::Result<usize> {
+        match tokio::time::timeout(LINK_TIMEOUT, self.iface.recv(chunk)).await {
+            Ok(result) => result,
+            Err(_) => Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("nothing from pixhawk in {:?}", LINK_TIMEOUT),
+            )),
+        }
+    }
+
     /// Waits for a message from the Pixhawk, reacts to it, and returns it.
     pub async fn recv(&mut self) -> anyhow::Result<apm::MavMessage> {
+        let (_, msg) = self.recv_with_header().await?;
+        Ok(msg)
+    }
+
+    /// Waits for a message from the Pixhawk, reacts to it, and returns it along
+    /// with the header identifying its sender.
+    pub async fn recv_with_header(&mut self) -> anyhow::Result<(MavHeader, apm::MavMessage)> {
         loop {
             let mut chunk = vec![0; 1024];
 
-            let magic = match self.version {
-                MavlinkVersion::V1 => 0xFE,
-                MavlinkVersion::V2 => 0xFD,
-            };
-
             trace!("buf is {:?} bytes long", self.buf.len());
 
+            // the autopilot can send either version no matter which one we
+            // use, so look for both
             let magic_position = loop {
-                let magic_position = self.buf.iter().position(|&b| b == magic);
+                let magic_position = self
+                    .buf
+                    .iter()
+                    .position(|&b| b == MAVLINK_V1_MAGIC || b == MAVLINK_V2_MAGIC);
 
                 match magic_position {
                     // we need at least two bytes after the magic in the buffer
@@ -155,9 +320,9 @@ impl PixhawkClient {
                     res => {
                         trace!("requesting more bytes, magic too close to end ({:?})", res);
 
-                        let (n, addr) = self.sock.recv_from(&mut chunk[..]).await?;
+                        let n = self.recv_chunk(&mut chunk[..]).await?;
                         self.buf.extend(&chunk[..n]);
-                        trace!("read {:?} bytes from {:?}", n, addr);
+                        trace!("read {:?} bytes", n);
                     }
                 };
             };
@@ -168,35 +333,28 @@ impl PixhawkClient {
                 self.buf.len()
             );
 
-            let payload_len = self.buf[magic_position + 1];
-
-            let msg_body_size = match self.version {
-                // in v1: 1 byte magic + 1 byte payload len + 4 byte header + 2 byte checksum
-                MavlinkVersion::V1 => payload_len as usize + 8,
-                // in v2: 1 byte magic + 1 byte payload len + 8 byte header + 2 byte checksum
-                MavlinkVersion::V2 => payload_len as usize + 12,
-            };
+            let (version, msg_body_size) = frame_len(&self.buf[magic_position..]);
 
-            trace!("need {:?} bytes", msg_body_size);
+            trace!("need {:?} bytes for {:?} message", msg_body_size, version);
 
             while magic_position + msg_body_size > self.buf.len() {
                 trace!("requesting more bytes, buffer insufficient");
 
                 let mut chunk = vec![0; 1024];
-                let (n, addr) = self.sock.recv_from(&mut chunk[..]).await?;
+                let n = self.recv_chunk(&mut chunk[..]).await?;
                 self.buf.extend(&chunk[..n]);
-                trace!("read {:?} bytes from {:?}", n, addr);
+                trace!("read {:?} bytes", n);
             }
 
             let msg_content = &self.buf[magic_position..magic_position + msg_body_size];
 
             // if we get a bad checksum, just drop the message and try again
-            let msg = match mavlink::read_versioned_msg(&mut &msg_content[..], self.version) {
-                Ok((_, msg)) => {
+            let (header, msg) = match mavlink::read_versioned_msg(&mut &msg_content[..], version) {
+                Ok((header, msg)) => {
                     let skip = magic_position + msg_body_size;
                     trace!("parsed message, success, skipping {:?} bytes", skip);
                     self.buf.advance(skip);
-                    msg
+                    (header, msg)
                 }
                 Err(MessageReadError::Parse(ParserError::InvalidChecksum { .. })) => {
                     trace!("got invalid checksum, dropping message");
@@ -204,6 +362,14 @@ impl PixhawkClient {
                     self.buf.advance(skip);
                     continue;
                 }
+                // a corrupted message ID looks like a message we don't know, so
+                // treat it like a bad checksum instead of giving up
+                Err(MessageReadError::Parse(ParserError::UnknownMessage { .. })) => {
+                    trace!("got unknown message, dropping message");
+                    let skip = magic_position + 1;
+                    self.buf.advance(skip);
+                    continue;
+                }
                 Err(err) => return Err(err).context("error while parsing message"),
             };
 
@@ -211,7 +377,7 @@ impl PixhawkClient {
 
             self.handle(&msg).await?;
 
-            return Ok(msg);
+            return Ok((header, msg));
         }
     }
 
@@ -225,10 +391,21 @@ impl PixhawkClient {
 
         loop {
             if let Ok(cmd) = self.cmd.try_recv() {
-                self.exec(cmd).await?;
+                let result = self.exec(cmd.request()).await;
+                let _ = cmd.respond(result);
             }
 
-            let _ = self.recv().await?;
+            if let Err(err) = self.recv().await {
+                if !is_disconnect(&err) {
+                    return Err(err);
+                }
+
+                warn!("pixhawk error: {:?}", err);
+
+                if !self.reconnect(&mut interrupt_recv).await? {
+                    break;
+                }
+            }
 
             if interrupt_recv.try_recv().is_ok() {
                 break;
@@ -238,13 +415,127 @@ impl PixhawkClient {
         Ok(())
     }
 
-    async fn exec(&mut self, _cmd: PixhawkCommand) -> anyhow::Result<()> {
-        unimplemented!()
+    async fn exec(&mut self, cmd: &PixhawkRequest) -> anyhow::Result<PixhawkResponse> {
+        match cmd {
+            PixhawkRequest::CameraTrigger(req) => match req {
+                PixhawkCameraTriggerRequest::Get => {
+                    let config = CameraTriggerConfig {
+                        duration: self.get_param("CAM_DURATION").await?,
+                        feedback_pin: self.get_param("CAM_FEEDBACK_PIN").await?,
+                        feedback_polarity: self.get_param("CAM_FEEDBACK_POL").await?,
+                    };
+
+                    Ok(PixhawkResponse::CameraTrigger { config })
+                }
+                PixhawkCameraTriggerRequest::Set {
+                    duration,
+                    feedback_pin,
+                    feedback_polarity,
+                } => {
+                    let mut config = self.camera_trigger;
+
+                    if let Some(duration) = duration {
+                        config.duration = *duration;
+                    }
+
+                    if let Some(feedback_pin) = feedback_pin {
+                        config.feedback_pin = *feedback_pin;
+                    }
+
+                    if let Some(feedback_polarity) = feedback_polarity {
+                        config.feedback_polarity = *feedback_polarity;
+                    }
+
+                    config.validate()?;
+                    self.set_camera_trigger(config).await?;
+
+                    Ok(PixhawkResponse::CameraTrigger { config })
+                }
+            },
+            PixhawkRequest::MessageInterval {
+                message_id,
+                interval_us,
+            } => {
+                self.set_message_interval(*message_id, *interval_us).await?;
+                Ok(PixhawkResponse::Unit)
+            }
+            PixhawkRequest::UploadMission { mission } => {
+                let count = self.upload_mission(&mission.items).await?;
+                Ok(PixhawkResponse::UploadMission { count })
+            }
+        }
+    }
+
+    async fn set_camera_trigger(&mut self, config: CameraTriggerConfig) -> anyhow::Result<()> {
+        self.set_param_f32("CAM_DURATION", config.duration).await?;
+        self.set_param_i8("CAM_FEEDBACK_PIN", config.feedback_pin)
+            .await?;
+        self.set_param_u8("CAM_FEEDBACK_POL", config.feedback_polarity)
+            .await?;
+
+        info!(
+            "camera trigger: duration {}, feedback pin {}, feedback polarity {}",
+            config.duration, config.feedback_pin, config.feedback_polarity
+        );
+
+        self.camera_trigger = config;
+
+        Ok(())
     }
 
     /// Reacts to a message received from the Pixhawk.
     async fn handle(&self, message: &apm::MavMessage) -> anyhow::Result<()> {
         match message {
+            apm::MavMessage::common(common::MavMessage::HEARTBEAT(data))
+                if data.mavtype == common::MavType::MAV_TYPE_GCS =>
+            {
+                let _ = self
+                    .channels
+                    .pixhawk_event
+                    .send(PixhawkEvent::GroundHeartbeat);
+            }
+            apm::MavMessage::common(common::MavMessage::HEARTBEAT(data))
+                if data.autopilot != common::MavAutopilot::MAV_AUTOPILOT_INVALID =>
+            {
+                let _ = self.channels.pixhawk_event.send(PixhawkEvent::Armed {
+                    armed: data
+                        .base_mode
+                        .contains(common::MavModeFlag::MAV_MODE_FLAG_SAFETY_ARMED),
+                });
+            }
+            apm::MavMessage::common(common::MavMessage::SYSTEM_TIME(data)) => {
+                if let Some(time) = system_time_utc(data.time_unix_usec) {
+                    let _ = self
+                        .channels
+                        .pixhawk_event
+                        .send(PixhawkEvent::SystemTime { time });
+                }
+            }
+            // a voltage of u16::MAX means the autopilot doesn't know it
+            apm::MavMessage::common(common::MavMessage::SYS_STATUS(data))
+                if data.voltage_battery != u16::MAX =>
+            {
+                let _ = self.channels.pixhawk_event.send(PixhawkEvent::Battery {
+                    voltage: data.voltage_battery as f32 / 1e3,
+                    current: battery_current(data.current_battery),
+                    remaining_percent: battery_remaining(data.battery_remaining),
+                });
+            }
+            apm::MavMessage::common(common::MavMessage::BATTERY_STATUS(data)) => {
+                // cells that aren't used are set to u16::MAX
+                let voltage = data
+                    .voltages
+                    .iter()
+                    .filter(|&&cell| cell != u16::MAX)
+                    .map(|&cell| cell as f32 / 1e3)
+                    .sum();
+
+                let _ = self.channels.pixhawk_event.send(PixhawkEvent::Battery {
+                    voltage,
+                    current: battery_current(data.current_battery),
+                    remaining_percent: battery_remaining(data.battery_remaining),
+                });
+            }
             apm::MavMessage::common(common::MavMessage::GLOBAL_POSITION_INT(data)) => {
                 let _ = self.channels.pixhawk_event.send(PixhawkEvent::Gps {
                     coords: Coords3D::new(
@@ -284,7 +575,9 @@ impl PixhawkClient {
         Ok(())
     }
 
-    pub async fn wait_for_message<F: Fn(&apm::MavMessage) -> bool>(
+    /// Waits for a message for which `predicate` returns true. The predicate
+    /// receives the header of each message so that it can check the sender.
+    pub async fn wait_for_message<F: Fn(&MavHeader, &apm::MavMessage) -> bool>(
         &mut self,
         predicate: F,
         timeout: Duration,
@@ -294,13 +587,13 @@ impl PixhawkClient {
         loop {
             let remaining_time = deadline - Instant::now();
 
-            let message = tokio::time::timeout(remaining_time, self.recv()).await;
+            let message = tokio::time::timeout(remaining_time, self.recv_with_header()).await;
             let message = message
                 .context("Timeout occurred while waiting for a message from the Pixhawk.")?;
-            let message =
+            let (header, message) =
                 message.context("Error occurred while reading a message from the Pixhawk.")?;
 
-            if predicate(&message) {
+            if predicate(&header, &message) {
                 return Ok(message);
             }
         }
@@ -315,14 +608,14 @@ impl PixhawkClient {
                 .unwrap()
                 .as_millis() as u64,
             seq: 0,
-            target_system: 0,
-            target_component: 0,
+            target_system: self.target.system,
+            target_component: self.target.component,
         }));
 
         self.send(message).await?;
 
         self.wait_for_message(
-            |message| match message {
+            |_, message| match message {
                 apm::MavMessage::common(common::MavMessage::PING(_)) => {
                     debug!("received ping back");
                     true
@@ -336,7 +629,57 @@ impl PixhawkClient {
         Ok(())
     }
 
-    /// Sets a parameter on the Pixhawk and waits for acknowledgement. The
+    /// Reads a parameter from the autopilot. The default timeout is 10
+    /// seconds.
+    pub async fn get_param<T: num_traits::NumCast + std::fmt::Debug>(
+        &mut self,
+        id: &str,
+    ) -> anyhow::Result<T> {
+        debug!("reading param {:?}", id);
+
+        let mut param_id: [char; 16] = ['\0'; 16];
+        for (index, character) in id.char_indices() {
+            param_id[index] = character;
+        }
+
+        let message = apm::MavMessage::common(common::MavMessage::PARAM_REQUEST_READ(
+            common::PARAM_REQUEST_READ_DATA {
+                param_id,
+                // -1 means look the parameter up by its ID
+                param_index: -1,
+                target_system: self.target.system,
+                target_component: self.target.component,
+            },
+        ));
+
+        self.send(message).await?;
+
+        let target = self.target;
+        let reply = self
+            .wait_for_message(
+                |header, message| match message {
+                    apm::MavMessage::common(common::MavMessage::PARAM_VALUE(data)) => {
+                        data.param_id == param_id && target.matches(header)
+                    }
+                    _ => false,
+                },
+                Duration::from_secs(10),
+            )
+            .await
+            .context("Error occurred while waiting for parameter value")?;
+
+        match reply {
+            apm::MavMessage::common(common::MavMessage::PARAM_VALUE(data)) => {
+                let param_value = num_traits::cast(data.param_value)
+                    .with_context(|| format!("value of {} is out of range", id))?;
+                debug!("param {:?} is {:?}", id, param_value);
+                Ok(param_value)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Sets a parameter on the autopilot and waits for acknowledgement. The
     /// default timeout is 10 seconds.
     pub async fn set_param<T: num_traits::NumCast + std::fmt::Debug>(
         &mut self,
@@ -344,7 +687,23 @@ impl PixhawkClient {
         param_value: T,
         param_type: common::MavParamType,
     ) -> anyhow::Result<T> {
-        debug!("setting param {:?} to {:?}", id, param_value);
+        self.set_param_to(self.target, id, param_value, param_type)
+            .await
+    }
+
+    /// Sets a parameter on a specific component and waits for acknowledgement
+    /// from that component. The default timeout is 10 seconds.
+    pub async fn set_param_to<T: num_traits::NumCast + std::fmt::Debug>(
+        &mut self,
+        target: MavTarget,
+        id: &str,
+        param_value: T,
+        param_type: common::MavParamType,
+    ) -> anyhow::Result<T> {
+        debug!(
+            "setting param {:?} to {:?} on {:?}",
+            id, param_value, target
+        );
 
         let mut param_id: [char; 16] = ['\0'; 16];
         for (index, character) in id.char_indices() {
@@ -356,8 +715,8 @@ impl PixhawkClient {
                 param_id,
                 param_type,
                 param_value: num_traits::cast(param_value).unwrap(),
-                target_system: 0,
-                target_component: 0,
+                target_system: target.system,
+                target_component: target.component,
             }));
 
         // send message
@@ -368,9 +727,9 @@ impl PixhawkClient {
         // wait for ack or timeout
         let ack_message = self
             .wait_for_message(
-                |message| match message {
+                |header, message| match message {
                     apm::MavMessage::common(common::MavMessage::PARAM_VALUE(data)) => {
-                        data.param_id == param_id
+                        data.param_id == param_id && target.matches(header)
                     }
                     _ => false,
                 },
@@ -389,14 +748,28 @@ impl PixhawkClient {
         }
     }
 
-    /// Sets a parameter on the Pixhawk and waits for acknowledgement. The
+    /// Sends a command to the autopilot and waits for acknowledgement. The
     /// default timeout is 10 seconds.
     pub async fn send_command(
         &mut self,
         command: common::MavCmd,
         params: [f32; 7],
     ) -> anyhow::Result<common::MavResult> {
-        debug!("sending command {:?} ({:?})", command, params);
+        self.send_command_to(self.target, command, params).await
+    }
+
+    /// Sends a command to a specific component and waits for acknowledgement
+    /// from that component. The default timeout is 10 seconds.
+    pub async fn send_command_to(
+        &mut self,
+        target: MavTarget,
+        command: common::MavCmd,
+        params: [f32; 7],
+    ) -> anyhow::Result<common::MavResult> {
+        debug!(
+            "sending command {:?} ({:?}) to {:?}",
+            command, params, target
+        );
 
         let message = apm::MavMessage::common(common::MavMessage::COMMAND_LONG(
             common::COMMAND_LONG_DATA {
@@ -409,8 +782,8 @@ impl PixhawkClient {
                 param5: params[4],
                 param6: params[5],
                 param7: params[6],
-                target_system: 0,
-                target_component: 0,
+                target_system: target.system,
+                target_component: target.component,
             },
         ));
 
@@ -422,9 +795,9 @@ impl PixhawkClient {
         // wait for ack or timeout
         let ack_message = self
             .wait_for_message(
-                |message| match message {
+                |header, message| match message {
                     apm::MavMessage::common(common::MavMessage::COMMAND_ACK(data)) => {
-                        data.command == command
+                        data.command == command && target.matches(header)
                     }
                     _ => false,
                 },
@@ -448,6 +821,114 @@ impl PixhawkClient {
         }
     }
 
+    /// Asks the autopilot to send a message every `interval_us` microseconds.
+    /// An interval of 0 restores the default rate, and a negative interval
+    /// stops the message.
+    pub async fn set_message_interval(
+        &mut self,
+        message_id: u32,
+        interval_us: i32,
+    ) -> anyhow::Result<()> {
+        // the autopilot only understands -1 as "stop"
+        let interval_us = interval_us.max(-1);
+
+        debug!(
+            "setting interval of message {} to {} us",
+            message_id, interval_us
+        );
+
+        self.send_command(
+            common::MavCmd::MAV_CMD_SET_MESSAGE_INTERVAL,
+            [message_id as f32, interval_us as f32, 0., 0., 0., 0., 0.],
+        )
+        .await
+        .with_context(|| format!("failed to set interval of message {}", message_id))?;
+
+        Ok(())
+    }
+
+    /// Replaces the mission on the autopilot. The autopilot asks for each item
+    /// in turn, and we send it when asked; if we don't hear anything for a
+    /// while, we send the last message again in case it got lost. Returns the
+    /// number of items uploaded.
+    pub async fn upload_mission(&mut self, items: &[MissionItem]) -> anyhow::Result<u16> {
+        let count = u16::try_from(items.len()).context("too many mission items")?;
+        let target = self.target;
+
+        info!("uploading mission with {} items", count);
+
+        let mut last_message = apm::MavMessage::common(common::MavMessage::MISSION_COUNT(
+            common::MISSION_COUNT_DATA {
+                count,
+                target_system: target.system,
+                target_component: target.component,
+                ..Default::default()
+            },
+        ));
+
+        self.send(last_message.clone()).await?;
+
+        let mut retries = 0;
+
+        loop {
+            let result = self
+                .wait_for_message(
+                    |header, message| {
+                        target.matches(header)
+                            && match message {
+                                apm::MavMessage::common(common::MavMessage::MISSION_REQUEST(_))
+                                | apm::MavMessage::common(
+                                    common::MavMessage::MISSION_REQUEST_INT(_),
+                                )
+                                | apm::MavMessage::common(common::MavMessage::MISSION_ACK(_)) => {
+                                    true
+                                }
+                                _ => false,
+                            }
+                    },
+                    MISSION_TIMEOUT,
+                )
+                .await;
+
+            let seq = match result {
+                Ok(apm::MavMessage::common(common::MavMessage::MISSION_REQUEST(data))) => data.seq,
+                Ok(apm::MavMessage::common(common::MavMessage::MISSION_REQUEST_INT(data))) => {
+                    data.seq
+                }
+                Ok(apm::MavMessage::common(common::MavMessage::MISSION_ACK(data))) => {
+                    match data.mavtype {
+                        common::MavMissionResult::MAV_MISSION_ACCEPTED => {
+                            info!("uploaded mission");
+                            return Ok(count);
+                        }
+                        result => bail!("autopilot rejected mission: {:?}", result),
+                    }
+                }
+                Ok(_) => unreachable!(),
+                Err(err) if is_timeout(&err) && retries < MISSION_RETRIES => {
+                    retries += 1;
+                    warn!(
+                        "no reply from autopilot during mission upload, resending (retry {} of {})",
+                        retries, MISSION_RETRIES
+                    );
+                    self.send(last_message.clone()).await?;
+                    continue;
+                }
+                Err(err) => return Err(err).context("mission upload failed"),
+            };
+
+            let item = items.get(seq as usize).with_context(|| {
+                format!("autopilot asked for mission item {} of {}", seq, count)
+            })?;
+
+            debug!("sending mission item {}: {:?}", seq, item);
+
+            last_message = mission_item_message(target, seq, item);
+            self.send(last_message.clone()).await?;
+            retries = 0;
+        }
+    }
+
     pub async fn set_param_f32(&mut self, id: &str, value: f32) -> anyhow::Result<f32> {
         self.set_param(id, value, common::MavParamType::MAV_PARAM_TYPE_REAL32)
             .await
@@ -493,3 +974,656 @@ impl PixhawkClient {
             .await
     }
 }
+
+/// Builds the message that describes a mission item to the autopilot.
+fn mission_item_message(target: MavTarget, seq: u16, item: &MissionItem) -> apm::MavMessage {
+    let (command, param1) = match item.command {
+        MissionCommand::Waypoint => (common::MavCmd::MAV_CMD_NAV_WAYPOINT, item.hold_secs),
+        MissionCommand::Takeoff => (common::MavCmd::MAV_CMD_NAV_TAKEOFF, 0.),
+        MissionCommand::Land => (common::MavCmd::MAV_CMD_NAV_LAND, 0.),
+        MissionCommand::Loiter => (common::MavCmd::MAV_CMD_NAV_LOITER_UNLIM, 0.),
+        MissionCommand::ReturnToLaunch => (common::MavCmd::MAV_CMD_NAV_RETURN_TO_LAUNCH, 0.),
+    };
+
+    apm::MavMessage::common(common::MavMessage::MISSION_ITEM_INT(
+        common::MISSION_ITEM_INT_DATA {
+            param1,
+            param2: 0.,
+            param3: 0.,
+            param4: 0.,
+            x: (item.coords.latitude as f64 * 1e7) as i32,
+            y: (item.coords.longitude as f64 * 1e7) as i32,
+            z: item.coords.altitude,
+            seq,
+            command,
+            target_system: target.system,
+            target_component: target.component,
+            frame: common::MavFrame::MAV_FRAME_GLOBAL_RELATIVE_ALT_INT,
+            current: 0,
+            autocontinue: 1,
+            ..Default::default()
+        },
+    ))
+}
+
+/// Works out the MAVLink version and the total length of the frame at the start
+/// of `frame`, which must begin with a magic byte and contain at least three
+/// bytes.
+fn frame_len(frame: &[u8]) -> (MavlinkVersion, usize) {
+    let version = if frame[0] == MAVLINK_V2_MAGIC {
+        MavlinkVersion::V2
+    } else {
+        MavlinkVersion::V1
+    };
+
+    let payload_len = frame[1];
+
+    let len = match version {
+        // in v1: 1 byte magic + 1 byte payload len + 4 byte header + 2 byte checksum
+        MavlinkVersion::V1 => payload_len as usize + 8,
+        // in v2: 1 byte magic + 1 byte payload len + 8 byte header + 2 byte checksum,
+        // followed by a 13 byte signature if the message is signed
+        MavlinkVersion::V2 => {
+            let incompat_flags = frame[2];
+
+            if incompat_flags & MAVLINK_IFLAG_SIGNED != 0 {
+                payload_len as usize + 12 + MAVLINK_SIGNATURE_LEN
+            } else {
+                payload_len as usize + 12
+            }
+        }
+    };
+
+    (version, len)
+}
+
+fn is_timeout(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|cause| cause.is::<tokio::time::error::Elapsed>())
+}
+
+/// Reads the UTC time from a SYSTEM_TIME message. The autopilot works this
+/// out from the GPS week and time of week, taking off the leap seconds that
+/// the GPS clock doesn't count; 0 means that it doesn't know what time it is
+/// yet, e.g. before the GPS gets a fix.
+fn system_time_utc(time_unix_usec: u64) -> Option<SystemTime> {
+    if time_unix_usec == 0 {
+        None
+    } else {
+        Some(SystemTime::UNIX_EPOCH + Duration::from_micros(time_unix_usec))
+    }
+}
+
+/// Converts a battery current from centiamps to amps. -1 means that the
+/// autopilot doesn't measure the current.
+fn battery_current(current: i16) -> Option<f32> {
+    if current == -1 {
+        None
+    } else {
+        Some(current as f32 / 1e2)
+    }
+}
+
+/// -1 means that the autopilot doesn't know how much charge is left.
+fn battery_remaining(remaining: i8) -> Option<u8> {
+    if remaining < 0 {
+        None
+    } else {
+        Some(remaining as u8)
+    }
+}
+
+/// Checks whether an error means that we lost the connection to the Pixhawk, as
+/// opposed to the Pixhawk sending us something that we don't understand.
+fn is_disconnect(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|cause| cause.downcast_ref::<std::io::Error>().is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    use super::super::interface::MockListener;
+    use super::*;
+    use crate::TestChannels;
+
+    /// A client connected to a fake autopilot over an in-memory link, and the
+    /// fake autopilot's end of the link.
+    async fn mock_client(config: serde_json::Value) -> (PixhawkClient, DuplexStream) {
+        let listener = MockListener::default();
+        let link = listener.link();
+        (mock_client_on(&listener, config).await, link)
+    }
+
+    /// A client that connects to `listener` whenever it connects, which it
+    /// does once now.
+    async fn mock_client_on(
+        listener: &MockListener,
+        mut config: serde_json::Value,
+    ) -> PixhawkClient {
+        if config.get("mavlink").is_none() {
+            config["mavlink"] = serde_json::json!({ "type": "V2" });
+        }
+        let config: PixhawkConfig = serde_json::from_value(config).unwrap();
+
+        let transport = PixhawkTransport::Mock(listener.clone());
+        let iface = PixhawkInterface::open(&transport).await.unwrap();
+        let (_, cmd) = mpsc::channel(1);
+
+        PixhawkClient::with_interface(TestChannels::new().channels, cmd, transport, iface, &config)
+    }
+
+    /// HEARTBEAT from a plane in GUIDED mode, captured as a MAVLink v1 frame.
+    const HEARTBEAT_V1: [u8; 17] = [
+        0xFE, 0x09, 0x07, 0x01, 0x01, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x01, 0x03, 0x81, 0x04, 0x03,
+        0x23, 0xF4,
+    ];
+
+    /// The same HEARTBEAT as a MAVLink v2 frame.
+    const HEARTBEAT_V2: [u8; 21] = [
+        0xFD, 0x09, 0x00, 0x00, 0x07, 0x01, 0x01, 0x00, 0x00, 0x00, 0x0F, 0x00, 0x00, 0x00, 0x01,
+        0x03, 0x81, 0x04, 0x03, 0x7D, 0xF5,
+    ];
+
+    fn decode(frame: &[u8]) -> (MavHeader, apm::MavMessage) {
+        let (version, len) = frame_len(frame);
+        assert_eq!(len, frame.len());
+        mavlink::read_versioned_msg(&mut &frame[..len], version).unwrap()
+    }
+
+    #[test]
+    fn measures_frames_of_both_versions() {
+        assert!(matches!(frame_len(&HEARTBEAT_V1), (MavlinkVersion::V1, 17)));
+        assert!(matches!(frame_len(&HEARTBEAT_V2), (MavlinkVersion::V2, 21)));
+
+        let mut signed = HEARTBEAT_V2;
+        signed[2] |= MAVLINK_IFLAG_SIGNED;
+        assert!(matches!(frame_len(&signed), (MavlinkVersion::V2, 34)));
+    }
+
+    #[test]
+    fn decodes_both_versions_to_the_same_message() {
+        let (header_v1, msg_v1) = decode(&HEARTBEAT_V1);
+        let (header_v2, msg_v2) = decode(&HEARTBEAT_V2);
+
+        assert_eq!(header_v1, header_v2);
+        assert_eq!(header_v1.system_id, 1);
+        assert_eq!(header_v1.component_id, 1);
+        assert_eq!(header_v1.sequence, 7);
+        assert_eq!(msg_v1, msg_v2);
+
+        match msg_v1 {
+            apm::MavMessage::common(common::MavMessage::HEARTBEAT(data)) => {
+                assert_eq!(data.custom_mode, 15)
+            }
+            other => panic!("expected a heartbeat, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn system_time_is_utc_from_the_gps() {
+        // Thursday of GPS week 2160 at noon GPS time, which is 18 leap
+        // seconds ahead of UTC: 2021-06-03 11:59:42 UTC
+        let gps_week_secs = 315_964_800 + 2160 * 604_800 + 388_800;
+        let utc_secs = gps_week_secs - 18;
+        assert_eq!(utc_secs, 1_622_721_582);
+
+        assert_eq!(
+            system_time_utc(utc_secs * 1_000_000 + 250_000),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_millis(1_622_721_582_250))
+        );
+        assert_eq!(system_time_utc(0), None);
+    }
+
+    #[tokio::test]
+    async fn recv_skips_noise_and_bad_frames_between_messages() {
+        let (mut client, mut autopilot) = mock_client(serde_json::json!({})).await;
+
+        let mut corrupted = HEARTBEAT_V2;
+        corrupted[12] ^= 0xFF;
+
+        // noise, a frame split across two writes, a frame with a bad checksum
+        // and a v2 frame straight after it
+        autopilot.write_all(&[0x00, 0x42, 0x13]).await.unwrap();
+        autopilot.write_all(&HEARTBEAT_V1[..5]).await.unwrap();
+        autopilot.write_all(&HEARTBEAT_V1[5..]).await.unwrap();
+        autopilot.write_all(&corrupted).await.unwrap();
+        autopilot.write_all(&HEARTBEAT_V2).await.unwrap();
+
+        let (header, first) = client.recv_with_header().await.unwrap();
+        assert_eq!(header.sequence, 7);
+        assert!(matches!(
+            first,
+            apm::MavMessage::common(common::MavMessage::HEARTBEAT(_))
+        ));
+
+        let (_, second) = client.recv_with_header().await.unwrap();
+        assert_eq!(first, second);
+        assert!(client.buf.is_empty());
+
+        // once the autopilot hangs up, the client sees a disconnect
+        drop(autopilot);
+        assert!(is_disconnect(&client.recv().await.unwrap_err()));
+    }
+
+    /// A fake autopilot on the other end of `link`. It is another client,
+    /// which sends as the autopilot.
+    fn fake_autopilot(link: DuplexStream) -> PixhawkClient {
+        let config: PixhawkConfig =
+            serde_json::from_value(serde_json::json!({ "mavlink": { "type": "V2" } })).unwrap();
+        let (_, cmd) = mpsc::channel(1);
+
+        PixhawkClient::with_interface(
+            TestChannels::new().channels,
+            cmd,
+            PixhawkTransport::Mock(MockListener::default()),
+            PixhawkInterface::Mock(link),
+            &config,
+        )
+    }
+
+    /// Sends a heartbeat, then answers parameters and commands the way that
+    /// the autopilot does until the link closes. Keeps everything that the
+    /// client sends in `received`.
+    async fn answer_as_autopilot(
+        autopilot: &mut PixhawkClient,
+        received: &mut Vec<apm::MavMessage>,
+    ) -> anyhow::Result<()> {
+        autopilot
+            .send(apm::MavMessage::common(common::MavMessage::HEARTBEAT(
+                common::HEARTBEAT_DATA {
+                    mavtype: common::MavType::MAV_TYPE_FIXED_WING,
+                    autopilot: common::MavAutopilot::MAV_AUTOPILOT_ARDUPILOTMEGA,
+                    ..Default::default()
+                },
+            )))
+            .await?;
+
+        loop {
+            let message = autopilot.recv().await?;
+            received.push(message.clone());
+
+            let reply = match message {
+                apm::MavMessage::common(common::MavMessage::PARAM_SET(data)) => {
+                    common::MavMessage::PARAM_VALUE(common::PARAM_VALUE_DATA {
+                        param_id: data.param_id,
+                        param_value: data.param_value,
+                        param_type: data.param_type,
+                        ..Default::default()
+                    })
+                }
+                apm::MavMessage::common(common::MavMessage::COMMAND_LONG(data)) => {
+                    common::MavMessage::COMMAND_ACK(common::COMMAND_ACK_DATA {
+                        command: data.command,
+                        result: common::MavResult::MAV_RESULT_ACCEPTED,
+                        ..Default::default()
+                    })
+                }
+                _ => continue,
+            };
+
+            autopilot.send(apm::MavMessage::common(reply)).await?;
+        }
+    }
+
+    fn heartbeat() -> apm::MavMessage {
+        apm::MavMessage::common(common::MavMessage::HEARTBEAT(common::HEARTBEAT_DATA {
+            mavtype: common::MavType::MAV_TYPE_ONBOARD_CONTROLLER,
+            autopilot: common::MavAutopilot::MAV_AUTOPILOT_INVALID,
+            ..Default::default()
+        }))
+    }
+
+    #[tokio::test]
+    async fn send_uses_the_configured_ids_and_counts_up() {
+        for (version, magic) in &[("V1", MAVLINK_V1_MAGIC), ("V2", MAVLINK_V2_MAGIC)] {
+            let (mut client, mut autopilot) = mock_client(serde_json::json!({
+                "mavlink": { "type": version },
+                "system_id": 255,
+                "component_id": 190
+            }))
+            .await;
+
+            for sequence in 0..2 {
+                client.send(heartbeat()).await.unwrap();
+
+                let mut frame = vec![0; 1024];
+                let n = autopilot.read(&mut frame).await.unwrap();
+                assert_eq!(frame[0], *magic);
+
+                let (header, message) = decode(&frame[..n]);
+                assert_eq!(header.system_id, 255);
+                assert_eq!(header.component_id, 190);
+                assert_eq!(header.sequence, sequence);
+                assert_eq!(message, heartbeat());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_sets_up_again_after_the_link_drops() {
+        let listener = MockListener::default();
+        let first_link = listener.link();
+        let second_link = listener.link();
+
+        let mut client = mock_client_on(
+            &listener,
+            serde_json::json!({ "reconnect_attempts": 2, "reconnect_delay_ms": 1 }),
+        )
+        .await;
+        let mut interrupt_recv = client.channels.interrupt.subscribe();
+
+        let mut autopilot = fake_autopilot(first_link);
+        let mut received = Vec::new();
+        tokio::select! {
+            result = client.init() => result.unwrap(),
+            result = answer_as_autopilot(&mut autopilot, &mut received) => {
+                panic!("fake autopilot stopped: {:?}", result)
+            }
+        }
+
+        drop(autopilot);
+        assert!(is_disconnect(&client.recv().await.unwrap_err()));
+
+        let mut autopilot = fake_autopilot(second_link);
+        let mut received = Vec::new();
+        let reconnected = tokio::select! {
+            result = client.reconnect(&mut interrupt_recv) => result.unwrap(),
+            result = answer_as_autopilot(&mut autopilot, &mut received) => {
+                panic!("fake autopilot stopped: {:?}", result)
+            }
+        };
+
+        // the client set the camera trigger up again on the new link
+        assert!(reconnected);
+        assert!(received.iter().any(|message| matches!(
+            message,
+            apm::MavMessage::common(common::MavMessage::PARAM_SET(_))
+        )));
+
+        autopilot.send(heartbeat()).await.unwrap();
+        assert_eq!(client.recv().await.unwrap(), heartbeat());
+    }
+
+    #[tokio::test]
+    async fn gives_up_reconnecting_after_the_configured_attempts() {
+        let listener = MockListener::default();
+        let link = listener.link();
+
+        let mut client = mock_client_on(
+            &listener,
+            serde_json::json!({ "reconnect_attempts": 2, "reconnect_delay_ms": 1 }),
+        )
+        .await;
+        let mut interrupt_recv = client.channels.interrupt.subscribe();
+
+        // the link drops and nothing is listening any more
+        drop(link);
+        assert!(is_disconnect(&client.recv().await.unwrap_err()));
+
+        let err = client.reconnect(&mut interrupt_recv).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "could not reconnect to pixhawk after 2 attempts"
+        );
+    }
+
+    /// Sends `message` from the fake autopilot and returns the battery event
+    /// that the client sends out when it receives it, if any.
+    async fn battery_event(message: common::MavMessage) -> Option<PixhawkEvent> {
+        let (mut client, link) = mock_client(serde_json::json!({})).await;
+        let mut autopilot = fake_autopilot(link);
+        let mut events = client.channels.pixhawk_event.subscribe();
+
+        autopilot
+            .send(apm::MavMessage::common(message))
+            .await
+            .unwrap();
+        client.recv().await.unwrap();
+
+        events.try_recv().ok()
+    }
+
+    #[tokio::test]
+    async fn sys_status_is_decoded_into_a_battery_event() {
+        let event = battery_event(common::MavMessage::SYS_STATUS(common::SYS_STATUS_DATA {
+            voltage_battery: 12_150,
+            current_battery: 1_025,
+            battery_remaining: 64,
+            ..Default::default()
+        }))
+        .await;
+
+        assert!(matches!(
+            event,
+            Some(PixhawkEvent::Battery {
+                voltage,
+                current: Some(current),
+                remaining_percent: Some(64),
+            }) if voltage == 12.15 && current == 10.25
+        ));
+    }
+
+    #[tokio::test]
+    async fn unknown_battery_readings_are_left_out() {
+        // -1 means the autopilot doesn't measure the current or know the
+        // remaining charge
+        let event = battery_event(common::MavMessage::SYS_STATUS(common::SYS_STATUS_DATA {
+            voltage_battery: 11_100,
+            current_battery: -1,
+            battery_remaining: -1,
+            ..Default::default()
+        }))
+        .await;
+
+        assert!(matches!(
+            event,
+            Some(PixhawkEvent::Battery {
+                current: None,
+                remaining_percent: None,
+                ..
+            })
+        ));
+
+        // and u16::MAX means it doesn't know the voltage, so there is nothing
+        // to report
+        let event = battery_event(common::MavMessage::SYS_STATUS(common::SYS_STATUS_DATA {
+            voltage_battery: u16::MAX,
+            current_battery: -1,
+            battery_remaining: -1,
+            ..Default::default()
+        }))
+        .await;
+
+        assert!(event.is_none());
+    }
+
+    #[tokio::test]
+    async fn battery_status_adds_up_the_cells_that_are_used() {
+        let mut voltages = [u16::MAX; 10];
+        voltages[..3].copy_from_slice(&[4_100, 4_150, 4_200]);
+
+        let event = battery_event(common::MavMessage::BATTERY_STATUS(
+            common::BATTERY_STATUS_DATA {
+                voltages,
+                current_battery: -1,
+                battery_remaining: 80,
+                ..Default::default()
+            },
+        ))
+        .await;
+
+        assert!(matches!(
+            event,
+            Some(PixhawkEvent::Battery {
+                voltage,
+                current: None,
+                remaining_percent: Some(80),
+            }) if (voltage - 12.45).abs() < 1e-4
+        ));
+    }
+
+    fn two_waypoints() -> Vec<MissionItem> {
+        vec![
+            MissionItem {
+                command: MissionCommand::Waypoint,
+                coords: Coords3D::new(42.44, -76.48, 0.0),
+                hold_secs: 0.0,
+            },
+            MissionItem {
+                command: MissionCommand::Waypoint,
+                coords: Coords3D::new(42.45, -76.47, 100.0),
+                hold_secs: 5.0,
+            },
+        ]
+    }
+
+    /// Waits for the client to send a mission message, and returns the
+    /// sequence number of the item, or `None` for the item count.
+    async fn next_mission_message(autopilot: &mut PixhawkClient) -> Option<u16> {
+        loop {
+            match autopilot.recv().await.unwrap() {
+                apm::MavMessage::common(common::MavMessage::MISSION_COUNT(_)) => return None,
+                apm::MavMessage::common(common::MavMessage::MISSION_ITEM_INT(data)) => {
+                    return Some(data.seq)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    async fn request_mission_item(autopilot: &mut PixhawkClient, seq: u16) {
+        autopilot
+            .send(apm::MavMessage::common(
+                common::MavMessage::MISSION_REQUEST_INT(common::MISSION_REQUEST_INT_DATA {
+                    seq,
+                    ..Default::default()
+                }),
+            ))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn mission_upload_resends_an_item_that_got_no_reply() {
+        tokio::time::pause();
+
+        let (mut client, link) = mock_client(serde_json::json!({})).await;
+        let mut autopilot = fake_autopilot(link);
+        let items = two_waypoints();
+
+        let autopilot_side = async {
+            let mut received = Vec::new();
+
+            assert_eq!(next_mission_message(&mut autopilot).await, None);
+            request_mission_item(&mut autopilot, 0).await;
+
+            // don't answer the first copy of item 0, as if our reply got lost
+            received.push(next_mission_message(&mut autopilot).await);
+            received.push(next_mission_message(&mut autopilot).await);
+            request_mission_item(&mut autopilot, 1).await;
+
+            received.push(next_mission_message(&mut autopilot).await);
+            autopilot
+                .send(apm::MavMessage::common(common::MavMessage::MISSION_ACK(
+                    common::MISSION_ACK_DATA {
+                        mavtype: common::MavMissionResult::MAV_MISSION_ACCEPTED,
+                        ..Default::default()
+                    },
+                )))
+                .await
+                .unwrap();
+
+            received
+        };
+
+        let (count, received) = tokio::join!(client.upload_mission(&items), autopilot_side);
+
+        assert_eq!(count.unwrap(), 2);
+        assert_eq!(received, vec![Some(0), Some(0), Some(1)]);
+    }
+
+    #[tokio::test]
+    async fn mission_upload_fails_if_the_autopilot_asks_for_an_item_that_does_not_exist() {
+        let (mut client, link) = mock_client(serde_json::json!({})).await;
+        let mut autopilot = fake_autopilot(link);
+        let items = two_waypoints();
+
+        let autopilot_side = async {
+            assert_eq!(next_mission_message(&mut autopilot).await, None);
+            request_mission_item(&mut autopilot, 5).await;
+        };
+
+        let (result, _) = tokio::join!(client.upload_mission(&items), autopilot_side);
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "autopilot asked for mission item 5 of 2"
+        );
+    }
+
+    #[tokio::test]
+    async fn mission_upload_gives_up_after_the_retries() {
+        tokio::time::pause();
+
+        let (mut client, link) = mock_client(serde_json::json!({})).await;
+        let mut autopilot = fake_autopilot(link);
+        let items = two_waypoints();
+        let mut counts_sent = 0;
+
+        let result = tokio::select! {
+            result = client.upload_mission(&items) => result,
+            _ = async {
+                loop {
+                    if next_mission_message(&mut autopilot).await.is_none() {
+                        counts_sent += 1;
+                    }
+                }
+            } => unreachable!(),
+        };
+
+        assert!(result.is_err());
+        assert_eq!(counts_sent, 1 + MISSION_RETRIES);
+    }
+
+    #[tokio::test]
+    async fn message_intervals_are_set_with_a_command() {
+        let (mut client, link) = mock_client(serde_json::json!({})).await;
+        let mut autopilot = fake_autopilot(link);
+        let mut received = Vec::new();
+
+        tokio::select! {
+            result = async {
+                client.set_message_interval(33, 250_000).await?;
+                // anything below -1 is sent as -1, which stops the message
+                client.set_message_interval(30, -5).await
+            } => result.unwrap(),
+            result = answer_as_autopilot(&mut autopilot, &mut received) => {
+                panic!("fake autopilot stopped: {:?}", result)
+            }
+        }
+
+        let commands = received
+            .iter()
+            .filter_map(|message| match message {
+                apm::MavMessage::common(common::MavMessage::COMMAND_LONG(data)) => Some(data),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(commands.len(), 2);
+
+        for command in &commands {
+            assert_eq!(
+                command.command,
+                common::MavCmd::MAV_CMD_SET_MESSAGE_INTERVAL
+            );
+            assert_eq!(command.target_system, MavTarget::AUTOPILOT.system);
+            assert_eq!(command.target_component, MavTarget::AUTOPILOT.component);
+        }
+
+        assert_eq!((commands[0].param1, commands[0].param2), (33.0, 250_000.0));
+        assert_eq!((commands[1].param1, commands[1].param2), (30.0, -1.0));
+    }
+}
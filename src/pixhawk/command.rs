@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+use crate::{cli::config::CameraTriggerConfig, state::Coords3D, Command};
+
+pub type PixhawkCommand = Command<PixhawkRequest, PixhawkResponse>;
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum PixhawkRequest {
+    /// view or change the parameters that the autopilot uses to trigger the
+    /// camera and detect its feedback
+    CameraTrigger(PixhawkCameraTriggerRequest),
+
+    /// change how often the autopilot sends a message
+    MessageInterval {
+        /// the ID of the message, e.g. 30 for ATTITUDE
+        message_id: u32,
+
+        /// microseconds between messages; 0 restores the default rate and a
+        /// negative number stops the message
+        #[structopt(allow_hyphen_values = true)]
+        interval_us: i32,
+    },
+
+    /// replace the autopilot's mission with the mission in a JSON file
+    UploadMission {
+        /// a JSON file with an `items` list; see `MissionItem` for what each
+        /// item looks like
+        #[structopt(name = "file", parse(try_from_str = Mission::read))]
+        mission: Mission,
+    },
+}
+
+#[derive(StructOpt, Debug, Clone)]
+pub enum PixhawkCameraTriggerRequest {
+    /// read the current camera trigger parameters from the autopilot
+    Get,
+
+    /// change camera trigger parameters on the autopilot; parameters that are
+    /// not given are left alone
+    Set {
+        /// how long to hold the shutter, in tenths of a second
+        #[structopt(long)]
+        duration: Option<f32>,
+
+        /// the pin that the camera feedback signal is wired to, or -1 to
+        /// disable feedback
+        #[structopt(long, allow_hyphen_values = true)]
+        feedback_pin: Option<i8>,
+
+        /// 1 if the feedback pin goes high when the camera fires, 0 if it
+        /// goes low
+        #[structopt(long)]
+        feedback_polarity: Option<u8>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum PixhawkResponse {
+    Unit,
+    CameraTrigger { config: CameraTriggerConfig },
+    UploadMission { count: u16 },
+}
+
+/// A list of mission items to upload to the autopilot. The autopilot treats
+/// the first item as the home position, and ArduPilot overwrites it with the
+/// actual home position, so the first item that the plane flies to is the
+/// second one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mission {
+    pub items: Vec<MissionItem>,
+}
+
+impl Mission {
+    /// Reads a mission from a JSON file.
+    pub fn read(path: &str) -> anyhow::Result<Self> {
+        let path = Path::new(path);
+
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read mission file {:?}", path))?;
+
+        serde_json::from_str(&contents)
+            .with_context(|| format!("failed to parse mission file {:?}", path))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct MissionItem {
+    pub command: MissionCommand,
+
+    /// Where the item takes place; the altitude is relative to the home
+    /// position. Ignored by `returntolaunch`.
+    pub coords: Coords3D,
+
+    /// How long to stay at a waypoint before moving on, in seconds.
+    #[serde(default)]
+    pub hold_secs: f32,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MissionCommand {
+    Waypoint,
+    Takeoff,
+    Land,
+    /// Circle the location until the mission is changed.
+    Loiter,
+    ReturnToLaunch,
+}
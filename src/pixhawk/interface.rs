@@ -0,0 +1,242 @@
+use std::{
+    io::{Read, Write},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+#[cfg(test)]
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+use tokio::{net::UdpSocket, sync::mpsc};
+
+/// How long a read from the serial port blocks before the reader thread checks
+/// whether it should stop.
+const SERIAL_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Where the Pixhawk is connected.
+#[derive(Debug, Clone)]
+pub enum PixhawkTransport {
+    /// MAVProxy forwards packets to this address.
+    Udp(SocketAddr),
+
+    /// The Pixhawk is plugged into this serial device (e.g. over USB).
+    Serial { path: String, baud: u32 },
+
+    /// A fake autopilot in a test.
+    #[cfg(test)]
+    Mock(MockListener),
+}
+
+/// Hands out in-memory links to a fake autopilot, one each time the client
+/// connects, so that a test can drop a link and check that the client
+/// connects again.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct MockListener(Arc<Mutex<std::collections::VecDeque<DuplexStream>>>);
+
+#[cfg(test)]
+impl MockListener {
+    /// Queues a link for the client's next connection, and returns the fake
+    /// autopilot's end of it.
+    pub fn link(&self) -> DuplexStream {
+        let (client, autopilot) = tokio::io::duplex(4096);
+        self.0.lock().unwrap().push_back(client);
+        autopilot
+    }
+}
+
+#[cfg(test)]
+impl std::fmt::Debug for MockListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "MockListener")
+    }
+}
+
+/// An open connection to the Pixhawk.
+pub enum PixhawkInterface {
+    Udp(UdpSocket),
+    Serial {
+        /// Used for writing; a background thread reads from a clone of this
+        /// port and sends what it reads through `recv`.
+        port: Arc<Mutex<serialport::TTYPort>>,
+        recv: mpsc::Receiver<std::io::Result<Vec<u8>>>,
+
+        /// Tells the reader thread to stop so that it lets go of the port.
+        stop: Arc<AtomicBool>,
+    },
+
+    /// An in-memory link to a fake autopilot in a test.
+    #[cfg(test)]
+    Mock(DuplexStream),
+
+    /// The previous connection was closed and the next one is not open yet.
+    Closed,
+}
+
+impl PixhawkInterface {
+    pub async fn open(transport: &PixhawkTransport) -> anyhow::Result<Self> {
+        match transport {
+            PixhawkTransport::Udp(addr) => {
+                let sock = UdpSocket::bind(addr)
+                    .await
+                    .context("failed to connect to pixhawk")?;
+
+                Self::lock_to_sender(&sock).await?;
+
+                Ok(PixhawkInterface::Udp(sock))
+            }
+            PixhawkTransport::Serial { path, baud } => {
+                let port = serialport::new(path, *baud)
+                    .timeout(SERIAL_READ_TIMEOUT)
+                    .open_native()
+                    .with_context(|| format!("failed to open serial port {}", path))?;
+
+                let reader = port
+                    .try_clone_native()
+                    .context("failed to clone serial port")?;
+
+                info!("opened serial port {} at {} baud", path, baud);
+
+                let stop = Arc::new(AtomicBool::new(false));
+
+                Ok(PixhawkInterface::Serial {
+                    port: Arc::new(Mutex::new(port)),
+                    recv: Self::spawn_reader(reader, stop.clone()),
+                    stop,
+                })
+            }
+            #[cfg(test)]
+            PixhawkTransport::Mock(listener) => {
+                let link = listener.0.lock().unwrap().pop_front();
+                Ok(PixhawkInterface::Mock(
+                    link.context("fake autopilot is not listening")?,
+                ))
+            }
+        }
+    }
+
+    /// Waits for a packet from MAVProxy and connects the socket to its sender,
+    /// so that we only talk to that address from then on.
+    async fn lock_to_sender(sock: &UdpSocket) -> anyhow::Result<()> {
+        debug!("waiting for packet from mavproxy");
+
+        let (_, remote_addr) =
+            tokio::time::timeout(Duration::from_secs(60), sock.recv_from(&mut []))
+                .await
+                .context("timed out while waiting for packet from mavproxy")?
+                .context("error retrieving packet from mavproxy")?;
+
+        info!(
+            "received packet from {:?}, locking to this address",
+            remote_addr
+        );
+
+        sock.connect(remote_addr)
+            .await
+            .context("failed to lock to address")?;
+
+        Ok(())
+    }
+
+    /// Reads from the serial port on a separate thread, since reads from it
+    /// block. The thread stops after a read fails or once `stop` is set.
+    fn spawn_reader(
+        mut port: serialport::TTYPort,
+        stop: Arc<AtomicBool>,
+    ) -> mpsc::Receiver<std::io::Result<Vec<u8>>> {
+        let (sender, receiver) = mpsc::channel(64);
+
+        std::thread::spawn(move || {
+            let mut chunk = vec![0; 1024];
+
+            while !stop.load(Ordering::SeqCst) {
+                let result = match port.read(&mut chunk[..]) {
+                    Ok(n) => Ok(chunk[..n].to_vec()),
+                    Err(err) if err.kind() == std::io::ErrorKind::TimedOut => continue,
+                    Err(err) => Err(err),
+                };
+
+                let failed = result.is_err();
+
+                if sender.blocking_send(result).is_err() || failed {
+                    break;
+                }
+            }
+
+            trace!("serial reader thread exiting");
+        });
+
+        receiver
+    }
+
+    /// Reads the next chunk of bytes from the Pixhawk into `chunk` and
+    /// returns how many bytes were read.
+    pub async fn recv(&mut self, chunk: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            PixhawkInterface::Udp(sock) => sock.recv(chunk).await,
+            PixhawkInterface::Serial { recv, .. } => match recv.recv().await {
+                Some(Ok(data)) => {
+                    // the reader thread never reads more than a chunk at a time
+                    let n = data.len().min(chunk.len());
+                    chunk[..n].copy_from_slice(&data[..n]);
+                    Ok(n)
+                }
+                Some(Err(err)) => Err(err),
+                None => Err(std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    "serial reader thread stopped",
+                )),
+            },
+            #[cfg(test)]
+            PixhawkInterface::Mock(link) => match link.read(chunk).await? {
+                // unlike a UDP socket, the link ends when the other side
+                // closes it
+                0 => Err(std::io::Error::new(
+                    std::io::ErrorKind::BrokenPipe,
+                    "fake autopilot closed the link",
+                )),
+                n => Ok(n),
+            },
+            PixhawkInterface::Closed => Err(closed()),
+        }
+    }
+
+    pub async fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            PixhawkInterface::Udp(sock) => sock.send(data).await.map(|_| ()),
+            PixhawkInterface::Serial { port, .. } => {
+                // writes to the serial port block until the bytes are out,
+                // which takes a while at 57600 baud, so keep them off the
+                // runtime's threads
+                let port = port.clone();
+                let data = data.to_vec();
+
+                tokio::task::spawn_blocking(move || port.lock().unwrap().write_all(&data))
+                    .await
+                    .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, err))?
+            }
+            #[cfg(test)]
+            PixhawkInterface::Mock(link) => link.write_all(data).await,
+            PixhawkInterface::Closed => Err(closed()),
+        }
+    }
+}
+
+impl Drop for PixhawkInterface {
+    fn drop(&mut self) {
+        if let PixhawkInterface::Serial { stop, .. } = self {
+            stop.store(true, Ordering::SeqCst);
+        }
+    }
+}
+
+fn closed() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::NotConnected,
+        "connection to pixhawk is closed",
+    )
+}
@@ -1,5 +1,8 @@
 pub mod client;
+pub mod command;
+pub mod interface;
 pub mod state;
 
 pub use client::*;
+pub use command::*;
 pub use state::*;
@@ -33,7 +33,23 @@ pub enum PixhawkEvent {
     Orientation {
         attitude: Attitude,
     },
+    /// A heartbeat was received from a ground control station.
+    GroundHeartbeat,
+    /// The autopilot sent a heartbeat, saying whether the plane is armed.
+    Armed {
+        armed: bool,
+    },
+    /// The autopilot reported its current time.
+    SystemTime {
+        time: SystemTime,
+    },
+    /// The autopilot reported the state of the battery.
+    Battery {
+        /// Volts.
+        voltage: f32,
+        /// Amps, or `None` if the autopilot doesn't measure it.
+        current: Option<f32>,
+        /// Percent, or `None` if the autopilot doesn't know.
+        remaining_percent: Option<u8>,
+    },
 }
-
-// TODO
-pub type PixhawkCommand = ();
@@ -1,6 +1,7 @@
 use crate::{
-    scheduler::state::*,
-    state::{Coords2D, RegionOfInterest, TelemetryInfo},
+    cli::config::{ROIEvictionPolicy, SchedulerConfig},
+    scheduler::{command::AddROIOutcome, state::*},
+    state::{ClientType, Coords2D, RegionOfInterest, RegionOfInterestId, TelemetryInfo},
 };
 
 use geo::{
@@ -8,11 +9,50 @@ use geo::{
     Point,
 };
 
+/// An ROI that has not been photographed yet.
+struct PendingROI {
+    roi: RegionOfInterest,
+
+    /// Who sent us this ROI.
+    client_type: ClientType,
+
+    /// How many passes over this ROI have failed to photograph it.
+    attempts: u32,
+
+    /// Set when an attempt fails, so that we don't try again until the plane
+    /// has left the capture radius and come back.
+    tried_this_pass: bool,
+}
+
+impl PendingROI {
+    fn new(roi: RegionOfInterest, client_type: ClientType) -> Self {
+        PendingROI {
+            roi,
+            client_type,
+            attempts: 0,
+            tried_this_pass: false,
+        }
+    }
+
+    /// How important it is to photograph this ROI; ROIs of the same kind are
+    /// ranked by who sent them.
+    fn priority(&self) -> (u8, u8) {
+        priority(&self.roi, self.client_type)
+    }
+}
+
+fn priority(roi: &RegionOfInterest, client_type: ClientType) -> (u8, u8) {
+    (roi.kind().priority(), client_type.priority())
+}
+
 pub struct SchedulerBackend {
     /// List of regions of interest that should be photographed as soon as
     /// possible. Scheduler will prioritize attempting to photograph nearby ROIs
     /// over increasing ground coverage.
-    rois: Vec<RegionOfInterest>,
+    rois: Vec<PendingROI>,
+
+    /// How many ROIs have been photographed.
+    completed: usize,
 
     /// The current telemetry that the backend will make base decisions on. The
     /// frontend should update this as it receives new telemetry.
@@ -23,20 +63,178 @@ pub struct SchedulerBackend {
 
     /// Temporary hack for test flight purposes.
     gps: Coords2D,
+
+    /// The most ROIs that we keep in `rois` at once.
+    max_rois: usize,
+
+    /// Which ROI to drop from `rois` when it is full.
+    eviction: ROIEvictionPolicy,
+
+    /// How close the plane has to be to an ROI to photograph it, in meters.
+    capture_radius_m: f64,
+
+    /// How many passes over an ROI can fail before it is dropped.
+    max_attempts: u32,
+
+    /// New ROIs closer than this to an existing ROI, in meters, are treated
+    /// as the same ROI.
+    duplicate_radius_m: f64,
 }
 
 impl SchedulerBackend {
-    pub fn new(gps: Coords2D) -> Self {
+    pub fn new(config: &SchedulerConfig) -> Self {
         Self {
             rois: Vec::new(),
+            completed: 0,
             telemetry: TelemetryInfo::default(),
             time_for_capture: true,
-            gps,
+            gps: config.gps,
+            max_rois: config.max_rois,
+            eviction: config.eviction,
+            capture_radius_m: config.capture_radius_m,
+            max_attempts: config.max_roi_attempts,
+            duplicate_radius_m: config.duplicate_radius_m,
+        }
+    }
+
+    /// Adds an ROI, making room for it according to the eviction policy if we
+    /// already have as many ROIs as we can keep track of. `rois` is kept in
+    /// the order that the ROIs were added, so the oldest ROI is first. ROIs
+    /// that are right next to one that we already have are skipped, since
+    /// both clients often report the same target.
+    pub fn add_roi(&mut self, roi: RegionOfInterest, client_type: ClientType) -> AddROIOutcome {
+        let location = Point::<f64>::new(
+            roi.location().longitude as f64,
+            roi.location().latitude as f64,
+        );
+
+        let duplicate = self.rois.iter().find(|pending| {
+            let other = Point::<f64>::new(
+                pending.roi.location().longitude as f64,
+                pending.roi.location().latitude as f64,
+            );
+
+            location.haversine_distance(&other) <= self.duplicate_radius_m
+        });
+
+        if let Some(duplicate) = duplicate {
+            debug!("{:?} is a duplicate of {:?}", roi, duplicate.roi);
+            return AddROIOutcome::Duplicate {
+                existing: duplicate.roi.id(),
+            };
+        }
+
+        if self.rois.len() < self.max_rois {
+            self.rois.push(PendingROI::new(roi, client_type));
+            return AddROIOutcome::Accepted;
+        }
+
+        if self.rois.is_empty() {
+            // max_rois is 0, so there is no room for anything
+            return AddROIOutcome::Rejected;
+        }
+
+        let victim = match self.eviction {
+            ROIEvictionPolicy::Oldest => 0,
+            ROIEvictionPolicy::Priority => {
+                // min_by_key returns the first minimum, which is the oldest
+                let (victim, lowest) = self
+                    .rois
+                    .iter()
+                    .enumerate()
+                    .min_by_key(|(_, pending)| pending.priority())
+                    .map(|(i, pending)| (i, pending.priority()))
+                    .unwrap();
+
+                if priority(&roi, client_type) < lowest {
+                    warn!("too many ROIs, rejecting {:?}", roi);
+                    return AddROIOutcome::Rejected;
+                }
+
+                victim
+            }
+        };
+
+        let evicted = self.rois.remove(victim).roi;
+        warn!("too many ROIs, dropping {:?} to make room", evicted);
+        self.rois.push(PendingROI::new(roi, client_type));
+
+        AddROIOutcome::Evicted {
+            evicted: evicted.id(),
         }
     }
 
     pub fn update_telemetry(&mut self, telemetry: TelemetryInfo) {
         self.telemetry = telemetry;
+
+        // once the plane leaves an ROI behind, the next time it comes close
+        // is a new pass
+        for i in 0..self.rois.len() {
+            if self.rois[i].tried_this_pass
+                && self.distance_to(self.rois[i].roi.location()) > self.capture_radius_m
+            {
+                self.rois[i].tried_this_pass = false;
+            }
+        }
+    }
+
+    /// Distance in meters from the plane to a location on the ground.
+    fn distance_to(&self, location: Coords2D) -> f64 {
+        let current_loc = Point::<f64>::new(
+            self.telemetry.position.longitude as f64,
+            self.telemetry.position.latitude as f64,
+        );
+        let target_loc = Point::<f64>::new(location.longitude as f64, location.latitude as f64);
+
+        current_loc.haversine_distance(&target_loc)
+    }
+
+    /// Picks the nearest ROI within the capture radius that hasn't already
+    /// failed on this pass.
+    pub fn next_roi(&self) -> Option<RegionOfInterest> {
+        self.rois
+            .iter()
+            .filter(|pending| !pending.tried_this_pass)
+            .map(|pending| (pending.roi, self.distance_to(pending.roi.location())))
+            .filter(|(_, distance)| *distance <= self.capture_radius_m)
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(roi, _)| roi)
+    }
+
+    /// Removes an ROI that has been photographed.
+    pub fn complete_roi(&mut self, id: RegionOfInterestId) {
+        if let Some(i) = self.rois.iter().position(|pending| pending.roi.id() == id) {
+            let pending = self.rois.remove(i);
+            info!(
+                "captured {:?} after {} failed passes",
+                pending.roi, pending.attempts
+            );
+            self.completed += 1;
+        }
+    }
+
+    /// Records that photographing an ROI failed on this pass, and drops the
+    /// ROI if it has failed too many times.
+    pub fn fail_roi(&mut self, id: RegionOfInterestId) {
+        if let Some(i) = self.rois.iter().position(|pending| pending.roi.id() == id) {
+            let pending = &mut self.rois[i];
+            pending.attempts += 1;
+            pending.tried_this_pass = true;
+
+            if pending.attempts >= self.max_attempts {
+                let pending = self.rois.remove(i);
+                warn!(
+                    "could not capture {:?} after {} passes, dropping it",
+                    pending.roi, pending.attempts
+                );
+            }
+        }
+    }
+
+    /// How many ROIs are left to photograph, and how many have been
+    /// photographed.
+    pub fn roi_counts(&self) -> (usize, usize) {
+        (self.rois.len(), self.completed)
     }
 
     pub fn get_capture_request(&mut self) -> Option<CaptureRequest> {
@@ -48,6 +246,12 @@ impl SchedulerBackend {
     }
 
     pub fn get_target_gimbal_angles(&mut self) -> (f64, f64) {
+        self.target_gimbal_angles(self.gps)
+    }
+
+    /// Works out the gimbal angles that point the camera at a location on the
+    /// ground.
+    pub fn target_gimbal_angles(&self, target: Coords2D) -> (f64, f64) {
         // altitude in m, no conversion needed
         let altitude = self.telemetry.position.altitude as f64;
 
@@ -61,7 +265,7 @@ impl SchedulerBackend {
             self.telemetry.position.longitude as f64,
             self.telemetry.position.latitude as f64,
         );
-        let gps_loc = Point::<f64>::new(self.gps.longitude as f64, self.gps.latitude as f64);
+        let gps_loc = Point::<f64>::new(target.longitude as f64, target.latitude as f64);
 
         // distance is given in m, no conversion needed
         let distance = current_loc.haversine_distance(&gps_loc);
@@ -96,3 +300,135 @@ impl SchedulerBackend {
         self.time_for_capture = true;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::RegionOfInterestKind;
+
+    fn backend(max_rois: usize, eviction: ROIEvictionPolicy) -> SchedulerBackend {
+        SchedulerBackend::new(&SchedulerConfig {
+            enabled: true,
+            gps: Coords2D::default(),
+            max_rois,
+            eviction,
+            capture_radius_m: 150.0,
+            max_roi_attempts: 2,
+            duplicate_radius_m: 5.0,
+            gimbal_tolerance_deg: 2.0,
+            gimbal_timeout_ms: 3000,
+        })
+    }
+
+    /// An ROI north of the plane, which sits at (0, 0); 0.001 degrees of
+    /// latitude is about 111 meters.
+    fn roi(latitude: f32, kind: RegionOfInterestKind) -> RegionOfInterest {
+        RegionOfInterest::with_location_and_kind(Coords2D::new(latitude, 0.0), kind)
+    }
+
+    fn next_id(backend: &SchedulerBackend) -> Option<RegionOfInterestId> {
+        backend.next_roi().map(|roi| roi.id())
+    }
+
+    #[test]
+    fn captures_nearest_roi_first() {
+        let mut backend = backend(10, ROIEvictionPolicy::Oldest);
+        let far = roi(0.001, RegionOfInterestKind::Normal);
+        let near = roi(0.0005, RegionOfInterestKind::Normal);
+        let out_of_range = roi(0.01, RegionOfInterestKind::Normal);
+
+        for roi in &[far, near, out_of_range] {
+            assert!(matches!(
+                backend.add_roi(*roi, ClientType::MDLC),
+                AddROIOutcome::Accepted
+            ));
+        }
+
+        assert_eq!(next_id(&backend), Some(near.id()));
+        backend.complete_roi(near.id());
+        assert_eq!(next_id(&backend), Some(far.id()));
+        backend.complete_roi(far.id());
+        assert_eq!(next_id(&backend), None);
+        assert_eq!(backend.roi_counts(), (1, 2));
+    }
+
+    #[test]
+    fn failed_roi_waits_for_next_pass_and_is_dropped_eventually() {
+        let mut backend = backend(10, ROIEvictionPolicy::Oldest);
+        let near = roi(0.0005, RegionOfInterestKind::Normal);
+        let far = roi(0.001, RegionOfInterestKind::Normal);
+        backend.add_roi(near, ClientType::MDLC);
+        backend.add_roi(far, ClientType::MDLC);
+
+        backend.fail_roi(near.id());
+        assert_eq!(next_id(&backend), Some(far.id()));
+
+        // fly away and come back for a second pass
+        let mut telemetry = TelemetryInfo::default();
+        telemetry.position.latitude = 0.01;
+        backend.update_telemetry(telemetry);
+        backend.update_telemetry(TelemetryInfo::default());
+        assert_eq!(next_id(&backend), Some(near.id()));
+
+        backend.fail_roi(near.id());
+        assert_eq!(backend.roi_counts(), (1, 0));
+        assert_eq!(next_id(&backend), Some(far.id()));
+    }
+
+    #[test]
+    fn oldest_policy_evicts_first_roi_added() {
+        let mut backend = backend(2, ROIEvictionPolicy::Oldest);
+        let first = roi(0.001, RegionOfInterestKind::EmergentTarget);
+        let second = roi(0.002, RegionOfInterestKind::Normal);
+        let third = roi(0.003, RegionOfInterestKind::Normal);
+
+        backend.add_roi(first, ClientType::MDLC);
+        backend.add_roi(second, ClientType::ADLC);
+
+        match backend.add_roi(third, ClientType::ADLC) {
+            AddROIOutcome::Evicted { evicted } => assert_eq!(evicted, first.id()),
+            other => panic!("expected an eviction, got {:?}", other),
+        }
+        assert_eq!(backend.roi_counts(), (2, 0));
+    }
+
+    #[test]
+    fn priority_policy_evicts_least_important_roi() {
+        let mut backend = backend(2, ROIEvictionPolicy::Priority);
+        let important = roi(0.001, RegionOfInterestKind::EmergentTarget);
+        let from_adlc = roi(0.002, RegionOfInterestKind::Normal);
+        let from_mdlc = roi(0.003, RegionOfInterestKind::Normal);
+
+        backend.add_roi(important, ClientType::MDLC);
+        backend.add_roi(from_adlc, ClientType::ADLC);
+
+        match backend.add_roi(from_mdlc, ClientType::MDLC) {
+            AddROIOutcome::Evicted { evicted } => assert_eq!(evicted, from_adlc.id()),
+            other => panic!("expected an eviction, got {:?}", other),
+        }
+
+        // less important than everything that we have
+        let unimportant = roi(0.004, RegionOfInterestKind::Normal);
+        assert!(matches!(
+            backend.add_roi(unimportant, ClientType::ADLC),
+            AddROIOutcome::Rejected
+        ));
+
+        // ties go to the oldest ROI
+        let tie = roi(0.005, RegionOfInterestKind::Normal);
+        match backend.add_roi(tie, ClientType::MDLC) {
+            AddROIOutcome::Evicted { evicted } => assert_eq!(evicted, from_mdlc.id()),
+            other => panic!("expected an eviction, got {:?}", other),
+        }
+        assert_eq!(backend.roi_counts(), (2, 0));
+    }
+
+    #[test]
+    fn nothing_fits_without_room() {
+        let mut backend = backend(0, ROIEvictionPolicy::Priority);
+        assert!(matches!(
+            backend.add_roi(roi(0.001, RegionOfInterestKind::Normal), ClientType::MDLC),
+            AddROIOutcome::Rejected
+        ));
+    }
+}
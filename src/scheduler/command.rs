@@ -0,0 +1,48 @@
+use serde::Serialize;
+
+use crate::{
+    state::{ClientType, RegionOfInterest, RegionOfInterestId},
+    Command,
+};
+
+pub type SchedulerCommand = Command<SchedulerRequest, SchedulerResponse>;
+
+#[derive(Debug, Clone)]
+pub enum SchedulerRequest {
+    /// Adds ROIs to the list of ROIs that the scheduler will try to
+    /// photograph.
+    AddROIs {
+        rois: Vec<RegionOfInterest>,
+        client_type: ClientType,
+    },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum SchedulerResponse {
+    AddROIs {
+        /// How many of the ROIs were added.
+        accepted: usize,
+        /// What happened to each of the ROIs that were added, in the same
+        /// order.
+        outcomes: Vec<AddROIOutcome>,
+    },
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddROIOutcome {
+    /// The ROI was added.
+    Accepted,
+
+    /// The ROI was added, but the scheduler was full and had to drop another
+    /// ROI to make room for it.
+    Evicted { evicted: RegionOfInterestId },
+
+    /// The scheduler was full and this ROI was less important than all of the
+    /// ROIs that it already had, so it was not added.
+    Rejected,
+
+    /// The scheduler already has an ROI in the same place, so this one was
+    /// not added.
+    Duplicate { existing: RegionOfInterestId },
+}
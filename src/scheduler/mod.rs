@@ -1,13 +1,27 @@
 use anyhow::Context;
+use tokio::sync::mpsc;
 
-use crate::{gimbal::GimbalRequest, state::Coords2D, Channels, Command};
+use crate::{
+    camera::CameraRequest,
+    cli::config::SchedulerConfig,
+    gimbal::GimbalRequest,
+    state::{RegionOfInterest, TelemetryInfo},
+    Channels, Command,
+};
 
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
 mod backend;
-mod state;
+mod command;
+pub mod state;
 
 use backend::*;
+pub use command::*;
+use state::SchedulerEvent;
+
+/// How long to wait for the gimbal or the camera while photographing an ROI
+/// before counting the attempt as failed.
+const ROI_CAPTURE_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Controls whether the plane is taking pictures of the ground (first-pass),
 /// taking pictures of ROIs (second-pass), or doing nothing. Coordinates sending
@@ -16,15 +30,122 @@ use backend::*;
 pub struct Scheduler {
     /// Channel for receiving from the pixhawk client
     channels: Arc<Channels>,
+    cmd: mpsc::Receiver<SchedulerCommand>,
     backend: SchedulerBackend,
+
+    /// How close the gimbal has to get to its target before capturing an ROI.
+    gimbal_tolerance_deg: f64,
+    gimbal_timeout: Duration,
+
+    /// Whether ROI captures are paused because the telemetry is stale.
+    paused_for_stale_telemetry: bool,
 }
 
 impl Scheduler {
-    pub fn new(channels: Arc<Channels>, gps: Coords2D) -> Self {
+    pub fn new(
+        channels: Arc<Channels>,
+        cmd: mpsc::Receiver<SchedulerCommand>,
+        config: SchedulerConfig,
+    ) -> Self {
         Self {
             channels,
-            backend: SchedulerBackend::new(gps),
+            cmd,
+            backend: SchedulerBackend::new(&config),
+            gimbal_tolerance_deg: config.gimbal_tolerance_deg,
+            gimbal_timeout: Duration::from_millis(config.gimbal_timeout_ms),
+            paused_for_stale_telemetry: false,
+        }
+    }
+
+    fn exec(backend: &mut SchedulerBackend, cmd: &SchedulerRequest) -> SchedulerResponse {
+        match cmd {
+            SchedulerRequest::AddROIs { rois, client_type } => {
+                let outcomes: Vec<_> = rois
+                    .iter()
+                    .map(|roi| backend.add_roi(*roi, *client_type))
+                    .collect();
+
+                let accepted = outcomes
+                    .iter()
+                    .filter(|outcome| match outcome {
+                        AddROIOutcome::Accepted | AddROIOutcome::Evicted { .. } => true,
+                        AddROIOutcome::Rejected | AddROIOutcome::Duplicate { .. } => false,
+                    })
+                    .count();
+
+                SchedulerResponse::AddROIs { accepted, outcomes }
+            }
+        }
+    }
+
+    /// Points the gimbal at an ROI and takes a picture of it.
+    async fn capture_roi(&mut self, roi: RegionOfInterest) -> anyhow::Result<()> {
+        info!("capturing {:?}", roi);
+
+        let (roll, pitch) = self.backend.target_gimbal_angles(roi.location());
+        let (cmd, chan) = Command::new(GimbalRequest::Control {
+            roll,
+            pitch,
+            tolerance: Some(self.gimbal_tolerance_deg),
+            timeout_ms: self.gimbal_timeout.as_millis() as u64,
+        });
+        self.channels.gimbal_cmd.clone().send(cmd).await?;
+
+        tokio::time::timeout(ROI_CAPTURE_TIMEOUT, chan)
+            .await
+            .context("gimbal did not respond")???;
+
+        let (cmd, chan) = Command::new(CameraRequest::Capture {
+            require_focus: true,
+            burst_duration: None,
+            burst_high_speed: false,
+        });
+        self.channels.camera_cmd.clone().send(cmd).await?;
+
+        tokio::time::timeout(ROI_CAPTURE_TIMEOUT, chan)
+            .await
+            .context("camera did not respond")???;
+
+        Ok(())
+    }
+
+    /// Passes telemetry on to the backend. Returns false if there is no
+    /// telemetry yet or it is stale, in which case nothing should be
+    /// captured; we don't want to point at ROIs from where the plane used to
+    /// be. Logs once when stale telemetry pauses captures and once when they
+    /// resume.
+    fn accept_telemetry(&mut self, telemetry: Option<TelemetryInfo>) -> bool {
+        let telemetry = match telemetry {
+            Some(telemetry) if telemetry.stale => {
+                if !self.paused_for_stale_telemetry {
+                    warn!("telemetry is stale, pausing ROI captures until it comes back");
+                    self.paused_for_stale_telemetry = true;
+                }
+
+                return false;
+            }
+            Some(telemetry) => telemetry,
+            None => {
+                debug!("telemetry is not available yet, waiting");
+                return false;
+            }
+        };
+
+        if self.paused_for_stale_telemetry {
+            info!("telemetry is back, resuming ROI captures");
+            self.paused_for_stale_telemetry = false;
         }
+
+        self.backend.update_telemetry(telemetry);
+        true
+    }
+
+    fn send_roi_counts(&self) {
+        let (remaining, completed) = self.backend.roi_counts();
+        let _ = self.channels.scheduler_event.send(SchedulerEvent::ROIs {
+            remaining,
+            completed,
+        });
     }
 
     pub async fn run(&mut self) -> anyhow::Result<()> {
@@ -37,13 +158,37 @@ impl Scheduler {
         let mut telemetry_recv = self.channels.telemetry.clone();
         let loop_fut = async move {
             loop {
-                telemetry_recv
-                    .changed()
-                    .await
-                    .context("telemetry channel closed")?;
+                // the telemetry is marked as stale if the pixhawk goes quiet,
+                // so only give up if the sender is gone
+                let telemetry_result = tokio::select! {
+                    telemetry_result = telemetry_recv.changed() => telemetry_result,
+                    Some(cmd) = self.cmd.recv() => {
+                        let response = Self::exec(&mut self.backend, cmd.request());
+                        let _ = cmd.success(response);
+                        self.send_roi_counts();
+                        continue;
+                    }
+                };
 
-                if let Some(telemetry) = telemetry_recv.borrow().as_ref() {
-                    self.backend.update_telemetry(telemetry.clone());
+                telemetry_result.context("telemetry channel closed")?;
+
+                let telemetry = telemetry_recv.borrow().clone();
+
+                if !self.accept_telemetry(telemetry) {
+                    continue;
+                }
+
+                if let Some(roi) = self.backend.next_roi() {
+                    match self.capture_roi(roi).await {
+                        Ok(()) => self.backend.complete_roi(roi.id()),
+                        Err(err) => {
+                            warn!("failed to capture {:?}: {:?}", roi, err);
+                            self.backend.fail_roi(roi.id());
+                        }
+                    }
+
+                    self.send_roi_counts();
+                    continue;
                 }
 
                 if let Some(capture_request) = self.backend.get_capture_request() {
@@ -51,7 +196,12 @@ impl Scheduler {
                 }
 
                 let (roll, pitch) = self.backend.get_target_gimbal_angles();
-                let request = GimbalRequest::Control { roll, pitch };
+                let request = GimbalRequest::Control {
+                    roll,
+                    pitch,
+                    tolerance: None,
+                    timeout_ms: 0,
+                };
                 let (cmd, _) = Command::new(request);
                 self.channels.gimbal_cmd.clone().send(cmd).await?;
             }
@@ -69,3 +219,78 @@ impl Scheduler {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cli::config::ROIEvictionPolicy,
+        state::{ClientType, Coords2D, Coords3D, RegionOfInterestKind},
+        TestChannels,
+    };
+
+    fn scheduler() -> Scheduler {
+        let (_, cmd) = mpsc::channel(1);
+
+        Scheduler::new(
+            TestChannels::new().channels,
+            cmd,
+            SchedulerConfig {
+                enabled: true,
+                gps: Coords2D::default(),
+                max_rois: 10,
+                eviction: ROIEvictionPolicy::Oldest,
+                capture_radius_m: 150.0,
+                max_roi_attempts: 2,
+                duplicate_radius_m: 5.0,
+                gimbal_tolerance_deg: 2.0,
+                gimbal_timeout_ms: 3000,
+            },
+        )
+    }
+
+    fn telemetry(latitude: f32, stale: bool) -> Option<TelemetryInfo> {
+        let mut telemetry = TelemetryInfo::default();
+        telemetry.position = Coords3D::new(latitude, 0.0, 100.0);
+        telemetry.stale = stale;
+        Some(telemetry)
+    }
+
+    #[test]
+    fn stale_telemetry_pauses_roi_captures_until_it_comes_back() {
+        let mut scheduler = scheduler();
+        let roi = RegionOfInterest::with_location_and_kind(
+            Coords2D::new(0.01, 0.0),
+            RegionOfInterestKind::Normal,
+        );
+        scheduler.backend.add_roi(roi, ClientType::MDLC);
+
+        // far away from the ROI
+        assert!(scheduler.accept_telemetry(telemetry(0.0, false)));
+        assert!(scheduler.backend.next_roi().is_none());
+
+        // over the ROI, but the pixhawk has gone quiet, so this position
+        // can't be trusted
+        assert!(!scheduler.accept_telemetry(telemetry(0.01, true)));
+        assert!(scheduler.paused_for_stale_telemetry);
+        assert!(scheduler.backend.next_roi().is_none());
+
+        assert!(!scheduler.accept_telemetry(telemetry(0.01, true)));
+        assert!(scheduler.paused_for_stale_telemetry);
+
+        assert!(scheduler.accept_telemetry(telemetry(0.01, false)));
+        assert!(!scheduler.paused_for_stale_telemetry);
+        assert_eq!(
+            scheduler.backend.next_roi().map(|roi| roi.id()),
+            Some(roi.id())
+        );
+    }
+
+    #[test]
+    fn missing_telemetry_is_not_stale() {
+        let mut scheduler = scheduler();
+
+        assert!(!scheduler.accept_telemetry(None));
+        assert!(!scheduler.paused_for_stale_telemetry);
+    }
+}
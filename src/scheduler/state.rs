@@ -38,3 +38,10 @@ impl CaptureRequest {
 pub struct CaptureResponse {
     request_id: usize,
 }
+
+#[derive(Debug, Clone)]
+pub enum SchedulerEvent {
+    /// The list of ROIs changed; `remaining` ROIs are still waiting to be
+    /// photographed and `completed` have been photographed so far.
+    ROIs { remaining: usize, completed: usize },
+}
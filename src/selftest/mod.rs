@@ -0,0 +1,193 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+use crate::{
+    camera::{CameraRequest, CameraResponse},
+    cli::config::SelfTestConfig,
+    gimbal::{GimbalRequest, GimbalResponse},
+    pixhawk::state::PixhawkEvent,
+    Channels, Command,
+};
+
+/// How long to wait for the camera or the gimbal to answer before counting the
+/// check as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Which devices the self-test should check; devices that are disabled in the
+/// config are skipped.
+#[derive(Debug, Copy, Clone)]
+pub struct SelfTestDevices {
+    pub pixhawk: bool,
+    pub camera: bool,
+    pub gimbal: bool,
+}
+
+/// Periodically checks that every device link is still alive, so that a link
+/// that dies while the plane is sitting on the ground gets noticed before
+/// launch.
+pub struct SelfTest {
+    channels: Arc<Channels>,
+    config: SelfTestConfig,
+    devices: SelfTestDevices,
+}
+
+impl SelfTest {
+    pub fn new(channels: Arc<Channels>, config: SelfTestConfig, devices: SelfTestDevices) -> Self {
+        Self {
+            channels,
+            config,
+            devices,
+        }
+    }
+
+    pub async fn run(&self) -> anyhow::Result<()> {
+        let interval = Duration::from_secs(self.config.interval_secs);
+
+        let mut interrupt_recv = self.channels.interrupt.subscribe();
+        let mut pixhawk_recv = self.channels.pixhawk_event.subscribe();
+
+        loop {
+            tokio::select! {
+                _ = interrupt_recv.recv() => break,
+                _ = tokio::time::sleep(interval) => {}
+            }
+
+            let mut healthy = true;
+
+            if self.devices.pixhawk {
+                healthy &= self.report("pixhawk", check_pixhawk(&mut pixhawk_recv));
+                healthy &= self.report("ground link", self.check_ground_link(interval));
+                healthy &= self.report("battery", self.check_battery());
+            }
+
+            if self.devices.camera {
+                healthy &= self.report("camera", self.check_camera().await);
+            }
+
+            if self.devices.gimbal {
+                healthy &= self.report("gimbal", self.check_gimbal().await);
+            }
+
+            if healthy {
+                info!("self-test passed");
+            } else {
+                warn!("self-test failed");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_ground_link(&self, interval: Duration) -> anyhow::Result<()> {
+        let last_ground_contact = self
+            .channels
+            .telemetry
+            .borrow()
+            .as_ref()
+            .and_then(|telemetry| telemetry.last_ground_contact)
+            .context("never heard from the ground station")?;
+
+        let elapsed = last_ground_contact.elapsed().unwrap_or_default();
+
+        if elapsed > interval {
+            bail!("last heard from the ground station {:?} ago", elapsed);
+        }
+
+        Ok(())
+    }
+
+    fn check_battery(&self) -> anyhow::Result<()> {
+        let battery = self
+            .channels
+            .telemetry
+            .borrow()
+            .as_ref()
+            .and_then(|telemetry| telemetry.battery)
+            .context("never heard the battery status from the pixhawk")?;
+
+        if let Some(remaining_percent) = battery.remaining_percent {
+            if remaining_percent < self.config.min_battery_percent {
+                bail!(
+                    "battery is low: {}% left ({:.2} V)",
+                    remaining_percent,
+                    battery.voltage
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads the camera's battery level, which makes the camera send us all
+    /// of its properties.
+    async fn check_camera(&self) -> anyhow::Result<()> {
+        let (cmd, chan) = Command::new(CameraRequest::Battery);
+        self.channels.camera_cmd.clone().send(cmd).await?;
+
+        match tokio::time::timeout(CHECK_TIMEOUT, chan)
+            .await
+            .context("camera did not respond")???
+        {
+            CameraResponse::Battery { level_percent, .. } => {
+                debug!("self-test: camera battery is at {}%", level_percent);
+                Ok(())
+            }
+            other => bail!("unexpected response from camera: {:?}", other),
+        }
+    }
+
+    /// Asks the gimbal for its firmware version.
+    async fn check_gimbal(&self) -> anyhow::Result<()> {
+        let (cmd, chan) = Command::new(GimbalRequest::Ping);
+        self.channels.gimbal_cmd.clone().send(cmd).await?;
+
+        match tokio::time::timeout(CHECK_TIMEOUT, chan)
+            .await
+            .context("gimbal did not respond")???
+        {
+            GimbalResponse::Version { firmware } => {
+                debug!("self-test: gimbal firmware is {}", firmware);
+                Ok(())
+            }
+            other => bail!("unexpected response from gimbal: {:?}", other),
+        }
+    }
+
+    /// Logs the result of a check and records it for the health check.
+    /// Returns whether the check passed.
+    fn report(&self, name: &'static str, result: anyhow::Result<()>) -> bool {
+        self.channels.health.checked(name, &result);
+
+        match result {
+            Ok(()) => {
+                debug!("self-test: {} ok", name);
+                true
+            }
+            Err(err) => {
+                warn!("self-test: {} failed: {:?}", name, err);
+                false
+            }
+        }
+    }
+}
+
+/// Checks that the pixhawk has sent us something since the last check.
+fn check_pixhawk(pixhawk_recv: &mut broadcast::Receiver<PixhawkEvent>) -> anyhow::Result<()> {
+    let mut received = false;
+
+    loop {
+        match pixhawk_recv.try_recv() {
+            Ok(_) | Err(TryRecvError::Lagged(_)) => received = true,
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Closed) => bail!("pixhawk stream closed"),
+        }
+    }
+
+    if !received {
+        bail!("no messages since the last check");
+    }
+
+    Ok(())
+}
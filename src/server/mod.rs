@@ -1,59 +1,491 @@
 use anyhow::Context;
+use futures::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::{convert::Infallible, net::SocketAddr, sync::Arc};
-use warp::{self, Filter};
+use warp::{self, http::StatusCode, Filter, Reply};
 
-use crate::state::RegionOfInterest;
-use crate::Channels;
+use tokio::sync::mpsc;
+
+use geo::{
+    algorithm::{
+        closest_point::ClosestPoint, contains::Contains, haversine_distance::HaversineDistance,
+    },
+    Closest, LineString, Point, Polygon,
+};
+
+use crate::camera::{CameraDownloadRequest, CameraErrorsRequest, CameraRequest};
+use crate::cli::config::BoundaryConfig;
+use crate::pixhawk::{Mission, PixhawkCameraTriggerRequest, PixhawkRequest};
+use crate::scheduler::SchedulerRequest;
+use crate::state::{ClientType, RegionOfInterest};
+use crate::{Channels, Command};
 
 #[derive(Clone)]
 struct ServerState {}
 
-enum ServerMessage {
-    AddROIs(Vec<RegionOfInterest>),
-}
-
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct AddROIs {
     pub rois: Vec<RegionOfInterest>,
     pub client_type: ClientType,
 }
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
-#[serde(rename_all = "lowercase")]
-enum ClientType {
-    MDLC,
-    ADLC,
+#[derive(Serialize, Debug, Clone)]
+struct ErrorResponse {
+    error: String,
 }
 
-pub async fn serve(channels: Arc<Channels>, address: SocketAddr) -> anyhow::Result<()> {
-    use tokio_compat_02::FutureExt;
+fn with_channels(
+    channels: Arc<Channels>,
+) -> impl Filter<Extract = (Arc<Channels>,), Error = Infallible> + Clone {
+    warp::any().map(move || channels.clone())
+}
 
-    info!("initializing server");
+#[derive(Debug)]
+struct Unauthorized;
+
+impl warp::reject::Reject for Unauthorized {}
+
+/// Rejects requests that don't have the right `Authorization: Bearer` header.
+/// If no token is configured, every request is allowed.
+fn with_auth(
+    auth_token: Option<String>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    let expected = auth_token.map(|token| format!("Bearer {}", token));
+
+    warp::header::optional::<String>("authorization")
+        .and_then(move |header: Option<String>| {
+            let authorized = match &expected {
+                Some(expected) => header.as_ref() == Some(expected),
+                None => true,
+            };
+
+            async move {
+                if authorized {
+                    Ok(())
+                } else {
+                    Err(warp::reject::custom(Unauthorized))
+                }
+            }
+        })
+        .untuple_one()
+}
+
+async fn handle_rejection(
+    rejection: warp::Rejection,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, warp::Rejection> {
+    if rejection.find::<Unauthorized>().is_some() {
+        return Ok(warp::reply::with_status(
+            warp::reply::json(&ErrorResponse {
+                error: "missing or invalid auth token".to_owned(),
+            }),
+            StatusCode::UNAUTHORIZED,
+        ));
+    }
+
+    Err(rejection)
+}
+
+/// Sends a request to a task and replies with its response. Replies with 503
+/// if the task is not running.
+async fn task_request<Req, Res: Serialize>(
+    sender: mpsc::Sender<Command<Req, Res>>,
+    name: &str,
+    request: Req,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible> {
+    let (cmd, chan) = Command::new(request);
+
+    let unavailable = warp::reply::with_status(
+        warp::reply::json(&ErrorResponse {
+            error: format!("{} is not available", name),
+        }),
+        StatusCode::SERVICE_UNAVAILABLE,
+    );
+
+    if sender.send(cmd).await.is_err() {
+        return Ok(unavailable);
+    }
+
+    let reply = match chan.await {
+        Ok(Ok(response)) => warp::reply::with_status(warp::reply::json(&response), StatusCode::OK),
+        Ok(Err(err)) => warp::reply::with_status(
+            warp::reply::json(&ErrorResponse {
+                error: format!("{:?}", err),
+            }),
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ),
+        Err(_) => unavailable,
+    };
+
+    Ok(reply)
+}
+
+async fn camera_request(
+    channels: Arc<Channels>,
+    request: CameraRequest,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible> {
+    task_request(channels.camera_cmd.clone(), "camera", request).await
+}
+
+async fn pixhawk_request(
+    channels: Arc<Channels>,
+    request: PixhawkRequest,
+) -> Result<warp::reply::WithStatus<warp::reply::Json>, Infallible> {
+    task_request(channels.pixhawk_cmd.clone(), "pixhawk", request).await
+}
+
+/// Sends telemetry to a websocket client every time it changes, until the
+/// client goes away or the system shuts down.
+async fn stream_telemetry(socket: warp::ws::WebSocket, channels: Arc<Channels>) {
+    let (mut tx, mut rx) = socket.split();
+    let mut telemetry_recv = channels.telemetry.clone();
+    let mut interrupt_recv = channels.interrupt.subscribe();
+
+    debug!("telemetry websocket connected");
+
+    loop {
+        tokio::select! {
+            result = telemetry_recv.changed() => {
+                if result.is_err() {
+                    break;
+                }
+
+                // only the latest value is sent, so a slow client skips
+                // updates instead of falling further and further behind
+                let telemetry = telemetry_recv.borrow().clone();
+
+                if let Some(telemetry) = telemetry {
+                    let json = match serde_json::to_string(&telemetry) {
+                        Ok(json) => json,
+                        Err(err) => {
+                            warn!("failed to serialize telemetry: {:?}", err);
+                            continue;
+                        }
+                    };
+
+                    if tx.send(warp::ws::Message::text(json)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            message = rx.next() => match message {
+                // we don't expect the client to say anything, but it is
+                // still allowed to ping us
+                Some(Ok(message)) if !message.is_close() => {}
+                _ => break,
+            },
+            _ = interrupt_recv.recv() => break,
+        }
+    }
+
+    let _ = tx.close().await;
+
+    debug!("telemetry websocket disconnected");
+}
+
+/// Body of a request to change the camera trigger parameters; parameters that
+/// are left out are not changed.
+#[derive(Deserialize, Debug, Clone)]
+struct SetCameraTrigger {
+    duration: Option<f32>,
+    feedback_pin: Option<i8>,
+    feedback_polarity: Option<u8>,
+}
+
+/// Body of a request to capture an image; all fields are optional, and so is
+/// the body itself.
+#[derive(Deserialize, Debug, Clone)]
+struct Capture {
+    #[serde(default = "default_capture_require_focus")]
+    require_focus: bool,
+    #[serde(default)]
+    burst_duration: Option<f32>,
+    #[serde(default)]
+    burst_high_speed: bool,
+}
+
+fn default_capture_require_focus() -> bool {
+    true
+}
+
+impl Capture {
+    fn parse(body: impl AsRef<[u8]>) -> serde_json::Result<Self> {
+        let body = body.as_ref();
+
+        if body.is_empty() {
+            return Ok(Capture {
+                require_focus: default_capture_require_focus(),
+                burst_duration: None,
+                burst_high_speed: false,
+            });
+        }
+
+        serde_json::from_slice(body)
+    }
+}
+
+/// Checks that an ROI is somewhere that we could actually photograph.
+fn validate_roi(roi: &RegionOfInterest, boundary: Option<&BoundaryConfig>) -> Result<(), String> {
+    let location = roi.location();
+
+    if !(-90.0..=90.0).contains(&location.latitude) {
+        return Err(format!("latitude {} is out of range", location.latitude));
+    }
+
+    if !(-180.0..=180.0).contains(&location.longitude) {
+        return Err(format!("longitude {} is out of range", location.longitude));
+    }
+
+    if let Some(boundary) = boundary {
+        let point = Point::new(location.longitude as f64, location.latitude as f64);
+
+        let polygon = Polygon::new(
+            LineString::from(
+                boundary
+                    .points
+                    .iter()
+                    .map(|p| (p.longitude as f64, p.latitude as f64))
+                    .collect::<Vec<_>>(),
+            ),
+            vec![],
+        );
+
+        if polygon.contains(&point) {
+            return Ok(());
+        }
 
+        let distance = match polygon.exterior().closest_point(&point) {
+            Closest::Intersection(closest) | Closest::SinglePoint(closest) => {
+                point.haversine_distance(&closest)
+            }
+            Closest::Indeterminate => f64::INFINITY,
+        };
+
+        if distance > boundary.margin_m {
+            return Err(format!(
+                "({}, {}) is {:.0} m outside of the flight boundary",
+                location.latitude, location.longitude, distance
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// All of the API routes, behind the auth check.
+fn api(
+    channels: Arc<Channels>,
+    boundary: Option<BoundaryConfig>,
+    auth_token: Option<String>,
+) -> impl Filter<Extract = impl Reply, Error = Infallible> + Clone {
     let telemetry_receiver = Arc::new(channels.telemetry.clone());
 
     let route_roi = warp::path!("api" / "roi")
         .and(warp::post())
         .and(warp::body::json())
-        .map(move |body: AddROIs| {
+        .and(with_channels(channels.clone()))
+        .and_then(move |body: AddROIs, channels: Arc<Channels>| {
             debug!("received ROIs: {:?}", &body);
-            warp::reply()
+
+            let invalid =
+                body.rois.iter().enumerate().find_map(|(i, roi)| {
+                    validate_roi(roi, boundary.as_ref()).err().map(|e| (i, e))
+                });
+
+            async move {
+                if let Some((i, error)) = invalid {
+                    warn!("rejecting ROIs: ROI {} is invalid: {}", i, error);
+
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&ErrorResponse {
+                            error: format!("ROI {} is invalid: {}", i, error),
+                        }),
+                        StatusCode::BAD_REQUEST,
+                    ));
+                }
+
+                task_request(
+                    channels.scheduler_cmd.clone(),
+                    "scheduler",
+                    SchedulerRequest::AddROIs {
+                        rois: body.rois,
+                        client_type: body.client_type,
+                    },
+                )
+                .await
+            }
         });
 
     let route_telem = warp::path!("api" / "telemetry").and(warp::get()).and_then({
         move || {
             let telemetry = telemetry_receiver.clone().borrow().clone();
-            async move { Result::<_, Infallible>::Ok(warp::reply::json(&telemetry)) }
+            async move {
+                // there is nothing to send until the pixhawk has told us
+                // something
+                let reply = match telemetry {
+                    Some(telemetry) => warp::reply::json(&telemetry).into_response(),
+                    None => StatusCode::NO_CONTENT.into_response(),
+                };
+
+                Result::<_, Infallible>::Ok(reply)
+            }
         }
     });
 
-    let api = route_roi.or(route_telem);
+    let route_health = warp::path!("api" / "health")
+        .and(warp::get())
+        .and(with_channels(channels.clone()))
+        .map(|channels: Arc<Channels>| {
+            let report = channels.health.report();
 
-    info!("initialized server");
+            let status = if report.healthy {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
 
-    async {
-        let (_, server) = warp::serve(api).bind_with_graceful_shutdown(address, async move {
+            warp::reply::with_status(warp::reply::json(&report), status)
+        });
+
+    let route_telem_ws = warp::path!("api" / "telemetry" / "ws")
+        .and(warp::ws())
+        .and(with_channels(channels.clone()))
+        .map(|ws: warp::ws::Ws, channels: Arc<Channels>| {
+            ws.on_upgrade(move |socket| stream_telemetry(socket, channels))
+        });
+
+    let route_download_pause = warp::path!("api" / "camera" / "download" / "pause")
+        .and(warp::post())
+        .and(with_channels(channels.clone()))
+        .and_then(|channels| {
+            camera_request(
+                channels,
+                CameraRequest::Download(CameraDownloadRequest::Pause),
+            )
+        });
+
+    let route_download_resume = warp::path!("api" / "camera" / "download" / "resume")
+        .and(warp::post())
+        .and(with_channels(channels.clone()))
+        .and_then(|channels| {
+            camera_request(
+                channels,
+                CameraRequest::Download(CameraDownloadRequest::Resume),
+            )
+        });
+
+    let route_camera_flush = warp::path!("api" / "camera" / "flush")
+        .and(warp::post())
+        .and(with_channels(channels.clone()))
+        .and_then(|channels| camera_request(channels, CameraRequest::Flush));
+
+    let route_camera_errors = warp::path!("api" / "camera" / "errors")
+        .and(warp::get())
+        .and(with_channels(channels.clone()))
+        .and_then(|channels| {
+            camera_request(channels, CameraRequest::Errors(CameraErrorsRequest::Get))
+        });
+
+    let route_camera_capture = warp::path!("api" / "camera" / "capture")
+        .and(warp::post())
+        .and(with_channels(channels.clone()))
+        .and(warp::body::bytes())
+        .and_then(|channels, body| async move {
+            let body = match Capture::parse(body) {
+                Ok(body) => body,
+                Err(err) => {
+                    return Ok(warp::reply::with_status(
+                        warp::reply::json(&ErrorResponse {
+                            error: format!("invalid capture request: {}", err),
+                        }),
+                        StatusCode::BAD_REQUEST,
+                    ))
+                }
+            };
+
+            camera_request(
+                channels,
+                CameraRequest::Capture {
+                    require_focus: body.require_focus,
+                    burst_duration: body.burst_duration,
+                    burst_high_speed: body.burst_high_speed,
+                },
+            )
+            .await
+        });
+
+    let route_camera_trigger_get = warp::path!("api" / "pixhawk" / "camera-trigger")
+        .and(warp::get())
+        .and(with_channels(channels.clone()))
+        .and_then(|channels| {
+            pixhawk_request(
+                channels,
+                PixhawkRequest::CameraTrigger(PixhawkCameraTriggerRequest::Get),
+            )
+        });
+
+    let route_camera_trigger_set = warp::path!("api" / "pixhawk" / "camera-trigger")
+        .and(warp::post())
+        .and(with_channels(channels.clone()))
+        .and(warp::body::json())
+        .and_then(|channels, body: SetCameraTrigger| {
+            pixhawk_request(
+                channels,
+                PixhawkRequest::CameraTrigger(PixhawkCameraTriggerRequest::Set {
+                    duration: body.duration,
+                    feedback_pin: body.feedback_pin,
+                    feedback_polarity: body.feedback_polarity,
+                }),
+            )
+        });
+
+    let route_mission_upload = warp::path!("api" / "pixhawk" / "mission")
+        .and(warp::post())
+        .and(with_channels(channels.clone()))
+        .and(warp::body::json())
+        .and_then(|channels, mission: Mission| {
+            pixhawk_request(channels, PixhawkRequest::UploadMission { mission })
+        });
+
+    // allow dashboards running in a browser to call the API
+    let cors = warp::cors()
+        .allow_any_origin()
+        .allow_methods(vec!["GET", "POST"])
+        .allow_headers(vec!["authorization", "content-type"]);
+
+    let routes = route_roi
+        .or(route_telem)
+        .or(route_telem_ws)
+        .or(route_health)
+        .or(route_download_pause)
+        .or(route_download_resume)
+        .or(route_camera_errors)
+        .or(route_camera_flush)
+        .or(route_camera_capture)
+        .or(route_camera_trigger_get)
+        .or(route_camera_trigger_set)
+        .or(route_mission_upload);
+
+    with_auth(auth_token)
+        .and(routes)
+        .recover(handle_rejection)
+        .with(cors)
+}
+
+/// Starts listening on `address`. Returns the address that was actually
+/// bound, which only differs if the port is 0, and the server, which runs
+/// until the system is interrupted.
+///
+/// This has to be called from within the tokio 0.2 compatibility layer.
+fn bind(
+    channels: Arc<Channels>,
+    address: SocketAddr,
+    boundary: Option<BoundaryConfig>,
+    auth_token: Option<String>,
+) -> anyhow::Result<(SocketAddr, impl std::future::Future<Output = ()>)> {
+    let api = api(channels.clone(), boundary, auth_token);
+
+    warp::serve(api)
+        .try_bind_with_graceful_shutdown(address, async move {
             channels
                 .interrupt
                 .subscribe()
@@ -62,14 +494,497 @@ pub async fn serve(channels: Arc<Channels>, address: SocketAddr) -> anyhow::Resu
                 .expect("error while waiting on interrupt channel");
 
             debug!("server recv interrupt");
-        });
+        })
+        .with_context(|| format!("failed to listen at {:?}", address))
+}
+
+pub async fn serve(
+    channels: Arc<Channels>,
+    address: SocketAddr,
+    boundary: Option<BoundaryConfig>,
+    auth_token: Option<String>,
+) -> anyhow::Result<()> {
+    use tokio_compat_02::FutureExt;
+
+    info!("initializing server");
+
+    if auth_token.is_none() {
+        warn!("no auth token configured, the server will accept any request");
+    }
+
+    async {
+        let (address, server) = bind(channels, address, boundary, auth_token)?;
 
         info!("listening at {:?}", address);
 
         server.await;
+
+        Ok(())
     }
     .compat()
-    .await;
+    .await
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        scheduler::{state::SchedulerEvent, Scheduler},
+        state::{Coords2D, RegionOfInterestKind, TelemetryInfo},
+        TestChannels,
+    };
+
+    fn roi(latitude: f32, longitude: f32) -> RegionOfInterest {
+        RegionOfInterest::with_location_and_kind(
+            Coords2D::new(latitude, longitude),
+            RegionOfInterestKind::Normal,
+        )
+    }
+
+    async fn post_rois(
+        channels: Arc<Channels>,
+        boundary: Option<BoundaryConfig>,
+        rois: &[RegionOfInterest],
+    ) -> (StatusCode, serde_json::Value) {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/roi")
+            .json(&serde_json::json!({ "rois": rois, "client_type": "mdlc" }))
+            .reply(&api(channels, boundary, None))
+            .await;
+
+        (res.status(), serde_json::from_slice(res.body()).unwrap())
+    }
+
+    async fn get_health(channels: Arc<Channels>) -> (StatusCode, serde_json::Value) {
+        let res = warp::test::request()
+            .method("GET")
+            .path("/api/health")
+            .reply(&api(channels, None, None))
+            .await;
+
+        (res.status(), serde_json::from_slice(res.body()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn health_is_unavailable_once_a_critical_task_fails() {
+        let channels = TestChannels::new().channels;
+        channels.health.started("camera");
+        channels.health.started("server");
+
+        let (status, _) = get_health(channels.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        channels
+            .health
+            .failed("camera", "camera disconnected".to_owned());
+
+        let (status, body) = get_health(channels.clone()).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["healthy"], false);
+        assert_eq!(body["tasks"]["camera"]["state"], "failed");
+        assert_eq!(body["tasks"]["camera"]["last_error"], "camera disconnected");
+        assert_eq!(body["tasks"]["server"]["state"], "running");
+    }
+
+    #[tokio::test]
+    async fn health_ignores_non_critical_tasks() {
+        let channels = TestChannels::new().channels;
+        channels.health.started("camera");
+        channels
+            .health
+            .failed("startup", "command failed".to_owned());
+
+        let (status, body) = get_health(channels).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["tasks"]["startup"]["critical"], false);
+    }
+
+    #[tokio::test]
+    async fn rois_reach_the_scheduler() {
+        let test_channels = TestChannels::new();
+        let channels = test_channels.channels.clone();
+        let mut events = channels.scheduler_event.subscribe();
+
+        let config = serde_json::from_value(serde_json::json!({
+            "enabled": true,
+            "gps": { "latitude": 0.0, "longitude": 0.0 }
+        }))
+        .unwrap();
+        let mut scheduler = Scheduler::new(channels.clone(), test_channels.scheduler_cmd, config);
+        tokio::spawn(async move { scheduler.run().await });
+
+        let (status, body) = post_rois(
+            channels.clone(),
+            None,
+            &[roi(42.44, -76.48), roi(42.45, -76.48)],
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["AddROIs"]["accepted"], 2);
+        assert_eq!(
+            body["AddROIs"]["outcomes"],
+            serde_json::json!(["accepted", "accepted"])
+        );
+        assert!(matches!(
+            events.recv().await.unwrap(),
+            SchedulerEvent::ROIs {
+                remaining: 2,
+                completed: 0
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn invalid_rois_never_reach_the_scheduler() {
+        let mut test_channels = TestChannels::new();
+
+        let (status, body) = post_rois(
+            test_channels.channels.clone(),
+            None,
+            &[roi(42.44, -76.48), roi(91.0, -76.48)],
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["error"],
+            "ROI 1 is invalid: latitude 91 is out of range"
+        );
+        assert!(test_channels.scheduler_cmd.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn rois_are_unavailable_without_a_scheduler() {
+        let (status, body) =
+            post_rois(TestChannels::new().channels, None, &[roi(42.44, -76.48)]).await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error"], "scheduler is not available");
+    }
+
+    async fn get_telemetry(channels: Arc<Channels>) -> (StatusCode, Vec<u8>) {
+        let res = warp::test::request()
+            .method("GET")
+            .path("/api/telemetry")
+            .reply(&api(channels, None, None))
+            .await;
+
+        (res.status(), res.body().to_vec())
+    }
+
+    #[tokio::test]
+    async fn telemetry_is_empty_until_the_first_update() {
+        let (status, body) = get_telemetry(TestChannels::new().channels).await;
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(body.is_empty());
+    }
+
+    #[tokio::test]
+    async fn telemetry_is_the_latest_update_as_json() {
+        let test_channels = TestChannels::new();
+
+        let mut telemetry = TelemetryInfo::default();
+        telemetry.position.latitude = 42.44;
+        telemetry.position.altitude = 100.0;
+        telemetry.stale = true;
+        test_channels.telemetry.send(Some(telemetry)).unwrap();
+
+        let (status, body) = get_telemetry(test_channels.channels.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["position"]["altitude"], 100.0);
+        assert_eq!(body["stale"], true);
+        assert!(body.get("last_pixhawk_update").is_none());
+    }
+
+    #[tokio::test]
+    async fn telemetry_websocket_streams_changes_until_the_client_leaves() {
+        use tokio_compat_02::FutureExt;
+
+        async {
+            let test_channels = TestChannels::new();
+            let channels = test_channels.channels.clone();
+
+            let mut client = warp::test::ws()
+                .path("/api/telemetry/ws")
+                .handshake(api(channels.clone(), None, None))
+                .await
+                .expect("handshake");
+
+            for altitude in &[100.0, 120.0] {
+                let mut telemetry = TelemetryInfo::default();
+                telemetry.position.altitude = *altitude;
+                test_channels.telemetry.send(Some(telemetry)).unwrap();
+
+                let message = client.recv().await.unwrap();
+                let json: serde_json::Value =
+                    serde_json::from_str(message.to_str().unwrap()).unwrap();
+                assert_eq!(json["position"]["altitude"], *altitude);
+            }
+
+            // nothing is sent while the telemetry stays the same
+            let quiet =
+                tokio::time::timeout(std::time::Duration::from_millis(50), client.recv()).await;
+            assert!(quiet.is_err());
+
+            // the socket stops listening for interrupts once the client leaves
+            assert_eq!(channels.interrupt.receiver_count(), 1);
+            drop(client);
+
+            tokio::time::timeout(std::time::Duration::from_secs(1), async {
+                while channels.interrupt.receiver_count() > 0 {
+                    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                }
+            })
+            .await
+            .expect("websocket was not closed");
+        }
+        .compat()
+        .await
+    }
+
+    async fn post_capture(channels: Arc<Channels>, body: &str) -> (StatusCode, serde_json::Value) {
+        let res = warp::test::request()
+            .method("POST")
+            .path("/api/camera/capture")
+            .body(body)
+            .reply(&api(channels, None, None))
+            .await;
+
+        (res.status(), serde_json::from_slice(res.body()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn capture_is_passed_to_the_camera() {
+        use crate::camera::{CameraResponse, TimeSource};
+
+        let test_channels = TestChannels::new();
+        let mut camera_cmd = test_channels.camera_cmd;
+
+        // stands in for the camera task and answers a single capture
+        let camera = tokio::spawn(async move {
+            let cmd = camera_cmd.recv().await.unwrap();
+
+            let (require_focus, burst_duration) = match cmd.request() {
+                CameraRequest::Capture {
+                    require_focus,
+                    burst_duration,
+                    ..
+                } => (*require_focus, *burst_duration),
+                _ => panic!("expected a capture request"),
+            };
+
+            let _ = cmd.success(CameraResponse::TimedCapture {
+                fire_time: std::time::SystemTime::UNIX_EPOCH,
+                latency_ms: 120,
+                time_source: TimeSource::Gps,
+                path: None,
+            });
+
+            (require_focus, burst_duration)
+        });
+
+        let (status, body) = post_capture(
+            test_channels.channels.clone(),
+            r#"{ "require_focus": false, "burst_duration": 1.5 }"#,
+        )
+        .await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["TimedCapture"]["latency_ms"], 120);
+        assert_eq!(body["TimedCapture"]["time_source"], "gps");
+        assert_eq!(camera.await.unwrap(), (false, Some(1.5)));
+    }
+
+    #[tokio::test]
+    async fn capture_is_unavailable_without_a_camera() {
+        let (status, body) = post_capture(TestChannels::new().channels, "").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["error"], "camera is not available");
+    }
+
+    #[tokio::test]
+    async fn capture_rejects_a_malformed_body() {
+        let mut test_channels = TestChannels::new();
+
+        let (status, body) =
+            post_capture(test_channels.channels.clone(), r#"{ "require_focus": 1 }"#).await;
+
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid capture request"));
+        assert!(test_channels.camera_cmd.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn serves_on_an_ephemeral_port_from_the_config() {
+        use crate::cli::config::ServerConfig;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio_compat_02::FutureExt;
+
+        let config: ServerConfig =
+            serde_json::from_value(serde_json::json!({ "address": "127.0.0.1:0" })).unwrap();
+        let channels = TestChannels::new().channels;
+
+        let (address, server) = async {
+            bind(
+                channels.clone(),
+                config.socket_address().unwrap(),
+                None,
+                None,
+            )
+        }
+        .compat()
+        .await
+        .unwrap();
+        assert_ne!(address.port(), 0);
+
+        let server = tokio::spawn(server.compat());
+
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream
+            .write_all(b"GET /api/health HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+        channels.interrupt.send(()).unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn binding_a_port_in_use_is_an_error() {
+        use tokio_compat_02::FutureExt;
+
+        let channels = TestChannels::new().channels;
+
+        async {
+            let (address, _server) =
+                bind(channels.clone(), "127.0.0.1:0".parse().unwrap(), None, None).unwrap();
+
+            let err = bind(channels.clone(), address, None, None)
+                .err()
+                .expect("bound the same port twice");
+            assert_eq!(
+                err.to_string(),
+                format!("failed to listen at {:?}", address)
+            );
+        }
+        .compat()
+        .await
+    }
+
+    async fn get_health_with_auth(
+        auth_token: Option<&str>,
+        header: Option<&str>,
+    ) -> (StatusCode, serde_json::Value) {
+        let mut req = warp::test::request().method("GET").path("/api/health");
+
+        if let Some(header) = header {
+            req = req.header("authorization", header);
+        }
+
+        let res = req
+            .reply(&api(
+                TestChannels::new().channels,
+                None,
+                auth_token.map(str::to_owned),
+            ))
+            .await;
+
+        (res.status(), serde_json::from_slice(res.body()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn requests_with_the_token_are_accepted() {
+        let (status, _) = get_health_with_auth(Some("hunter2"), Some("Bearer hunter2")).await;
+
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn requests_without_the_token_are_unauthorized() {
+        for header in &[None, Some("Bearer hunter3"), Some("hunter2")] {
+            let (status, body) = get_health_with_auth(Some("hunter2"), *header).await;
+
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{:?}", header);
+            assert_eq!(body["error"], "missing or invalid auth token");
+        }
+    }
+
+    #[tokio::test]
+    async fn every_request_is_accepted_without_a_token() {
+        for header in &[None, Some("Bearer anything")] {
+            let (status, _) = get_health_with_auth(None, *header).await;
+
+            assert_eq!(status, StatusCode::OK, "{:?}", header);
+        }
+    }
+
+    fn square_boundary() -> BoundaryConfig {
+        BoundaryConfig {
+            points: vec![
+                Coords2D::new(42.44, -76.50),
+                Coords2D::new(42.46, -76.50),
+                Coords2D::new(42.46, -76.48),
+                Coords2D::new(42.44, -76.48),
+            ],
+            margin_m: 100.0,
+        }
+    }
+
+    #[test]
+    fn rois_must_be_on_the_globe() {
+        assert!(validate_roi(&roi(90.0, 180.0), None).is_ok());
+        assert!(validate_roi(&roi(-90.0, -180.0), None).is_ok());
+
+        assert_eq!(
+            validate_roi(&roi(-90.5, 0.0), None),
+            Err("latitude -90.5 is out of range".to_owned())
+        );
+        assert_eq!(
+            validate_roi(&roi(0.0, 180.5), None),
+            Err("longitude 180.5 is out of range".to_owned())
+        );
+        assert_eq!(
+            validate_roi(&roi(f32::NAN, 0.0), None),
+            Err("latitude NaN is out of range".to_owned())
+        );
+    }
+
+    #[test]
+    fn rois_can_be_anywhere_without_a_boundary() {
+        assert!(validate_roi(&roi(-33.86, 151.21), None).is_ok());
+    }
+
+    #[test]
+    fn rois_must_be_within_the_margin_of_the_boundary() {
+        let boundary = square_boundary();
+
+        // inside, and about 55 m north of the boundary
+        assert!(validate_roi(&roi(42.45, -76.49), Some(&boundary)).is_ok());
+        assert!(validate_roi(&roi(42.4605, -76.49), Some(&boundary)).is_ok());
+
+        // about 220 m north of the boundary
+        let err = validate_roi(&roi(42.462, -76.49), Some(&boundary)).unwrap_err();
+        assert!(err.ends_with("m outside of the flight boundary"), "{}", err);
+
+        let distance: f64 = err
+            .trim_end_matches(" m outside of the flight boundary")
+            .rsplit(' ')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((200.0..240.0).contains(&distance), "{}", err);
+    }
 }
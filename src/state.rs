@@ -1,6 +1,7 @@
 use std::{
     path::PathBuf,
     sync::atomic::{AtomicUsize, Ordering},
+    time::SystemTime,
 };
 
 use serde::{Deserialize, Serialize};
@@ -36,6 +37,18 @@ impl RegionOfInterest {
             kind,
         }
     }
+
+    pub fn id(&self) -> RegionOfInterestId {
+        self.id
+    }
+
+    pub fn location(&self) -> Coords2D {
+        self.location
+    }
+
+    pub fn kind(&self) -> RegionOfInterestKind {
+        self.kind
+    }
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -46,6 +59,39 @@ pub enum RegionOfInterestKind {
     EmergentTarget,
 }
 
+impl RegionOfInterestKind {
+    /// How important it is to photograph this kind of ROI; higher is more
+    /// important.
+    pub fn priority(&self) -> u8 {
+        match self {
+            RegionOfInterestKind::Normal => 0,
+            RegionOfInterestKind::OffAxis => 1,
+            RegionOfInterestKind::EmergentTarget => 2,
+        }
+    }
+}
+
+/// Who found an ROI: a person looking at the images (MDLC) or the automatic
+/// detection software (ADLC).
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientType {
+    MDLC,
+    ADLC,
+}
+
+impl ClientType {
+    /// Breaks ties between ROIs of the same kind; higher is more important.
+    /// ADLC finds more false positives than a person does, so its ROIs are
+    /// less important.
+    pub fn priority(&self) -> u8 {
+        match self {
+            ClientType::MDLC => 1,
+            ClientType::ADLC => 0,
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Mode {
     Idle,
@@ -125,8 +171,135 @@ impl Attitude {
 #[derive(Default, Debug, Clone, Copy, Serialize)]
 pub struct TelemetryInfo {
     pub plane_attitude: Attitude,
-    pub gimbal_attitude: Attitude,
+
+    /// The angles that the gimbal is measured to be pointing at, relative to
+    /// the plane; `None` if the gimbal isn't answering.
+    pub gimbal_attitude: Option<Attitude>,
+
+    /// Which way the camera is pointing, combining the plane's attitude with
+    /// the gimbal's; `None` if we haven't heard from the gimbal.
+    pub camera_direction: Option<LookDirection>,
+
     pub position: Coords3D,
+
+    /// The last time that we received a heartbeat from a ground control
+    /// station.
+    #[serde(with = "serde_millis")]
+    pub last_ground_contact: Option<SystemTime>,
+
+    /// The most recent UTC time reported by the autopilot's GPS.
+    pub gps_time: Option<GpsTime>,
+
+    /// The most recent battery reading from the autopilot.
+    pub battery: Option<Battery>,
+
+    /// The last time that the pixhawk sent a position or attitude. This is
+    /// on the runtime's clock, since it is only used to work out `stale`.
+    #[serde(skip)]
+    pub last_pixhawk_update: Option<tokio::time::Instant>,
+
+    /// Whether the pixhawk has gone quiet, so that the position and attitude
+    /// are out of date.
+    pub stale: bool,
+}
+
+/// A unit vector in the north-east-down frame.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct LookDirection {
+    pub north: f32,
+    pub east: f32,
+    pub down: f32,
+}
+
+impl LookDirection {
+    /// Works out which way a camera on the gimbal is pointing. The gimbal's
+    /// angles follow the scheduler's convention: at (0, 0) the camera points
+    /// straight down out of the plane, positive roll tilts it to the left and
+    /// positive pitch tilts it backwards. The gimbal's yaw is ignored.
+    pub fn from_attitudes(plane: &Attitude, gimbal: &Attitude) -> Self {
+        let (gimbal_roll, gimbal_pitch) = (
+            (gimbal.roll as f64).to_radians(),
+            (gimbal.pitch as f64).to_radians(),
+        );
+
+        // the camera's direction in the plane's frame (forward, right, down)
+        let forward = -gimbal_pitch.sin();
+        let right = -gimbal_roll.sin() * gimbal_pitch.cos();
+        let down = gimbal_roll.cos() * gimbal_pitch.cos();
+
+        let (sin_roll, cos_roll) = (plane.roll as f64).to_radians().sin_cos();
+        let (sin_pitch, cos_pitch) = (plane.pitch as f64).to_radians().sin_cos();
+        let (sin_yaw, cos_yaw) = (plane.yaw as f64).to_radians().sin_cos();
+
+        // rotate into the world frame by roll, then pitch, then yaw
+        let (forward, right, down) = (
+            forward,
+            cos_roll * right - sin_roll * down,
+            sin_roll * right + cos_roll * down,
+        );
+
+        let (forward, right, down) = (
+            cos_pitch * forward + sin_pitch * down,
+            right,
+            -sin_pitch * forward + cos_pitch * down,
+        );
+
+        LookDirection {
+            north: (cos_yaw * forward - sin_yaw * right) as f32,
+            east: (sin_yaw * forward + cos_yaw * right) as f32,
+            down: down as f32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Battery {
+    /// Volts.
+    pub voltage: f32,
+
+    /// Amps, or `None` if the autopilot doesn't measure it.
+    pub current: Option<f32>,
+
+    /// Percent, or `None` if the autopilot doesn't know.
+    pub remaining_percent: Option<u8>,
+}
+
+/// A UTC time reported by the autopilot, along with when we received it, so
+/// that times from the host clock can be converted to GPS time.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct GpsTime {
+    #[serde(with = "serde_millis")]
+    pub utc: SystemTime,
+
+    #[serde(with = "serde_millis")]
+    pub received: SystemTime,
+}
+
+impl GpsTime {
+    /// Converts a time from the host clock to GPS time.
+    pub fn convert(&self, host_time: SystemTime) -> SystemTime {
+        match host_time.duration_since(self.received) {
+            Ok(after) => self.utc + after,
+            Err(err) => self.utc - err.duration(),
+        }
+    }
+
+    /// How far ahead of the host clock the GPS clock is, in seconds. This is
+    /// negative if the host clock is ahead.
+    pub fn offset_secs(&self) -> f64 {
+        match self.utc.duration_since(self.received) {
+            Ok(ahead) => ahead.as_secs_f64(),
+            Err(err) => -err.duration().as_secs_f64(),
+        }
+    }
+
+    /// Converts a GPS time to the host clock.
+    pub fn to_host(&self, gps_time: SystemTime) -> SystemTime {
+        match gps_time.duration_since(self.utc) {
+            Ok(after) => self.received + after,
+            Err(err) => self.received - err.duration(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -135,3 +308,122 @@ pub struct Image {
     mode: Mode,
     geotag: Coords2D,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn assert_direction(actual: LookDirection, north: f32, east: f32, down: f32) {
+        let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+
+        assert!(
+            close(actual.north, north) && close(actual.east, east) && close(actual.down, down),
+            "expected ({}, {}, {}), got {:?}",
+            north,
+            east,
+            down,
+            actual
+        );
+    }
+
+    #[test]
+    fn level_plane_and_centered_gimbal_look_straight_down() {
+        let direction =
+            LookDirection::from_attitudes(&Attitude::new(0.0, 0.0, 0.0), &Attitude::default());
+
+        assert_direction(direction, 0.0, 0.0, 1.0);
+    }
+
+    #[test]
+    fn gimbal_roll_looks_out_of_the_left_side() {
+        // heading east, so the plane's left is north
+        let direction = LookDirection::from_attitudes(
+            &Attitude::new(0.0, 0.0, 90.0),
+            &Attitude::new(90.0, 0.0, 0.0),
+        );
+
+        assert_direction(direction, 1.0, 0.0, 0.0);
+    }
+
+    #[test]
+    fn gimbal_pitch_looks_backwards() {
+        // sin 45 = cos 45 = 0.7071
+        let direction = LookDirection::from_attitudes(
+            &Attitude::new(0.0, 0.0, 0.0),
+            &Attitude::new(0.0, 45.0, 0.0),
+        );
+
+        assert_direction(direction, -0.70711, 0.0, 0.70711);
+    }
+
+    #[test]
+    fn plane_roll_tilts_the_camera() {
+        // rolling right points the belly to the left (west when heading
+        // north): east = -sin 30 = -0.5, down = cos 30 = 0.8660
+        let direction =
+            LookDirection::from_attitudes(&Attitude::new(30.0, 0.0, 0.0), &Attitude::default());
+
+        assert_direction(direction, 0.0, -0.5, 0.86603);
+    }
+
+    #[test]
+    fn gimbal_pitch_cancels_plane_pitch() {
+        // nose up tilts the belly forwards, and the gimbal tilts the camera
+        // back by the same amount
+        let direction = LookDirection::from_attitudes(
+            &Attitude::new(0.0, 10.0, 45.0),
+            &Attitude::new(0.0, 10.0, 0.0),
+        );
+
+        assert_direction(direction, 0.0, 0.0, 1.0);
+    }
+
+    /// Works out UTC from a GPS week and time of week the same way that the
+    /// autopilot does before it sends SYSTEM_TIME. The GPS clock started on
+    /// 1980-01-06 and does not count leap seconds, so it is 18 seconds ahead
+    /// of UTC.
+    fn gps_to_utc(week: u64, time_of_week_ms: u64) -> SystemTime {
+        const GPS_EPOCH_UNIX_SECS: u64 = 315_964_800;
+        const LEAP_SECONDS: u64 = 18;
+
+        SystemTime::UNIX_EPOCH
+            + Duration::from_secs(GPS_EPOCH_UNIX_SECS - LEAP_SECONDS + week * 604_800)
+            + Duration::from_millis(time_of_week_ms)
+    }
+
+    fn unix_ms(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn gps_time_is_behind_by_leap_seconds() {
+        // Thursday of week 2160 at noon GPS time is 2021-06-03 11:59:42 UTC
+        assert_eq!(gps_to_utc(2160, 388_800_000), unix_ms(1_622_721_582_000));
+    }
+
+    #[test]
+    fn converts_host_time_to_gps_time() {
+        // the host clock is 2.25 s behind the GPS
+        let received = unix_ms(1_622_721_579_750);
+        let gps_time = GpsTime {
+            utc: gps_to_utc(2160, 388_800_000),
+            received,
+        };
+
+        assert_eq!(gps_time.offset_secs(), 2.25);
+        assert_eq!(
+            gps_time.convert(received + Duration::from_millis(1500)),
+            unix_ms(1_622_721_583_500)
+        );
+        assert_eq!(
+            gps_time.convert(received - Duration::from_millis(500)),
+            unix_ms(1_622_721_581_500)
+        );
+        assert_eq!(
+            gps_time.to_host(unix_ms(1_622_721_583_500)),
+            received + Duration::from_millis(1500)
+        );
+    }
+}
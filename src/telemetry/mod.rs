@@ -1,24 +1,46 @@
-use crate::{pixhawk::state::PixhawkEvent, state::TelemetryInfo, util::ReceiverExt, Channels};
+use crate::{
+    cli::config::TelemetryConfig,
+    gimbal::state::GimbalEvent,
+    pixhawk::state::PixhawkEvent,
+    state::{Attitude, Battery, GpsTime, LookDirection, TelemetryInfo},
+    util::ReceiverExt,
+    Channels,
+};
 
-use std::sync::{Arc, Mutex};
+use std::{
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
 
 use anyhow::Context;
 use std::time::Duration;
-use tokio::time::interval;
+use tokio::time::{interval, Instant};
 use tokio::{spawn, sync::watch};
 
+/// How much the offset between the GPS clock and the host clock has to change
+/// by before we log it again, in seconds.
+const CLOCK_OFFSET_LOG_THRESHOLD: f64 = 0.05;
+
+/// The telemetry, and whether it changed since it was last published.
+#[derive(Default)]
+struct TelemetryState {
+    info: TelemetryInfo,
+    changed: bool,
+}
+
 // Noteworthy that this isn't a RwLock because we have at most one reader at any given moment
-type TelemetryState = Arc<Mutex<TelemetryInfo>>;
+type SharedTelemetryState = Arc<Mutex<TelemetryState>>;
 
 struct TelemetryCollector {
-    state: TelemetryState,
+    state: SharedTelemetryState,
     channels: Arc<Channels>,
 }
 
 struct TelemetryPublisher {
-    state: TelemetryState,
+    state: SharedTelemetryState,
     sender: watch::Sender<Option<TelemetryInfo>>,
     channels: Arc<Channels>,
+    config: TelemetryConfig,
 }
 
 pub struct TelemetryStream {
@@ -27,13 +49,20 @@ pub struct TelemetryStream {
 }
 
 impl TelemetryCollector {
-    fn new(telemetry_state: TelemetryState, channels: Arc<Channels>) -> Self {
+    fn new(telemetry_state: SharedTelemetryState, channels: Arc<Channels>) -> Self {
         Self {
             state: telemetry_state,
             channels,
         }
     }
 
+    /// Changes the telemetry and marks it to be published.
+    fn update(&self, f: impl FnOnce(&mut TelemetryInfo)) {
+        let mut state = self.state.lock().unwrap();
+        f(&mut state.info);
+        state.changed = true;
+    }
+
     async fn run(&self) -> anyhow::Result<()> {
         let mut interrupt_recv = self.channels.interrupt.subscribe();
         let interrupt_fut = interrupt_recv.recv();
@@ -42,17 +71,73 @@ impl TelemetryCollector {
         // is no cleanup for telemetry stream so we can just do a select
         let loop_fut = async {
             let mut pixhawk_recv = self.channels.pixhawk_event.subscribe();
+            let mut gimbal_recv = self.channels.gimbal_event.subscribe();
+            let mut logged_offset: Option<f64> = None;
 
             loop {
-                let message = pixhawk_recv
-                    .recv_skip()
-                    .await
-                    .context("pixhawk stream closed")?;
+                let message = tokio::select! {
+                    message = pixhawk_recv.recv_skip() => message.context("pixhawk stream closed")?,
+                    Some(event) = gimbal_recv.recv_skip() => {
+                        self.update(|state| {
+                            state.gimbal_attitude = match event {
+                                GimbalEvent::Angles { roll, pitch } => {
+                                    Some(Attitude::new(roll as f32, pitch as f32, 0.0))
+                                }
+                                GimbalEvent::Unavailable => None,
+                            };
+                            update_camera_direction(state);
+                        });
+                        continue;
+                    }
+                };
 
                 match message {
-                    PixhawkEvent::Gps { coords } => self.state.lock().unwrap().position = coords,
-                    PixhawkEvent::Orientation { attitude } => {
-                        self.state.lock().unwrap().plane_attitude = attitude
+                    PixhawkEvent::Gps { coords } => self.update(|state| {
+                        state.position = coords;
+                        state.last_pixhawk_update = Some(Instant::now());
+                    }),
+                    PixhawkEvent::Orientation { attitude } => self.update(|state| {
+                        state.plane_attitude = attitude;
+                        state.last_pixhawk_update = Some(Instant::now());
+                        update_camera_direction(state);
+                    }),
+                    PixhawkEvent::GroundHeartbeat => {
+                        self.update(|state| state.last_ground_contact = Some(SystemTime::now()))
+                    }
+                    PixhawkEvent::Battery {
+                        voltage,
+                        current,
+                        remaining_percent,
+                    } => self.update(|state| {
+                        state.battery = Some(Battery {
+                            voltage,
+                            current,
+                            remaining_percent,
+                        })
+                    }),
+                    PixhawkEvent::SystemTime { time } => {
+                        let gps_time = GpsTime {
+                            utc: time,
+                            received: SystemTime::now(),
+                        };
+
+                        // the offset is recomputed every time the autopilot
+                        // sends the time, but only log it when it moves
+                        let offset = gps_time.offset_secs();
+
+                        match logged_offset {
+                            Some(logged)
+                                if (offset - logged).abs() < CLOCK_OFFSET_LOG_THRESHOLD =>
+                            {
+                                trace!("gps clock offset: {:.3} s", offset)
+                            }
+                            _ => {
+                                info!("gps clock is {:.3} s ahead of host clock", offset);
+                                logged_offset = Some(offset);
+                            }
+                        }
+
+                        self.update(|state| state.gps_time = Some(gps_time))
                     }
                     _ => {}
                 }
@@ -72,16 +157,26 @@ impl TelemetryCollector {
     }
 }
 
+/// Recomputes which way the camera is pointing after the plane or the gimbal
+/// moves.
+fn update_camera_direction(state: &mut TelemetryInfo) {
+    state.camera_direction = state
+        .gimbal_attitude
+        .map(|gimbal| LookDirection::from_attitudes(&state.plane_attitude, &gimbal));
+}
+
 impl TelemetryPublisher {
     fn new(
-        state: TelemetryState,
+        state: SharedTelemetryState,
         sender: watch::Sender<Option<TelemetryInfo>>,
         channels: Arc<Channels>,
+        config: TelemetryConfig,
     ) -> Self {
         Self {
             state,
             sender,
             channels,
+            config,
         }
     }
 
@@ -91,10 +186,21 @@ impl TelemetryPublisher {
 
         let mut interval = interval(Duration::from_millis(5));
 
+        let stale_after = Duration::from_millis(self.config.stale_after_ms);
+        let started = Instant::now();
+
         loop {
-            if let Ok(telemetry) = self.state.lock() {
-                if let Err(_) = self.sender.send(Some(telemetry.clone())) {
-                    break;
+            if let Ok(mut state) = self.state.lock() {
+                let went_stale = update_stale(&mut state.info, started, stale_after);
+
+                // only wake up everyone watching the telemetry when there is
+                // something new to see
+                if went_stale || state.changed {
+                    state.changed = false;
+
+                    if let Err(_) = self.sender.send(Some(state.info.clone())) {
+                        break;
+                    }
                 }
             }
 
@@ -109,12 +215,46 @@ impl TelemetryPublisher {
     }
 }
 
+/// Marks the telemetry as stale if the pixhawk hasn't sent a position or
+/// attitude in `stale_after`, counting from `started` if it never has.
+/// Returns whether the flag changed; it only changes when the pixhawk goes
+/// quiet or comes back, so this logs once each way.
+fn update_stale(telemetry: &mut TelemetryInfo, started: Instant, stale_after: Duration) -> bool {
+    let last_update = telemetry.last_pixhawk_update.unwrap_or(started);
+    let stale = last_update.elapsed() > stale_after;
+
+    if stale == telemetry.stale {
+        return false;
+    }
+
+    if stale {
+        warn!(
+            "no position or attitude from pixhawk in {:?}, telemetry is stale",
+            stale_after
+        );
+    } else {
+        info!("pixhawk telemetry resumed");
+    }
+
+    telemetry.stale = stale;
+    true
+}
+
 impl TelemetryStream {
-    pub fn new(channels: Arc<Channels>, sender: watch::Sender<Option<TelemetryInfo>>) -> Self {
-        let telemetry_state = Arc::new(Mutex::new(TelemetryInfo::default()));
+    pub fn new(
+        channels: Arc<Channels>,
+        sender: watch::Sender<Option<TelemetryInfo>>,
+        config: TelemetryConfig,
+    ) -> Self {
+        // publish the telemetry that we start with straight away
+        let telemetry_state = Arc::new(Mutex::new(TelemetryState {
+            info: TelemetryInfo::default(),
+            changed: true,
+        }));
 
         let collector = TelemetryCollector::new(telemetry_state.clone(), channels.clone());
-        let publisher = TelemetryPublisher::new(telemetry_state.clone(), sender, channels.clone());
+        let publisher =
+            TelemetryPublisher::new(telemetry_state.clone(), sender, channels.clone(), config);
 
         Self {
             collector,
@@ -138,3 +278,76 @@ impl TelemetryStream {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn telemetry_goes_stale_once_after_the_pixhawk_goes_quiet() {
+        tokio::time::pause();
+
+        let stale_after = Duration::from_millis(3000);
+        let started = Instant::now();
+        let mut telemetry = TelemetryInfo::default();
+        telemetry.last_pixhawk_update = Some(Instant::now());
+
+        tokio::time::advance(Duration::from_millis(2999)).await;
+        assert!(!update_stale(&mut telemetry, started, stale_after));
+        assert!(!telemetry.stale);
+
+        tokio::time::advance(Duration::from_millis(2)).await;
+        assert!(update_stale(&mut telemetry, started, stale_after));
+        assert!(telemetry.stale);
+
+        // it doesn't flip again while the pixhawk stays quiet
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(!update_stale(&mut telemetry, started, stale_after));
+        assert!(telemetry.stale);
+
+        telemetry.last_pixhawk_update = Some(Instant::now());
+        assert!(update_stale(&mut telemetry, started, stale_after));
+        assert!(!telemetry.stale);
+    }
+
+    #[tokio::test]
+    async fn telemetry_is_only_published_when_it_changes() {
+        tokio::time::pause();
+
+        let mut info = TelemetryInfo::default();
+        info.last_pixhawk_update = Some(Instant::now());
+        let state = Arc::new(Mutex::new(TelemetryState {
+            info,
+            changed: true,
+        }));
+
+        let (sender, mut receiver) = watch::channel(None);
+        let publisher = TelemetryPublisher::new(
+            state.clone(),
+            sender,
+            crate::TestChannels::new().channels,
+            TelemetryConfig {
+                stale_after_ms: 3000,
+            },
+        );
+        spawn(async move { publisher.run().await });
+
+        receiver.changed().await.unwrap();
+
+        // nothing new for a second, so nobody gets woken up
+        let quiet = tokio::time::timeout(Duration::from_secs(1), receiver.changed()).await;
+        assert!(quiet.is_err());
+
+        {
+            let mut state = state.lock().unwrap();
+            state.info.position.altitude = 120.0;
+            state.info.last_pixhawk_update = Some(Instant::now());
+            state.changed = true;
+        }
+
+        receiver.changed().await.unwrap();
+        let published = receiver.borrow().unwrap();
+        assert_eq!(published.position.altitude, 120.0);
+        assert!(!published.stale);
+    }
+}